@@ -0,0 +1,54 @@
+// Owned against borrowed CSV parsing of a big layout, run with
+// `cargo bench --bench csv`
+
+use std::hint::black_box;
+use std::io::Cursor;
+use std::time::Instant;
+
+use twiddler_cfg::{buttons::ButtonState, csv};
+
+const ROWS: u32 = 2000;
+const RUNS: u32 = 50;
+
+fn layout() -> String {
+    let mut data = String::from("Thumbs,Fingers,Keyboard Output\n");
+    for bits in (1..).filter_map(ButtonState::from_bits).take(ROWS as usize) {
+        let (thumbs, fingers) = bits.to_notation();
+        data.push_str(&format!("{},{},word{} \n", thumbs, fingers, bits.bits()));
+    }
+    data
+}
+
+fn time(name: &str, mut run: impl FnMut()) {
+    run();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        run();
+    }
+    let per_run = start.elapsed() / RUNS;
+    println!(
+        "{:<10} {:>10.3?} per run, {:>8.3?} per row",
+        name,
+        per_run,
+        per_run / ROWS
+    );
+}
+
+fn main() {
+    let data = layout();
+
+    time("owned", || {
+        let chords = csv::parse(&mut Cursor::new(&data)).unwrap();
+        for chord in &chords {
+            black_box(chord.buttons());
+        }
+    });
+
+    time("borrowed", || {
+        csv::for_each_borrowed(data.as_bytes(), |chord| {
+            black_box(chord.buttons()?);
+            Ok(())
+        })
+        .unwrap();
+    });
+}
@@ -0,0 +1,30 @@
+// Key code lookups both ways, what the optimizer does for every candidate
+// chord, run with `cargo bench --bench hid`
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use twiddler_cfg::{csv::Chord, hid};
+
+const RUNS: u32 = 200;
+
+fn main() {
+    let outputs = [
+        "the ",
+        "<L-Ctrl>c</L-Ctrl>",
+        "Hello, World!",
+        "<F5><Return>",
+        "a",
+    ];
+    let chords: Vec<Chord> = outputs.iter().map(|o| Chord::new("", "1L", *o)).collect();
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        for chord in &chords {
+            let pairs = black_box(chord.get_hid_pairs().unwrap());
+            black_box(hid::pairs_to_output(&pairs));
+        }
+    }
+    let per_chord = start.elapsed() / (RUNS * chords.len() as u32);
+    println!("{:.3?} per chord to key codes and back", per_chord);
+}
@@ -0,0 +1,21 @@
+fn main() {
+    // Only the C API needs a header. It's generated into OUT_DIR, building
+    // doesn't touch the source tree, and a test checks the one in include/
+    // still matches it.
+    #[cfg(feature = "capi")]
+    {
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out = std::env::var("OUT_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+        cbindgen::Builder::new()
+            .with_crate(&dir)
+            .with_config(config)
+            .generate()
+            .expect("Unable to generate the C header")
+            .write_to_file(format!("{}/twiddler_cfg.h", out));
+    }
+    #[cfg(not(feature = "capi"))]
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,87 @@
+/* Generated by cbindgen from src/capi.rs, see the capi feature in the README to update */
+
+#ifndef TWIDDLER_CFG_H
+#define TWIDDLER_CFG_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Bytes allocated by this library, free them with [`twiddler_buffer_free`]
+ */
+typedef struct TwiddlerBuffer {
+  uint8_t *data;
+  size_t len;
+} TwiddlerBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * The message of the last error on this thread, or NULL. It stays valid
+ * until the next call that fails.
+ */
+const char *twiddler_last_error(void);
+
+/**
+ * Converts `len` bytes at `data` from the format named `from`, detected
+ * when NULL, to the one named `to`. Names are the ones the CLI takes, like
+ * "csv" or "v7". Returns 0 and fills `output` on success, -1 otherwise.
+ *
+ * # Safety
+ *
+ * `data` has to point to `len` readable bytes, `from` and `to` to NUL
+ * terminated strings and `output` to a writable [`TwiddlerBuffer`].
+ */
+int32_t twiddler_convert(const uint8_t *data,
+                         size_t len,
+                         const char *from,
+                         const char *to,
+                         struct TwiddlerBuffer *output);
+
+/**
+ * Parses a config like [`twiddler_convert`] and gives it as a JSON config
+ * string, or NULL on errors. Free it with [`twiddler_string_free`].
+ *
+ * # Safety
+ *
+ * `data` has to point to `len` readable bytes and `from` to a NUL
+ * terminated string or be NULL.
+ */
+char *twiddler_parse(const uint8_t *data, size_t len, const char *from);
+
+/**
+ * The `validate` command's checks as a JSON string,
+ * `{"conflicts": [...], "duplicate_outputs": [...]}` with chords referred
+ * to by their index, or NULL on errors. Free it with
+ * [`twiddler_string_free`].
+ *
+ * # Safety
+ *
+ * Same as [`twiddler_parse`].
+ */
+char *twiddler_validate(const uint8_t *data, size_t len, const char *from);
+
+/**
+ * # Safety
+ *
+ * `string` has to come from this library and not be freed already.
+ */
+void twiddler_string_free(char *string);
+
+/**
+ * # Safety
+ *
+ * `buffer` has to come from [`twiddler_convert`] and not be freed already.
+ */
+void twiddler_buffer_free(struct TwiddlerBuffer buffer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* TWIDDLER_CFG_H */
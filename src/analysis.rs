@@ -0,0 +1,674 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{
+    buttons::ButtonState,
+    csv::Chord,
+    output::{self, OutputToken},
+};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayoutStats {
+    pub chords: usize,
+    /// Chords pressing a single button, thumb or finger
+    pub single_button: usize,
+    pub multi_button: usize,
+    /// Chords using each thumb, T1 first
+    pub thumbs: [usize; 4],
+    /// Chords using each finger row, row 0 first
+    pub fingers: [usize; 5],
+    /// Chords holding each modifier, in `hid::MODIFIER_TAGS` order
+    pub modifiers: [usize; 8],
+    /// Lower case letters that no chord types on its own
+    pub unassigned_letters: Vec<char>,
+    /// Chords in each category, by name, chords without one aren't counted
+    pub categories: BTreeMap<String, usize>,
+}
+
+impl LayoutStats {
+    /// Ratio of single button chords to all chords
+    pub fn single_button_ratio(&self) -> f64 {
+        if self.chords == 0 {
+            0.0
+        } else {
+            self.single_button as f64 / self.chords as f64
+        }
+    }
+}
+
+pub fn stats(chords: &[Chord]) -> LayoutStats {
+    let mut stats = LayoutStats {
+        chords: chords.len(),
+        ..Default::default()
+    };
+
+    for chord in chords {
+        let buttons = chord.buttons();
+        let thumbs = buttons.thumbs();
+        let fingers = buttons.fingers();
+
+        let pressed: usize = thumbs.iter().filter(|t| **t).count()
+            + fingers.iter().flatten().filter(|f| **f).count();
+        if pressed == 1 {
+            stats.single_button += 1;
+        } else if pressed > 1 {
+            stats.multi_button += 1;
+        }
+
+        for (count, pressed) in stats.thumbs.iter_mut().zip(thumbs) {
+            *count += pressed as usize;
+        }
+        for (count, row) in stats.fingers.iter_mut().zip(fingers) {
+            *count += row.contains(&true) as usize;
+        }
+
+        // Outputs that don't parse have no modifiers to count
+        let held = output::parse(chord.output())
+            .map(|tokens| held_modifiers(&tokens))
+            .unwrap_or_default();
+        for (bit, count) in stats.modifiers.iter_mut().enumerate() {
+            *count += (held & (1 << bit) != 0) as usize;
+        }
+
+        if let Some(category) = chord.category() {
+            *stats.categories.entry(category.to_string()).or_default() += 1;
+        }
+    }
+
+    stats.unassigned_letters = ('a'..='z')
+        .filter(|letter| {
+            !chords
+                .iter()
+                .any(|chord| chord.output() == letter.to_string())
+        })
+        .collect();
+
+    stats
+}
+
+/// How much work typing a corpus takes with a layout, lower is better
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Score {
+    /// Sum of the effort of every chord pressed
+    pub effort: f64,
+    /// Characters of the corpus typed by a chord
+    pub typed: usize,
+    /// Characters no chord types
+    pub untyped: usize,
+}
+
+impl Score {
+    /// Effort per typed character, comparable between corpora
+    pub fn per_char(&self) -> f64 {
+        if self.typed == 0 {
+            0.0
+        } else {
+            self.effort / self.typed as f64
+        }
+    }
+}
+
+/// Score a layout by typing `corpus` with it, see [`output_counts`] for how
+/// chords are picked and [`effort`] for the cost of a chord
+pub fn score(chords: &[Chord], corpus: &str) -> Score {
+    score_with(chords, corpus, &Weights::default())
+}
+
+/// [`score`] with the cost of each chord from `model`
+pub fn score_with(chords: &[Chord], corpus: &str, model: &dyn ChordCostModel) -> Score {
+    let (counts, untyped) = output_counts(chords, corpus);
+
+    let mut score = Score {
+        untyped,
+        ..Default::default()
+    };
+    // The cheapest chord when the same output is on several
+    let cost = |(text, count): (&str, usize)| {
+        let cost = chords
+            .iter()
+            .filter(|chord| plain_text(chord) == Some(text))
+            .map(|chord| model.cost(&chord.buttons()))
+            .fold(f64::INFINITY, f64::min);
+        (cost * count as f64, text.chars().count() * count)
+    };
+    #[cfg(feature = "rayon")]
+    let costs: Vec<(f64, usize)> = counts.into_par_iter().map(cost).collect();
+    #[cfg(not(feature = "rayon"))]
+    let costs: Vec<(f64, usize)> = counts.into_iter().map(cost).collect();
+
+    for (effort, typed) in costs {
+        score.effort += effort;
+        score.typed += typed;
+    }
+
+    score
+}
+
+/// How often each output is typed when typing `corpus`, always taking the
+/// chord with the longest matching output, and the number of characters no
+/// chord types. Only chords with plain text outputs are used, counted by
+/// their output borrowed from `chords`.
+pub fn output_counts<'a>(chords: &'a [Chord], corpus: &str) -> (HashMap<&'a str, usize>, usize) {
+    let outputs: Vec<&str> = chords.iter().filter_map(plain_text).collect();
+
+    // No output can match across a line break unless it has one, so lines
+    // are counted on their own
+    #[cfg(feature = "rayon")]
+    if !outputs.iter().any(|text| text.contains('\n')) {
+        return corpus
+            .par_split_inclusive('\n')
+            .map(|line| count_outputs(&outputs, line))
+            .reduce(
+                Default::default,
+                |(mut counts, untyped), (more, more_untyped)| {
+                    for (text, count) in more {
+                        *counts.entry(text).or_insert(0) += count;
+                    }
+                    (counts, untyped + more_untyped)
+                },
+            );
+    }
+
+    count_outputs(&outputs, corpus)
+}
+
+fn count_outputs<'a>(outputs: &[&'a str], corpus: &str) -> (HashMap<&'a str, usize>, usize) {
+    let mut counts = HashMap::new();
+    let mut untyped = 0;
+    let mut rest = corpus;
+    while let Some(c) = rest.chars().next() {
+        let longest = outputs
+            .iter()
+            .filter(|text| rest.starts_with(**text))
+            .max_by_key(|text| text.len());
+        match longest {
+            Some(text) => {
+                *counts.entry(*text).or_insert(0) += 1;
+                rest = &rest[text.len()..];
+            }
+            None => {
+                untyped += 1;
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+
+    (counts, untyped)
+}
+
+/// The chords pressed typing `corpus`, picked like [`output_counts`] does
+/// and the first when several type the same output. `None` stands for a
+/// character no chord types.
+pub fn chord_sequence(chords: &[Chord], corpus: &str) -> Vec<Option<usize>> {
+    let outputs: Vec<(usize, &str)> = chords
+        .iter()
+        .enumerate()
+        .filter_map(|(i, chord)| Some((i, plain_text(chord)?)))
+        .collect();
+
+    let mut sequence = vec![];
+    let mut rest = corpus;
+    while let Some(c) = rest.chars().next() {
+        let longest = outputs
+            .iter()
+            .filter(|(_, text)| rest.starts_with(*text))
+            .max_by_key(|(i, text)| (text.len(), std::cmp::Reverse(*i)));
+        match longest {
+            Some((i, text)) => {
+                sequence.push(Some(*i));
+                rest = &rest[text.len()..];
+            }
+            None => {
+                sequence.push(None);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    sequence
+}
+
+fn plain_text(chord: &Chord) -> Option<&str> {
+    // Anything with a tag or escape in it isn't typed as written, lone
+    // characters always are
+    if chord.output().chars().count() == 1 {
+        return Some(chord.output());
+    }
+    match output::parse(chord.output()).as_deref() {
+        Ok([OutputToken::Text(text)]) if text == chord.output() => Some(chord.output()),
+        _ => None,
+    }
+}
+
+/// How hard a chord is to press, lower is easier. [`score_with`] and
+/// [`optimize_with`](crate::optimize::optimize_with) take one, so other
+/// models of the hand can be tried without touching either. Closures taking
+/// the buttons are models too.
+pub trait ChordCostModel: Sync {
+    fn cost(&self, buttons: &ButtonState) -> f64;
+
+    /// Extra cost of pressing `to` right after `from`, for
+    /// [`optimize_ngrams`](crate::optimize::optimize_ngrams). None by
+    /// default.
+    fn transition(&self, from: &ButtonState, to: &ButtonState) -> f64 {
+        let _ = (from, to);
+        0.0
+    }
+}
+
+impl<F: Fn(&ButtonState) -> f64 + Sync> ChordCostModel for F {
+    fn cost(&self, buttons: &ButtonState) -> f64 {
+        self(buttons)
+    }
+}
+
+/// The built in [`ChordCostModel`], a sum of what each button pressed costs
+/// plus penalties for harder combinations
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Weights {
+    /// Each thumb button, T1 first
+    pub thumbs: [f64; 4],
+    /// A button of each finger row, row 0 first
+    pub fingers: [f64; 5],
+    /// Added for every button past the first a finger holds in its row
+    pub same_row: f64,
+    /// Added once when a chord presses thumbs and finger buttons together
+    pub thumb_with_fingers: f64,
+    /// Added for each finger, or the thumb, that has to move to other buttons
+    /// between one chord and the next. Buttons both chords share cost
+    /// nothing.
+    pub same_finger: f64,
+}
+
+impl Default for Weights {
+    /// The weights of [`effort`]
+    fn default() -> Self {
+        Weights {
+            thumbs: [1.0; 4],
+            fingers: [1.5, 1.0, 1.0, 1.0, 1.5],
+            same_row: 1.0,
+            thumb_with_fingers: 0.0,
+            same_finger: 1.0,
+        }
+    }
+}
+
+impl ChordCostModel for Weights {
+    fn cost(&self, buttons: &ButtonState) -> f64 {
+        let thumbs = buttons.thumbs();
+        let mut cost: f64 = self
+            .thumbs
+            .iter()
+            .zip(thumbs)
+            .filter(|(_, pressed)| *pressed)
+            .map(|(weight, _)| weight)
+            .sum();
+        let mut any_finger = false;
+        for (weight, pressed) in self.fingers.iter().zip(buttons.fingers()) {
+            let count = pressed.iter().filter(|f| **f).count() as f64;
+            cost += count * weight;
+            if count > 1.0 {
+                cost += (count - 1.0) * self.same_row;
+            }
+            any_finger |= count > 0.0;
+        }
+        if any_finger && thumbs.contains(&true) {
+            cost += self.thumb_with_fingers;
+        }
+        cost
+    }
+
+    fn transition(&self, from: &ButtonState, to: &ButtonState) -> f64 {
+        let moves =
+            |from: &[bool], to: &[bool]| from.contains(&true) && to.contains(&true) && from != to;
+        let mut moved = moves(&from.thumbs(), &to.thumbs()) as usize;
+        for (from, to) in from.fingers().iter().zip(to.fingers()) {
+            moved += moves(from, &to) as usize;
+        }
+        moved as f64 * self.same_finger
+    }
+}
+
+/// Difficulty of pressing a chord with the default [`Weights`]: one per
+/// button, an extra half for the pinky and row 0, and another one whenever a
+/// finger has to hold two buttons at once
+pub fn effort(buttons: &ButtonState) -> f64 {
+    Weights::default().cost(buttons)
+}
+
+/// How much each button is pressed over a layout, see [`button_usage`]
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ButtonUsage {
+    /// T1 first
+    pub thumbs: [f64; 4],
+    /// Row 0 first, then the L M R column
+    pub fingers: [[f64; 3]; 5],
+    /// Weight of all the chords, buttons pressed together each count it
+    pub total: f64,
+}
+
+impl ButtonUsage {
+    /// The most any button is used
+    pub fn max(&self) -> f64 {
+        self.thumbs
+            .iter()
+            .chain(self.fingers.iter().flatten())
+            .fold(0.0, |max, usage| f64::max(max, *usage))
+    }
+
+    /// Share of the chord weight a usage is, 0 to 1
+    pub fn share(&self, usage: f64) -> f64 {
+        if self.total == 0.0 {
+            0.0
+        } else {
+            usage / self.total
+        }
+    }
+}
+
+/// Sum of `weight` over the chords pressing each button, `|_| 1.0` counts
+/// chords and [`letter_frequency`] weighs them by how much English typing
+/// uses them
+pub fn button_usage(chords: &[Chord], weight: impl Fn(&Chord) -> f64) -> ButtonUsage {
+    let mut usage = ButtonUsage::default();
+    for chord in chords {
+        let weight = weight(chord);
+        let buttons = chord.buttons();
+        for (usage, pressed) in usage.thumbs.iter_mut().zip(buttons.thumbs()) {
+            *usage += weight * pressed as u8 as f64;
+        }
+        for (row, pressed) in usage.fingers.iter_mut().zip(buttons.fingers()) {
+            for (usage, pressed) in row.iter_mut().zip(pressed) {
+                *usage += weight * pressed as u8 as f64;
+            }
+        }
+        usage.total += weight;
+    }
+    usage
+}
+
+/// Percent of the letters in English text that are each letter, a to z
+pub const ENGLISH_LETTER_FREQUENCIES: [f64; 26] = [
+    8.167, 1.492, 2.782, 4.253, 12.702, 2.228, 2.015, 6.094, 6.966, 0.153, 0.772, 4.025, 2.406,
+    6.749, 7.507, 1.929, 0.095, 5.987, 6.327, 9.056, 2.758, 0.978, 2.360, 0.150, 1.974, 0.074,
+];
+
+/// How often English text has the letter a chord types, either case, and
+/// nothing for chords typing anything else
+pub fn letter_frequency(chord: &Chord) -> f64 {
+    let mut chars = chord.output().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => {
+            ENGLISH_LETTER_FREQUENCIES[(c.to_ascii_lowercase() as u8 - b'a') as usize]
+        }
+        _ => 0.0,
+    }
+}
+
+/// Two chords one mispress apart typing different things, see
+/// [`confusions`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Confusion {
+    /// Indices of the chords, in order
+    pub chords: (usize, usize),
+    /// Buttons one of the chords presses and the other doesn't
+    pub difference: ButtonState,
+    /// How likely the mispress is times how different the outputs are,
+    /// 0 to 1
+    pub risk: f64,
+}
+
+/// Pairs of chords a single button apart, riskiest first. A finger button
+/// pressed or missed is the likeliest mispress, then a thumb, then a finger
+/// slipping to the next button in its row. Outputs are most different when
+/// one is a shortcut or named key and the other text, and hardly at all
+/// when they only differ in case. Later chords on buttons already mapped
+/// are left out, the device never types them.
+pub fn confusions(chords: &[Chord]) -> Vec<Confusion> {
+    let mut seen = HashSet::new();
+    let firsts: Vec<(usize, ButtonState)> = chords
+        .iter()
+        .enumerate()
+        .map(|(i, chord)| (i, chord.buttons()))
+        .filter(|(_, buttons)| seen.insert(*buttons))
+        .collect();
+
+    // Every chord against the ones after it
+    let pairs = |n: usize| {
+        let (i, a) = firsts[n];
+        firsts[n + 1..].iter().filter_map(move |&(j, b)| {
+            let difference = ButtonState::from_bits(a.bits() ^ b.bits())?;
+            let likelihood = mispress_likelihood(difference, a)?;
+            let risk = likelihood * output_distance(chords[i].output(), chords[j].output());
+            (risk > 0.0).then_some(Confusion {
+                chords: (i, j),
+                difference,
+                risk,
+            })
+        })
+    };
+    #[cfg(feature = "rayon")]
+    let mut confusions: Vec<Confusion> = (0..firsts.len())
+        .into_par_iter()
+        .flat_map_iter(pairs)
+        .collect();
+    #[cfg(not(feature = "rayon"))]
+    let mut confusions: Vec<Confusion> = (0..firsts.len()).flat_map(pairs).collect();
+
+    confusions.sort_by(|a, b| b.risk.total_cmp(&a.risk));
+    confusions
+}
+
+// How likely pressing one chord for another is, None when it takes more than
+// one mistake. `pressed` is either of the chords.
+fn mispress_likelihood(difference: ButtonState, pressed: ButtonState) -> Option<f64> {
+    let thumbs = ButtonState::T1 | ButtonState::T2 | ButtonState::T3 | ButtonState::T4;
+    match difference.len() {
+        1 if thumbs.contains(difference) => Some(0.7),
+        1 => Some(1.0),
+        2 => {
+            // One button released and the next one in the same row pressed
+            let bits: Vec<u32> = difference
+                .iter()
+                .map(|b| b.bits().trailing_zeros())
+                .collect();
+            let moved = bits[0] >= 4
+                && (bits[0] - 4) / 3 == (bits[1] - 4) / 3
+                && bits[1] - bits[0] == 1
+                && difference.iter().filter(|b| pressed.contains(*b)).count() == 1;
+            moved.then_some(0.5)
+        }
+        _ => None,
+    }
+}
+
+// 0 for outputs that are the same, 1 for a shortcut or key against text
+fn output_distance(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 0.0;
+    }
+    // Anything that doesn't parse is treated like a shortcut, but
+    // lone characters never are, like in plain_text
+    let is_command = |output: &str| {
+        output.chars().count() != 1 && !output::parse(output).is_ok_and(|tokens| is_text(&tokens))
+    };
+    match (is_command(a), is_command(b)) {
+        (true, true) => 0.75,
+        (false, false) if a.to_lowercase() == b.to_lowercase() => 0.1,
+        (false, false) => {
+            let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+            let shared = a.iter().zip(&b).take_while(|(a, b)| a == b).count();
+            0.5 * (1.0 - shared as f64 / a.len().max(b.len()) as f64)
+        }
+        _ => 1.0,
+    }
+}
+
+// Only characters, shifted or not
+fn is_text(tokens: &[OutputToken]) -> bool {
+    tokens.iter().all(|token| match token {
+        OutputToken::Text(_) => true,
+        OutputToken::Modifier {
+            modifier, tokens, ..
+        } => modifier & !0x22 == 0 && is_text(tokens),
+        _ => false,
+    })
+}
+
+pub(crate) fn held_modifiers(tokens: &[OutputToken]) -> u8 {
+    tokens.iter().fold(0, |held, token| match token {
+        OutputToken::Modifier {
+            modifier, tokens, ..
+        } => held | modifier | held_modifiers(tokens),
+        _ => held,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "2R", "<L-Ctrl>c</L-Ctrl>"),
+            Chord::new("4", "", "<L-Ctrl><L-Shift>t</L-Shift></L-Ctrl>"),
+        ];
+        chords[2].set_category("macros");
+        chords[3].set_category("macros");
+        let stats = stats(&chords);
+        assert!(stats.chords == 4);
+        assert!(stats.single_button == 3 && stats.multi_button == 1);
+        assert!(stats.single_button_ratio() == 0.75);
+        assert!(stats.thumbs == [1, 0, 0, 1]);
+        assert!(stats.fingers == [0, 2, 2, 0, 0]);
+        assert!(stats.modifiers == [2, 1, 0, 0, 0, 0, 0, 0]);
+        assert!(stats.unassigned_letters.len() == 25);
+        assert!(!stats.unassigned_letters.contains(&'e'));
+        assert!(stats.categories.len() == 1 && stats.categories["macros"] == 2);
+    }
+
+    #[test]
+    fn test_effort() {
+        assert!(effort(&"1L".parse().unwrap()) == 1.0);
+        assert!(effort(&"T1 4R".parse().unwrap()) == 2.5);
+        assert!(effort(&"2L 2M".parse().unwrap()) == 3.0);
+    }
+
+    #[test]
+    fn test_weights() {
+        let weights = Weights {
+            thumbs: [0.5, 2.0, 2.0, 2.0],
+            same_row: 3.0,
+            thumb_with_fingers: 0.25,
+            ..Default::default()
+        };
+        assert!(weights.cost(&"T1".parse().unwrap()) == 0.5);
+        assert!(weights.cost(&"T1 4R".parse().unwrap()) == 0.5 + 1.5 + 0.25);
+        assert!(weights.cost(&"2L 2M".parse().unwrap()) == 2.0 + 3.0);
+
+        let chords = vec![Chord::new("1", "", "a"), Chord::new("", "1L", "b")];
+        assert!(score_with(&chords, "ab", &weights).effort == 0.5 + 1.0);
+        // Any closure will do
+        let presses = |buttons: &ButtonState| buttons.len() as f64 * 2.0;
+        assert!(score_with(&chords, "ab", &presses).effort == 4.0);
+
+        let transition = |from: &str, to: &str| {
+            Weights::default().transition(&from.parse().unwrap(), &to.parse().unwrap())
+        };
+        assert!(transition("1L", "1L 2M") == 0.0);
+        assert!(transition("1L", "1M") == 1.0);
+        assert!(transition("T1 1L 2M", "T2 1L 2R") == 2.0);
+        assert!(transition("T1", "1L") == 0.0);
+    }
+
+    #[test]
+    fn test_chord_sequence() {
+        let chords = vec![
+            Chord::new("", "1L", "t"),
+            Chord::new("", "1M", "th"),
+            Chord::new("", "1R", "e"),
+            Chord::new("", "2L", "e"),
+        ];
+        assert!(chord_sequence(&chords, "the ex") == [Some(1), Some(2), None, Some(2), None]);
+    }
+
+    #[test]
+    fn test_score() {
+        let chords = vec![
+            Chord::new("", "1L", "t"),
+            Chord::new("", "2L", "h"),
+            Chord::new("", "3L", "e"),
+            Chord::new("1", "1L 2M", "the"),
+            Chord::new("", "1M", "<L-Shift>t</L-Shift>"),
+        ];
+        let score = score(&chords, "the tea");
+        assert!(score.effort == 3.0 + 1.0 + 1.0);
+        assert!(score.typed == 5);
+        // Neither the space nor the a have a chord
+        assert!(score.untyped == 2);
+        assert!(score.per_char() == 1.0);
+    }
+
+    #[test]
+    fn test_output_counts() {
+        let chords = vec![Chord::new("", "1L", "ab"), Chord::new("", "1M", "a")];
+        let corpus = "ab\naab\nb\n";
+        let expected = count_outputs(&["ab", "a"], corpus);
+        assert!(output_counts(&chords, corpus) == expected);
+        assert!(expected.0["ab"] == 2 && expected.0["a"] == 1 && expected.1 == 4);
+    }
+
+    #[test]
+    fn test_button_usage() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "2M", "Q"),
+        ];
+        let usage = button_usage(&chords, |_| 1.0);
+        assert!(usage.fingers[1] == [2.0, 0.0, 0.0]);
+        assert!(usage.fingers[2] == [0.0, 2.0, 0.0]);
+        assert!(usage.thumbs == [1.0, 0.0, 0.0, 0.0]);
+        assert!(usage.total == 3.0 && usage.max() == 2.0);
+
+        let usage = button_usage(&chords, letter_frequency);
+        assert!(usage.fingers[1][0] == 12.702 && usage.fingers[2][1] == 0.095);
+        assert!(usage.thumbs[0] == 0.0);
+        assert!(usage.share(usage.fingers[1][0]) == 12.702 / 12.797);
+    }
+
+    #[test]
+    fn test_confusions() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("", "1L 2L", "<L-Ctrl>w</L-Ctrl>"),
+            Chord::new("", "1M", "E"),
+            Chord::new("1", "1L", "the"),
+            Chord::new("", "1R", "then"),
+            Chord::new("", "2L", "<Backspace>"),
+            Chord::new("", "1L", "duplicate"),
+        ];
+        let confusions = confusions(&chords);
+        let pairs: Vec<(usize, usize)> = confusions.iter().map(|c| c.chords).collect();
+        assert!(pairs == [(0, 1), (1, 5), (0, 3), (2, 4), (0, 2)]);
+        assert!(confusions[0].difference == ButtonState::F2L && confusions[0].risk == 1.0);
+        assert!(confusions[1].risk == 0.75);
+        assert!(confusions[2].difference == ButtonState::T1);
+        assert!(confusions[2].risk == 0.7 * 0.5);
+        // A finger slipping to the next button in its row
+        assert!(confusions[3].difference == ButtonState::F1M | ButtonState::F1R);
+        assert!(confusions[3].risk == 0.5 * 0.5);
+        // Only the case changes
+        assert!(confusions[4].risk == 0.5 * 0.1);
+    }
+}
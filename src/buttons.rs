@@ -1,3 +1,15 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+use core::ops::{BitAnd, BitOr, BitOrAssign};
+use core::str::FromStr;
+
+use crate::{Error, Result};
+
 #[rustfmt::skip]
 pub enum TwiddlerButtons {
     T1, T2, T3, T4,
@@ -8,91 +20,563 @@ pub enum TwiddlerButtons {
     F4L, F4M, F4R,
 }
 
-#[derive(Debug)]
-pub struct ButtonState {
-    pub t1: bool,
-    pub t2: bool,
-    pub t3: bool,
-    pub t4: bool,
-    pub f0l: bool,
-    pub f0m: bool,
-    pub f0r: bool,
-    pub f1l: bool,
-    pub f1m: bool,
-    pub f1r: bool,
-    pub f2l: bool,
-    pub f2m: bool,
-    pub f2r: bool,
-    pub f3l: bool,
-    pub f3m: bool,
-    pub f3r: bool,
-    pub f4l: bool,
-    pub f4m: bool,
-    pub f4r: bool,
-}
-
-pub(crate) fn parse_notation(thumb: String, finger: String) -> ButtonState {
-    if thumb.contains(&['0', '1', '2', '3', '4'][..]) {
+/// The buttons pressed for a chord, one bit per button. Buttons combine
+/// like flags, e.g. `ButtonState::T1 | ButtonState::F1R`, and order by
+/// their bits so thumbs sort before fingers.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct ButtonState(u32);
+
+macro_rules! buttons {
+    ($($name:ident $getter:ident $bit:literal,)*) => {
+        impl ButtonState {
+            $(pub const $name: ButtonState = ButtonState(1 << $bit);)*
+            const ALL: u32 = $(1 << $bit)|*;
+
+            $(
+                pub const fn $getter(self) -> bool {
+                    self.contains(Self::$name)
+                }
+            )*
+        }
+
+        impl fmt::Debug for ButtonState {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let names: Vec<&str> = [$((Self::$name, stringify!($name)),)*]
+                    .iter()
+                    .filter(|(button, _)| self.contains(*button))
+                    .map(|(_, name)| *name)
+                    .collect();
+                write!(f, "ButtonState({})", names.join(" | "))
+            }
+        }
+    };
+}
+
+// Finger buttons are bit 4 + 3 * row + column
+#[rustfmt::skip]
+buttons! {
+    T1 t1 0, T2 t2 1, T3 t3 2, T4 t4 3,
+    F0L f0l 4, F0M f0m 5, F0R f0r 6,
+    F1L f1l 7, F1M f1m 8, F1R f1r 9,
+    F2L f2l 10, F2M f2m 11, F2R f2r 12,
+    F3L f3l 13, F3M f3m 14, F3R f3r 15,
+    F4L f4l 16, F4M f4m 17, F4R f4r 18,
+}
+
+/// The thumbs Twiddler 2 firmware also holds as modifiers, Alt, Ctrl and
+/// Shift (the A, C and S of MCC notation), with their modifier bits. Held
+/// with a chord the config has no entry for, they type that chord without
+/// them with the modifiers held. Num (T1) is only ever a chord button, and
+/// the Twiddler 3 and 4 treat every thumb as one.
+pub const THUMB_MODIFIERS: [(ButtonState, u8); 3] = [
+    (ButtonState::T2, 0x04),
+    (ButtonState::T3, 0x01),
+    (ButtonState::T4, 0x02),
+];
+
+const LEFT: u32 = 0b001_001_001_001_001 << 4;
+const RIGHT: u32 = LEFT << 2;
+
+impl ButtonState {
+    /// No buttons pressed
+    pub const fn empty() -> ButtonState {
+        ButtonState(0)
+    }
+
+    /// Every button pressed
+    pub const fn all() -> ButtonState {
+        ButtonState(Self::ALL)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// None if a bit doesn't belong to a button
+    pub const fn from_bits(bits: u32) -> Option<ButtonState> {
+        if bits & !Self::ALL == 0 {
+            Some(ButtonState(bits))
+        } else {
+            None
+        }
+    }
+
+    /// Whether every button in `other` is pressed
+    pub const fn contains(self, other: ButtonState) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn union(self, other: ButtonState) -> ButtonState {
+        ButtonState(self.0 | other.0)
+    }
+
+    /// These buttons with `buttons` pressed or released, for building states
+    /// in const contexts
+    pub const fn with(self, buttons: ButtonState, pressed: bool) -> ButtonState {
+        if pressed {
+            ButtonState(self.0 | buttons.0)
+        } else {
+            ButtonState(self.0 & !buttons.0)
+        }
+    }
+
+    pub fn insert(&mut self, buttons: ButtonState) {
+        *self = self.with(buttons, true);
+    }
+
+    pub fn remove(&mut self, buttons: ButtonState) {
+        *self = self.with(buttons, false);
+    }
+
+    pub fn set(&mut self, buttons: ButtonState, pressed: bool) {
+        *self = self.with(buttons, pressed);
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The number of buttons pressed
+    pub const fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Each pressed button on its own, thumbs first then fingers row by row
+    pub fn iter(self) -> impl Iterator<Item = ButtonState> {
+        (0..u32::BITS)
+            .map(|bit| ButtonState(1 << bit))
+            .filter(move |button| self.contains(*button))
+    }
+
+    /// Thumb and finger strings in the T4 notation Twiddler Tuner exports,
+    /// e.g. ("1", "1R 2M")
+    pub fn to_notation(&self) -> (String, String) {
+        let mut thumb = String::new();
+        for (pressed, button) in self.thumbs().iter().zip(['1', '2', '3', '4']) {
+            if *pressed {
+                thumb.push(button);
+            }
+        }
+
+        let mut finger = vec![];
+        for (row, cols) in self.fingers().iter().enumerate() {
+            for (pressed, col) in cols.iter().zip(['L', 'M', 'R']) {
+                if *pressed {
+                    finger.push(format!("{}{}", row, col));
+                }
+            }
+        }
+
+        (thumb, finger.join(" "))
+    }
+
+    /// Thumb and finger strings in `notation`, buttons on row 0 can't be
+    /// written in [`Notation::Mcc`]
+    pub fn to_notation_as(&self, notation: Notation) -> Result<(String, String)> {
+        if notation == Notation::T4 {
+            return Ok(self.to_notation());
+        }
+
+        let fingers = self.fingers();
+        if fingers[0].contains(&true) {
+            return Err(Error::Unsupported(format!(
+                "{} presses row 0, which MCC notation can't write",
+                self
+            )));
+        }
+        let thumb = self
+            .thumbs()
+            .iter()
+            .zip(['N', 'A', 'C', 'S'])
+            .filter(|(pressed, _)| **pressed)
+            .map(|(_, button)| button)
+            .collect();
+
+        // The nth group has the nth button pressed on each row
+        let mut groups: Vec<String> = vec![];
+        for (row, cols) in fingers[1..].iter().enumerate() {
+            let pressed = cols.iter().zip(['L', 'M', 'R']).filter(|(p, _)| **p);
+            for (n, (_, col)) in pressed.enumerate() {
+                if n == groups.len() {
+                    groups.push("0000".to_string());
+                }
+                groups[n].replace_range(row..=row, &col.to_string());
+            }
+        }
+        if groups.is_empty() {
+            groups.push("0000".to_string());
+        }
+        Ok((thumb, groups.join(" ")))
+    }
+
+    /// Whether T1 to T4 are pressed
+    pub fn thumbs(&self) -> [bool; 4] {
+        [self.t1(), self.t2(), self.t3(), self.t4()]
+    }
+
+    /// Whether the L, M and R buttons of each row are pressed, row 0 first
+    pub fn fingers(&self) -> [[bool; 3]; 5] {
+        let mut fingers = [[false; 3]; 5];
+        for (row, cols) in fingers.iter_mut().enumerate() {
+            for (col, pressed) in cols.iter_mut().enumerate() {
+                *pressed = self.0 & finger_bit(row, col) != 0;
+            }
+        }
+        fingers
+    }
+
+    /// Whether one hand can press all the buttons at once. A finger covers
+    /// neighbouring buttons of its row, L and M or M and R, but can't reach
+    /// across M to press L and R together. Any thumbs can be pressed.
+    pub fn is_physically_possible(self) -> bool {
+        self.fingers().iter().all(|[l, _, r]| !(*l && *r))
+    }
+
+    /// The modifier bits of the [`THUMB_MODIFIERS`] pressed
+    pub fn thumb_modifiers(self) -> u8 {
+        THUMB_MODIFIERS
+            .iter()
+            .filter(|(thumb, _)| self.contains(*thumb))
+            .fold(0, |modifiers, (_, modifier)| modifiers | modifier)
+    }
+
+    /// The buttons pressed besides the [`THUMB_MODIFIERS`], the chord Twiddler
+    /// 2 firmware looks up when a modifier thumb has no entry of its own
+    pub fn without_thumb_modifiers(self) -> ButtonState {
+        THUMB_MODIFIERS
+            .iter()
+            .fold(self, |state, (thumb, _)| state.with(*thumb, false))
+    }
+
+    /// The same chord with the L and R columns swapped, for holding the
+    /// Twiddler in the other hand
+    pub fn mirrored(&self) -> ButtonState {
+        let bits = self.0;
+        ButtonState(bits & !(LEFT | RIGHT) | (bits & LEFT) << 2 | (bits & RIGHT) >> 2)
+    }
+
+    // Both return false for buttons that don't exist
+    fn press_thumb(&mut self, button: char) -> bool {
+        match button.to_digit(10) {
+            Some(n @ 1..=4) => {
+                self.0 |= 1 << (n - 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn press_finger(&mut self, row: char, col: char) -> bool {
+        let row = match row.to_digit(10) {
+            Some(row @ 0..=4) => row as usize,
+            _ => return false,
+        };
+        let col = match col {
+            'L' => 0,
+            'M' => 1,
+            'R' => 2,
+            _ => return false,
+        };
+        self.0 |= finger_bit(row, col);
+        true
+    }
+}
+
+const fn finger_bit(row: usize, col: usize) -> u32 {
+    1 << (4 + 3 * row + col)
+}
+
+impl BitOr for ButtonState {
+    type Output = ButtonState;
+
+    fn bitor(self, rhs: ButtonState) -> ButtonState {
+        self.union(rhs)
+    }
+}
+
+impl BitOrAssign for ButtonState {
+    fn bitor_assign(&mut self, rhs: ButtonState) {
+        self.insert(rhs);
+    }
+}
+
+/// The buttons pressed in both
+impl BitAnd for ButtonState {
+    type Output = ButtonState;
+
+    fn bitand(self, rhs: ButtonState) -> ButtonState {
+        ButtonState(self.0 & rhs.0)
+    }
+}
+
+/// Thumbs then fingers in T4 notation, e.g. "T1 T4 1R 2M"
+impl fmt::Display for ButtonState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (thumb, finger) = self.to_notation();
+        let buttons: Vec<String> = thumb
+            .chars()
+            .map(|button| format!("T{}", button))
+            .chain(finger.split_whitespace().map(str::to_string))
+            .collect();
+        write!(f, "{}", buttons.join(" "))
+    }
+}
+
+/// Accepts what Display writes, thumbs can also be grouped like "T14" or
+/// written the Tuner way as "<Thumb1>", and buttons can be joined with +
+impl FromStr for ButtonState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad_notation = |button: &str| Error::BadNotation {
+            notation: s.to_string(),
+            row: None,
+            reason: format!("unknown button {:?}", button),
+        };
+
+        let mut button_state = ButtonState::default();
+        for button in s.split(|c: char| c.is_whitespace() || c == '+') {
+            if button.is_empty() {
+                continue;
+            }
+
+            let thumbs = button.strip_prefix('T').or_else(|| {
+                button
+                    .strip_prefix("<Thumb")
+                    .and_then(|b| b.strip_suffix('>'))
+            });
+            let pressed = match thumbs {
+                Some(thumbs) => {
+                    !thumbs.is_empty() && thumbs.chars().all(|t| button_state.press_thumb(t))
+                }
+                None => {
+                    let mut chars = button.chars();
+                    match (chars.next(), chars.next(), chars.next()) {
+                        (Some(row), Some(col), None) => button_state.press_finger(row, col),
+                        _ => false,
+                    }
+                }
+            };
+            if !pressed {
+                return Err(bad_notation(button));
+            }
+        }
+
+        Ok(button_state)
+    }
+}
+
+/// Written in the same notation as Display, e.g. "T1 1R 2M"
+#[cfg(feature = "serde")]
+impl serde::Serialize for ButtonState {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ButtonState {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> {
+        let notation = String::deserialize(deserializer)?;
+        notation.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Any set of buttons, including none
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for ButtonState {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(ButtonState(u32::arbitrary(u)? & Self::ALL))
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        u32::size_hint(depth)
+    }
+}
+
+fn is_t4_notation(thumb: &str, finger: &str) -> bool {
+    // Legacy finger notation is always four of 0, L, M and R so row numbers
+    // or short buttons like 0M mean T4
+    thumb.contains(&['0', '1', '2', '3', '4'][..])
+        || finger.contains(&['1', '2', '3', '4'][..])
+        || finger.split_whitespace().any(|button| button.len() == 2)
+}
+
+/// How the thumbs and fingers of a chord are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Notation {
+    /// What Twiddler Tuner exports for the T4, e.g. "1" and "1R 2M"
+    #[default]
+    T4,
+    /// The older numeric notation, NACS for the thumbs and a letter or 0 for
+    /// each of finger rows 1 to 4, e.g. "N" and "0L00 R0M0". A second group
+    /// presses another button on the same rows. Row 0 can't be written.
+    Mcc,
+}
+
+/// Ignores anything it doesn't understand, see [`try_parse_notation`]
+pub fn parse_notation(thumb: String, finger: String) -> ButtonState {
+    if is_t4_notation(&thumb, &finger) {
         parse_t4_notation(thumb, finger)
     } else {
         parse_legacy_notation(thumb, finger)
     }
 }
 
-fn parse_t4_notation(thumb: String, finger: String) -> ButtonState {
-    let mut button_state = ButtonState {
-        t1: false,
-        t2: false,
-        t3: false,
-        t4: false,
-        f0l: false,
-        f0m: false,
-        f0r: false,
-        f1l: false,
-        f1m: false,
-        f1r: false,
-        f2l: false,
-        f2m: false,
-        f2r: false,
-        f3l: false,
-        f3m: false,
-        f3r: false,
-        f4l: false,
-        f4m: false,
-        f4r: false,
+/// Like [`try_parse_notation`] but only accepting `notation` instead of
+/// telling them apart
+pub fn parse_notation_as(notation: Notation, thumb: &str, finger: &str) -> Result<ButtonState> {
+    if is_t4_notation(thumb, finger) != (notation == Notation::T4) {
+        return Err(Error::BadNotation {
+            notation: format!("{} {}", thumb, finger).trim().to_string(),
+            row: None,
+            reason: format!("not in {:?} notation", notation),
+        });
+    }
+    try_parse_notation(thumb, finger)
+}
+
+/// Like [`parse_notation`] but unknown buttons and buttons given twice are
+/// errors
+pub fn try_parse_notation(thumb: &str, finger: &str) -> Result<ButtonState> {
+    let bad_notation = |reason: String| Error::BadNotation {
+        notation: format!("{} {}", thumb, finger).trim().to_string(),
+        row: None,
+        reason,
     };
 
+    if !is_t4_notation(thumb, finger) {
+        let mut seen = vec![];
+        for button in thumb.chars().filter(|c| !c.is_whitespace()) {
+            if !"NACS".contains(button) {
+                return Err(bad_notation(format!("unknown thumb button {:?}", button)));
+            }
+            if seen.contains(&button) {
+                return Err(bad_notation(format!("{:?} is pressed twice", button)));
+            }
+            seen.push(button);
+        }
+        let mut fingers = ButtonState::empty();
+        for group in finger.split_whitespace() {
+            if group.chars().count() != 4 || !group.chars().all(|c| "0LMR".contains(c)) {
+                return Err(bad_notation(format!(
+                    "legacy fingers should be four of 0, L, M and R, not {:?}",
+                    group
+                )));
+            }
+            let pressed = parse_legacy_notation(String::new(), group.to_string());
+            if !(fingers & pressed).is_empty() {
+                return Err(bad_notation(format!("{:?} presses a button again", group)));
+            }
+            fingers |= pressed;
+        }
+        return Ok(parse_legacy_notation(thumb.to_string(), finger.to_string()));
+    }
+
+    let mut button_state = ButtonState::default();
+    for button in thumb.chars().filter(|c| !c.is_whitespace()) {
+        let before = button_state;
+        if !button_state.press_thumb(button) {
+            return Err(bad_notation(format!("unknown thumb button {:?}", button)));
+        }
+        if button_state == before {
+            return Err(bad_notation(format!("{:?} is pressed twice", button)));
+        }
+    }
+    for button in finger.split_whitespace() {
+        let before = button_state;
+        let mut chars = button.chars();
+        let pressed = match (chars.next(), chars.next(), chars.next()) {
+            (Some(row), Some(col), None) => button_state.press_finger(row, col),
+            _ => false,
+        };
+        if !pressed {
+            return Err(bad_notation(format!("unknown finger button {:?}", button)));
+        }
+        if button_state == before {
+            return Err(bad_notation(format!("{:?} is pressed twice", button)));
+        }
+    }
+
+    Ok(button_state)
+}
+
+/// Buttons written any way the CLI prints them or the Tuner and older
+/// configs write them, as one string: "T1 1L 2M", "<Thumb1>+1L",
+/// "N RM00" or "<Num> RM00", with `<Num>`, `<Alt>`, `<Ctrl>` and `<Shift>`
+/// for the NACS thumbs. The notations can't be mixed.
+pub fn parse_any(text: &str) -> Result<ButtonState> {
+    let mut thumb = String::new();
+    let mut finger = vec![];
+    let mut rest = vec![];
+    for button in text.split(|c: char| c.is_whitespace() || c == '+') {
+        let legacy_thumb = match button {
+            "<Num>" => Some("N"),
+            "<Alt>" => Some("A"),
+            "<Ctrl>" => Some("C"),
+            "<Shift>" => Some("S"),
+            _ if !button.is_empty() && button.chars().all(|c| "NACS".contains(c)) => Some(button),
+            _ => None,
+        };
+        if let Some(letters) = legacy_thumb {
+            thumb.push_str(letters);
+        } else if button.len() == 4 && button.chars().all(|c| "0LMR".contains(c)) {
+            finger.push(button);
+        } else if !button.is_empty() {
+            rest.push(button);
+        }
+    }
+
+    if thumb.is_empty() && finger.is_empty() {
+        return rest.join(" ").parse();
+    }
+    if !rest.is_empty() {
+        return Err(Error::BadNotation {
+            notation: text.to_string(),
+            row: None,
+            reason: format!("{:?} isn't in the MCC notation of the rest", rest[0]),
+        });
+    }
+    parse_notation_as(Notation::Mcc, &thumb, &finger.join(" "))
+}
+
+/// Thumb and finger strings with their buttons in one order, so the same
+/// chord is always written the same way, e.g. ("SN", "2M 1R") is
+/// ("NS", "1R 2M"). They're read with [`try_parse_notation`], so what it
+/// rejects, like lower case buttons, is an error here too. The notation
+/// stays legacy or T4 as it was.
+pub fn canonicalize(thumb: &str, finger: &str) -> Result<(String, String)> {
+    let state = try_parse_notation(thumb, finger)?;
+    match is_t4_notation(thumb, finger) {
+        true => Ok(state.to_notation()),
+        // Chords of only thumbs are left without fingers if they were
+        false if finger.is_empty() => {
+            Ok((state.to_notation_as(Notation::Mcc)?.0, finger.to_string()))
+        }
+        false => state.to_notation_as(Notation::Mcc),
+    }
+}
+
+fn parse_t4_notation(thumb: String, finger: String) -> ButtonState {
+    let mut button_state = ButtonState::default();
+
     // Parse thumb notation
     for button in thumb.chars() {
-        match button {
-            '1' => button_state.t1 = true,
-            '2' => button_state.t2 = true,
-            '3' => button_state.t3 = true,
-            '4' => button_state.t4 = true,
-            _ => (),
-        }
+        button_state.press_thumb(button);
     }
 
     // Parse finger notation
     for finger_button in finger.split_whitespace() {
         let mut chars = finger_button.chars();
-        let finger_row = chars.next().unwrap();
-        let finger_col = chars.next().unwrap();
-        match (finger_row, finger_col) {
-            ('0', 'M') => button_state.f0m = true,
-            ('1', 'L') => button_state.f1l = true,
-            ('1', 'M') => button_state.f1m = true,
-            ('1', 'R') => button_state.f1r = true,
-            ('2', 'L') => button_state.f2l = true,
-            ('2', 'M') => button_state.f2m = true,
-            ('2', 'R') => button_state.f2r = true,
-            ('3', 'L') => button_state.f3l = true,
-            ('3', 'M') => button_state.f3m = true,
-            ('3', 'R') => button_state.f3r = true,
-            ('4', 'L') => button_state.f4l = true,
-            ('4', 'M') => button_state.f4m = true,
-            ('4', 'R') => button_state.f4r = true,
-            _ => (),
+        if let (Some(finger_row), Some(finger_col)) = (chars.next(), chars.next()) {
+            button_state.press_finger(finger_row, finger_col);
         }
     }
 
@@ -100,66 +584,187 @@ fn parse_t4_notation(thumb: String, finger: String) -> ButtonState {
 }
 
 fn parse_legacy_notation(thumb: String, finger: String) -> ButtonState {
-    let mut button_state = ButtonState {
-        t1: false,
-        t2: false,
-        t3: false,
-        t4: false,
-        f0l: false,
-        f0m: false,
-        f0r: false,
-        f1l: false,
-        f1m: false,
-        f1r: false,
-        f2l: false,
-        f2m: false,
-        f2r: false,
-        f3l: false,
-        f3m: false,
-        f3r: false,
-        f4l: false,
-        f4m: false,
-        f4r: false,
-    };
+    let mut button_state = ButtonState::default();
 
     // Parse thumb notation
     for button in thumb.chars() {
         match button {
-            'N' => button_state.t1 = true,
-            'A' => button_state.t2 = true,
-            'C' => button_state.t3 = true,
-            'S' => button_state.t4 = true,
+            'N' => button_state.insert(ButtonState::T1),
+            'A' => button_state.insert(ButtonState::T2),
+            'C' => button_state.insert(ButtonState::T3),
+            'S' => button_state.insert(ButtonState::T4),
             _ => (),
         }
     }
 
-    // Parse finger notation
-    for (i, finger_button) in finger.chars().enumerate() {
-        match finger_button {
-            'L' => match i {
-                0 => button_state.f1l = true,
-                1 => button_state.f2l = true,
-                2 => button_state.f3l = true,
-                3 => button_state.f4l = true,
-                _ => (),
-            },
-            'M' => match i {
-                0 => button_state.f1m = true,
-                1 => button_state.f2m = true,
-                2 => button_state.f3m = true,
-                3 => button_state.f4m = true,
-                _ => (),
-            },
-            'R' => match i {
-                0 => button_state.f1r = true,
-                1 => button_state.f2r = true,
-                2 => button_state.f3r = true,
-                3 => button_state.f4r = true,
-                _ => (),
-            },
-            _ => (),
+    // Parse finger notation, each group has a letter for rows 1 to 4 and
+    // more groups press more buttons on the same rows
+    for group in finger.split_whitespace() {
+        for (row, col) in ['1', '2', '3', '4'].into_iter().zip(group.chars()) {
+            button_state.press_finger(row, col);
         }
     }
 
     button_state
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let state = parse_notation("14".to_string(), "1R 2M".to_string());
+        assert!(state.to_string() == "T1 T4 1R 2M");
+        assert!(ButtonState::default().to_string().is_empty());
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let canonical = |thumb, finger| canonicalize(thumb, finger).unwrap();
+        assert!(canonical("SN", "0L0R") == ("NS".to_string(), "0L0R".to_string()));
+        assert!(canonical("4 1", "2M 1R") == ("14".to_string(), "1R 2M".to_string()));
+        assert!(canonical("", "") == (String::new(), String::new()));
+        assert!(canonicalize("1", "1R 1R").is_err());
+        assert!(canonical("N", "00L0 0R00 L000") == ("N".to_string(), "LRL0".to_string()));
+        // Lower case buttons are rejected as they are when converting
+        for (thumb, finger) in [("1", "1r"), ("sn", "0L0R"), ("", "0l00")] {
+            assert!(canonicalize(thumb, finger).is_err());
+            assert!(try_parse_notation(thumb, finger).is_err());
+        }
+    }
+
+    #[test]
+    fn test_mcc_notation() {
+        let state = parse_notation_as(Notation::Mcc, "N", "0L00 R0M0").unwrap();
+        assert!(state.to_string() == "T1 1R 2L 3M");
+        assert!(
+            state.to_notation_as(Notation::Mcc).unwrap() == ("N".to_string(), "RLM0".to_string())
+        );
+        assert!(parse_notation("N".to_string(), "0L00 R0M0".to_string()) == state);
+
+        let state: ButtonState = "T4 1L 1R".parse().unwrap();
+        let (thumb, finger) = state.to_notation_as(Notation::Mcc).unwrap();
+        assert!(thumb == "S" && finger == "L000 R000");
+        assert!(try_parse_notation(&thumb, &finger).unwrap() == state);
+
+        assert!(parse_notation_as(Notation::Mcc, "1", "1R").is_err());
+        assert!(parse_notation_as(Notation::T4, "N", "L000").is_err());
+        assert!(try_parse_notation("", "L000 L000").is_err());
+        assert!(ButtonState::F0L.to_notation_as(Notation::Mcc).is_err());
+    }
+
+    #[test]
+    fn test_mirrored() {
+        let state: ButtonState = "T1 0L 1M 2R".parse().unwrap();
+        assert!(state.mirrored().to_string() == "T1 0R 1M 2L");
+        assert!(state.mirrored().mirrored() == state);
+    }
+
+    #[test]
+    fn test_flags() {
+        const CHORD: ButtonState = ButtonState::T1.union(ButtonState::F1R);
+        let state = CHORD | ButtonState::F2M;
+        assert!(state == "T1 1R 2M".parse().unwrap());
+        assert!(state.contains(CHORD) && !CHORD.contains(state));
+        assert!(state.len() == 3 && !state.is_empty());
+        assert!(
+            state.iter().collect::<Vec<_>>()
+                == [ButtonState::T1, ButtonState::F1R, ButtonState::F2M]
+        );
+        assert!(state & ButtonState::F2M == ButtonState::F2M);
+        assert!(state.with(ButtonState::F2M, false) == CHORD);
+        assert!(ButtonState::from_bits(state.bits()) == Some(state));
+        assert!(ButtonState::from_bits(1 << 19).is_none());
+        assert!(ButtonState::T4 < ButtonState::F0L);
+        assert!(format!("{:?}", CHORD) == "ButtonState(T1 | F1R)");
+    }
+
+    #[test]
+    fn test_is_physically_possible() {
+        for possible in ["1L", "T1 T2 1L 1M", "1M 1R 2L 3R", "T1 T2 T3 T4"] {
+            assert!(possible
+                .parse::<ButtonState>()
+                .unwrap()
+                .is_physically_possible());
+        }
+        for impossible in ["1L 1R", "T1 0L 0M 0R", "2M 4L 4R"] {
+            assert!(!impossible
+                .parse::<ButtonState>()
+                .unwrap()
+                .is_physically_possible());
+        }
+        assert!(ButtonState::empty().is_physically_possible());
+        assert!(!ButtonState::all().is_physically_possible());
+    }
+
+    #[test]
+    fn test_thumb_modifiers() {
+        // Ctrl and Shift in MCC notation
+        let state = try_parse_notation("NCS", "L000").unwrap();
+        assert!(state.thumb_modifiers() == 0x03);
+        assert!(state.without_thumb_modifiers() == ButtonState::T1 | ButtonState::F1L);
+        assert!(ButtonState::T2.thumb_modifiers() == 0x04);
+        assert!(ButtonState::T1.thumb_modifiers() == 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let state: ButtonState = "T1 1R 2M".parse().unwrap();
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(json == "\"T1 1R 2M\"");
+        assert!(serde_json::from_str::<ButtonState>(&json).unwrap() == state);
+        assert!(serde_json::from_str::<ButtonState>("\"T9\"").is_err());
+    }
+
+    #[test]
+    fn test_try_parse_notation() {
+        let state = try_parse_notation("14", "0L 1R 2M").unwrap();
+        assert!(state == parse_notation("14".to_string(), "0L 1R 2M".to_string()));
+        let state = try_parse_notation("NS", "L0M0").unwrap();
+        assert!(state == parse_notation("NS".to_string(), "L0M0".to_string()));
+        assert!(try_parse_notation("", "").unwrap() == ButtonState::default());
+
+        for (thumb, finger) in [
+            ("1", "1X"),
+            ("5", "1L"),
+            ("", "1L 1L"),
+            ("11", "1L"),
+            ("", "1L2M"),
+            ("NN", "L000"),
+            ("N", "L00"),
+        ] {
+            assert!(matches!(
+                try_parse_notation(thumb, finger),
+                Err(Error::BadNotation { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_parse_any() {
+        let num_1r_2m = ButtonState::T1 | ButtonState::F1R | ButtonState::F2M;
+        assert!(parse_any("<Num> RM00").unwrap() == num_1r_2m);
+        assert!(parse_any("N RM00").unwrap() == num_1r_2m);
+        assert!(parse_any("T1 1R 2M").unwrap() == num_1r_2m);
+        assert!(parse_any("<Thumb1>+1R+2M").unwrap() == num_1r_2m);
+        assert!(parse_any("<Ctrl> <Shift> L000").unwrap() == "T3 T4 1L".parse().unwrap());
+        assert!(parse_any("<Num> 1R").is_err());
+        assert!(parse_any("RM00 X").is_err());
+        assert!(parse_any("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_from_str() {
+        let state = parse_notation("14".to_string(), "0L 1R 2M".to_string());
+        assert!("T1 T4 0L 1R 2M".parse::<ButtonState>().unwrap() == state);
+        assert!("T14+0L+1R+2M".parse::<ButtonState>().unwrap() == state);
+        assert!("<Thumb1>+<Thumb4>+0L 1R 2M".parse::<ButtonState>().unwrap() == state);
+        assert!(state.to_string().parse::<ButtonState>().unwrap() == state);
+
+        assert!("T5".parse::<ButtonState>().is_err());
+        assert!("1X".parse::<ButtonState>().is_err());
+        assert!("1".parse::<ButtonState>().is_err());
+    }
+}
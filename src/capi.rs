@@ -0,0 +1,296 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::{
+    config,
+    convert::{self, Format},
+    json,
+    layout::Layout,
+    validate, Error, Result,
+};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Bytes allocated by this library, free them with [`twiddler_buffer_free`]
+#[repr(C)]
+pub struct TwiddlerBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+impl TwiddlerBuffer {
+    fn new(bytes: Vec<u8>) -> Self {
+        let bytes = Box::into_raw(bytes.into_boxed_slice());
+        Self {
+            data: bytes.cast(),
+            len: bytes.len(),
+        }
+    }
+}
+
+// Keeps the error for twiddler_last_error and gives the failure value
+fn fail<T>(error: Error, failure: T) -> T {
+    let message = CString::new(error.to_string().replace('\0', " "));
+    LAST_ERROR.with(|last| *last.borrow_mut() = message.ok());
+    failure
+}
+
+// Runs `f`, a panic fails the call like an error would instead of
+// unwinding into C, which is undefined behaviour
+fn guard<T>(failure: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown");
+        let error = Error::Invalid(format!("Panicked: {}", message));
+        fail(error, failure)
+    })
+}
+
+unsafe fn input<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, len)
+    }
+}
+
+// NULL is left for the caller to detect
+unsafe fn format(name: *const c_char) -> Result<Option<Format>> {
+    if name.is_null() {
+        return Ok(None);
+    }
+    let name = CStr::from_ptr(name)
+        .to_str()
+        .map_err(|_| Error::Invalid("The format name isn't UTF-8".to_string()))?;
+    name.parse().map(Some)
+}
+
+fn read_json(data: &[u8], format: Option<Format>) -> Result<json::Config> {
+    let mut reader = Cursor::new(data);
+    let format = match format {
+        Some(format) => format,
+        None => config::detect_version(&mut reader)?.format()?,
+    };
+    convert::to_json(convert::read(format, &mut reader)?, Layout::Us)
+}
+
+fn convert_bytes(data: &[u8], from: Option<Format>, to: Format) -> Result<Vec<u8>> {
+    let mut reader = Cursor::new(data);
+    let from = match from {
+        Some(format) => format,
+        None => config::detect_version(&mut reader)?.format()?,
+    };
+    let mut writer = Cursor::new(vec![]);
+    convert::convert(from, to, &mut reader, &mut writer)?;
+    Ok(writer.into_inner())
+}
+
+fn to_c_string(string: String) -> Result<*mut c_char> {
+    let string = CString::new(string).map_err(|e| Error::Invalid(e.to_string()))?;
+    Ok(string.into_raw())
+}
+
+/// The message of the last error on this thread, or NULL. It stays valid
+/// until the next call that fails.
+#[no_mangle]
+pub extern "C" fn twiddler_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Converts `len` bytes at `data` from the format named `from`, detected
+/// when NULL, to the one named `to`. Names are the ones the CLI takes, like
+/// "csv" or "v7". Returns 0 and fills `output` on success, -1 otherwise.
+///
+/// # Safety
+///
+/// `data` has to point to `len` readable bytes, `from` and `to` to NUL
+/// terminated strings and `output` to a writable [`TwiddlerBuffer`].
+#[no_mangle]
+pub unsafe extern "C" fn twiddler_convert(
+    data: *const u8,
+    len: usize,
+    from: *const c_char,
+    to: *const c_char,
+    output: *mut TwiddlerBuffer,
+) -> i32 {
+    guard(-1, || {
+        let to = match format(to) {
+            Ok(Some(to)) => to,
+            Ok(None) => return fail(Error::Invalid("No output format".to_string()), -1),
+            Err(error) => return fail(error, -1),
+        };
+        let result = format(from).and_then(|from| convert_bytes(input(data, len), from, to));
+
+        match (result, output.is_null()) {
+            (Ok(bytes), false) => {
+                *output = TwiddlerBuffer::new(bytes);
+                0
+            }
+            (Ok(_), true) => fail(Error::Invalid("No output buffer".to_string()), -1),
+            (Err(error), _) => fail(error, -1),
+        }
+    })
+}
+
+/// Parses a config like [`twiddler_convert`] and gives it as a JSON config
+/// string, or NULL on errors. Free it with [`twiddler_string_free`].
+///
+/// # Safety
+///
+/// `data` has to point to `len` readable bytes and `from` to a NUL
+/// terminated string or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn twiddler_parse(
+    data: *const u8,
+    len: usize,
+    from: *const c_char,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        format(from)
+            .and_then(|from| read_json(input(data, len), from))
+            .and_then(|config| to_c_string(serde_json::to_string(&config)?))
+            .unwrap_or_else(|error| fail(error, ptr::null_mut()))
+    })
+}
+
+/// The `validate` command's checks as a JSON string,
+/// `{"conflicts": [...], "duplicate_outputs": [...]}` with chords referred
+/// to by their index, or NULL on errors. Free it with
+/// [`twiddler_string_free`].
+///
+/// # Safety
+///
+/// Same as [`twiddler_parse`].
+#[no_mangle]
+pub unsafe extern "C" fn twiddler_validate(
+    data: *const u8,
+    len: usize,
+    from: *const c_char,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        format(from)
+            .and_then(|from| read_json(input(data, len), from))
+            .and_then(|config| {
+                let report = serde_json::json!({
+                    "conflicts": validate::find_conflicts(&config.chords),
+                    "duplicate_outputs": validate::find_duplicate_outputs(&config.chords),
+                });
+                to_c_string(report.to_string())
+            })
+            .unwrap_or_else(|error| fail(error, ptr::null_mut()))
+    })
+}
+
+/// # Safety
+///
+/// `string` has to come from this library and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn twiddler_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        guard((), || drop(CString::from_raw(string)));
+    }
+}
+
+/// # Safety
+///
+/// `buffer` has to come from [`twiddler_convert`] and not be freed already.
+#[no_mangle]
+pub unsafe extern "C" fn twiddler_buffer_free(buffer: TwiddlerBuffer) {
+    if !buffer.data.is_null() {
+        let bytes = ptr::slice_from_raw_parts_mut(buffer.data, buffer.len);
+        guard((), || drop(Box::from_raw(bytes)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert() {
+        let data = b"Thumbs,Fingers,Keyboard Output\n,1L,a\n,1L,b\n";
+        let mut output = TwiddlerBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        unsafe {
+            let to = c"json".as_ptr();
+            assert!(twiddler_convert(data.as_ptr(), data.len(), ptr::null(), to, &mut output) == 0);
+            let json = std::slice::from_raw_parts(output.data, output.len);
+            assert!(std::str::from_utf8(json)
+                .unwrap()
+                .contains("\"output\": \"b\""));
+            twiddler_buffer_free(output);
+
+            let report = twiddler_validate(data.as_ptr(), data.len(), c"csv".as_ptr());
+            let json = CStr::from_ptr(report).to_str().unwrap();
+            assert!(json.starts_with("{\"conflicts\":[{\"SameButtons\""));
+            twiddler_string_free(report);
+
+            let config = twiddler_parse(data.as_ptr(), data.len(), c"v8".as_ptr());
+            assert!(config.is_null());
+            let error = CStr::from_ptr(twiddler_last_error()).to_str().unwrap();
+            assert!(error.contains("Unknown format v8"));
+        }
+    }
+
+    #[test]
+    fn test_convert_errors() {
+        let data = b"Thumbs,Fingers,Keyboard Output\n,1L,a\n";
+        let mut output = TwiddlerBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        let last_error = || unsafe { CStr::from_ptr(twiddler_last_error()).to_str().unwrap() };
+        unsafe {
+            let (csv, v7) = (c"csv".as_ptr(), c"v7".as_ptr());
+            assert!(
+                twiddler_convert(data.as_ptr(), data.len(), csv, ptr::null(), &mut output) == -1
+            );
+            assert!(last_error().ends_with("No output format"));
+            assert!(twiddler_convert(data.as_ptr(), data.len(), csv, v7, ptr::null_mut()) == -1);
+            assert!(last_error().ends_with("No output buffer"));
+            let not_utf8 = c"\xFF".as_ptr();
+            assert!(twiddler_convert(data.as_ptr(), data.len(), csv, not_utf8, &mut output) == -1);
+            assert!(last_error().ends_with("The format name isn't UTF-8"));
+            assert!(output.data.is_null());
+
+            // A NULL `from` guesses the format
+            let config = twiddler_parse(data.as_ptr(), data.len(), ptr::null());
+            assert!(!config.is_null());
+            assert!(CStr::from_ptr(config)
+                .to_str()
+                .unwrap()
+                .contains("\"output\":\"a\""));
+            twiddler_string_free(config);
+
+            twiddler_string_free(ptr::null_mut());
+            twiddler_buffer_free(output);
+        }
+    }
+
+    #[test]
+    fn test_guard() {
+        let failed = guard(-1, || panic!("bad chord"));
+        assert!(failed == -1);
+        let error = unsafe { CStr::from_ptr(twiddler_last_error()) };
+        assert!(error.to_str().unwrap() == "Invalid config: Panicked: bad chord");
+        assert!(guard(-1, || 0) == 0);
+    }
+
+    #[test]
+    fn test_header() {
+        // Copy the generated one over it after changing the API
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/twiddler_cfg.h"));
+        let header = std::fs::read_to_string("include/twiddler_cfg.h").unwrap();
+        assert!(header == generated, "include/twiddler_cfg.h is out of date");
+    }
+}
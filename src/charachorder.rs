@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use serde::Deserialize;
+
+use crate::{
+    analysis::effort,
+    buttons::ButtonState,
+    csv::{Chord, Diagnostic, Problem},
+    hid, Error, Result,
+};
+
+/// A chord backup from the CharaChorder Device Manager, every chord is the
+/// action codes of the keys pressed and the action codes it types
+#[derive(Deserialize)]
+struct Backup {
+    #[serde(rename = "type")]
+    kind: String,
+    chords: Vec<(Vec<u16>, Vec<u16>)>,
+}
+
+/// How the keys of CharaChorder chords are put on Twiddler buttons
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Assignment {
+    /// Keys in the most chords get the easiest buttons, one button each
+    #[default]
+    ByFrequency,
+    /// Each key on the buttons given, chords with other keys are left out
+    Fixed(HashMap<char, ButtonState>),
+}
+
+/// Read a CCOS chord backup. Chords whose keys can't be put on buttons, that
+/// type something without a key code, or that end up on the same buttons as
+/// an earlier chord are reported with their 1-based position in the backup
+/// instead of a line.
+pub fn parse<R: Read>(reader: R, assignment: &Assignment) -> Result<(Vec<Chord>, Vec<Diagnostic>)> {
+    let backup: Backup = serde_json::from_reader(reader)?;
+    if backup.kind != "chords" {
+        return Err(Error::Invalid(format!(
+            "Expected a CharaChorder chord backup, not {:?}",
+            backup.kind
+        )));
+    }
+
+    let keys = match assignment {
+        Assignment::ByFrequency => by_frequency(&backup.chords),
+        Assignment::Fixed(keys) => keys.clone(),
+    };
+
+    let mut chords = vec![];
+    let mut diagnostics = vec![];
+    let mut taken: HashMap<ButtonState, usize> = HashMap::new();
+    for (i, (input, output)) in backup.chords.iter().enumerate() {
+        let line = i + 1;
+        let chord = to_buttons(input, &keys).and_then(|buttons| {
+            if let Some(first) = taken.get(&buttons) {
+                return Err(format!("on the same buttons as chord {}", first));
+            }
+            Ok(Chord::from_buttons(&buttons, to_output(output)?))
+        });
+        match chord {
+            Ok(chord) => {
+                taken.insert(chord.buttons(), line);
+                chords.push(chord);
+            }
+            Err(reason) => diagnostics.push(Diagnostic {
+                line,
+                column: None,
+                problem: Problem::Unreadable,
+                reason,
+            }),
+        }
+    }
+
+    Ok((chords, diagnostics))
+}
+
+fn by_frequency(chords: &[(Vec<u16>, Vec<u16>)]) -> HashMap<char, ButtonState> {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in chords
+        .iter()
+        .flat_map(|(input, _)| input)
+        .filter_map(|a| action_char(*a))
+    {
+        *counts.entry(c).or_default() += 1;
+    }
+    let mut keys: Vec<(char, usize)> = counts.into_iter().collect();
+    keys.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+
+    let mut buttons: Vec<ButtonState> = ButtonState::all().iter().collect();
+    // Fingers before thumbs, a chord of only thumbs is awkward to press
+    let thumb = |buttons: &ButtonState| buttons.thumbs().contains(&true);
+    buttons.sort_by(|a, b| {
+        effort(a)
+            .total_cmp(&effort(b))
+            .then(thumb(a).cmp(&thumb(b)))
+    });
+
+    keys.into_iter().map(|(c, _)| c).zip(buttons).collect()
+}
+
+fn to_buttons(
+    input: &[u16],
+    keys: &HashMap<char, ButtonState>,
+) -> std::result::Result<ButtonState, String> {
+    let mut buttons = ButtonState::empty();
+    for action in input {
+        let c = action_char(*action).ok_or_else(|| format!("key {} isn't a character", action))?;
+        buttons |= *keys
+            .get(&c)
+            .ok_or_else(|| format!("no button for {:?}", c))?;
+    }
+    Ok(buttons)
+}
+
+// Actions below 256 are ASCII, the next 256 are keyboard key codes
+fn to_output(output: &[u16]) -> std::result::Result<String, String> {
+    let mut pairs = vec![];
+    for action in output {
+        let pair = match action_char(*action) {
+            Some(c) => hid::char_to_hid(c),
+            None => (0x100..0x200)
+                .contains(action)
+                .then(|| (0, (action - 0x100) as u8)),
+        };
+        pairs.push(pair.ok_or_else(|| format!("can't type action {}", action))?);
+    }
+    if pairs.is_empty() {
+        return Err("types nothing".to_string());
+    }
+    Ok(hid::pairs_to_output(&pairs))
+}
+
+fn action_char(action: u16) -> Option<char> {
+    (32..127).contains(&action).then_some(action as u8 as char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        // the, and, a then one on the same buttons as "the" and one typing F24
+        let backup = r#"{"charaVersion": 1, "type": "chords", "chords": [
+            [[116, 104, 101], [116, 104, 101]],
+            [[97, 110, 100], [97, 110, 100]],
+            [[97, 101], [60, 300]],
+            [[101, 104, 116], [84, 104, 101]],
+            [[118], [371]]
+        ]}"#;
+        let (chords, diagnostics) = parse(backup.as_bytes(), &Assignment::ByFrequency).unwrap();
+        assert!(chords.len() == 4);
+        assert!(chords[0].output() == "the");
+        assert!(chords[2].output() == "\\< ");
+        assert!(chords[3].output() == "<F24>");
+        assert!(diagnostics.len() == 1 && diagnostics[0].line == 4);
+
+        // a and e are in two chords so they get the easiest buttons
+        assert!(chords[2].thumbs() == "" && chords[2].fingers() == "1L 1M");
+    }
+
+    #[test]
+    fn test_fixed() {
+        let backup = r#"{"type": "chords", "chords": [[[97], [97]], [[98], [98]]]}"#;
+        let keys = HashMap::from([('a', ButtonState::F1L)]);
+        let (chords, diagnostics) = parse(backup.as_bytes(), &Assignment::Fixed(keys)).unwrap();
+        assert!(chords == [Chord::new("", "1L", "a")]);
+        assert!(
+            diagnostics
+                == [Diagnostic {
+                    line: 2,
+                    column: None,
+                    problem: Problem::Unreadable,
+                    reason: "no button for 'b'".to_string()
+                }]
+        );
+
+        let layout = r#"{"type": "layout", "chords": []}"#;
+        assert!(parse(layout.as_bytes(), &Assignment::ByFrequency).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use crate::{buttons::ButtonState, csv::Chord, Error, Result};
+
+/// What [`ChordMap::insert`] does when the buttons are already mapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OnConflict {
+    /// Ignore the new chord, like the device does with later duplicates
+    #[default]
+    Keep,
+    /// Put the new chord in place of the old one
+    Replace,
+    /// Return an error
+    Fail,
+}
+
+/// Chords keyed by their buttons, in the order they were first inserted
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChordMap {
+    chords: Vec<Chord>,
+    by_buttons: HashMap<ButtonState, usize>,
+    by_output: HashMap<String, Vec<usize>>,
+}
+
+impl ChordMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a map from chords in config order, see [`ChordMap::insert`]
+    pub fn from_chords(
+        chords: impl IntoIterator<Item = Chord>,
+        on_conflict: OnConflict,
+    ) -> Result<Self> {
+        let mut map = Self::new();
+        for chord in chords {
+            map.insert(chord, on_conflict)?;
+        }
+        Ok(map)
+    }
+
+    /// Adds a chord, returning the chord it replaced or, with
+    /// [`OnConflict::Keep`], the rejected new one
+    pub fn insert(&mut self, chord: Chord, on_conflict: OnConflict) -> Result<Option<Chord>> {
+        let buttons = chord.buttons();
+        let Some(&i) = self.by_buttons.get(&buttons) else {
+            self.index(self.chords.len(), &chord);
+            self.chords.push(chord);
+            return Ok(None);
+        };
+
+        match on_conflict {
+            OnConflict::Keep => Ok(Some(chord)),
+            OnConflict::Replace => {
+                self.unindex_output(i);
+                self.by_output
+                    .entry(chord.output().to_string())
+                    .or_default()
+                    .push(i);
+                Ok(Some(std::mem::replace(&mut self.chords[i], chord)))
+            }
+            OnConflict::Fail => Err(Error::Invalid(format!(
+                "{} is mapped to both {:?} and {:?}",
+                buttons,
+                self.chords[i].output(),
+                chord.output()
+            ))),
+        }
+    }
+
+    pub fn get(&self, buttons: &ButtonState) -> Option<&Chord> {
+        self.by_buttons.get(buttons).map(|&i| &self.chords[i])
+    }
+
+    pub fn contains(&self, buttons: &ButtonState) -> bool {
+        self.by_buttons.contains_key(buttons)
+    }
+
+    /// Every chord typing exactly `output`, in map order
+    pub fn by_output<'a>(&'a self, output: &str) -> impl Iterator<Item = &'a Chord> + 'a {
+        let mut indices = self.by_output.get(output).cloned().unwrap_or_default();
+        indices.sort_unstable();
+        indices.into_iter().map(|i| &self.chords[i])
+    }
+
+    /// Removes the chord on `buttons`, keeping the order of the rest
+    pub fn remove(&mut self, buttons: &ButtonState) -> Option<Chord> {
+        let i = self.by_buttons.remove(buttons)?;
+        let chord = self.chords.remove(i);
+        self.reindex();
+        Some(chord)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chords.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chords.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Chord> {
+        self.chords.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<Chord> {
+        self.chords
+    }
+
+    fn index(&mut self, i: usize, chord: &Chord) {
+        self.by_buttons.insert(chord.buttons(), i);
+        self.by_output
+            .entry(chord.output().to_string())
+            .or_default()
+            .push(i);
+    }
+
+    fn unindex_output(&mut self, i: usize) {
+        let output = self.chords[i].output();
+        if let Some(indices) = self.by_output.get_mut(output) {
+            indices.retain(|&j| j != i);
+            if indices.is_empty() {
+                self.by_output.remove(output);
+            }
+        }
+    }
+
+    fn reindex(&mut self) {
+        self.by_buttons.clear();
+        self.by_output.clear();
+        for i in 0..self.chords.len() {
+            let chord = self.chords[i].clone();
+            self.index(i, &chord);
+        }
+    }
+}
+
+/// Later chords on buttons already mapped are dropped, like on the device
+impl FromIterator<Chord> for ChordMap {
+    fn from_iter<I: IntoIterator<Item = Chord>>(chords: I) -> Self {
+        let mut map = Self::new();
+        for chord in chords {
+            let _ = map.insert(chord, OnConflict::Keep);
+        }
+        map
+    }
+}
+
+impl From<Vec<Chord>> for ChordMap {
+    fn from(chords: Vec<Chord>) -> Self {
+        chords.into_iter().collect()
+    }
+}
+
+impl From<ChordMap> for Vec<Chord> {
+    fn from(map: ChordMap) -> Self {
+        map.into_vec()
+    }
+}
+
+impl IntoIterator for ChordMap {
+    type Item = Chord;
+    type IntoIter = std::vec::IntoIter<Chord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chords.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ChordMap {
+    type Item = &'a Chord;
+    type IntoIter = std::slice::Iter<'a, Chord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chords.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut map = ChordMap::from(vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("", "1M", "b"),
+            Chord::new("", "1L", "c"),
+        ]);
+        assert!(map.len() == 2);
+        assert!(map.get(&ButtonState::F1L).unwrap().output() == "a");
+
+        let replaced = map.insert(Chord::new("", "1L", "b"), OnConflict::Replace);
+        assert!(replaced.unwrap().unwrap().output() == "a");
+        assert!(map.by_output("a").next().is_none());
+        let fingers: Vec<&str> = map.by_output("b").map(Chord::fingers).collect();
+        assert!(fingers == ["1L", "1M"]);
+
+        let err = map.insert(Chord::new("", "1M", "x"), OnConflict::Fail);
+        assert!(matches!(err, Err(Error::Invalid(_))));
+        assert!(map.get(&ButtonState::F1M).unwrap().output() == "b");
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut map: ChordMap = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("", "1M", "b"),
+            Chord::new("", "1R", "c"),
+        ]
+        .into();
+        assert!(map.remove(&ButtonState::F1M).unwrap().output() == "b");
+        assert!(map.remove(&ButtonState::F1M).is_none());
+        assert!(map.get(&ButtonState::F1R).unwrap().output() == "c");
+        assert!(map.by_output("c").count() == 1);
+        let outputs: Vec<String> = Vec::from(map)
+            .iter()
+            .map(|c| c.output().to_string())
+            .collect();
+        assert!(outputs == ["a", "c"]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Telling what a config file is from its first bytes, so it can be read
+//! without being told its format.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{Error, Format, Result};
+
+/// What [`detect_version`] finds a config to be. Unlike [`Format`] it has
+/// versions that are recognized but can't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FormatVersion {
+    /// A Twiddler 2 config from before v5
+    Twiddler4,
+    Twiddler5,
+    Twiddler6,
+    Twiddler7,
+    Csv,
+    Json,
+    Dido,
+}
+
+impl FormatVersion {
+    /// The format to read the config as, an error for versions that can't
+    /// be read
+    pub fn format(self) -> Result<Format> {
+        match self {
+            FormatVersion::Twiddler4 => Err(Error::Unsupported(
+                "Twiddler 4 configs can't be read yet".to_string(),
+            )),
+            FormatVersion::Twiddler5 => Ok(Format::Twiddler5),
+            FormatVersion::Twiddler6 => Ok(Format::Twiddler6),
+            FormatVersion::Twiddler7 => Ok(Format::Twiddler7),
+            FormatVersion::Csv => Ok(Format::Csv),
+            FormatVersion::Json => Ok(Format::Json),
+            FormatVersion::Dido => Ok(Format::Dido),
+        }
+    }
+}
+
+/// Work out the format and version of a config from its first bytes. v4
+/// and v5 configs start with their version, v6 and v7 ones have it after
+/// four bytes of padding. Text ones are told apart by their first
+/// character, or their first line that isn't a `#` comment since Dido and
+/// CSV configs can both start with one. The reader is left at the start.
+pub fn detect_version<R: Read + Seek>(reader: &mut R) -> Result<FormatVersion> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = vec![];
+    reader.by_ref().take(5).read_to_end(&mut header)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    match header.as_slice() {
+        [] => Err(Error::Invalid("The config is empty".to_string())),
+        [0x04, ..] => Ok(FormatVersion::Twiddler4),
+        [0x05, ..] => Ok(FormatVersion::Twiddler5),
+        [_, _, _, _, 0x06] => Ok(FormatVersion::Twiddler6),
+        [_, _, _, _, 0x07] => Ok(FormatVersion::Twiddler7),
+        _ => match header.iter().find(|b| !b.is_ascii_whitespace()) {
+            Some(b'#') => detect_commented(reader),
+            Some(b'{') => Ok(FormatVersion::Json),
+            _ => Ok(FormatVersion::Csv),
+        },
+    }
+}
+
+// Dido options are `key=value` lines, where a CSV file has its header
+fn detect_commented<R: Read + Seek>(reader: &mut R) -> Result<FormatVersion> {
+    let mut start = vec![];
+    reader.by_ref().take(4096).read_to_end(&mut start)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let line = start
+        .split(|b| *b == b'\n')
+        .map(|line| line.trim_ascii())
+        .find(|line| !line.is_empty() && !line.starts_with(b"#"));
+    match line {
+        Some(line) if !line.contains(&b'=') => Ok(FormatVersion::Csv),
+        _ => Ok(FormatVersion::Dido),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_detect_version() {
+        for (path, version) in [
+            ("configs/backspice2_v5.cfg", FormatVersion::Twiddler5),
+            ("configs/backspicev2_v6.cfg", FormatVersion::Twiddler6),
+            ("test/configs/v7/default.cfg", FormatVersion::Twiddler7),
+            ("configs/twiddler_cfg_CoolHand.csv", FormatVersion::Csv),
+            ("configs/CoolHand.txt", FormatVersion::Dido),
+        ] {
+            let mut file = std::fs::File::open(path).unwrap();
+            assert!(detect_version(&mut file).unwrap() == version, "{}", path);
+        }
+
+        let detect = |data: &[u8]| detect_version(&mut Cursor::new(data));
+        assert!(detect(b"  {\"chords\": []}").unwrap() == FormatVersion::Json);
+        let commented = b"# name: CoolHand\nthumbs,fingers,output\n";
+        assert!(detect(commented).unwrap() == FormatVersion::Csv);
+        assert!(matches!(detect(b""), Err(Error::Invalid(_))));
+
+        // Recognized, but not read
+        let v4 = detect(&[0x04, 0x00, 0x02, 0x00, 0x00, 0x00]).unwrap();
+        assert!(v4 == FormatVersion::Twiddler4);
+        assert!(matches!(v4.format(), Err(Error::Unsupported(_))));
+        assert!(FormatVersion::Dido.format().unwrap() == Format::Dido);
+    }
+}
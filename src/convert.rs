@@ -0,0 +1,1290 @@
+use std::io::{Read, Seek, Write};
+use std::str::FromStr;
+
+use crate::{
+    buttons::ButtonState,
+    charachorder, config, csv, dido, hid,
+    intern::{self, Interned},
+    json,
+    layout::{Host, Layout},
+    output::{self, ChordOutput, MacroStep, ParseOptions},
+    twiddler5, twiddler6, twiddler7, Error, Metadata, Result, Warning,
+};
+
+/// Config formats, every conversion goes through a twiddler7 config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Format {
+    Csv,
+    Json,
+    Dido,
+    /// A CharaChorder chord backup, read only
+    Charachorder,
+    #[cfg_attr(feature = "cli", value(name = "v5"))]
+    Twiddler5,
+    #[cfg_attr(feature = "cli", value(name = "v6"))]
+    Twiddler6,
+    #[cfg_attr(feature = "cli", value(name = "v7"))]
+    Twiddler7,
+}
+
+/// The names the CLI takes, e.g. "csv" or "v7"
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "dido" => Ok(Format::Dido),
+            "charachorder" => Ok(Format::Charachorder),
+            "v5" => Ok(Format::Twiddler5),
+            "v6" => Ok(Format::Twiddler6),
+            "v7" => Ok(Format::Twiddler7),
+            _ => Err(Error::Unsupported(format!("Unknown format {}", name))),
+        }
+    }
+}
+
+/// Read a config in `input_format` and write it out in `output_format`
+pub fn convert<R: Read + Seek, W: Write + Seek>(
+    input_format: Format,
+    output_format: Format,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<()> {
+    let config = read(input_format, reader)?;
+    write(output_format, config, writer)
+}
+
+/// Read a config in whatever format
+/// [`detect_version`](crate::config::detect_version) finds
+pub fn read_any<R: Read + Seek>(reader: &mut R) -> Result<twiddler7::Config> {
+    let format = config::detect_version(reader)?.format()?;
+    read(format, reader)
+}
+
+pub fn read<R: Read + Seek>(format: Format, reader: &mut R) -> Result<twiddler7::Config> {
+    read_in(format, reader, Layout::Us)
+}
+
+/// Like [`read`] with text outputs typed on `host`, a [`Layout`] or a
+/// [`Host`]. Binary configs already have their key codes.
+pub fn read_in<R: Read + Seek>(
+    format: Format,
+    reader: &mut R,
+    host: impl Into<Host>,
+) -> Result<twiddler7::Config> {
+    read_with(format, reader, host, ParseOptions::STRICT).map(|(config, _)| config)
+}
+
+/// Like [`read_in`], a lenient parse turns unknown keys and tags in text
+/// outputs into warnings and leaves them out, see [`ParseOptions`]
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(reader, host, options)))]
+pub fn read_with<R: Read + Seek>(
+    format: Format,
+    reader: &mut R,
+    host: impl Into<Host>,
+    options: ParseOptions,
+) -> Result<(twiddler7::Config, Vec<Warning>)> {
+    let host = host.into();
+    match format {
+        Format::Csv => {
+            let mut chords = csv::parse(reader)?;
+            let metadata = match chords.first_mut() {
+                Some(first) => Metadata::take_comments(&mut first.leading_comments),
+                None => Metadata::default(),
+            };
+            let (mut config7, warnings) = csv_to_twiddler7(chords, host, options)?;
+            config7.metadata = metadata;
+            Ok((config7, warnings))
+        }
+        Format::Json => from_json(json::parse(reader)?, host, options),
+        Format::Dido => Ok((dido_to_twiddler7(dido::parse(reader)?)?, vec![])),
+        // Chords that can't be put on buttons are left out with a warning
+        // for each, even in a strict parse
+        Format::Charachorder => {
+            let (chords, diagnostics) = charachorder::parse(reader, &Default::default())?;
+            let (config7, mut warnings) = csv_to_twiddler7(chords, host, options)?;
+            warnings.extend(diagnostics.into_iter().map(|d| Warning {
+                error: Error::Unsupported(format!(
+                    "Left out chord {} of the backup, {}",
+                    d.line, d.reason
+                )),
+            }));
+            Ok((config7, warnings))
+        }
+        Format::Twiddler5 => Ok((twiddler5_to_twiddler7(&twiddler5::parse(reader)?)?, vec![])),
+        Format::Twiddler6 => Ok((twiddler6_to_twiddler7(&twiddler6::parse(reader)?), vec![])),
+        Format::Twiddler7 => Ok((twiddler7::parse(reader)?, vec![])),
+    }
+}
+
+/// Order of the chords in written configs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SortOrder {
+    /// The order they were read in
+    #[default]
+    Input,
+    /// By the buttons pressed, thumbs before fingers
+    Buttons,
+    /// By the output as written in CSV
+    Output,
+}
+
+/// How [`write_with`] writes a config
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExportOptions {
+    /// A fixed order keeps regenerated configs from changing under version
+    /// control when nothing but the order did
+    pub sort: SortOrder,
+    /// Write repeated command lists of v6 and v7 configs once, for configs
+    /// with too many macros to fit otherwise, see [`intern`](crate::intern)
+    pub share_command_lists: bool,
+    /// Keep the metadata in v7 configs, in a block after the command lists
+    /// that neither the Tuner nor the firmware know about. Only CSV and JSON
+    /// files have it otherwise.
+    pub binary_metadata: bool,
+}
+
+pub fn write<W: Write + Seek>(
+    format: Format,
+    config: twiddler7::Config,
+    writer: &mut W,
+) -> Result<()> {
+    write_in(format, config, writer, Layout::Us)
+}
+
+/// Like [`write`] with text outputs for what the key codes type on a host
+/// using `layout`
+pub fn write_in<W: Write + Seek>(
+    format: Format,
+    config: twiddler7::Config,
+    writer: &mut W,
+    layout: Layout,
+) -> Result<()> {
+    write_with(format, config, writer, layout, ExportOptions::default())
+}
+
+/// Like [`write_in`], sorting the chords first, see [`ExportOptions`]
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(skip(config, writer, layout), fields(chords = config.chords.len()))
+)]
+pub fn write_with<W: Write + Seek>(
+    format: Format,
+    mut config: twiddler7::Config,
+    writer: &mut W,
+    layout: Layout,
+    options: ExportOptions,
+) -> Result<()> {
+    sort_chords(&mut config, options.sort, layout);
+    match format {
+        Format::Csv => {
+            let mut chords = twiddler7_to_csv(&config, layout)?;
+            if let Some(first) = chords.first_mut() {
+                first.leading_comments = config.metadata.to_comments();
+            }
+            csv::export(writer, &chords)
+        }
+        Format::Json => json::export(writer, &to_json(config, layout)?),
+        Format::Twiddler6 => twiddler6::write(
+            twiddler7_to_twiddler6(&config)?,
+            writer,
+            None,
+            options.share_command_lists,
+        )
+        .map(|_| ()),
+        Format::Twiddler7 => {
+            if !options.binary_metadata {
+                config.metadata = Metadata::default();
+            }
+            twiddler7::write(config, writer, None, false, options.share_command_lists).map(|_| ())
+        }
+        Format::Dido | Format::Charachorder | Format::Twiddler5 => Err(Error::Unsupported(
+            format!("Writing {:?} configs is not supported", format),
+        )),
+    }
+}
+
+/// A JSON config as a twiddler7 config, like reading it with [`read_with`]
+pub fn from_json(
+    config: json::Config,
+    host: impl Into<Host>,
+    options: ParseOptions,
+) -> Result<(twiddler7::Config, Vec<Warning>)> {
+    let (mut config7, warnings) = csv_to_twiddler7(config.chords, host.into(), options)?;
+    config7.set_settings(&config.settings);
+    config7.metadata = config.metadata;
+    Ok((config7, warnings))
+}
+
+/// The JSON config [`write_in`] writes, for callers that want the chords
+/// without going through a file
+pub fn to_json(config: twiddler7::Config, layout: Layout) -> Result<json::Config> {
+    let mut json_config = json::Config::new(twiddler7_to_csv(&config, layout)?);
+    json_config.settings = config.settings();
+    json_config.metadata = config.metadata;
+    Ok(json_config)
+}
+
+impl From<twiddler6::CommandType> for twiddler7::CommandType {
+    fn from(command_type: twiddler6::CommandType) -> Self {
+        match command_type {
+            twiddler6::CommandType::None => twiddler7::CommandType::None,
+            twiddler6::CommandType::System => twiddler7::CommandType::System,
+            twiddler6::CommandType::Keyboard => twiddler7::CommandType::Keyboard,
+            twiddler6::CommandType::Mouse => twiddler7::CommandType::Mouse,
+            twiddler6::CommandType::Delay => twiddler7::CommandType::Delay,
+            twiddler6::CommandType::ListOfCommands => twiddler7::CommandType::ListOfCommands,
+        }
+    }
+}
+
+impl From<twiddler7::CommandType> for twiddler6::CommandType {
+    fn from(command_type: twiddler7::CommandType) -> Self {
+        match command_type {
+            twiddler7::CommandType::None => twiddler6::CommandType::None,
+            twiddler7::CommandType::System => twiddler6::CommandType::System,
+            twiddler7::CommandType::Keyboard => twiddler6::CommandType::Keyboard,
+            twiddler7::CommandType::Mouse => twiddler6::CommandType::Mouse,
+            twiddler7::CommandType::Delay => twiddler6::CommandType::Delay,
+            twiddler7::CommandType::ListOfCommands => twiddler6::CommandType::ListOfCommands,
+        }
+    }
+}
+
+pub(crate) fn csv_to_twiddler7(
+    chords: Vec<csv::Chord>,
+    host: Host,
+    options: ParseOptions,
+) -> Result<(twiddler7::Config, Vec<Warning>)> {
+    let mut config7 = twiddler7::Config::new();
+    let mut warnings = vec![];
+    for (i, chord) in chords.into_iter().enumerate() {
+        // Rows are counted from 1 and the header takes the first one
+        event!(
+            trace,
+            row = i + 2,
+            thumbs = chord.thumbs(),
+            fingers = chord.fingers(),
+            output = chord.output(),
+            "Converting chord"
+        );
+        let button_state = ButtonState::try_from(&chord).map_err(|e| e.with_row(i + 2))?;
+        let (output, chord_warnings) = chord
+            .get_output_with(host, options)
+            .map_err(|e| e.with_row(i + 2))?;
+        warnings.extend(chord_warnings.into_iter().map(|w| Warning {
+            error: w.error.with_row(i + 2),
+        }));
+
+        let layer = usize::from(chord.layer());
+        if layer > config7.layers.len() {
+            config7.layers.resize_with(layer, twiddler7::Config::new);
+        }
+        let target = match layer {
+            0 => &mut config7,
+            _ => &mut config7.layers[layer - 1],
+        };
+        let steps = match output {
+            ChordOutput::Mouse(buttons) => {
+                target.chords.push(twiddler7::Chord {
+                    buttons: button_state.into(),
+                    command: twiddler7::Command {
+                        command_type: twiddler7::CommandType::Mouse,
+                        data: twiddler7::CommandData::Mouse(twiddler7::MouseCommand {
+                            buttons,
+                            unknown: 0,
+                        }),
+                    },
+                });
+                continue;
+            }
+            ChordOutput::SwitchLayer(layer) => {
+                target.chords.push(twiddler7::Chord {
+                    buttons: button_state.into(),
+                    command: twiddler7::Command::switch_layer(layer),
+                });
+                continue;
+            }
+            ChordOutput::Sticky(modifier) => {
+                target.chords.push(twiddler7::Chord {
+                    buttons: button_state.into(),
+                    command: twiddler7::Command::sticky(modifier),
+                });
+                continue;
+            }
+            ChordOutput::Macro(steps) => steps,
+        };
+
+        let step_command = |step: MacroStep| match step {
+            MacroStep::Key { modifier, key_code } => twiddler7::Command {
+                command_type: twiddler7::CommandType::Keyboard,
+                data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                    modifier,
+                    key_code,
+                }),
+            },
+            MacroStep::Delay(ms) => twiddler7::Command {
+                command_type: twiddler7::CommandType::Delay,
+                data: twiddler7::CommandData::Delay(ms),
+            },
+        };
+
+        // A single key fits in the chord, anything else needs a command list
+        let command = match steps.as_slice() {
+            [] => step_command(MacroStep::Key {
+                modifier: 0,
+                key_code: 0,
+            }),
+            [step @ MacroStep::Key { .. }] => step_command(*step),
+            _ => {
+                target.command_lists.push(twiddler7::CommandList(
+                    steps.into_iter().map(step_command).collect(),
+                ));
+
+                twiddler7::Command {
+                    command_type: twiddler7::CommandType::ListOfCommands,
+                    data: twiddler7::CommandData::ListOfCommands(0),
+                }
+            }
+        };
+
+        target.chords.push(twiddler7::Chord {
+            buttons: button_state.into(),
+            command,
+        });
+    }
+
+    Ok((config7, warnings))
+}
+
+fn dido_to_twiddler7(config: dido::Config) -> Result<twiddler7::Config> {
+    let mut config7 = twiddler7::Config::new();
+    for c in &config.chords {
+        let command = match &c.output {
+            dido::ChordOutput::HidCode(key_code) => {
+                let key_code = match key_code.parse() {
+                    Ok(key_code) => key_code,
+                    Err(_) => {
+                        return Err(Error::Invalid(format!(
+                            "{:?} isn't a HID key code",
+                            key_code
+                        )))
+                    }
+                };
+                twiddler7::Command {
+                    command_type: twiddler7::CommandType::Keyboard,
+                    data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                        modifier: c.modifiers,
+                        key_code,
+                    }),
+                }
+            }
+            dido::ChordOutput::StringIndex(index) => {
+                let out_string_hids = match index.parse::<usize>() {
+                    Ok(i) if i < config.strings.len() => &config.strings[i],
+                    _ => {
+                        return Err(Error::Invalid(format!(
+                            "String {} isn't one of the {} in the config",
+                            index,
+                            config.strings.len()
+                        )))
+                    }
+                };
+                let command = twiddler7::Command {
+                    command_type: twiddler7::CommandType::ListOfCommands,
+                    data: twiddler7::CommandData::ListOfCommands(0),
+                };
+
+                let mut command_list = vec![];
+
+                for hids in out_string_hids {
+                    command_list.push(twiddler7::Command {
+                        command_type: twiddler7::CommandType::Keyboard,
+                        data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                            key_code: hids.0,
+                            modifier: hids.1,
+                        }),
+                    });
+                }
+
+                config7
+                    .command_lists
+                    .push(twiddler7::CommandList(command_list));
+
+                command
+            }
+        };
+
+        config7.chords.push(twiddler7::Chord {
+            buttons: twiddler7::ButtonData::from(&c.buttons),
+            command,
+        });
+    }
+
+    Ok(config7)
+}
+
+pub(crate) fn twiddler5_to_twiddler7(config: &twiddler5::Config) -> Result<twiddler7::Config> {
+    let mut config7 = twiddler7::Config::new();
+    config7.set_settings(&config.settings().into());
+
+    for c in &config.chords {
+        let button_state = c.button_state();
+
+        let command = match c.mapping {
+            twiddler5::ChordMapping::KeyMapping(modifier, key_code) => twiddler7::Command {
+                command_type: twiddler7::CommandType::Keyboard,
+                data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                    modifier,
+                    key_code,
+                }),
+            },
+            twiddler5::ChordMapping::StringMapping(_, index) => {
+                let command = twiddler7::Command {
+                    command_type: twiddler7::CommandType::ListOfCommands,
+                    data: twiddler7::CommandData::ListOfCommands(0),
+                };
+
+                let string_pos = match config.string_locations.get(index as usize) {
+                    Some(string_pos) => *string_pos,
+                    None => {
+                        return Err(Error::Invalid(format!(
+                            "String {} isn't one of the {} in the config",
+                            index,
+                            config.string_locations.len()
+                        )))
+                    }
+                };
+                let contents = match config
+                    .string_contents
+                    .iter()
+                    .find(|sc| sc.pos == u64::from(string_pos))
+                {
+                    Some(contents) => contents,
+                    None => {
+                        return Err(Error::Invalid(format!(
+                            "No string starts at offset {}",
+                            string_pos
+                        )))
+                    }
+                };
+
+                let mut command_list = vec![];
+
+                for c in &contents.keys {
+                    if let twiddler5::ChordMapping::KeyMapping(modifier, key_code) = c {
+                        command_list.push(twiddler7::Command {
+                            command_type: twiddler7::CommandType::Keyboard,
+                            data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                                modifier: *modifier,
+                                key_code: *key_code,
+                            }),
+                        });
+                    }
+                }
+
+                config7
+                    .command_lists
+                    .push(twiddler7::CommandList(command_list));
+
+                command
+            }
+        };
+
+        config7.chords.push(twiddler7::Chord {
+            buttons: button_state.into(),
+            command,
+        });
+    }
+
+    Ok(config7)
+}
+
+pub(crate) fn twiddler6_to_twiddler7(config: &twiddler6::Config) -> twiddler7::Config {
+    let mut config7 = twiddler7::Config::new();
+    config7.set_settings(&config.settings());
+
+    let command = |command: &twiddler6::Command| twiddler7::Command {
+        command_type: command.command_type.clone().into(),
+        data: match command.data {
+            twiddler6::CommandData::ListOfCommands(_, _) => {
+                twiddler7::CommandData::ListOfCommands(0)
+            }
+            twiddler6::CommandData::Keyboard(ref hid_command, _) => {
+                twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                    modifier: hid_command.modifier,
+                    key_code: hid_command.key_code,
+                })
+            }
+            twiddler6::CommandData::Mouse(ref mouse_command, _) => {
+                twiddler7::CommandData::Mouse(twiddler7::MouseCommand {
+                    buttons: mouse_command.buttons,
+                    unknown: mouse_command.unknown,
+                })
+            }
+            twiddler6::CommandData::System(a, b, _) => twiddler7::CommandData::System(a, b),
+            twiddler6::CommandData::Delay(ms, _) => twiddler7::CommandData::Delay(ms),
+            twiddler6::CommandData::None(a, b, _) => twiddler7::CommandData::None(a, b),
+        },
+    };
+
+    for chord in &config.chords {
+        let button_state: ButtonState = chord.buttons.into();
+        config7.chords.push(twiddler7::Chord {
+            buttons: twiddler7::ButtonData::from(button_state).with_t0(chord.buttons.t0()),
+            command: command(&chord.command),
+        });
+    }
+
+    for command_list in &config.command_lists {
+        config7.command_lists.push(twiddler7::CommandList(
+            command_list.0.iter().map(command).collect(),
+        ));
+    }
+
+    config7
+}
+
+// v6 has no layers, those are only kept in CSV and JSON configs
+pub(crate) fn twiddler7_to_twiddler6(config: &twiddler7::Config) -> Result<twiddler6::Config> {
+    config.check_binary("v6")?;
+    let mut config6 = twiddler6::Config::new();
+    config6.set_settings(&config.settings());
+
+    let command = |command: &twiddler7::Command| twiddler6::Command {
+        command_type: command.command_type.clone().into(),
+        data: match command.data {
+            twiddler7::CommandData::ListOfCommands(_) => {
+                twiddler6::CommandData::ListOfCommands(0, 0)
+            }
+            twiddler7::CommandData::Keyboard(ref hid_command) => twiddler6::CommandData::Keyboard(
+                twiddler6::HidCommand {
+                    modifier: hid_command.modifier,
+                    key_code: hid_command.key_code,
+                },
+                0,
+            ),
+            twiddler7::CommandData::Mouse(ref mouse_command) => twiddler6::CommandData::Mouse(
+                twiddler6::MouseCommand {
+                    buttons: mouse_command.buttons,
+                    unknown: mouse_command.unknown,
+                },
+                0,
+            ),
+            twiddler7::CommandData::System(a, b) => twiddler6::CommandData::System(a, b, 0),
+            twiddler7::CommandData::Delay(ms) => twiddler6::CommandData::Delay(ms, 0),
+            twiddler7::CommandData::None(a, b) => twiddler6::CommandData::None(a, b, 0),
+        },
+    };
+
+    for chord in &config.chords {
+        let button_state: ButtonState = chord.buttons.into();
+        config6.chords.push(twiddler6::Chord {
+            buttons: twiddler6::ButtonData::from(button_state).with_t0(chord.buttons.t0()),
+            command: command(&chord.command),
+        });
+    }
+
+    for command_list in &config.command_lists {
+        config6.command_lists.push(twiddler6::CommandList(
+            command_list.0.iter().map(command).collect(),
+        ));
+    }
+
+    Ok(config6)
+}
+
+pub(crate) fn twiddler7_to_csv(
+    config: &twiddler7::Config,
+    layout: Layout,
+) -> Result<Vec<csv::Chord>> {
+    let mut chords = layer_to_csv(config, layout)?;
+    for (i, layer) in config.layers.iter().enumerate() {
+        for mut chord in layer_to_csv(layer, layout)? {
+            chord.set_layer(i as u8 + 1);
+            chords.push(chord);
+        }
+    }
+    Ok(chords)
+}
+
+fn layer_to_csv(config: &twiddler7::Config, layout: Layout) -> Result<Vec<csv::Chord>> {
+    let mut chords = vec![];
+    let mut command_lists = config.command_lists.iter();
+
+    for chord in &config.chords {
+        let command_list = match chord.command.command_type {
+            twiddler7::CommandType::ListOfCommands => command_lists.next(),
+            _ => None,
+        };
+        if let Some(output) = chord_output(chord, command_list, layout)? {
+            let button_state: ButtonState = chord.buttons.into();
+            chords.push(csv::Chord::from_buttons(&button_state, output));
+        }
+    }
+
+    Ok(chords)
+}
+
+// What a chord types in CSV, None for the system chords that are added
+// back when writing a binary config
+fn chord_output(
+    chord: &twiddler7::Chord,
+    command_list: Option<&twiddler7::CommandList>,
+    layout: Layout,
+) -> Result<Option<String>> {
+    if let Some(layer) = chord.command.switched_layer() {
+        return Ok(Some(format!("<Layer:{}>", layer)));
+    }
+    if let Some(modifier) = chord.command.sticky_modifiers() {
+        return Ok(Some(sticky_output(modifier)));
+    }
+    let output = match &chord.command.data {
+        twiddler7::CommandData::Keyboard(hid_command) => {
+            hid::pairs_to_output_in(&[(hid_command.modifier, hid_command.key_code)], layout)
+        }
+        twiddler7::CommandData::ListOfCommands(_) => {
+            let command_list = match command_list {
+                Some(command_list) => command_list,
+                None => {
+                    return Err(Error::Invalid(
+                        "A chord's command list is missing from the config".to_string(),
+                    ))
+                }
+            };
+            let mut steps = vec![];
+            for command in &command_list.0 {
+                match &command.data {
+                    twiddler7::CommandData::Keyboard(hid_command) => steps.push(MacroStep::Key {
+                        modifier: hid_command.modifier,
+                        key_code: hid_command.key_code,
+                    }),
+                    twiddler7::CommandData::Delay(ms) => steps.push(MacroStep::Delay(*ms)),
+                    data => {
+                        return Err(Error::Unsupported(format!(
+                            "CSV outputs can't hold a {:?} command",
+                            data
+                        )))
+                    }
+                }
+            }
+            output::macro_to_output_in(&steps, layout)
+        }
+        twiddler7::CommandData::Mouse(mouse_command) if mouse_command.buttons != 0 => {
+            mouse_output(mouse_command.buttons)
+        }
+        twiddler7::CommandData::System(..) => return Ok(None),
+        // CSV only knows about keyboard and mouse output
+        data => {
+            return Err(Error::Unsupported(format!(
+                "CSV outputs can't hold a {:?} command",
+                data
+            )))
+        }
+    };
+    Ok(Some(output))
+}
+
+/// Bytes `config` takes written as a v6 or v7 config, command lists shared
+/// when `share` is set like [`ExportOptions::share_command_lists`]. v6 has
+/// no metadata, and neither has layers so a config with them errors like
+/// writing it would.
+pub fn encoded_size(config: &twiddler7::Config, format: Format, share: bool) -> Result<usize> {
+    let lists = interned(config, format, share)?;
+    match format {
+        Format::Twiddler7 => {
+            let mut size = 0x80 + config.chords.len() * 8 + lists.len;
+            if !config.metadata.is_empty() {
+                size += 8 + serde_json::to_vec(&config.metadata)?.len();
+            }
+            Ok(size)
+        }
+        _ => Ok(0x28 + config.chords.len() * 8 + lists.len),
+    }
+}
+
+/// Where the command lists of `config` go written as a v6 or v7 config,
+/// what [`twiddler6::write`] and [`twiddler7::write`] return
+pub fn interned(config: &twiddler7::Config, format: Format, share: bool) -> Result<Interned> {
+    let lists = match format {
+        Format::Twiddler7 => {
+            config.check_binary("v7")?;
+            intern::encode(&config.command_lists)?
+        }
+        Format::Twiddler6 => intern::encode(&twiddler7_to_twiddler6(config)?.command_lists)?,
+        _ => {
+            return Err(Error::Unsupported(format!(
+                "{:?} isn't a binary format, only v6 and v7 configs have command lists",
+                format
+            )))
+        }
+    };
+    Ok(intern::lay_out(&lists, share))
+}
+
+/// A chord typing a macro, and the bytes its command list takes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MacroSize {
+    pub layer: u8,
+    pub buttons: ButtonState,
+    pub output: String,
+    pub bytes: usize,
+}
+
+/// The macros of a config and its layers, largest first, for what to trim
+/// when a config is too big
+pub fn largest_macros(config: &twiddler7::Config, layout: Layout) -> Vec<MacroSize> {
+    let layers = core::iter::once(config).chain(&config.layers);
+    let mut macros = vec![];
+    for (layer, config) in layers.enumerate() {
+        let lists = config.command_lists.iter();
+        let chords = config
+            .chords
+            .iter()
+            .filter(|chord| chord.command.command_type == twiddler7::CommandType::ListOfCommands);
+        for (chord, list) in chords.zip(lists) {
+            macros.push(MacroSize {
+                layer: layer as u8,
+                buttons: chord.buttons.into(),
+                output: chord_output(chord, Some(list), layout)
+                    .ok()
+                    .flatten()
+                    .unwrap_or_default(),
+                bytes: (list.0.len() + 1) * 4,
+            });
+        }
+    }
+    macros.sort_by_key(|size| core::cmp::Reverse(size.bytes));
+    macros
+}
+
+/// Put the chords of a config and its layers in `order`, each chord keeping
+/// its command list. Ties are broken by the other key, then input order.
+pub fn sort_chords(config: &mut twiddler7::Config, order: SortOrder, layout: Layout) {
+    for layer in &mut config.layers {
+        sort_chords(layer, order, layout);
+    }
+    if order == SortOrder::Input {
+        return;
+    }
+
+    let mut command_lists = std::mem::take(&mut config.command_lists).into_iter();
+    let mut chords: Vec<_> = std::mem::take(&mut config.chords)
+        .into_iter()
+        .map(|chord| {
+            let command_list = match chord.command.command_type {
+                twiddler7::CommandType::ListOfCommands => command_lists.next(),
+                _ => None,
+            };
+            let buttons = ButtonState::from(chord.buttons);
+            let output = chord_output(&chord, command_list.as_ref(), layout)
+                .ok()
+                .flatten()
+                .unwrap_or_default();
+            (buttons, output, chord, command_list)
+        })
+        .collect();
+    match order {
+        SortOrder::Input => (),
+        SortOrder::Buttons => chords.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1))),
+        SortOrder::Output => chords.sort_by(|a, b| (&a.1, a.0).cmp(&(&b.1, b.0))),
+    }
+
+    // Lists no chord uses stay at the end, writing the config rejects them
+    let rest: Vec<_> = command_lists.collect();
+    for (_, _, chord, command_list) in chords {
+        config.chords.push(chord);
+        config.command_lists.extend(command_list);
+    }
+    config.command_lists.extend(rest);
+}
+
+fn mouse_output(buttons: u8) -> String {
+    hid::MOUSE_BUTTONS
+        .iter()
+        .filter(|(_, bit)| buttons & bit != 0)
+        .map(|(name, _)| format!("<{}>", name))
+        .collect()
+}
+
+fn sticky_output(modifier: u8) -> String {
+    hid::MODIFIER_TAGS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| modifier & (1 << bit) != 0)
+        .map(|(_, tag)| format!("<Sticky:{}>", tag))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_twiddler6_round_trip() {
+        let original = std::fs::read("configs/backspicev2_v6.cfg").unwrap();
+
+        let mut output = Cursor::new(vec![]);
+        convert(
+            Format::Twiddler6,
+            Format::Twiddler6,
+            &mut Cursor::new(&original),
+            &mut output,
+        )
+        .unwrap();
+
+        assert!(output.into_inner() == original);
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let original = std::fs::read("configs/twiddler_cfg_CoolHand.csv").unwrap();
+
+        let mut binary = Cursor::new(vec![]);
+        convert(
+            Format::Csv,
+            Format::Twiddler7,
+            &mut Cursor::new(&original),
+            &mut binary,
+        )
+        .unwrap();
+
+        let mut first = Cursor::new(vec![]);
+        binary.set_position(0);
+        convert(Format::Twiddler7, Format::Csv, &mut binary, &mut first).unwrap();
+
+        let mut binary = Cursor::new(vec![]);
+        first.set_position(0);
+        convert(Format::Csv, Format::Twiddler7, &mut first, &mut binary).unwrap();
+
+        let mut second = Cursor::new(vec![]);
+        binary.set_position(0);
+        convert(Format::Twiddler7, Format::Csv, &mut binary, &mut second).unwrap();
+
+        let first = String::from_utf8(first.into_inner()).unwrap();
+        let second = String::from_utf8(second.into_inner()).unwrap();
+        assert!(first.lines().count() == 104);
+        assert!(first == second);
+    }
+
+    #[test]
+    fn test_corrupt_strings() {
+        let mut file = std::fs::File::open("configs/backspice2_v5.cfg").unwrap();
+        let mut config = twiddler5::parse(&mut file).unwrap();
+        assert!(twiddler5_to_twiddler7(&config).is_ok());
+        // A string that starts nowhere
+        config.string_locations[0] += 1;
+        assert!(matches!(
+            twiddler5_to_twiddler7(&config),
+            Err(Error::Invalid(message)) if message.contains("No string starts")
+        ));
+        // Chords pointing past the strings
+        config.string_locations.truncate(1);
+        assert!(twiddler5_to_twiddler7(&config).is_err());
+
+        let data = std::fs::read("configs/backspice2_v5.cfg").unwrap();
+        for len in [1, 16, data.len() / 2, data.len() - 1] {
+            assert!(read(Format::Twiddler5, &mut Cursor::new(&data[..len])).is_err());
+        }
+
+        let chord = |output| dido::Chord {
+            buttons: crate::buttons::parse_notation(String::new(), "1L".to_string()),
+            output,
+            modifiers: 0,
+            comment: String::new(),
+        };
+        let config = |output| dido::Config {
+            chords: vec![chord(output)],
+            strings: vec![vec![(0x04, 0)]],
+        };
+        let string = |index: &str| dido::ChordOutput::StringIndex(index.to_string());
+        let hid = |code: &str| dido::ChordOutput::HidCode(code.to_string());
+        assert!(dido_to_twiddler7(config(string("0"))).is_ok());
+        assert!(dido_to_twiddler7(config(string("1"))).is_err());
+        assert!(dido_to_twiddler7(config(string("x"))).is_err());
+        assert!(dido_to_twiddler7(config(hid("4"))).is_ok());
+        assert!(dido_to_twiddler7(config(hid("256"))).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_commands_to_csv() {
+        let command = |command_type, data| twiddler7::Command { command_type, data };
+        let mut config = twiddler7::Config::new();
+        config.chords.push(twiddler7::Chord {
+            buttons: twiddler7::ButtonData::new(),
+            command: command(
+                twiddler7::CommandType::ListOfCommands,
+                twiddler7::CommandData::ListOfCommands(0),
+            ),
+        });
+        // Without its list the chord can't be written
+        assert!(twiddler7_to_csv(&config, Layout::Us).is_err());
+
+        // CSV can't hold a system command in a list, it's an error instead
+        // of a missing chord
+        config
+            .command_lists
+            .push(twiddler7::CommandList(vec![command(
+                twiddler7::CommandType::System,
+                twiddler7::CommandData::System(1, 0),
+            )]));
+        assert!(twiddler7_to_csv(&config, Layout::Us).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_output() {
+        let mut input = std::fs::File::open("test/configs/v7/default.cfg").unwrap();
+        let mut output = Cursor::new(vec![]);
+        assert!(convert(
+            Format::Twiddler7,
+            Format::Twiddler5,
+            &mut input,
+            &mut output
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_mouse_round_trip() {
+        let data =
+            "Thumbs,Fingers,Keyboard Output\n,0M,<MouseLeft>\n,1L 2L,<MouseLeft><MouseRight>\n";
+
+        let mut v7 = Cursor::new(vec![]);
+        convert(
+            Format::Csv,
+            Format::Twiddler7,
+            &mut Cursor::new(data),
+            &mut v7,
+        )
+        .unwrap();
+        let mut v6 = Cursor::new(vec![]);
+        v7.set_position(0);
+        convert(Format::Twiddler7, Format::Twiddler6, &mut v7, &mut v6).unwrap();
+        let mut csv = Cursor::new(vec![]);
+        v6.set_position(0);
+        convert(Format::Twiddler6, Format::Csv, &mut v6, &mut csv).unwrap();
+
+        let csv = String::from_utf8(csv.into_inner()).unwrap();
+        assert!(csv.contains(",0M,<MouseLeft>\n"));
+        assert!(csv.contains(",1L 2L,<MouseLeft><MouseRight>\n"));
+    }
+
+    #[test]
+    fn test_format_names() {
+        assert!("v7".parse::<Format>().unwrap() == Format::Twiddler7);
+        assert!("Twiddler7".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_delay_round_trip() {
+        let data = "Thumbs,Fingers,Keyboard Output\n,1L,<L-Gui>r</L-Gui><Delay:500>cmd<Return>\n";
+
+        let mut v6 = Cursor::new(vec![]);
+        convert(
+            Format::Csv,
+            Format::Twiddler6,
+            &mut Cursor::new(data),
+            &mut v6,
+        )
+        .unwrap();
+        let mut csv = Cursor::new(vec![]);
+        v6.set_position(0);
+        convert(Format::Twiddler6, Format::Csv, &mut v6, &mut csv).unwrap();
+
+        let csv = String::from_utf8(csv.into_inner()).unwrap();
+        assert!(csv.contains(",1L,<L-Gui>r</L-Gui><Delay:500>cmd<Return>\n"));
+    }
+
+    #[test]
+    fn test_csv_error_row() {
+        let data = "Thumbs,Fingers,Keyboard Output\n,1L,a\n,1M,<R-Ctrl\n";
+        let err = read(Format::Csv, &mut Cursor::new(data)).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::MalformedTag {
+                row: Some(3),
+                column: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_read_lenient() {
+        let data = "Thumbs,Fingers,Keyboard Output\n,1L,a<Nope>\n,1M,<Nope>\n";
+        let (config, warnings) = read_with(
+            Format::Csv,
+            &mut Cursor::new(data),
+            Layout::Us,
+            ParseOptions::LENIENT,
+        )
+        .unwrap();
+        assert!(warnings.len() == 2);
+        assert!(matches!(
+            warnings[1].error,
+            Error::UnknownKey { row: Some(3), .. }
+        ));
+        // Nothing is left of the second chord
+        assert!(
+            config.chords[1].command.data
+                == twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                    modifier: 0,
+                    key_code: 0
+                })
+        );
+        assert!(read(Format::Csv, &mut Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn test_charachorder_warnings() {
+        // "the", then "eht" on the same buttons
+        let backup = r#"{"type": "chords", "chords": [
+            [[116, 104, 101], [116, 104, 101]],
+            [[101, 104, 116], [84, 104, 101]]
+        ]}"#;
+        let (config, warnings) = read_with(
+            Format::Charachorder,
+            &mut Cursor::new(backup),
+            Layout::Us,
+            ParseOptions::STRICT,
+        )
+        .unwrap();
+        assert!(config.chords.len() == 1);
+        assert!(warnings.len() == 1);
+        assert!(
+            warnings[0].to_string()
+                == "Left out chord 2 of the backup, on the same buttons as chord 1"
+        );
+    }
+
+    #[test]
+    fn test_sticky_round_trip() {
+        let data =
+            "thumbs,fingers,output\n,1L,<Sticky:L-Shift>\n,1M,<Sticky:L-Ctrl><Sticky:R-Alt>\n";
+        let config = read(Format::Csv, &mut Cursor::new(data)).unwrap();
+        assert!(config.chords[0].command.sticky_modifiers() == Some(0x02));
+
+        let mut json = Cursor::new(vec![]);
+        write(Format::Json, config.clone(), &mut json).unwrap();
+        json.set_position(0);
+        let mut csv = Cursor::new(vec![]);
+        convert(Format::Json, Format::Csv, &mut json, &mut csv).unwrap();
+        assert!(String::from_utf8(csv.into_inner()).unwrap() == data);
+
+        // The Twiddler has no sticky chords
+        for format in [Format::Twiddler6, Format::Twiddler7] {
+            let written = write(format, config.clone(), &mut Cursor::new(vec![]));
+            assert!(
+                matches!(written, Err(Error::Unsupported(_))),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn test_modifier_only_round_trip() {
+        let data = "thumbs,fingers,output\n,1L,<L-Shift>\n,1M,<L-Ctrl><R-Alt>\n";
+        let config = read(Format::Csv, &mut Cursor::new(data)).unwrap();
+        assert!(
+            config.chords[0].command.data
+                == twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
+                    modifier: 0x02,
+                    key_code: 0
+                })
+        );
+
+        // Held while the chord is, not for the next one
+        for format in [Format::Twiddler6, Format::Twiddler7] {
+            let mut binary = Cursor::new(vec![]);
+            write(format, config.clone(), &mut binary).unwrap();
+            binary.set_position(0);
+            let mut csv = Cursor::new(vec![]);
+            convert(format, Format::Csv, &mut binary, &mut csv).unwrap();
+            assert!(
+                String::from_utf8(csv.into_inner()).unwrap() == data,
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn test_layers_round_trip() {
+        let data = "thumbs,fingers,output,layer\n,1L,a,\n,0R,<Layer:1>,\n,1L,1,1\n,1M,2 3,1\n,0R,<Layer:0>,1\n";
+        let config = read(Format::Csv, &mut Cursor::new(data)).unwrap();
+        assert!(config.chords.len() == 2 && config.layers.len() == 1);
+        assert!(config.chords[1].command.switched_layer() == Some(1));
+        assert!(config.layers[0].command_lists.len() == 1);
+
+        let mut json = Cursor::new(vec![]);
+        write(Format::Json, config.clone(), &mut json).unwrap();
+        json.set_position(0);
+        let mut csv = Cursor::new(vec![]);
+        convert(Format::Json, Format::Csv, &mut json, &mut csv).unwrap();
+        assert!(String::from_utf8(csv.into_inner()).unwrap() == data);
+
+        // The Twiddler's formats have no layers
+        for format in [Format::Twiddler6, Format::Twiddler7] {
+            let mut binary = Cursor::new(vec![]);
+            let written = write(format, config.clone(), &mut binary);
+            assert!(
+                matches!(written, Err(Error::Unsupported(_))),
+                "{:?}",
+                format
+            );
+        }
+        let mut base = config.clone();
+        base.layers.clear();
+        let written = write(Format::Twiddler7, base, &mut Cursor::new(vec![]));
+        assert!(matches!(written, Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn test_sorted_export() {
+        let data = "thumbs,fingers,output,layer\n,1M,b c,\n,0R,c,\n,1L,a b,\n,1M,z,1\n,1L,y x,1\n";
+        let write_sorted = |sort| {
+            let config = read(Format::Csv, &mut Cursor::new(data)).unwrap();
+            let mut csv = Cursor::new(vec![]);
+            let options = ExportOptions {
+                sort,
+                ..Default::default()
+            };
+            write_with(Format::Csv, config, &mut csv, Layout::Us, options).unwrap();
+            String::from_utf8(csv.into_inner()).unwrap()
+        };
+
+        assert!(write_sorted(SortOrder::Input) == data);
+        assert!(
+            write_sorted(SortOrder::Buttons)
+                == "thumbs,fingers,output,layer\n,0R,c,\n,1L,a b,\n,1M,b c,\n,1L,y x,1\n,1M,z,1\n"
+        );
+        // Command lists move with their chords
+        assert!(
+            write_sorted(SortOrder::Output)
+                == "thumbs,fingers,output,layer\n,1L,a b,\n,1M,b c,\n,0R,c,\n,1L,y x,1\n,1M,z,1\n"
+        );
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let data =
+            "# name: CoolHand\n# version: 2\nThumbs,Fingers,Keyboard Output\n# Vowels\n,1L,a\n";
+        let mut json = Cursor::new(vec![]);
+        convert(Format::Csv, Format::Json, &mut Cursor::new(data), &mut json).unwrap();
+        json.set_position(0);
+        let config = json::parse(&mut json.clone()).unwrap();
+        assert!(config.metadata.name.as_deref() == Some("CoolHand"));
+        assert!(config.metadata.version.as_deref() == Some("2"));
+
+        // Only kept in binary configs when asked for, the Tuner's own
+        // configs have none
+        let config = read(Format::Json, &mut json).unwrap();
+        let mut binary = Cursor::new(vec![]);
+        write(Format::Twiddler7, config.clone(), &mut binary).unwrap();
+        binary.set_position(0);
+        assert!(twiddler7::parse(&mut binary).unwrap().metadata.is_empty());
+        let options = ExportOptions {
+            binary_metadata: true,
+            ..Default::default()
+        };
+        let mut binary = Cursor::new(vec![]);
+        write_with(Format::Twiddler7, config, &mut binary, Layout::Us, options).unwrap();
+        binary.set_position(0);
+        let mut csv = Cursor::new(vec![]);
+        convert(Format::Twiddler7, Format::Csv, &mut binary, &mut csv).unwrap();
+        let csv = String::from_utf8(csv.into_inner()).unwrap();
+        assert!(csv == "# name: CoolHand\n# version: 2\nthumbs,fingers,output\n,1L,a\n");
+
+        // Notes that look like metadata after the header stay with the chord
+        let data = "# name: CoolHand\nthumbs,fingers,output\n# name: the a key\n,1L,a\n";
+        let mut csv = Cursor::new(vec![]);
+        convert(Format::Csv, Format::Csv, &mut Cursor::new(data), &mut csv).unwrap();
+        let csv = String::from_utf8(csv.into_inner()).unwrap();
+        assert!(csv == "# name: CoolHand\nthumbs,fingers,output\n,1L,a\n");
+        let chords = csv::parse(&mut Cursor::new(data)).unwrap();
+        let config = read(Format::Csv, &mut Cursor::new(data)).unwrap();
+        assert!(chords[0].comments() == [" name: the a key"]);
+        assert!(config.metadata.name.as_deref() == Some("CoolHand"));
+
+        let mut file = std::fs::File::open("test/configs/v7/empty.cfg").unwrap();
+        assert!(twiddler7::parse(&mut file).unwrap().metadata.is_empty());
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_round_trip() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        // Fixed xorshift bytes so failures reproduce
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..100 {
+            let data: Vec<u8> = (0..4096)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let config = twiddler7::Config::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            let chords = twiddler7_to_csv(&config, Layout::Us).ok();
+            let (settings, metadata) = (config.settings(), config.metadata.clone());
+
+            let mut binary = Cursor::new(vec![]);
+            if config.check_binary("v7").is_err() {
+                // Only CSV and JSON configs keep layers
+                let written = twiddler7::write(config, &mut binary, None, false, false);
+                assert!(matches!(written, Err(Error::Unsupported(_))));
+                continue;
+            }
+            twiddler7::write(config, &mut binary, None, false, false).unwrap();
+            binary.set_position(0);
+            let config = twiddler7::parse(&mut binary).unwrap();
+            assert!(twiddler7_to_csv(&config, Layout::Us).ok() == chords);
+            assert!(config.settings() == settings && config.metadata == metadata);
+        }
+    }
+
+    #[test]
+    fn test_encoded_size() {
+        let csv = "thumbs,fingers,output\n,1L,the\n,1M,he\n,0R,b\n";
+        let mut config = read(Format::Csv, &mut Cursor::new(csv)).unwrap();
+        config.metadata.name = Some("Size".to_string());
+
+        for format in [Format::Twiddler6, Format::Twiddler7] {
+            for share_command_lists in [false, true] {
+                let options = ExportOptions {
+                    share_command_lists,
+                    binary_metadata: true,
+                    ..Default::default()
+                };
+                let mut data = Cursor::new(vec![]);
+                write_with(format, config.clone(), &mut data, Layout::Us, options).unwrap();
+                let size = encoded_size(&config, format, share_command_lists).unwrap();
+                assert!(size == data.into_inner().len(), "{:?}", format);
+            }
+            // "he" is the end of "the"
+            let saved = encoded_size(&config, format, false).unwrap()
+                - encoded_size(&config, format, true).unwrap();
+            assert!(saved == 12, "{:?}", format);
+        }
+        assert!(encoded_size(&config, Format::Csv, false).is_err());
+
+        let macros = largest_macros(&config, Layout::Us);
+        let outputs: Vec<_> = macros
+            .iter()
+            .map(|m| (m.output.as_str(), m.bytes))
+            .collect();
+        assert!(outputs == [("the", 16), ("he", 12)]);
+    }
+}
@@ -0,0 +1,316 @@
+//! Frequency tables of letters, letter pairs and words, counted from text
+//! or, with the `corpora` feature, built in for English and source code, so
+//! layouts can be scored without a corpus of your own:
+//!
+//! ```ignore
+//! let text = corpus::BuiltIn::English.corpus().sample(10_000);
+//! let score = analysis::score(&chords, &text);
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::{Error, Result};
+
+/// How often each letter, pair of letters and word appears in some text.
+/// Letters and pairs are counted lower case and only within words, words
+/// as they're written.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Corpus {
+    pub letters: BTreeMap<char, usize>,
+    pub bigrams: BTreeMap<String, usize>,
+    pub words: BTreeMap<String, usize>,
+}
+
+/// Count the letters, pairs and words of `text`. Words are runs of letters,
+/// digits, `_` and `'`, without the quotes at either end.
+pub fn from_text(text: &str) -> Corpus {
+    let mut corpus = Corpus::default();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    for word in text.split(|c: char| !is_word(c)) {
+        let word = word.trim_matches('\'');
+        if !word.is_empty() {
+            corpus.add_word(word, 1);
+        }
+    }
+    corpus
+}
+
+/// Count the text of every file matching `pattern`, where `*` and `?` match
+/// within a file or directory name and `**` any number of directories, like
+/// `docs/**/*.md`
+pub fn from_files(pattern: &str) -> Result<Corpus> {
+    let paths = glob(pattern)?;
+    if paths.is_empty() {
+        return Err(Error::Invalid(format!("No files match {:?}", pattern)));
+    }
+    let mut corpus = Corpus::default();
+    for path in paths {
+        corpus.merge(&from_text(&std::fs::read_to_string(path)?));
+    }
+    Ok(corpus)
+}
+
+/// Read a table of `word count` lines, skipping empty ones and `#`
+/// comments. Letters and pairs are worked out from the words.
+pub fn from_words(table: &str) -> Result<Corpus> {
+    let mut corpus = Corpus::default();
+    for (i, line) in table.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (word, count) = line
+            .rsplit_once(char::is_whitespace)
+            .and_then(|(word, count)| Some((word.trim(), count.parse().ok()?)))
+            .ok_or_else(|| {
+                Error::Invalid(format!(
+                    "Line {} of the word table isn't a word and a count: {:?}",
+                    i + 1,
+                    line
+                ))
+            })?;
+        corpus.add_word(word, count);
+    }
+    Ok(corpus)
+}
+
+/// The corpora built in with the `corpora` feature
+#[cfg(feature = "corpora")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BuiltIn {
+    /// Common English words, counted per million so about half a million
+    /// words of text are behind the letters and pairs
+    English,
+    /// Common identifiers and keywords of Rust, Python and C code, comments
+    /// included
+    Code,
+}
+
+#[cfg(feature = "corpora")]
+impl BuiltIn {
+    pub fn corpus(self) -> Corpus {
+        let table = match self {
+            BuiltIn::English => include_str!("../corpora/english.txt"),
+            BuiltIn::Code => include_str!("../corpora/code.txt"),
+        };
+        from_words(table).expect("the built in tables are valid")
+    }
+}
+
+impl Corpus {
+    /// Count `word` `count` more times
+    pub fn add_word(&mut self, word: &str, count: usize) {
+        *self.words.entry(word.to_string()).or_default() += count;
+        let lower: Vec<char> = word.chars().flat_map(char::to_lowercase).collect();
+        for letter in lower.iter().filter(|c| c.is_alphabetic()) {
+            *self.letters.entry(*letter).or_default() += count;
+        }
+        for pair in lower
+            .windows(2)
+            .filter(|p| p.iter().all(|c| c.is_alphabetic()))
+        {
+            *self.bigrams.entry(pair.iter().collect()).or_default() += count;
+        }
+    }
+
+    /// Add the counts of another corpus
+    pub fn merge(&mut self, other: &Corpus) {
+        for (letter, count) in &other.letters {
+            *self.letters.entry(*letter).or_default() += count;
+        }
+        for (pair, count) in &other.bigrams {
+            *self.bigrams.entry(pair.clone()).or_default() += count;
+        }
+        for (word, count) in &other.words {
+            *self.words.entry(word.clone()).or_default() += count;
+        }
+    }
+
+    /// Words with their counts, the most common first
+    pub fn top_words(&self) -> Vec<(&str, usize)> {
+        let mut words: Vec<(&str, usize)> =
+            self.words.iter().map(|(w, c)| (w.as_str(), *c)).collect();
+        words.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        words
+    }
+
+    /// Text of about `len` words, each as often as in the corpus, for
+    /// [`analysis::score`](crate::analysis::score) and the optimizers.
+    /// Words are separated by spaces and mixed so no word follows itself
+    /// more than it has to.
+    pub fn sample(&self, len: usize) -> String {
+        let total: usize = self.words.values().sum();
+        if total == 0 {
+            return String::new();
+        }
+        let mut remaining: Vec<(&str, usize)> = self
+            .top_words()
+            .into_iter()
+            .map(|(word, count)| (word, (count * len + total / 2) / total))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+
+        let mut words = vec![];
+        while !remaining.is_empty() {
+            for (word, count) in &mut remaining {
+                words.push(*word);
+                *count -= 1;
+            }
+            remaining.retain(|(_, count)| *count > 0);
+        }
+        words.join(" ")
+    }
+}
+
+// Paths matching a pattern, sorted
+fn glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let (root, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (PathBuf::from("/"), rest),
+        None => (PathBuf::from("."), pattern),
+    };
+    let parts: Vec<&str> = rest.split('/').filter(|p| !p.is_empty()).collect();
+    let mut paths = vec![];
+    glob_in(&root, &parts, &mut paths)?;
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+fn glob_in(dir: &Path, parts: &[&str], paths: &mut Vec<PathBuf>) -> Result<()> {
+    let Some((part, rest)) = parts.split_first() else {
+        if dir.is_file() {
+            paths.push(dir.strip_prefix(".").unwrap_or(dir).to_path_buf());
+        }
+        return Ok(());
+    };
+    if !part.contains(['*', '?']) {
+        let path = dir.join(part);
+        if path.exists() {
+            glob_in(&path, rest, paths)?;
+        }
+        return Ok(());
+    }
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if *part == "**" {
+            // Zero directories here, one or more below. Symlinks aren't
+            // followed, one back up the tree would never end.
+            if entry.file_type()?.is_dir() {
+                glob_in(&path, parts, paths)?;
+            }
+        } else if matches(part, &name) {
+            glob_in(&path, rest, paths)?;
+        }
+    }
+    if *part == "**" {
+        glob_in(dir, rest, paths)?;
+    }
+    Ok(())
+}
+
+// Whether a file name matches a pattern with `*` and `?`
+fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // Where each prefix of the pattern can end in the name
+    let mut ends = vec![false; name.len() + 1];
+    ends[0] = true;
+    for c in pattern {
+        let mut next = vec![false; name.len() + 1];
+        for i in 0..=name.len() {
+            if !ends[i] {
+                continue;
+            }
+            match c {
+                '*' => next[i..].iter_mut().for_each(|end| *end = true),
+                '?' if i < name.len() => next[i + 1] = true,
+                c if i < name.len() && name[i] == c => next[i + 1] = true,
+                _ => (),
+            }
+        }
+        ends = next;
+    }
+    ends[name.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_text() {
+        let corpus = from_text("The cat's hat, the 'cat'.");
+        assert!(corpus.words["the"] == 1 && corpus.words["The"] == 1);
+        assert!(corpus.words["cat's"] == 1 && corpus.words["cat"] == 1);
+        assert!(corpus.letters[&'t'] == 5 && corpus.letters[&'a'] == 3);
+        assert!(corpus.bigrams["th"] == 2 && corpus.bigrams["at"] == 3);
+        // Pairs don't span the apostrophe or words
+        assert!(!corpus.bigrams.contains_key("ts") && !corpus.bigrams.contains_key("ec"));
+        // Ties go in the order of the words
+        assert!(corpus.top_words()[0] == ("The", 1));
+    }
+
+    #[test]
+    fn test_from_words() {
+        let corpus = from_words("# Counts\nthe 3\n\nof 2\n").unwrap();
+        assert!(corpus.letters[&'t'] == 3 && corpus.letters[&'o'] == 2);
+        assert!(corpus.bigrams["he"] == 3);
+        assert!(corpus.top_words() == [("the", 3), ("of", 2)]);
+        assert!(from_words("the").is_err());
+
+        let sample = corpus.sample(10);
+        assert!(sample.matches("the").count() == 6 && sample.matches("of").count() == 4);
+        assert!(sample.starts_with("the of the of"));
+        assert!(Corpus::default().sample(10).is_empty());
+    }
+
+    #[test]
+    fn test_from_files() {
+        let corpus = from_files("configs/*.csv").unwrap();
+        assert!(corpus.words.contains_key("Thumbs"));
+        assert!(from_files("src/**/corpus.rs")
+            .unwrap()
+            .words
+            .contains_key("glob"));
+        assert!(from_files("configs/*.none").is_err());
+
+        assert!(matches("*.md", "README.md") && !matches("*.md", "README.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_glob_symlink_loop() {
+        let dir = std::env::temp_dir().join(format!("twiddler-cfg-glob-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a/words.txt"), "loop").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("a/up")).unwrap();
+
+        let pattern = format!("{}/**/*.txt", dir.display());
+        assert!(glob(&pattern).unwrap() == [dir.join("a/words.txt")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches("v?.cfg", "v7.cfg") && matches("*", ""));
+    }
+
+    #[cfg(feature = "corpora")]
+    #[test]
+    fn test_built_in() {
+        let english = BuiltIn::English.corpus();
+        assert!(english.top_words()[0] == ("the", 56271));
+        assert!(english.letters[&'e'] > english.letters[&'k']);
+        let code = BuiltIn::Code.corpus();
+        assert!(code.words.contains_key("fn"));
+        // Nothing from vendored OpenSSL headers or encoding tables
+        assert!(!code.words.contains_key("sk") && !code.words.contains_key("LATIN"));
+    }
+}
@@ -1,113 +1,1046 @@
-use std::{
-    io::{Read, Seek, Write},
-};
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
 
 use crate::{
-    buttons::{self, ButtonState},
+    buttons::{self, ButtonState, Notation},
     hid,
+    layout::{Host, Layout},
+    output::{self, ChordOutput, OutputToken, ParseOptions},
+    Error, Result, Warning,
 };
 
-#[derive(Debug, serde::Deserialize, serde::Serialize,Clone)]
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Default, PartialEq, Eq)]
 pub struct Chord {
     #[serde(alias = "Thumbs")]
-    thumbs: Option<String>,
+    pub(crate) thumbs: Option<String>,
     #[serde(alias = " Fingers")] // Twiddler Tuner puts a space in the header name here lol
     #[serde(alias = "Fingers")]
-    fingers: Option<String>,
+    pub(crate) fingers: Option<String>,
     #[serde(alias = "Keyboard Output")]
-    output: String,
+    pub(crate) output: String,
+    /// Keymap the chord is in, the base one when empty, see
+    /// [`twiddler7::Config::layers`](crate::twiddler7::Config::layers)
+    #[serde(default, alias = "Layer", skip_serializing_if = "Option::is_none")]
+    pub(crate) layer: Option<u8>,
+    /// What the chord is for, like letters, punctuation, navigation or
+    /// macros, for grouping it in charts, stats and searches. Only CSV and
+    /// JSON files have room for it.
+    #[serde(default, alias = "Category", skip_serializing_if = "Option::is_none")]
+    pub(crate) category: Option<String>,
+    /// Columns besides the ones above, like Notes, by header name in the
+    /// order they were in
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "columns")]
+    pub(crate) extras: Vec<(String, String)>,
+    /// `#` lines before the header row, for the first chord in a file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) leading_comments: Vec<String>,
+    /// `#` lines before the chord's row, without the #
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) comments: Vec<String>,
+    /// `#` lines after the chord's row, for the last one in a file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) trailing_comments: Vec<String>,
 }
 
-pub fn parse<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chord>, Box<dyn std::error::Error>> {
-    let mut rdr = csv::Reader::from_reader(reader);
-    let result: Result<Vec<Chord>, csv::Error> = rdr.deserialize().collect();
-    match result {
-        Ok(chords) => Ok(chords),
-        Err(e) => Err(Box::new(e)),
+// Extra columns as a JSON object with its keys in order
+mod columns {
+    use serde::{de, ser::SerializeMap, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        columns: &[(String, String)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(columns.len()))?;
+        for (column, value) in columns {
+            map.serialize_entry(column, value)?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(String, String)>, D::Error> {
+        struct Columns;
+
+        impl<'de> de::Visitor<'de> for Columns {
+            type Value = Vec<(String, String)>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map of column names to values")
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut columns = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    columns.push(entry);
+                }
+                Ok(columns)
+            }
+        }
+
+        deserializer.deserialize_map(Columns)
     }
 }
 
-pub fn export<W: Write>(writer: &mut W, chords: &[Chord]) -> Result<(), Box<dyn std::error::Error>> {
-    let mut wtr = csv::Writer::from_writer(writer);
-    for chord in chords {
-        wtr.serialize(chord)?;
+const OUTPUT_COLUMNS: [&str; 2] = ["output", "Keyboard Output"];
+
+// Every header name a Chord field is read from
+const COLUMNS: [&str; 11] = [
+    "thumbs",
+    "Thumbs",
+    "fingers",
+    " Fingers",
+    "Fingers",
+    "output",
+    "Keyboard Output",
+    "layer",
+    "Layer",
+    "category",
+    "Category",
+];
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chord>> {
+    let chords: Vec<Chord> = parse_iter(reader).collect::<Result<_>>()?;
+    event!(debug, chords = chords.len(), "Read CSV config");
+    Ok(chords)
+}
+
+/// How [`parse_with`] reads a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Options {
+    /// `b'\t'` for TSV, or `b';'` for what Excel saves in many European
+    /// locales
+    pub delimiter: u8,
+    /// Without a header row the columns are the thumbs, fingers and output,
+    /// in that order
+    pub has_headers: bool,
+    /// Strip whitespace around every field
+    pub trim: bool,
+    pub output: ParseOptions,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            delimiter: b',',
+            has_headers: true,
+            trim: false,
+            output: ParseOptions::STRICT,
+        }
     }
-    wtr.flush()?;
-    Ok(())
 }
 
-impl Into<ButtonState> for Chord {
-    fn into(self) -> ButtonState {
-        let thumbs = self.thumbs.unwrap_or_default();
-        let fingers = self.fingers.unwrap_or_default();
-        buttons::parse_notation(thumbs, fingers)
+/// Read every row and check its buttons and output, see [`Options`] and
+/// [`ParseOptions`]. Errors and warnings give the CSV row of the chord. A
+/// lenient parse keeps rows with problems, unknown buttons are left out of
+/// the chord and unknown keys type nothing when it's converted the same way.
+pub fn parse_with<R: Read>(reader: R, options: Options) -> Result<(Vec<Chord>, Vec<Warning>)> {
+    let mut chords = vec![];
+    let mut warnings = vec![];
+    let first_row = if options.has_headers { 2 } else { 1 };
+    for (i, (_, chord)) in read_rows(reader, &options).enumerate() {
+        let chord = chord?;
+        let row = i + first_row;
+        if let Err(error) = ButtonState::try_from(&chord) {
+            options.output.problem(error.with_row(row), &mut warnings)?;
+        }
+        match chord.get_output_with(Layout::Us, options.output) {
+            Ok((_, chord_warnings)) => {
+                warnings.extend(chord_warnings.into_iter().map(|w| Warning {
+                    error: w.error.with_row(row),
+                }))
+            }
+            // Only unknown tags and keys are warnings, outputs the US layout
+            // can't type are left for the conversion to the host's
+            Err(Error::Unsupported(_)) => (),
+            Err(error) => return Err(error.with_row(row)),
+        }
+        chords.push(chord);
     }
+    Ok((chords, warnings))
 }
 
-impl Chord {
-    pub fn get_hid_pairs(&self) -> Vec<(u8, u8)> {
-        if self.output.len() == 1 {
-            match hid::keys_hid().get_by_right(&self.output) {
-                Some(key) => return vec![(0, *key)],
-                None => return vec![(0, 0)],
+/// Read chords one row at a time, a bad row doesn't stop the rows after it
+/// from being read. Columns besides the thumbs, fingers and output are kept
+/// in [`Chord::extras`], and `#` comment lines with the chord after them,
+/// in [`Chord::leading_comments`] of the first one for comments before the
+/// header, or in [`Chord::trailing_comments`] of the last one for comments
+/// at the end.
+pub fn parse_iter<R: Read>(reader: R) -> impl Iterator<Item = Result<Chord>> {
+    read_rows(reader, &Options::default()).map(|(_, chord)| chord)
+}
+
+/// Like [`parse`], each chord with the 1-based line of the file it starts
+/// on, for reporting problems where they are
+pub fn parse_lines<R: Read>(reader: R) -> Result<Vec<(usize, Chord)>> {
+    read_rows(reader, &Options::default())
+        .map(|(line, chord)| Ok((line, chord?)))
+        .collect()
+}
+
+/// Why a row was left out by [`parse_lenient`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    pub line: usize, // 1-based
+    /// 1-based character in the line where the problem is, for outputs
+    /// with a bad tag or key
+    pub column: Option<usize>,
+    pub problem: Problem,
+    pub reason: String,
+}
+
+/// What kind of problem a [`Diagnostic`] is, the same as the lint rule for
+/// it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Problem {
+    /// The row or its buttons can't be read
+    Unreadable,
+    /// A < in the output doesn't start a closed tag
+    MalformedTag,
+    /// The output names a key or tag that doesn't exist, or types something
+    /// the layout has no key for
+    UnknownKey,
+}
+
+/// Read every row that can be used, rows that can't be read or whose
+/// output can't be typed are reported instead of failing the whole file
+pub fn parse_lenient<R: Read>(reader: R) -> (Vec<Chord>, Vec<Diagnostic>) {
+    let (chords, diagnostics) = parse_lenient_lines(reader);
+    (
+        chords.into_iter().map(|(_, chord)| chord).collect(),
+        diagnostics,
+    )
+}
+
+/// Like [`parse_lenient`], each chord with the line it starts on as in
+/// [`parse_lines`]
+pub fn parse_lenient_lines<R: Read>(mut reader: R) -> (Vec<(usize, Chord)>, Vec<Diagnostic>) {
+    let mut data = vec![];
+    if let Err(e) = reader.read_to_end(&mut data) {
+        let diagnostic = Diagnostic {
+            line: 0,
+            column: None,
+            problem: Problem::Unreadable,
+            reason: e.to_string(),
+        };
+        return (vec![], vec![diagnostic]);
+    }
+    // For the columns, the rows are read from the bytes as they are
+    let text = String::from_utf8_lossy(&data);
+    let lines: Vec<&str> = text.lines().collect();
+
+    let mut chords = vec![];
+    let mut diagnostics = vec![];
+    for (line, chord) in read_rows(data.as_slice(), &Options::default()) {
+        let chord = match chord.and_then(|chord| ButtonState::try_from(&chord).map(|_| chord)) {
+            Ok(chord) => chord,
+            Err(e) => {
+                diagnostics.push(Diagnostic {
+                    line,
+                    column: None,
+                    problem: Problem::Unreadable,
+                    reason: e.to_string(),
+                });
+                continue;
             }
+        };
+        let (problem, reason, offset) = match chord.get_output() {
+            Ok(_) => {
+                chords.push((line, chord));
+                continue;
+            }
+            Err(Error::UnknownKey { name, column, .. }) => (
+                Problem::UnknownKey,
+                format!("{:?} isn't a known key", name),
+                Some(column),
+            ),
+            Err(Error::MalformedTag { tag, column, .. }) => (
+                Problem::MalformedTag,
+                format!("{:?} isn't a closed tag", tag),
+                Some(column),
+            ),
+            Err(e) => (Problem::UnknownKey, e.to_string(), None),
+        };
+        let row = line.checked_sub(1).and_then(|i| lines.get(i));
+        diagnostics.push(Diagnostic {
+            line,
+            column: offset
+                .zip(row)
+                .and_then(|(offset, row)| output_column(row, chord.output(), offset)),
+            problem,
+            reason,
+        });
+    }
+    (chords, diagnostics)
+}
+
+/// The 1-based character in a CSV row where byte `offset` of a chord's
+/// output is, when the output is in the row as it is rather than quoted
+/// with escapes
+pub(crate) fn output_column(row: &str, output: &str, offset: usize) -> Option<usize> {
+    let start = row.rfind(output)?;
+    Some(row.get(..start + offset)?.chars().count() + 1)
+}
+
+/// A row borrowed from the record it was read into, the thumbs, fingers
+/// and output columns without a String each. [`for_each_borrowed`] gives
+/// them, and every [`Chord`] read from a file is made from one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+pub struct ChordRef<'a> {
+    #[serde(alias = "Thumbs")]
+    pub thumbs: Option<&'a str>,
+    #[serde(alias = " Fingers", alias = "Fingers")]
+    pub fingers: Option<&'a str>,
+    #[serde(alias = "Keyboard Output")]
+    pub output: &'a str,
+    #[serde(default, alias = "Layer")]
+    pub layer: Option<u8>,
+    #[serde(default, alias = "Category")]
+    pub category: Option<&'a str>,
+}
+
+impl ChordRef<'_> {
+    pub fn buttons(&self) -> Result<ButtonState> {
+        buttons::try_parse_notation(
+            self.thumbs.unwrap_or_default(),
+            self.fingers.unwrap_or_default(),
+        )
+    }
+
+    pub fn layer(&self) -> u8 {
+        self.layer.unwrap_or_default()
+    }
+
+    pub fn to_chord(&self) -> Chord {
+        Chord {
+            thumbs: self.thumbs.map(str::to_string),
+            fingers: self.fingers.map(str::to_string),
+            output: self.output.to_string(),
+            layer: self.layer,
+            category: self.category.map(str::to_string),
+            ..Default::default()
         }
+    }
+}
 
-        let mut hid_pairs: Vec<(u8, u8)> = Vec::new();
-        let mut current_modifiers: u8 = 0;
+/// Calls `f` with every row of a CSV file with headers, each borrowed from
+/// one record that is reused for the next row. For going over big layouts
+/// where [`parse`] would allocate for every field of every row. Files are
+/// read like [`parse`] reads them, comments and columns besides the ones in
+/// [`ChordRef`] are skipped. The first error from reading a row or from `f`
+/// stops the rest.
+pub fn for_each_borrowed<R: Read>(
+    reader: R,
+    mut f: impl FnMut(ChordRef<'_>) -> Result<()>,
+) -> Result<()> {
+    let mut rows = read_rows(reader, &Options::default());
+    while let Some((read, result)) = rows.read_record() {
+        result?;
+        f(rows.row()?)?;
+        rows.rdr.get_mut().take_comments(read);
+    }
+    Ok(())
+}
 
-        let mut reading_tag = false;
-        let mut tag_start = 0;
-        let mut closing = false;
+// Every row with the 1-based line it starts on, read from `reader` as
+// they're asked for
+fn read_rows<R: Read>(reader: R, options: &Options) -> Rows<R> {
+    let rdr = csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_headers)
+        .trim(match options.trim {
+            true => csv::Trim::All,
+            false => csv::Trim::None,
+        })
+        .flexible(!options.has_headers)
+        .from_reader(Uncommented::new(reader));
+    Rows {
+        rdr,
+        headers: (!options.has_headers)
+            .then(|| csv::StringRecord::from(vec!["thumbs", "fingers", "output"])),
+        options: *options,
+        record: csv::StringRecord::new(),
+        ahead: None,
+        first: options.has_headers,
+        done: false,
+    }
+}
 
-        for (i, c) in self.output.chars().enumerate() {
-            match (c, reading_tag) {
-                ('<', false) => {
-                    reading_tag = true;
-                    tag_start = i;
+struct Rows<R> {
+    rdr: csv::Reader<Uncommented<R>>,
+    // Read with the first row when the file has them
+    headers: Option<csv::StringRecord>,
+    options: Options,
+    record: csv::StringRecord,
+    // The row after the one last returned, to know which one is last
+    ahead: Option<(usize, Result<Chord>)>,
+    // No chord has been read yet to take the comments before the header
+    first: bool,
+    done: bool,
+}
+
+impl<R: Read> Rows<R> {
+    // Reads the next row into `record`, with the line of what `Uncommented`
+    // handed on that it starts on
+    fn read_record(&mut self) -> Option<(usize, Result<()>)> {
+        if self.done {
+            return None;
+        }
+        let headers = match &mut self.headers {
+            Some(headers) => headers,
+            None => match self.rdr.headers().cloned() {
+                Ok(read) if read.iter().any(|header| OUTPUT_COLUMNS.contains(&header)) => {
+                    self.headers.insert(read)
+                }
+                Ok(read) => {
+                    self.done = true;
+                    return Some((1, Err(no_output_column(&read, &self.options))));
                 }
-                ('<', true) => {
-                    tag_start = i;
-                    hid_pairs.push((current_modifiers, 0x64));
+                Err(e) => {
+                    self.done = true;
+                    return Some((1, Err(e.into())));
                 }
-                ('>', true) => {
-                    reading_tag = false;
-                    let tag_contents = &self.output[tag_start + 1..i];
-
-                    let modifier = match tag_contents {
-                        "L-Ctrl" => 0x01,
-                        "L-Shift" => 0x02,
-                        "L-Alt" => 0x04,
-                        "L-Gui" => 0x08,
-                        "R-Ctrl" => 0x10,
-                        "R-Shift" => 0x20,
-                        "R-Alt" => 0x40,
-                        "R-Gui" => 0x80,
-                        _ => 0,
-                    };
-
-                    if closing {
-                        current_modifiers &= !modifier;
-                    } else {
-                        current_modifiers |= modifier;
+            },
+        };
+        match self.rdr.read_record(&mut self.record) {
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Ok(true) => {
+                // Columns past the output of a headerless file are named by
+                // their number
+                while headers.len() < self.record.len() {
+                    headers.push_field(&(headers.len() + 1).to_string());
+                }
+                Some((
+                    self.record.position().map_or(0, |p| p.line() as usize),
+                    Ok(()),
+                ))
+            }
+            Err(e) => {
+                self.done = e.is_io_error();
+                Some((e.position().map_or(0, |p| p.line() as usize), Err(e.into())))
+            }
+        }
+    }
+
+    // The row last read into `record`
+    fn row(&self) -> Result<ChordRef<'_>> {
+        Ok(self.record.deserialize(self.headers.as_ref())?)
+    }
+
+    fn read(&mut self) -> Option<(usize, Result<Chord>)> {
+        let (read, result) = self.read_record()?;
+        let chord = result.and_then(|()| {
+            let mut chord = self.row()?.to_chord();
+            for (header, value) in self.headers.iter().flatten().zip(&self.record) {
+                if !COLUMNS.contains(&header) {
+                    chord.set_extra(header, value);
+                }
+            }
+            Ok(chord)
+        });
+        let uncommented = self.rdr.get_mut();
+        let line = uncommented.line_in_file(read);
+        let chord = chord.map(|mut chord| {
+            if std::mem::take(&mut self.first) {
+                chord.leading_comments = uncommented.take_comments(1);
+            }
+            chord.comments = uncommented.take_comments(read);
+            chord
+        });
+        Some((line, chord))
+    }
+}
+
+impl<R: Read> Iterator for Rows<R> {
+    type Item = (usize, Result<Chord>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut row = match self.ahead.take() {
+            Some(row) => row,
+            None => self.read()?,
+        };
+        self.ahead = self.read();
+        // Comments after the last row stay after it
+        if let (None, (_, Ok(last))) = (&self.ahead, &mut row) {
+            last.trailing_comments = self.rdr.get_mut().take_comments(usize::MAX);
+        }
+        Some(row)
+    }
+}
+
+// Rather than every row failing for its missing output, which is what a
+// file with some other delimiter looks like
+fn no_output_column(headers: &csv::StringRecord, options: &Options) -> Error {
+    let delimiter = [b',', b'\t', b';']
+        .into_iter()
+        .filter(|delimiter| *delimiter != options.delimiter)
+        .find(|delimiter| {
+            headers
+                .iter()
+                .any(|header| header.as_bytes().contains(delimiter))
+        });
+    match delimiter {
+        Some(delimiter) => Error::Invalid(format!(
+            "The header has no output column, the file looks separated by {:?} instead",
+            delimiter as char
+        )),
+        None => Error::Invalid(format!(
+            "The header has no output column, expected one of {}",
+            OUTPUT_COLUMNS.join(", ")
+        )),
+    }
+}
+
+// Reads a CSV file as UTF-8 without its `#` lines outside quoted fields, a
+// line at a time. Files from Excel can start with a byte order mark, be
+// UTF-16 when saved as Unicode text, or be Windows-1252 otherwise. Lines
+// that aren't UTF-8 are read as Windows-1252 since every byte is a
+// character in it.
+struct Uncommented<R> {
+    reader: BufReader<R>,
+    encoding: Option<Encoding>,
+    line: Vec<u8>,
+    at: usize,
+    // Lines handed on so far
+    kept: usize,
+    quoted: bool,
+    // Each comment with the line handed on after it, counting from 1
+    comments: Vec<(usize, String)>,
+    // Comments before line `kept` that were taken out of `comments`
+    taken: usize,
+    // Empty lines, which the CSV reader skips without counting, with the
+    // line handed on after each
+    empty: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl<R: Read> Uncommented<R> {
+    fn new(reader: R) -> Self {
+        Uncommented {
+            reader: BufReader::new(reader),
+            encoding: None,
+            line: vec![],
+            at: 0,
+            kept: 0,
+            quoted: false,
+            comments: vec![],
+            taken: 0,
+            empty: vec![],
+        }
+    }
+
+    // The line in the file of line `kept` of what was handed on
+    fn line_in_file(&self, kept: usize) -> usize {
+        let before = self.comments.iter().filter(|(l, _)| *l <= kept).count();
+        let empty = self.empty.iter().filter(|l| **l <= kept).count();
+        kept + self.taken + before + empty
+    }
+
+    // The comments before line `kept` of what was handed on
+    fn take_comments(&mut self, kept: usize) -> Vec<String> {
+        let before = self.comments.iter().take_while(|(l, _)| *l <= kept).count();
+        self.taken += before;
+        self.comments.drain(..before).map(|(_, c)| c).collect()
+    }
+
+    // Reads the next line into `line`, false at the end of the file
+    fn read_line(&mut self) -> io::Result<bool> {
+        let encoding = match self.encoding {
+            Some(encoding) => encoding,
+            None => {
+                let (encoding, bom) = match self.reader.fill_buf()? {
+                    [0xEF, 0xBB, 0xBF, ..] => (Encoding::Utf8, 3),
+                    [0xFF, 0xFE, ..] => (Encoding::Utf16Le, 2),
+                    [0xFE, 0xFF, ..] => (Encoding::Utf16Be, 2),
+                    _ => (Encoding::Utf8, 0),
+                };
+                self.reader.consume(bom);
+                *self.encoding.insert(encoding)
+            }
+        };
+        self.line.clear();
+        self.at = 0;
+        match encoding {
+            Encoding::Utf8 => {
+                self.reader.read_until(b'\n', &mut self.line)?;
+                if std::str::from_utf8(&self.line).is_err() {
+                    self.line = windows_1252(&self.line).into_bytes();
+                }
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut units = vec![];
+                let mut unit = [0; 2];
+                while units.last() != Some(&u16::from(b'\n')) {
+                    match self.reader.read_exact(&mut unit) {
+                        Ok(()) if encoding == Encoding::Utf16Le => {
+                            units.push(u16::from_le_bytes(unit))
+                        }
+                        Ok(()) => units.push(u16::from_be_bytes(unit)),
+                        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                        Err(e) => return Err(e),
                     }
                 }
-                ('>', false) => {
-                    hid_pairs.push((current_modifiers, 0x64));
+                self.line = char::decode_utf16(units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect::<String>()
+                    .into_bytes();
+            }
+        }
+        Ok(!self.line.is_empty())
+    }
+}
+
+impl<R: Read> Read for Uncommented<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.at == self.line.len() {
+            if !self.read_line()? {
+                return Ok(0);
+            }
+            if !self.quoted && self.line.starts_with(b"#") {
+                let text = String::from_utf8_lossy(&self.line[1..]);
+                let text = text.trim_end_matches(['\r', '\n']).to_string();
+                self.comments.push((self.kept + 1, text));
+                self.line.clear();
+                continue;
+            }
+            if !self.quoted && matches!(&self.line[..], b"\n" | b"\r\n") {
+                self.empty.push(self.kept + 1);
+                self.line.clear();
+                continue;
+            }
+            self.quoted ^= self.line.iter().filter(|b| **b == b'"').count() % 2 == 1;
+            self.kept += 1;
+        }
+        let n = buf.len().min(self.line.len() - self.at);
+        buf[..n].copy_from_slice(&self.line[self.at..self.at + n]);
+        self.at += n;
+        Ok(n)
+    }
+}
+
+fn windows_1252(data: &[u8]) -> String {
+    data.iter()
+        .map(|&b| match b {
+            0x80..=0x9F => WINDOWS_1252[b as usize - 0x80],
+            _ => b as char,
+        })
+        .collect()
+}
+
+// Windows-1252 for 0x80 to 0x9F, where it differs from Latin-1. The unused
+// bytes are kept as the control characters Latin-1 has there.
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+/// Write the chords with a header row, any extra columns after the output
+/// in the order they first come up and comments around the chord they came
+/// with
+pub fn export<W: Write>(writer: &mut W, chords: &[Chord]) -> Result<()> {
+    let mut extras: Vec<&str> = vec![];
+    for (column, _) in chords.iter().flat_map(|chord| &chord.extras) {
+        if !extras.contains(&column.as_str()) {
+            extras.push(column);
+        }
+    }
+
+    // A writer for each stretch of rows between comments, comments are
+    // written around it as they are
+    let row = |writer: &mut W, record: &[&str]| -> Result<()> {
+        let mut wtr = csv::Writer::from_writer(writer);
+        wtr.write_record(record)?;
+        wtr.flush()?;
+        Ok(())
+    };
+    // Only layouts with more than one layer get the column, and only ones
+    // with categories that one
+    let layers = chords.iter().any(|chord| chord.layer() != 0);
+    let categories = chords.iter().any(|chord| chord.category.is_some());
+    if let Some(first) = chords.first() {
+        for comment in &first.leading_comments {
+            writeln!(writer, "#{}", comment)?;
+        }
+        let mut header = vec!["thumbs", "fingers", "output"];
+        if layers {
+            header.push("layer");
+        }
+        if categories {
+            header.push("category");
+        }
+        header.extend(&extras);
+        row(writer, &header)?;
+    }
+    for chord in chords {
+        for comment in &chord.comments {
+            writeln!(writer, "#{}", comment)?;
+        }
+        let layer = chord.layer.map(|layer| layer.to_string());
+        let mut record = vec![chord.thumbs(), chord.fingers(), chord.output()];
+        if layers {
+            record.push(layer.as_deref().unwrap_or_default());
+        }
+        if categories {
+            record.push(chord.category().unwrap_or_default());
+        }
+        record.extend(
+            extras
+                .iter()
+                .map(|column| chord.extra(column).unwrap_or_default()),
+        );
+        row(writer, &record)?;
+        for comment in &chord.trailing_comments {
+            writeln!(writer, "#{}", comment)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrite the thumbs and fingers of every chord with
+/// [`buttons::canonicalize`], or in `notation` if given, and the tags of
+/// its output with [`output::normalize`], returning how many changed.
+/// Errors give the CSV row of the chord.
+pub fn canonicalize(chords: &mut [Chord], notation: Option<Notation>) -> Result<usize> {
+    let mut changed = 0;
+    for (i, chord) in chords.iter_mut().enumerate() {
+        let (thumbs, fingers) = buttons::canonicalize(chord.thumbs(), chord.fingers())
+            .and_then(|(thumbs, fingers)| match notation {
+                Some(notation) => {
+                    buttons::try_parse_notation(&thumbs, &fingers)?.to_notation_as(notation)
                 }
-                ('/', true) => {
-                    closing = false;
+                None => Ok((thumbs, fingers)),
+            })
+            .map_err(|e| e.with_row(i + 2))?;
+        let output = output::normalize(chord.output());
+        if thumbs != chord.thumbs() || fingers != chord.fingers() || output != chord.output() {
+            chord.set_thumbs(thumbs);
+            chord.set_fingers(fingers);
+            chord.set_output(output);
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+impl TryFrom<&Chord> for ButtonState {
+    type Error = Error;
+
+    fn try_from(chord: &Chord) -> Result<Self> {
+        buttons::try_parse_notation(chord.thumbs(), chord.fingers())
+    }
+}
+
+impl TryFrom<Chord> for ButtonState {
+    type Error = Error;
+
+    fn try_from(chord: Chord) -> Result<Self> {
+        ButtonState::try_from(&chord)
+    }
+}
+
+/// Chords of at least one button in layer 0 to 2 whose outputs parse on the
+/// US layout: text, named keys, modifiers around text and delays, or a
+/// mouse, sticky or layer chord
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Chord {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut buttons = ButtonState::arbitrary(u)?;
+        if buttons.is_empty() {
+            buttons = ButtonState::F1L;
+        }
+
+        let mut output = String::new();
+        match u.int_in_range(0..=9)? {
+            0 => {
+                let (name, _) = u.choose(&hid::MOUSE_BUTTONS)?;
+                output = format!("<{}>", name);
+            }
+            1 => output = format!("<Sticky:{}>", u.choose(&hid::MODIFIER_TAGS)?),
+            2 => output = format!("<Layer:{}>", u.int_in_range(0..=2)?),
+            _ => {
+                for _ in 0..u.int_in_range(1..=4)? {
+                    match u.int_in_range(0..=5)? {
+                        0 => {
+                            let code = u.int_in_range(0x28..=0xA4)?;
+                            let name = hid::key_name(code).unwrap_or("Tab");
+                            output.push_str(&format!("<{}>", name));
+                        }
+                        1 => {
+                            let tag = u.choose(&hid::MODIFIER_TAGS)?;
+                            let c = char::from(u.int_in_range(b'a'..=b'z')?);
+                            output.push_str(&format!("<{tag}>{c}</{tag}>"));
+                        }
+                        2 => output.push_str(&format!("<Delay:{}>", u16::arbitrary(u)?)),
+                        _ => {
+                            let c = char::from(u.int_in_range(b' '..=b'~')?);
+                            if matches!(c, '<' | '>' | '\\') {
+                                output.push('\\');
+                            }
+                            output.push(c);
+                        }
+                    }
                 }
-                ('/', false) => hid_pairs.push((current_modifiers, 0x38)),
-                (_, false) => match hid::keys_hid().get_by_right(&self.output) {
-                    Some(key) => hid_pairs.push((current_modifiers, *key)),
-                    None => {}
-                },
-                (_, true) => {}
             }
         }
 
-        hid_pairs
+        let mut chord = Chord::from_buttons(&buttons, output);
+        chord.set_layer(u.int_in_range(0..=2)?);
+        Ok(chord)
+    }
+}
+
+impl ButtonState {
+    /// Skips over anything in the thumbs and fingers columns that isn't a
+    /// button instead of failing
+    pub fn lossy(chord: &Chord) -> Self {
+        buttons::parse_notation(chord.thumbs().to_string(), chord.fingers().to_string())
+    }
+}
+
+impl Chord {
+    /// A chord in the CSV notation, e.g. `Chord::new("1", "1R 2M", "the ")`
+    pub fn new(
+        thumbs: impl Into<String>,
+        fingers: impl Into<String>,
+        output: impl Into<String>,
+    ) -> Self {
+        Self {
+            thumbs: Some(thumbs.into()),
+            fingers: Some(fingers.into()),
+            output: output.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_buttons(buttons: &ButtonState, output: impl Into<String>) -> Self {
+        let (thumbs, fingers) = buttons.to_notation();
+        Self::new(thumbs, fingers, output)
+    }
+
+    pub fn thumbs(&self) -> &str {
+        self.thumbs.as_deref().unwrap_or_default()
+    }
+
+    pub fn set_thumbs(&mut self, thumbs: impl Into<String>) {
+        self.thumbs = Some(thumbs.into());
+    }
+
+    pub fn fingers(&self) -> &str {
+        self.fingers.as_deref().unwrap_or_default()
+    }
+
+    pub fn set_fingers(&mut self, fingers: impl Into<String>) {
+        self.fingers = Some(fingers.into());
+    }
+
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    pub fn set_output(&mut self, output: impl Into<String>) {
+        self.output = output.into();
+    }
+
+    /// 0 for the base layer
+    pub fn layer(&self) -> u8 {
+        self.layer.unwrap_or_default()
+    }
+
+    pub fn set_layer(&mut self, layer: u8) {
+        self.layer = (layer != 0).then_some(layer);
+    }
+
+    pub fn category(&self) -> Option<&str> {
+        self.category.as_deref()
+    }
+
+    /// Empty to leave the chord without a category
+    pub fn set_category(&mut self, category: impl Into<String>) {
+        let category = category.into();
+        self.category = (!category.is_empty()).then_some(category);
+    }
+
+    /// Every column besides the thumbs, fingers and output, with its header
+    /// name, in the order they were read or set
+    pub fn extras(&self) -> &[(String, String)] {
+        &self.extras
+    }
+
+    pub fn extra(&self, column: &str) -> Option<&str> {
+        self.extras
+            .iter()
+            .find(|(name, _)| name == column)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_extra(&mut self, column: impl Into<String>, value: impl Into<String>) {
+        let (column, value) = (column.into(), value.into());
+        match self.extras.iter_mut().find(|(name, _)| *name == column) {
+            Some((_, old)) => *old = value,
+            None => self.extras.push((column, value)),
+        }
+    }
+
+    /// Comment lines before the header row, only the first chord of a file
+    /// has them
+    pub fn leading_comments(&self) -> &[String] {
+        &self.leading_comments
+    }
+
+    pub fn set_leading_comments(&mut self, comments: Vec<String>) {
+        self.leading_comments = comments;
+    }
+
+    /// Comment lines before the chord, without the #
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    pub fn set_comments(&mut self, comments: Vec<String>) {
+        self.comments = comments;
+    }
+
+    /// Comment lines after the chord, only the last one of a file has them
+    pub fn trailing_comments(&self) -> &[String] {
+        &self.trailing_comments
+    }
+
+    pub fn set_trailing_comments(&mut self, comments: Vec<String>) {
+        self.trailing_comments = comments;
+    }
+
+    /// The buttons with anything unknown left out, `ButtonState::try_from`
+    /// checks them instead
+    pub fn buttons(&self) -> ButtonState {
+        ButtonState::lossy(self)
+    }
+
+    pub fn set_buttons(&mut self, buttons: &ButtonState) {
+        let (thumbs, fingers) = buttons.to_notation();
+        self.thumbs = Some(thumbs);
+        self.fingers = Some(fingers);
+    }
+
+    /// Mouse buttons clicked by outputs made up only of mouse tags,
+    /// e.g. <MouseLeft> or <MouseLeft><MouseRight>
+    pub fn get_mouse_buttons(&self) -> Option<u8> {
+        let mut buttons = 0;
+        let mut rest = self.output.as_str();
+        while !rest.is_empty() {
+            let (name, after) = rest.strip_prefix('<')?.split_once('>')?;
+            let (_, bit) = hid::MOUSE_BUTTONS
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))?;
+            buttons |= bit;
+            rest = after;
+        }
+
+        (buttons != 0).then_some(buttons)
+    }
+
+    /// Modifiers held for the next chord by outputs made up only of sticky
+    /// tags, e.g. <Sticky:L-Shift> or <Sticky:L-Ctrl><Sticky:L-Alt>
+    pub fn get_sticky_modifiers(&self) -> Option<u8> {
+        let mut modifiers = 0;
+        let mut rest = self.output.as_str();
+        while !rest.is_empty() {
+            let tag = rest.strip_prefix('<')?;
+            let (name, after) = output::strip_tag_prefix(tag, "Sticky:")?.split_once('>')?;
+            modifiers |= hid::modifier_tag(name)?;
+            rest = after;
+        }
+
+        (modifiers != 0).then_some(modifiers)
+    }
+
+    /// The layer switched to by an output of just a layer tag, e.g.
+    /// <Layer:1>
+    pub fn get_layer_switch(&self) -> Option<u8> {
+        let tag = self.output.strip_prefix('<')?.strip_suffix('>')?;
+        let layer = output::strip_tag_prefix(tag, "Layer:")?;
+        layer.parse().ok().filter(|layer| *layer < u8::MAX)
+    }
+
+    /// Modifier-only outputs like <L-Shift>, and sticky ones, are the
+    /// modifiers with key code 0
+    pub fn get_hid_pairs(&self) -> Result<Vec<(u8, u8)>> {
+        if let Some(modifiers) = self.get_sticky_modifiers() {
+            return Ok(vec![(modifiers, 0)]);
+        }
+        output::to_hid_pairs(&self.tokens()?)
+    }
+
+    /// What the chord does, mouse buttons, sticky modifiers, a layer switch
+    /// or a macro of keys and delays
+    pub fn get_output(&self) -> Result<ChordOutput> {
+        self.get_output_in(Layout::Us)
+    }
+
+    /// Like [`Chord::get_output`] with the key codes for `host`, see
+    /// [`output::to_macro_in`]
+    pub fn get_output_in(&self, host: impl Into<Host>) -> Result<ChordOutput> {
+        self.get_output_with(host, ParseOptions::STRICT)
+            .map(|(output, _)| output)
+    }
+
+    /// Like [`Chord::get_output_in`], a lenient parse leaves out the tags
+    /// and keys it doesn't know and returns them as warnings
+    pub fn get_output_with(
+        &self,
+        host: impl Into<Host>,
+        options: ParseOptions,
+    ) -> Result<(ChordOutput, Vec<Warning>)> {
+        let host = host.into();
+        if let Some(modifiers) = self.get_sticky_modifiers() {
+            return Ok((ChordOutput::Sticky(host.modifiers(modifiers)), vec![]));
+        }
+        if let Some(layer) = self.get_layer_switch() {
+            return Ok((ChordOutput::SwitchLayer(layer), vec![]));
+        }
+        match self.get_mouse_buttons() {
+            Some(buttons) => Ok((ChordOutput::Mouse(buttons), vec![])),
+            None => {
+                let (tokens, warnings) = self.tokens_with(options)?;
+                let steps = output::to_macro_in(&tokens, host)?;
+                Ok((ChordOutput::Macro(steps), warnings))
+            }
+        }
+    }
+
+    fn tokens(&self) -> Result<Vec<OutputToken>> {
+        self.tokens_with(ParseOptions::STRICT)
+            .map(|(tokens, _)| tokens)
+    }
+
+    fn tokens_with(&self, options: ParseOptions) -> Result<(Vec<OutputToken>, Vec<Warning>)> {
+        // A lone character is always literal, Tuner writes "<" and ">" that way
+        let mut chars = self.output.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            if !c.is_control() || hid::char_to_hid(c).is_some() {
+                return Ok((vec![OutputToken::Text(c.to_string())], vec![]));
+            }
+            let mut warnings = vec![];
+            let error = Error::UnknownKey {
+                name: c.to_string(),
+                row: None,
+                column: 0,
+            };
+            options.problem(error, &mut warnings)?;
+            return Ok((vec![], warnings));
+        }
+
+        output::parse_with(&self.output, options)
     }
 }
 
@@ -122,12 +1055,119 @@ mod tests {
         let mut cursor = Cursor::new(data);
         let chords = parse(&mut cursor).unwrap();
 
-
-
         assert_eq!(chords.len(), 1);
         assert_eq!(chords[0].output, "<L-Ctrl>F");
     }
 
+    #[test]
+    fn test_parse_iter() {
+        let data = "Thumbs,Fingers,Keyboard Output\n,1L,a\n,1M\n,1R,c\n";
+        let chords: Vec<_> = parse_iter(data.as_bytes()).collect();
+
+        assert!(chords.len() == 3);
+        assert!(chords[0].as_ref().unwrap().output == "a");
+        assert!(matches!(chords[1], Err(Error::Csv(_))));
+        assert!(chords[2].as_ref().unwrap().output == "c");
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let data =
+            "Thumbs,Fingers,Keyboard Output\n,1L,a\n,1M\n,1R,<Nope>\n,2L,\"two\nlines\"\n,2M,b\n";
+        let (chords, diagnostics) = parse_lenient(data.as_bytes());
+        assert!(chords.len() == 3);
+        assert!(chords[2].output == "b");
+        assert!(diagnostics.len() == 2);
+        assert!(diagnostics[0].line == 3);
+        assert!(diagnostics[1].line == 4);
+        assert!(diagnostics[1].reason.contains("Nope"));
+        assert!(diagnostics[0].problem == Problem::Unreadable);
+        assert!(diagnostics[1].problem == Problem::UnknownKey);
+        // The column is in the line, not the output
+        assert!(diagnostics[0].column.is_none() && diagnostics[1].column == Some(6));
+        assert!(!diagnostics[1].reason.contains("column"));
+
+        // The quoted output takes two lines
+        let (chords, _) = parse_lenient_lines(data.as_bytes());
+        let lines: Vec<usize> = chords.iter().map(|(line, _)| *line).collect();
+        assert!(lines == [2, 5, 7]);
+        let lines: Vec<usize> =
+            parse_lines("# name: x\nThumbs,Fingers,Keyboard Output\n,1L,a\n".as_bytes())
+                .unwrap()
+                .iter()
+                .map(|(line, _)| *line)
+                .collect();
+        assert!(lines == [3]);
+        let spaced = "Thumbs,Fingers,Keyboard Output\n,1L,a\n\n\r\n,1M,\"b\n\nc\"\n,2L,d\n";
+        let lines: Vec<usize> = parse_lines(spaced.as_bytes())
+            .unwrap()
+            .iter()
+            .map(|(line, _)| *line)
+            .collect();
+        assert!(lines == [2, 5, 8]);
+        assert!(parse_lines(data.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_lines_after_skipped_row() {
+        let data = "Thumbs,Fingers,Keyboard Output\n,1L,a\n,1M,<Nope>\n,2L,b\n,2L,c\n";
+        let (rows, diagnostics) = parse_lenient_lines(data.as_bytes());
+        assert!(diagnostics.len() == 1 && diagnostics[0].line == 3);
+        let (lines, chords): (Vec<usize>, Vec<Chord>) = rows.into_iter().unzip();
+        // The conflict is on lines 4 and 5, not the index plus the header
+        let conflicts = crate::validate::find_conflicts(&chords);
+        match &conflicts[..] {
+            [crate::validate::Conflict::SameButtons { chords, .. }] => {
+                let conflicting: Vec<usize> = chords.iter().map(|&i| lines[i]).collect();
+                assert!(conflicting == [4, 5]);
+            }
+            _ => panic!("expected one conflict, got {:?}", conflicts),
+        }
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut chord = Chord::new("1", "1R 2M", "the ");
+        assert!(chord.thumbs() == "1");
+        assert!(chord.fingers() == "1R 2M");
+        assert!(chord.output() == "the ");
+        assert!(chord.buttons() == "T1 1R 2M".parse().unwrap());
+
+        chord.set_buttons(&"T4 0M".parse().unwrap());
+        chord.set_output("<Tab>");
+        assert!(chord.thumbs() == "4");
+        assert!(chord.fingers() == "0M");
+        assert!(chord.output() == "<Tab>");
+
+        let chord = Chord::from_buttons(&chord.buttons(), "a");
+        assert!(chord.fingers() == "0M");
+        assert!(chord.get_hid_pairs().unwrap() == vec![(0, 0x04)]);
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let mut chords = vec![
+            Chord::new("SN", "L000", "a"),
+            Chord::new("1", "1R", "b"),
+            Chord::new("", "2M 1L", "c"),
+        ];
+        assert!(canonicalize(&mut chords, None).unwrap() == 2);
+        assert!(chords[0].thumbs() == "NS");
+        assert!(chords[2].fingers() == "1L 2M");
+
+        assert!(canonicalize(&mut chords, Some(Notation::Mcc)).unwrap() == 2);
+        assert!(chords[1].thumbs() == "N" && chords[1].fingers() == "R000");
+        assert!(chords[2].fingers() == "LM00");
+
+        let mut chords = vec![Chord::new("", "1L", "<ctrl>c</LCTRL>")];
+        assert!(canonicalize(&mut chords, None).unwrap() == 1);
+        assert!(chords[0].output() == "<L-Ctrl>c</L-Ctrl>");
+
+        let mut bad = vec![Chord::new("", "1L", "a"), Chord::new("", "1X", "b")];
+        let err = canonicalize(&mut bad, None).unwrap_err();
+        assert!(matches!(err, Error::BadNotation { row: Some(3), .. }));
+    }
+
     #[test]
     fn test_export() {
         let chords = vec![
@@ -135,11 +1175,13 @@ mod tests {
                 thumbs: Some("T1".to_string()),
                 fingers: Some("F1".to_string()),
                 output: "<L-Ctrl>F".to_string(),
+                ..Default::default()
             },
             Chord {
                 thumbs: Some("T2".to_string()),
                 fingers: Some("F2".to_string()),
                 output: "<R-Shift>A".to_string(),
+                ..Default::default()
             },
         ];
 
@@ -147,11 +1189,407 @@ mod tests {
         export(&mut buffer, &chords).unwrap();
         let result = String::from_utf8(buffer).unwrap();
 
-
-
         assert!(result.contains("T1,F1,<L-Ctrl>F"));
         assert!(result.contains("T2,F2,<R-Shift>A"));
     }
-}
 
+    #[test]
+    fn test_string_output() {
+        let chord = Chord {
+            thumbs: None,
+            fingers: Some("1L".to_string()),
+            output: "the".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            chord.get_hid_pairs().unwrap(),
+            vec![(0, 0x17), (0, 0x0B), (0, 0x08)]
+        );
+
+        let chord = Chord {
+            thumbs: None,
+            fingers: Some("1L".to_string()),
+            output: "Hi, you!".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            chord.get_hid_pairs().unwrap(),
+            vec![
+                (0x02, 0x0B),
+                (0, 0x0C),
+                (0, 0x36),
+                (0, 0x2C),
+                (0, 0x1C),
+                (0, 0x12),
+                (0, 0x18),
+                (0x02, 0x1E),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_named_keys() {
+        let chord = Chord {
+            thumbs: None,
+            fingers: Some("1L".to_string()),
+            output: "<Esc>:wq<Enter>".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            chord.get_hid_pairs().unwrap(),
+            vec![(0, 0x29), (0x02, 0x33), (0, 0x1A), (0, 0x14), (0, 0x28)]
+        );
+
+        let chord = Chord {
+            thumbs: None,
+            fingers: Some("1L".to_string()),
+            output: "<L-Alt><F4></L-Alt><Tab><UpArrow>".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            chord.get_hid_pairs().unwrap(),
+            vec![(0x04, 0x3D), (0, 0x2B), (0, 0x52)]
+        );
+
+        let chord = Chord {
+            thumbs: None,
+            fingers: Some("1L".to_string()),
+            output: "<Up><Down><Left><Right>".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            chord.get_hid_pairs().unwrap(),
+            vec![(0, 0x52), (0, 0x51), (0, 0x50), (0, 0x4F)]
+        );
 
+        let chord = Chord {
+            thumbs: None,
+            fingers: Some("1L".to_string()),
+            output: "a<Hyper>".to_string(),
+            ..Default::default()
+        };
+        assert!(matches!(
+            chord.get_hid_pairs(),
+            Err(Error::UnknownKey { column: 2, .. })
+        ));
+
+        // Characters the US layout doesn't have are an error, not a panic
+        let chord = Chord::new("", "1L", "<L-Alt>é</L-Alt><Tab>");
+        assert!(matches!(chord.get_hid_pairs(), Err(Error::Unsupported(_))));
+        let chord = Chord::new("", "1L", "<L-Alt>é</L-Alt><Tab>");
+        assert!(chord.get_output_in(Layout::Fr).is_ok());
+    }
+
+    #[test]
+    fn test_media_keys() {
+        let chord = |output: &str| Chord {
+            thumbs: None,
+            fingers: Some("0M".to_string()),
+            output: output.to_string(),
+            ..Default::default()
+        };
+
+        assert!(chord("<Media:VolumeUp>").get_hid_pairs().unwrap() == vec![(0, 0x80)]);
+        assert!(chord("<Media:Mute>").get_hid_pairs().unwrap() == vec![(0, 0x7F)]);
+        assert!(matches!(
+            chord("<Media:PlayPause>").get_hid_pairs(),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            chord("<Media:Louder>").get_hid_pairs(),
+            Err(Error::UnknownKey { .. })
+        ));
+    }
+
+    #[test]
+    fn test_mouse_buttons() {
+        let chord = |output: &str| Chord {
+            thumbs: None,
+            fingers: Some("0M".to_string()),
+            output: output.to_string(),
+            ..Default::default()
+        };
+
+        assert!(chord("<MouseLeft>").get_mouse_buttons() == Some(0x01));
+        assert!(chord("<MouseLeft><MouseMiddle>").get_mouse_buttons() == Some(0x05));
+        assert!(chord("<MouseLeft>a").get_mouse_buttons().is_none());
+        assert!(chord("<Tab>").get_mouse_buttons().is_none());
+
+        assert!(chord("<Sticky:L-Shift>").get_sticky_modifiers() == Some(0x02));
+        let ctrl_alt = chord("<Sticky:L-Ctrl><Sticky:AltGr>");
+        assert!(ctrl_alt.get_sticky_modifiers() == Some(0x41));
+        assert!(matches!(
+            ctrl_alt.get_output(),
+            Ok(ChordOutput::Sticky(0x41))
+        ));
+        assert!(chord("<Sticky:L-Shift>a").get_sticky_modifiers().is_none());
+        assert!(chord("<Sticky:Tab>").get_sticky_modifiers().is_none());
+    }
+
+    #[test]
+    fn test_lowercase_tags() {
+        let chord = |output: &str| Chord::new("", "0M", output);
+        assert!(chord("<mouseleft><MOUSERIGHT>").get_mouse_buttons() == Some(0x03));
+        assert!(chord("<sticky:lshift>").get_sticky_modifiers() == Some(0x02));
+        assert!(matches!(
+            chord("<STICKY:ctrl>").get_output(),
+            Ok(ChordOutput::Sticky(0x01))
+        ));
+        assert!(chord("<layer:1>").get_layer_switch() == Some(1));
+        assert!(matches!(
+            chord("<LAYER:2>").get_output(),
+            Ok(ChordOutput::SwitchLayer(2))
+        ));
+        assert!(matches!(
+            chord("<mousemiddle>").get_output(),
+            Ok(ChordOutput::Mouse(0x04))
+        ));
+    }
+
+    #[test]
+    fn test_pairs_round_trip() {
+        let all_pairs: [&[(u8, u8)]; 4] = [
+            &[(0x10, 0x13)],
+            &[(0x01, 0x06), (0x01, 0x19), (0, 0x28)],
+            &[(0x05, 0x4C), (0x02, 0x17)],
+            &[(0, 0x04), (0x02, 0x36), (0x02, 0x37)],
+        ];
+        for pairs in all_pairs {
+            let chord = Chord {
+                thumbs: None,
+                fingers: Some("1L".to_string()),
+                output: hid::pairs_to_output(pairs),
+                ..Default::default()
+            };
+            assert_eq!(chord.get_hid_pairs().unwrap(), pairs);
+        }
+    }
+
+    #[test]
+    fn test_malformed_tag() {
+        let chord = Chord {
+            thumbs: None,
+            fingers: Some("1L".to_string()),
+            output: "a<R-Ctrl".to_string(),
+            ..Default::default()
+        };
+
+        match chord.get_hid_pairs() {
+            Err(Error::MalformedTag { tag, column, .. }) => {
+                assert_eq!(tag, "<R-Ctrl");
+                assert_eq!(column, 1);
+            }
+            other => panic!("expected a malformed tag error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_with() {
+        let data = "Thumbs,Fingers,Keyboard Output\n,1L,a<Nope>\n,1X,b\n,1R,é\n";
+        let lenient = Options {
+            output: ParseOptions::LENIENT,
+            ..Default::default()
+        };
+        let (chords, warnings) = parse_with(data.as_bytes(), lenient).unwrap();
+        assert!(chords.len() == 3);
+        let rows: Vec<String> = warnings.iter().map(|w| w.to_string()).collect();
+        assert!(rows[0] == "Unknown key name \"Nope\" at row 2, column 2");
+        assert!(rows[1].contains("at row 3"));
+        assert!(rows.len() == 2);
+        let (output, _) = chords[0]
+            .get_output_with(Layout::Us, ParseOptions::LENIENT)
+            .unwrap();
+        assert!(
+            output == ChordOutput::Macro(output::to_macro(&output::parse("a").unwrap()).unwrap())
+        );
+
+        assert!(matches!(
+            parse_with(data.as_bytes(), Options::default()),
+            Err(Error::UnknownKey { row: Some(2), .. })
+        ));
+    }
+
+    #[test]
+    fn test_for_each_borrowed() {
+        let data = "# CoolHand\nThumbs,Fingers,Keyboard Output,Notes\n1,1R,the ,x\n,\"0M 1L\",\"<Tab>\",\n";
+        let mut borrowed = vec![];
+        for_each_borrowed(data.as_bytes(), |chord| {
+            borrowed.push((chord.buttons()?, chord.to_chord()));
+            Ok(())
+        })
+        .unwrap();
+        let chords = parse(&mut std::io::Cursor::new(data)).unwrap();
+        assert!(borrowed.len() == 2);
+        for ((buttons, chord), parsed) in borrowed.iter().zip(&chords) {
+            assert!(*buttons == parsed.buttons() && chord.output() == parsed.output());
+        }
+
+        let stop = for_each_borrowed(data.as_bytes(), |chord| match chord.output {
+            "<Tab>" => Err(Error::Invalid("stop".to_string())),
+            _ => Ok(()),
+        });
+        assert!(matches!(stop, Err(Error::Invalid(_))));
+        assert!(for_each_borrowed("a,b\n1,2\n".as_bytes(), |_| Ok(())).is_err());
+
+        // Read like parse reads it, from Excel with a byte order mark and
+        // Windows-1252 in a Latin-1 row
+        let data = b"\xEF\xBB\xBFThumbs,Fingers,Keyboard Output\n,1L,\xE9t\xE9\n# \"\n,1M,b\n";
+        let mut outputs = vec![];
+        for_each_borrowed(&data[..], |chord| {
+            outputs.push(chord.output.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert!(outputs == ["été", "b"]);
+    }
+
+    #[test]
+    fn test_parse_delimiters() {
+        let csv = "thumbs,fingers,output\n,1L,\"a,b\"\n1,1M,c\n";
+        let expected = parse(&mut std::io::Cursor::new(csv)).unwrap();
+
+        let tsv = "Thumbs\tFingers\tKeyboard Output\n\t1L\ta,b\n1\t1M\tc\n";
+        let options = Options {
+            delimiter: b'\t',
+            ..Default::default()
+        };
+        assert!(parse_with(tsv.as_bytes(), options).unwrap().0 == expected);
+
+        let excel = "thumbs; fingers; output\n; 1L; a,b\n1; 1M; c\n";
+        let options = Options {
+            delimiter: b';',
+            trim: true,
+            ..Default::default()
+        };
+        assert!(parse_with(excel.as_bytes(), options).unwrap().0 == expected);
+
+        let headerless = ",1L,\"a,b\"\n1,1M,c,vowel\n";
+        let options = Options {
+            has_headers: false,
+            ..Default::default()
+        };
+        let (chords, _) = parse_with(headerless.as_bytes(), options).unwrap();
+        assert!(chords[0] == expected[0]);
+        assert!(chords[1].output() == "c" && chords[1].extra("4") == Some("vowel"));
+
+        // The wrong delimiter says which one it looks like
+        match parse_with(excel.as_bytes(), Options::default()) {
+            Err(Error::Invalid(reason)) => assert!(reason.contains("';'"), "{}", reason),
+            other => panic!("expected a missing output column, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_encodings() {
+        let csv = "Thumbs,Fingers,Keyboard Output\n,1L,é\n,1M,€\n";
+        let expected = parse(&mut std::io::Cursor::new(csv)).unwrap();
+        let read = |data: Vec<u8>| -> Vec<Chord> {
+            parse_iter(data.as_slice()).map(Result::unwrap).collect()
+        };
+
+        let bom = [b"\xEF\xBB\xBF", csv.as_bytes()].concat();
+        assert!(read(bom) == expected);
+
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend(csv.encode_utf16().flat_map(u16::to_le_bytes));
+        assert!(read(utf16) == expected);
+
+        let windows_1252 = b"Thumbs,Fingers,Keyboard Output\n,1L,\xE9\n,1M,\x80\n".to_vec();
+        assert!(read(windows_1252) == expected);
+    }
+
+    #[test]
+    fn test_layers() {
+        let data = "Thumbs,Fingers,Keyboard Output,Layer\n,1L,a,\n1,0R,<Layer:1>,\n,1L,1,1\n1,0R,<Layer:0>,1\n";
+        let chords = parse(&mut std::io::Cursor::new(data)).unwrap();
+        assert!(chords[0].layer() == 0 && chords[2].layer() == 1);
+        assert!(chords[1].get_layer_switch() == Some(1));
+        assert!(matches!(
+            chords[3].get_output(),
+            Ok(ChordOutput::SwitchLayer(0))
+        ));
+        assert!(chords[0].get_layer_switch().is_none());
+
+        let mut buffer = vec![];
+        export(&mut buffer, &chords).unwrap();
+        let exported = String::from_utf8(buffer).unwrap();
+        assert!(exported.starts_with("thumbs,fingers,output,layer\n,1L,a,\n"));
+        assert!(exported.ends_with("\n1,0R,<Layer:0>,1\n"));
+
+        // No column for a single layer
+        let mut buffer = vec![];
+        export(&mut buffer, &chords[..1]).unwrap();
+        assert!(buffer == b"thumbs,fingers,output\n,1L,a\n");
+    }
+
+    #[test]
+    fn test_extras_and_comments() {
+        let data = concat!(
+            "# CoolHand with notes\n",
+            "Thumbs,Fingers,Keyboard Output,Notes,Category\n",
+            ",1L,a,first,letters\n",
+            "# Words\n",
+            "#\n",
+            "1,1L,\"two\n# not a comment\",,words\n",
+            ",1M,b,,\n",
+            "# the end\n",
+        );
+        let chords = parse(&mut Cursor::new(data)).unwrap();
+        assert!(chords.len() == 3);
+        assert!(chords[0].leading_comments() == [" CoolHand with notes"]);
+        assert!(chords[0].comments().is_empty());
+        assert!(chords[0].extra("Notes") == Some("first"));
+        assert!(chords[0].category() == Some("letters"));
+        assert!(chords[2].category().is_none());
+        assert!(chords[1].comments() == [" Words", ""]);
+        assert!(chords[1].output() == "two\n# not a comment");
+        assert!(chords[2].comments().is_empty());
+        assert!(chords[2].trailing_comments() == [" the end"]);
+
+        let mut buffer = vec![];
+        export(&mut buffer, &chords).unwrap();
+        let written = String::from_utf8(buffer).unwrap();
+        assert!(
+            written.starts_with("# CoolHand with notes\nthumbs,fingers,output,category,Notes\n,1L")
+        );
+        assert!(written.contains("\n,1L,a,letters,first\n# Words\n#\n1,1L,"));
+        assert!(written.ends_with("\n,1M,b,,\n# the end\n"));
+        assert!(parse(&mut Cursor::new(written)).unwrap() == chords);
+    }
+
+    #[test]
+    fn test_extras_order() {
+        let data = "thumbs,fingers,output,Zebra,Apple\n,1L,a,z,\n,1M,b,,x\n";
+        let chords = parse(&mut Cursor::new(data)).unwrap();
+        let columns: Vec<&str> = chords[0].extras().iter().map(|(c, _)| c.as_str()).collect();
+        assert!(columns == ["Zebra", "Apple"]);
+
+        let mut buffer = vec![];
+        export(&mut buffer, &chords).unwrap();
+        assert!(buffer == data.as_bytes());
+
+        let json = serde_json::to_string(&chords[1]).unwrap();
+        assert!(json.contains(r#""extras":{"Zebra":"","Apple":"x"}"#));
+        assert!(serde_json::from_str::<Chord>(&json).unwrap() == chords[1]);
+    }
+
+    #[test]
+    fn test_parse_iter_streams() {
+        // Fails once the rows so far are read
+        struct Broken;
+
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("unplugged"))
+            }
+        }
+
+        let data = "thumbs,fingers,output\n# first\n,1L,a\n,1M,b\n";
+        let mut chords = parse_iter(data.as_bytes().chain(Broken));
+        let first = chords.next().unwrap().unwrap();
+        assert!(first.output() == "a" && first.comments() == [" first"]);
+        assert!(chords.next().unwrap().unwrap().output() == "b");
+        assert!(matches!(chords.next(), Some(Err(Error::Csv(_)))));
+        assert!(chords.next().is_none());
+    }
+}
@@ -0,0 +1,370 @@
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{convert, layout::Layout, twiddler7, validate, Error, Result};
+
+/// Where on the drive the Twiddler loads its config from
+pub const CONFIG_FILE: &str = "0.cfg";
+
+/// A Twiddler plugged in as a USB drive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub mount: PathBuf,
+}
+
+impl Device {
+    pub fn at(mount: impl Into<PathBuf>) -> Self {
+        Device {
+            mount: mount.into(),
+        }
+    }
+
+    /// The one mounted drive named like a Twiddler, it's an error to have
+    /// none or more than one
+    pub fn find() -> Result<Self> {
+        let mut found = match cfg!(windows) {
+            true => windows_drives(),
+            false => find_in(candidates()),
+        };
+        event!(debug, ?found, "Looked for Twiddler drives");
+        match found.len() {
+            0 => Err(Error::Invalid(
+                "No Twiddler drive found, is it plugged in and mounted?".to_string(),
+            )),
+            1 => Ok(Device::at(found.remove(0))),
+            _ => Err(Error::Invalid(format!(
+                "Found more than one Twiddler drive: {}",
+                found
+                    .iter()
+                    .map(|mount| mount.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))),
+        }
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.mount.join(CONFIG_FILE)
+    }
+
+    /// Write a v7 config to the device, returning where the config it
+    /// replaced was backed up. The new config goes to a temporary file first
+    /// and is renamed over the old one, so a cable pulled halfway leaves
+    /// either the whole old config or the whole new one.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(mount = %self.mount.display(), len = config.len()))
+    )]
+    pub fn flash(&self, config: &[u8]) -> Result<Option<PathBuf>> {
+        check(config)?;
+
+        let path = self.config_path();
+        let backup = path.with_extension("cfg.bak");
+        let old = path.exists();
+        if old {
+            event!(debug, backup = %backup.display(), "Backing up the old config");
+            fs::copy(&path, &backup)?;
+        }
+
+        let temp = path.with_extension("cfg.tmp");
+        event!(debug, temp = %temp.display(), "Writing the new config");
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(config)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp, &path)?;
+        sync_dir(&self.mount);
+        event!(debug, path = %path.display(), "Flashed");
+
+        Ok(old.then_some(backup))
+    }
+
+    /// The config on the device now
+    pub fn read_config(&self) -> Result<Vec<u8>> {
+        Ok(fs::read(self.config_path())?)
+    }
+
+    /// Unmount the drive so it's safe to unplug
+    pub fn eject(&self) -> Result<()> {
+        let mount = self.mount.as_os_str();
+        let commands: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+            &[("diskutil", &["eject"])]
+        } else if cfg!(windows) {
+            &[]
+        } else {
+            &[("gio", &["mount", "--unmount"]), ("umount", &[])]
+        };
+
+        for (program, args) in commands {
+            let status = Command::new(program).args(*args).arg(mount).status();
+            event!(debug, program, ?status, "Tried ejecting");
+            if matches!(status, Ok(status) if status.success()) {
+                return Ok(());
+            }
+        }
+        Err(Error::Unsupported(format!(
+            "Couldn't eject {}, unmount it before unplugging the Twiddler",
+            self.mount.display()
+        )))
+    }
+}
+
+/// Where backups are kept, `~/.local/share/twiddler-cfg/backups` unless
+/// XDG_DATA_HOME says otherwise, or under %APPDATA% on Windows
+pub fn backup_dir() -> Result<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    let data = match (var("XDG_DATA_HOME"), var("APPDATA"), var("HOME")) {
+        (Some(data), _, _) => PathBuf::from(data),
+        (None, Some(appdata), _) if cfg!(windows) => PathBuf::from(appdata),
+        (None, _, Some(home)) => Path::new(&home).join(".local").join("share"),
+        _ => {
+            return Err(Error::Unsupported(
+                "No home directory to keep backups in".to_string(),
+            ))
+        }
+    };
+    Ok(data.join("twiddler-cfg").join("backups"))
+}
+
+/// Save `config` in `dir` named by the time in UTC, e.g.
+/// `2024-07-30T18-04-12Z.cfg`, returning its path. Nothing new is written
+/// when the latest backup is the same config.
+pub fn save_backup(dir: &Path, config: &[u8]) -> Result<PathBuf> {
+    if let Some(latest) = list_backups(dir)?.pop() {
+        if fs::read(&latest)? == config {
+            return Ok(latest);
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    let mut path = dir.join(format!("{}.cfg", timestamp(secs)));
+    // Two backups in the same second
+    for n in 2.. {
+        if !path.exists() {
+            break;
+        }
+        path = dir.join(format!("{}-{}.cfg", timestamp(secs), n));
+    }
+    fs::write(&path, config)?;
+    Ok(path)
+}
+
+/// Backups in `dir`, oldest first
+pub fn list_backups(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut backups: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "cfg"))
+        .collect();
+    // The names sort by time, then the number for backups in the same second
+    backups.sort_by_key(|path| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        match stem.rsplit_once("Z-") {
+            Some((time, n)) => (time.to_string(), n.parse().unwrap_or(0)),
+            None => (stem.trim_end_matches('Z').to_string(), 1),
+        }
+    });
+    Ok(backups)
+}
+
+/// A backup by its path, its name in `dir` with or without .cfg, or the
+/// latest one for None
+pub fn find_backup(dir: &Path, name: Option<&str>) -> Result<PathBuf> {
+    let not_found = || {
+        Error::Invalid(format!(
+            "No backup {:?} in {}",
+            name.unwrap_or_default(),
+            dir.display()
+        ))
+    };
+    let Some(name) = name else {
+        return list_backups(dir)?
+            .pop()
+            .ok_or_else(|| Error::Invalid(format!("No backups in {}", dir.display())));
+    };
+    [
+        PathBuf::from(name),
+        dir.join(name),
+        dir.join(format!("{}.cfg", name)),
+    ]
+    .into_iter()
+    .find(|path| path.is_file())
+    .ok_or_else(not_found)
+}
+
+// Seconds since 1970 as a date and time for file names, days to a date from
+// Howard Hinnant's civil_from_days
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}-{:02}-{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Make sure `config` reads back as a v7 config the Twiddler can use
+pub fn check(config: &[u8]) -> Result<()> {
+    let parsed = twiddler7::parse(&mut Cursor::new(config))?;
+    let chords = convert::twiddler7_to_csv(&parsed, Layout::Us)?;
+    for conflict in validate::find_conflicts(&chords) {
+        if let validate::Conflict::SameButtons { buttons, .. } = conflict {
+            return Err(Error::Invalid(format!(
+                "{} is mapped to more than one output",
+                buttons
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Drives named TWIDDLER, the way the Twiddler labels itself
+fn find_in(mounts: impl IntoIterator<Item = PathBuf>) -> Vec<PathBuf> {
+    mounts
+        .into_iter()
+        .filter(|mount| {
+            mount
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().to_lowercase().contains("twiddler"))
+        })
+        .filter(|mount| mount.is_dir())
+        .collect()
+}
+
+// Where Linux desktops and macOS mount USB drives
+fn candidates() -> Vec<PathBuf> {
+    let mut mounts = vec![];
+    let user = std::env::var("USER").unwrap_or_default();
+    for root in [
+        PathBuf::from("/Volumes"),
+        PathBuf::from("/media"),
+        Path::new("/media").join(&user),
+        Path::new("/run/media").join(&user),
+    ] {
+        if let Ok(entries) = fs::read_dir(root) {
+            mounts.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+    mounts
+}
+
+// Drive letters don't give the volume label, so drives with a config on them
+fn windows_drives() -> Vec<PathBuf> {
+    ('D'..='Z')
+        .map(|letter| PathBuf::from(format!("{}:\\", letter)))
+        .filter(|drive| drive.join(CONFIG_FILE).exists())
+        .collect()
+}
+
+// The rename only survives unplugging once the directory is written out too.
+// Directories can't be opened on Windows, which writes them out right away.
+fn sync_dir(dir: &Path) {
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("twiddler-cfg-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config() -> Vec<u8> {
+        let mut buffer = Cursor::new(vec![]);
+        twiddler7::write(twiddler7::Config::new(), &mut buffer, None, true, false).unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn test_flash() {
+        let mount = temp_dir("flash").join("TWIDDLER");
+        fs::create_dir_all(&mount).unwrap();
+        let device = Device::at(&mount);
+
+        assert!(device.flash(&config()).unwrap().is_none());
+        fs::write(device.config_path(), b"old").unwrap();
+        let backup = device.flash(&config()).unwrap().unwrap();
+        assert!(fs::read(backup).unwrap() == b"old");
+        assert!(fs::read(device.config_path()).unwrap() == config());
+        assert!(!mount.join("0.cfg.tmp").exists());
+
+        assert!(device.flash(b"not a config").is_err());
+        assert!(fs::read(device.config_path()).unwrap() == config());
+        let _ = fs::remove_dir_all(mount.parent().unwrap());
+    }
+
+    #[test]
+    fn test_backups() {
+        let dir = temp_dir("backups");
+        assert!(list_backups(&dir.join("none")).unwrap().is_empty());
+        assert!(find_backup(&dir, None).is_err());
+
+        let first = save_backup(&dir, b"one").unwrap();
+        assert!(save_backup(&dir, b"one").unwrap() == first);
+        let second = save_backup(&dir, b"two").unwrap();
+        assert!(list_backups(&dir).unwrap() == [first.clone(), second.clone()]);
+        assert!(find_backup(&dir, None).unwrap() == second);
+
+        let name = first.file_stem().unwrap().to_str().unwrap();
+        assert!(find_backup(&dir, Some(name)).unwrap() == first);
+        assert!(find_backup(&dir, Some("nope")).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert!(timestamp(0) == "1970-01-01T00-00-00Z");
+        assert!(timestamp(1722362652) == "2024-07-30T18-04-12Z");
+        assert!(timestamp(951782400) == "2000-02-29T00-00-00Z");
+    }
+
+    #[test]
+    fn test_find() {
+        let root = temp_dir("find");
+        for name in ["TWIDDLER", "USB STICK"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+        }
+        let mounts = vec![
+            root.join("TWIDDLER"),
+            root.join("USB STICK"),
+            root.join("twiddler2"),
+        ];
+        assert!(find_in(mounts) == [root.join("TWIDDLER")]);
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -5,14 +5,17 @@ use nom::{
     bytes::streaming::{is_not, tag, take},
     character::{
         complete::{alphanumeric0, digit0},
-        streaming::{alpha0, digit1, space0, space1},
+        streaming::{digit1, space0, space1},
     },
     combinator::{map, opt},
     error::VerboseError,
-    sequence::{delimited, pair, preceded, separated_pair, tuple},
+    sequence::{delimited, pair, preceded, tuple},
 };
 
-use crate::buttons::{self, ButtonState};
+use crate::{
+    buttons::{self, ButtonState},
+    Error, Result,
+};
 
 pub struct Config {
     pub chords: Vec<Chord>,
@@ -37,7 +40,9 @@ enum ParseState {
     Done,
 }
 
-pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn std::error::Error>> {
+// The errors are only logged
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config> {
     let mut parse_state = ParseState::Options;
 
     let buffered = std::io::BufReader::new(reader);
@@ -79,19 +84,19 @@ pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn st
                 match res {
                     Ok((key, value)) => match key.as_str() {
                         "mouse_left" => {
-                            println!("mouse_left: {}", value);
+                            event!(debug, mouse_left = %value, "Read DIDO header");
                             if value == "false" {
                                 lines.next();
                             }
                         }
                         "mouse_right" => {
-                            println!("mouse_right: {}", value);
+                            event!(debug, mouse_right = %value, "Read DIDO header");
                             if value == "false" {
                                 lines.next();
                             }
                         }
                         "mouse_mid" => {
-                            println!("mouse_mid: {}", value);
+                            event!(debug, mouse_mid = %value, "Read DIDO header");
                             if value == "false" {
                                 lines.next();
                             }
@@ -99,7 +104,7 @@ pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn st
                         _ => {}
                     },
                     Err(e) => {
-                        println!("error: {:?}", e);
+                        event!(warn, error = ?e, "Skipped a DIDO line");
                     }
                 }
             }
@@ -111,10 +116,10 @@ pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn st
             ParseState::Strings => {
                 let res = parse_string_index(&line);
                 match res {
-                    Ok((index, len)) => {
+                    Ok((_, len)) => {
                         let mut hids = vec![];
 
-                        for i in 0..len {
+                        for _ in 0..len {
                             let line = lines.next().unwrap().unwrap();
 
                             let mut string_line_parser = pair(
@@ -139,7 +144,7 @@ pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn st
                                     hids.push((hid_u8, mod_u8));
                                 }
                                 Err(e) => {
-                                    println!("error: {:?}", e);
+                                    event!(warn, error = ?e, "Skipped a DIDO line");
                                 }
                             }
                         }
@@ -147,7 +152,7 @@ pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn st
                         strings.push(hids);
                     }
                     Err(e) => {
-                        println!("error: {:?}", e);
+                        event!(warn, error = ?e, "Skipped a DIDO line");
                     }
                 }
             }
@@ -176,7 +181,7 @@ fn parse_mod_out(out: &str) -> u8 {
         })
 }
 
-fn parse_key_value(line: String) -> Result<(String, String), Box<dyn std::error::Error>> {
+fn parse_key_value(line: String) -> Result<(String, String)> {
     let mut items = line.split("=");
     if let Some(key) = items.next() {
         if let Some(value) = items.next() {
@@ -184,7 +189,7 @@ fn parse_key_value(line: String) -> Result<(String, String), Box<dyn std::error:
         }
     }
 
-    bail!("Invalid key value pair: {}", line);
+    Err(Error::Invalid(format!("Invalid key value pair: {}", line)))
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -201,7 +206,9 @@ fn hid_code(i: &str) -> ChordOutput {
     ChordOutput::HidCode(i.to_string())
 }
 
-pub fn parse_chord_line(line: String) -> Result<Chord, Box<dyn std::error::Error>> {
+// The errors are only logged
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub fn parse_chord_line(line: String) -> Result<Chord> {
     // NACS XXXX:HHH+LCLSLALGRCRSRARG:# comment
 
     let mut parser = nom::sequence::tuple((
@@ -247,13 +254,19 @@ pub fn parse_chord_line(line: String) -> Result<Chord, Box<dyn std::error::Error
             });
         }
         Err(e) => {
-            println!("error: {:?}", e);
+            event!(trace, error = ?e, "Not a DIDO chord line");
         }
     }
-    bail!("Invalid chord line: {}", line)
+    Err(Error::BadNotation {
+        notation: line.clone(),
+        row: None,
+        reason: "not a chord line".to_string(),
+    })
 }
 
-fn parse_string_index(input: &str) -> Result<(u32, usize), Box<dyn std::error::Error>> {
+// The errors are only logged
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn parse_string_index(input: &str) -> Result<(u32, usize)> {
     // # String[5]="you "
     // # String[60]="650-489-5484"
 
@@ -278,11 +291,11 @@ fn parse_string_index(input: &str) -> Result<(u32, usize), Box<dyn std::error::E
             }
         }
         Err(e) => {
-            println!("error: {:?}", e);
+            event!(trace, error = ?e, "Not a DIDO string line");
         }
     }
 
-    bail!("Invalid string index: {}", input);
+    Err(Error::Invalid(format!("Invalid string index: {}", input)))
 }
 
 #[cfg(test)]
@@ -290,13 +303,13 @@ mod tests {
     use super::*;
 
     #[test]
-    fn it_works() -> Result<(), Box<dyn std::error::Error>> {
+    fn it_works() -> Result<()> {
         let res = parse_chord_line("N    M000:034                 :# Keyboard 5 and %".to_owned())?;
         assert!(res.output == hid_code("034"));
 
-        parse_chord_line("     LMR0:037+RS              :# Keyboard 8 and *".to_owned());
-        parse_chord_line("     LMMM:String[4]:".to_owned());
-        parse_chord_line("   S LL00:045+RS              :# Keyboard - and _".to_owned());
+        parse_chord_line("     LMR0:037+RS              :# Keyboard 8 and *".to_owned())?;
+        parse_chord_line("     LMMM:String[4]:".to_owned())?;
+        parse_chord_line("   S LL00:045+RS              :# Keyboard - and _".to_owned())?;
 
         let res = parse_string_index("# String[60]=\"650-489-5484\"")?;
         assert!(res.0 == 60);
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use crate::{buttons::ButtonState, csv::Chord};
+
+/// Differences between two sets of chords, each in the order it appears in
+/// its config
+#[derive(Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diff {
+    /// Chords only in the new set
+    pub added: Vec<(ButtonState, String)>,
+    /// Chords only in the old set
+    pub removed: Vec<(ButtonState, String)>,
+    /// Buttons in both sets with different outputs
+    pub remapped: Vec<Remap>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Remap {
+    pub buttons: ButtonState,
+    pub old: String,
+    pub new: String,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.remapped.is_empty()
+    }
+}
+
+/// Compare chords by their buttons rather than their text, so reordered rows
+/// and different notations for the same buttons aren't changes. Outputs that
+/// type the same keys, like `A` and `<L-Shift>a</L-Shift>`, are the same too.
+/// When buttons are mapped more than once only the first chord counts, like
+/// on the device. Chords on other layers are other buttons.
+pub fn compare(old: &[Chord], new: &[Chord]) -> Diff {
+    let old = first_chords(old);
+    let new = first_chords(new);
+
+    let mut diff = Diff::default();
+    for (buttons, old_chord) in &old {
+        match new
+            .iter()
+            .find(|(b, c)| b == buttons && c.layer() == old_chord.layer())
+        {
+            Some((_, new_chord)) if !same_output(old_chord, new_chord) => {
+                diff.remapped.push(Remap {
+                    buttons: *buttons,
+                    old: old_chord.output().to_string(),
+                    new: new_chord.output().to_string(),
+                })
+            }
+            Some(_) => (),
+            None => diff
+                .removed
+                .push((*buttons, old_chord.output().to_string())),
+        }
+    }
+    for (buttons, new_chord) in &new {
+        if !old
+            .iter()
+            .any(|(b, c)| b == buttons && c.layer() == new_chord.layer())
+        {
+            diff.added.push((*buttons, new_chord.output().to_string()));
+        }
+    }
+
+    diff
+}
+
+pub(crate) fn first_chords(chords: &[Chord]) -> Vec<(ButtonState, &Chord)> {
+    let mut seen = HashSet::new();
+    chords
+        .iter()
+        .map(|chord| (chord.buttons(), chord))
+        .filter(|(buttons, chord)| seen.insert((chord.layer(), *buttons)))
+        .collect()
+}
+
+pub(crate) fn same_output(a: &Chord, b: &Chord) -> bool {
+    if a.output() == b.output() {
+        return true;
+    }
+    if let (Some(a), Some(b)) = (a.get_mouse_buttons(), b.get_mouse_buttons()) {
+        return a == b;
+    }
+    if let (Some(a), Some(b)) = (a.get_sticky_modifiers(), b.get_sticky_modifiers()) {
+        return a == b;
+    }
+    matches!((a.get_hid_pairs(), b.get_hid_pairs()), (Ok(a), Ok(b)) if a == b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let old = vec![
+            Chord::new("N", "L000", "a"),
+            Chord::new("", "2L", "b"),
+            Chord::new("", "3L", "<L-Shift>c</L-Shift>"),
+            Chord::new("", "4L", "d"),
+        ];
+        let new = vec![
+            Chord::new("", "3L", "C"),
+            Chord::new("1", "1L", "a"),
+            Chord::new("", "2L", "x"),
+            Chord::new("", "1M", "e"),
+        ];
+        let diff = compare(&old, &new);
+        assert!(diff.removed == vec![("4L".parse().unwrap(), "d".to_string())]);
+        assert!(diff.added == vec![("1M".parse().unwrap(), "e".to_string())]);
+        assert!(
+            diff.remapped
+                == vec![Remap {
+                    buttons: "2L".parse().unwrap(),
+                    old: "b".to_string(),
+                    new: "x".to_string()
+                }]
+        );
+        assert!(compare(&old, &old).is_empty());
+    }
+}
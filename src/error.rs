@@ -0,0 +1,87 @@
+use alloc::{format, string::String};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "std")]
+    #[error("CSV parse error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[cfg(feature = "std")]
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[cfg(feature = "std")]
+    #[error("Binary config error: {0}")]
+    Binary(#[from] binrw::Error),
+
+    /// `row` is the 1-based input line when known, `column` the byte offset
+    /// into the output string
+    #[error("Unknown key name {name:?}{}, column {column}", at_row(.row))]
+    UnknownKey {
+        name: String,
+        row: Option<usize>,
+        column: usize,
+    },
+
+    #[error("Malformed tag {tag:?}{}, column {column}", at_row(.row))]
+    MalformedTag {
+        tag: String,
+        row: Option<usize>,
+        column: usize,
+    },
+
+    #[error("Bad chord notation {notation:?}{}: {reason}", at_row(.row))]
+    BadNotation {
+        notation: String,
+        row: Option<usize>,
+        reason: String,
+    },
+
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+
+    #[error("{0}")]
+    Unsupported(String),
+}
+
+/// A problem a lenient parse left out instead of failing on, the error a
+/// strict one would have returned
+#[derive(Debug)]
+pub struct Warning {
+    pub error: Error,
+}
+
+impl core::fmt::Display for Warning {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+impl Error {
+    /// Fill in the input row for errors raised without knowing it
+    #[cfg(feature = "std")]
+    pub(crate) fn with_row(mut self, input_row: usize) -> Self {
+        match &mut self {
+            Error::UnknownKey { row, .. }
+            | Error::MalformedTag { row, .. }
+            | Error::BadNotation { row, .. } => *row = Some(input_row),
+            _ => (),
+        }
+        self
+    }
+}
+
+fn at_row(row: &Option<usize>) -> String {
+    match row {
+        Some(row) => format!(" at row {}", row),
+        None => String::new(),
+    }
+}
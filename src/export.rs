@@ -0,0 +1,727 @@
+use std::fmt::Write;
+
+use serde_json::json;
+
+use crate::{
+    analysis::effort,
+    buttons::ButtonState,
+    csv::Chord,
+    diff,
+    output::{ChordOutput, MacroStep},
+};
+
+// Other keyboards only get the base layer
+fn first_chords(chords: &[Chord]) -> Vec<(ButtonState, &Chord)> {
+    diff::first_chords(chords)
+        .into_iter()
+        .filter(|(_, chord)| chord.layer() == 0)
+        .collect()
+}
+
+/// Keyboard remappers and firmware the chords can be exported for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Target {
+    Qmk,
+    Kanata,
+    Karabiner,
+    /// JSON for chord trainers to drill the layout with
+    Trainer,
+}
+
+/// The chords as a config for `target`, see the function for each target
+pub fn export(target: Target, chords: &[Chord]) -> String {
+    match target {
+        Target::Qmk => qmk(chords),
+        Target::Kanata => kanata(chords),
+        Target::Karabiner => karabiner(chords),
+        Target::Trainer => trainer(chords),
+    }
+}
+
+/// A QMK keymap snippet typing the same outputs as the chords. Every
+/// Twiddler button gets a custom `TW_` keycode to put on a key, chords of
+/// one button are handled in `process_record_user` and the rest become
+/// combos. Outputs QMK can't type are left out with a comment saying why.
+pub fn qmk(chords: &[Chord]) -> String {
+    let mut header = String::from(concat!(
+        "// Twiddler layout exported by twiddler-cfg. Put the TW_ keycodes on\n",
+        "// the keys standing in for the Twiddler buttons and enable COMBO_ENABLE.\n",
+    ));
+    let mut combos = String::new();
+    let mut combo_list = String::new();
+    let mut combo_actions = String::new();
+    let mut single = String::new();
+
+    for (buttons, chord) in first_chords(chords) {
+        let output = match chord.get_output() {
+            Ok(ChordOutput::SwitchLayer(layer)) => {
+                let _ = writeln!(
+                    header,
+                    "// Skipped {}: layer {} isn't exported",
+                    buttons, layer
+                );
+                continue;
+            }
+            Ok(output) if !buttons.is_empty() => output,
+            Ok(_) => continue,
+            Err(e) => {
+                let _ = writeln!(header, "// Skipped {}: {}", buttons, e);
+                continue;
+            }
+        };
+        let held = held_keycode(&output);
+
+        if buttons.len() == 1 {
+            let _ = writeln!(
+                single,
+                "    case {}: // {:?}",
+                qmk_button(buttons),
+                chord.output()
+            );
+            match held {
+                Some(keycode) => {
+                    let _ = writeln!(single, "        if (record->event.pressed) {{");
+                    let _ = writeln!(single, "            register_code16({});", keycode);
+                    let _ = writeln!(single, "        }} else {{");
+                    let _ = writeln!(single, "            unregister_code16({});", keycode);
+                    let _ = writeln!(single, "        }}");
+                }
+                None => {
+                    let _ = writeln!(single, "        if (record->event.pressed) {{");
+                    push_taps(&mut single, &output, "            ");
+                    let _ = writeln!(single, "        }}");
+                }
+            }
+            let _ = writeln!(single, "        return false;");
+            continue;
+        }
+
+        let index = combo_list.lines().count();
+        let keys: Vec<String> = buttons.iter().map(qmk_button).collect();
+        let _ = writeln!(
+            combos,
+            "const uint16_t PROGMEM tw_combo_{}[] = {{{}, COMBO_END}};",
+            index,
+            keys.join(", ")
+        );
+        match held {
+            Some(keycode) => {
+                let _ = writeln!(
+                    combo_list,
+                    "    COMBO(tw_combo_{}, {}), // {:?}",
+                    index,
+                    keycode,
+                    chord.output()
+                );
+            }
+            None => {
+                let _ = writeln!(
+                    combo_list,
+                    "    COMBO_ACTION(tw_combo_{}), // {:?}",
+                    index,
+                    chord.output()
+                );
+                let _ = writeln!(combo_actions, "    case {}:", index);
+                push_taps(&mut combo_actions, &output, "        ");
+                let _ = writeln!(combo_actions, "        break;");
+            }
+        }
+    }
+
+    let mut qmk = header;
+    qmk.push_str("\n#include QMK_KEYBOARD_H\n\nenum twiddler_keycodes {\n");
+    for (i, button) in ButtonState::all().iter().enumerate() {
+        if i == 0 {
+            let _ = writeln!(qmk, "    {} = SAFE_RANGE,", qmk_button(button));
+        } else {
+            let _ = writeln!(qmk, "    {},", qmk_button(button));
+        }
+    }
+    qmk.push_str("};\n\n");
+
+    qmk.push_str(&combos);
+    qmk.push_str("combo_t key_combos[] = {\n");
+    qmk.push_str(&combo_list);
+    qmk.push_str("};\n\n");
+
+    qmk.push_str("void process_combo_event(uint16_t combo_index, bool pressed) {\n");
+    qmk.push_str("    if (!pressed) {\n        return;\n    }\n");
+    qmk.push_str("    switch (combo_index) {\n");
+    qmk.push_str(&combo_actions);
+    qmk.push_str("    }\n}\n\n");
+
+    qmk.push_str("bool process_record_user(uint16_t keycode, keyrecord_t *record) {\n");
+    qmk.push_str("    switch (keycode) {\n");
+    qmk.push_str(&single);
+    qmk.push_str("    }\n    return true;\n}\n");
+    qmk
+}
+
+// A lone key or mouse button is held for as long as the chord, like on the
+// Twiddler, anything longer is typed once when the chord is pressed
+fn held_keycode(output: &ChordOutput) -> Option<String> {
+    match output {
+        ChordOutput::Mouse(buttons) if buttons.count_ones() == 1 => {
+            Some(format!("KC_BTN{}", buttons.trailing_zeros() + 1))
+        }
+        ChordOutput::Macro(steps) => match steps[..] {
+            [MacroStep::Key { modifier, key_code }] => Some(qmk_keycode(modifier, key_code)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn push_taps(code: &mut String, output: &ChordOutput, indent: &str) {
+    match output {
+        // Left out with the other layers
+        ChordOutput::SwitchLayer(_) => (),
+        ChordOutput::Sticky(modifier) => {
+            let mods: Vec<String> = QMK_MODIFIERS
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| modifier & (1 << bit) != 0)
+                .map(|(_, name)| format!("MOD_BIT(KC_{})", name))
+                .collect();
+            let _ = writeln!(code, "{}add_oneshot_mods({});", indent, mods.join(" | "));
+        }
+        ChordOutput::Mouse(buttons) => {
+            for bit in 0..8 {
+                if buttons & (1 << bit) != 0 {
+                    let _ = writeln!(code, "{}tap_code16(KC_BTN{});", indent, bit + 1);
+                }
+            }
+        }
+        ChordOutput::Macro(steps) => {
+            for step in steps {
+                let _ = match step {
+                    MacroStep::Key { modifier, key_code } => writeln!(
+                        code,
+                        "{}tap_code16({});",
+                        indent,
+                        qmk_keycode(*modifier, *key_code)
+                    ),
+                    MacroStep::Delay(ms) => writeln!(code, "{}wait_ms({});", indent, ms),
+                };
+            }
+        }
+    }
+}
+
+fn qmk_button(button: ButtonState) -> String {
+    format!("TW_{}", button)
+}
+
+const QMK_MODIFIERS: [&str; 8] = [
+    "LCTL", "LSFT", "LALT", "LGUI", "RCTL", "RSFT", "RALT", "RGUI",
+];
+
+/// The QMK keycode for a modifier and key code, e.g. LCTL(KC_C)
+fn qmk_keycode(modifier: u8, key_code: u8) -> String {
+    let mut keycode = qmk_key(key_code);
+    for (bit, name) in QMK_MODIFIERS.iter().enumerate().rev() {
+        if modifier & (1 << bit) != 0 {
+            keycode = format!("{}({})", name, keycode);
+        }
+    }
+    keycode
+}
+
+// QMK's basic keycodes are the HID usages so anything without a name can be
+// written as a number
+#[rustfmt::skip]
+fn qmk_key(key_code: u8) -> String {
+    let name = match key_code {
+        0x04..=0x1D => return format!("KC_{}", (b'A' + key_code - 0x04) as char),
+        0x1E..=0x26 => return format!("KC_{}", key_code - 0x1D),
+        0x3A..=0x45 => return format!("KC_F{}", key_code - 0x39),
+        0x59..=0x61 => return format!("KC_P{}", key_code - 0x58),
+        0x27 => "0", 0x28 => "ENT", 0x29 => "ESC", 0x2A => "BSPC",
+        0x2B => "TAB", 0x2C => "SPC", 0x2D => "MINS", 0x2E => "EQL",
+        0x2F => "LBRC", 0x30 => "RBRC", 0x31 => "BSLS", 0x32 => "NUHS",
+        0x33 => "SCLN", 0x34 => "QUOT", 0x35 => "GRV", 0x36 => "COMM",
+        0x37 => "DOT", 0x38 => "SLSH", 0x39 => "CAPS", 0x46 => "PSCR",
+        0x47 => "SCRL", 0x48 => "PAUS", 0x49 => "INS", 0x4A => "HOME",
+        0x4B => "PGUP", 0x4C => "DEL", 0x4D => "END", 0x4E => "PGDN",
+        0x4F => "RGHT", 0x50 => "LEFT", 0x51 => "DOWN", 0x52 => "UP",
+        0x53 => "NUM", 0x54 => "PSLS", 0x55 => "PAST", 0x56 => "PMNS",
+        0x57 => "PPLS", 0x58 => "PENT", 0x62 => "P0", 0x63 => "PDOT",
+        0x64 => "NUBS", 0x65 => "APP", 0x7F => "MUTE", 0x80 => "VOLU",
+        0x81 => "VOLD",
+        _ => return format!("0x{:02X}", key_code),
+    };
+    format!("KC_{}", name)
+}
+
+/// Keys standing in for the Twiddler buttons on a regular keyboard, in
+/// button order. The thumbs are F D S A on the left hand, finger rows 0 to 4
+/// are the Y to P columns of the right hand with L on the top row.
+#[rustfmt::skip]
+const PRACTICE_KEYS: [u8; 19] = [
+    0x09, 0x07, 0x16, 0x04,
+    0x1C, 0x0B, 0x11,
+    0x18, 0x0D, 0x10,
+    0x0C, 0x0E, 0x36,
+    0x12, 0x0F, 0x37,
+    0x13, 0x33, 0x38,
+];
+
+// How long Kanata waits for the rest of a chord
+const CHORD_TIMEOUT_MS: u16 = 50;
+
+/// A Kanata config typing the chords on a regular keyboard, pressing the
+/// [`PRACTICE_KEYS`] together like the Twiddler buttons. KMonad has no
+/// chords so only Kanata is supported.
+pub fn kanata(chords: &[Chord]) -> String {
+    let buttons: Vec<ButtonState> = ButtonState::all().iter().collect();
+    let source: Vec<String> = PRACTICE_KEYS
+        .iter()
+        .map(|key_code| kanata_key(*key_code).unwrap_or_default())
+        .collect();
+    let mut layer = vec!["XX".to_string(); buttons.len()];
+
+    let mut header = String::from(concat!(
+        ";; Twiddler layout exported by twiddler-cfg. The thumbs are F D S A, finger\n",
+        ";; rows 0 to 4 are the Y U I O P columns with L on the top row.\n",
+    ));
+    let mut chord_list = String::new();
+    for (state, chord) in first_chords(chords) {
+        if state.is_empty() {
+            continue;
+        }
+        let action = match chord.get_output() {
+            Ok(output) => kanata_action(&output),
+            Err(e) => Err(e.to_string()),
+        };
+        let action = match action {
+            Ok(action) => action,
+            Err(reason) => {
+                let _ = writeln!(header, ";; Skipped {}: {}", state, reason);
+                continue;
+            }
+        };
+
+        let indices: Vec<usize> = state
+            .iter()
+            .filter_map(|button| buttons.iter().position(|b| *b == button))
+            .collect();
+        if let [i] = indices[..] {
+            layer[i] = action;
+        } else {
+            let _ = writeln!(
+                chord_list,
+                "  ({}) {} {} all-released ()",
+                indices
+                    .iter()
+                    .map(|i| source[*i].as_str())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                action,
+                CHORD_TIMEOUT_MS
+            );
+        }
+    }
+
+    format!(
+        concat!(
+            "{header}\n",
+            "(defcfg\n  process-unmapped-keys yes\n  concurrent-tap-hold yes\n)\n\n",
+            "(defsrc {source})\n\n",
+            "(deflayer twiddler {layer})\n\n",
+            "(defchordsv2\n{chords})\n"
+        ),
+        header = header,
+        source = source.join(" "),
+        layer = layer.join(" "),
+        chords = chord_list
+    )
+}
+
+fn kanata_action(output: &ChordOutput) -> std::result::Result<String, String> {
+    match output {
+        ChordOutput::SwitchLayer(layer) => Err(format!("layer {} isn't exported", layer)),
+        ChordOutput::Sticky(modifier) => {
+            let mods: Vec<&str> = [
+                "lctl", "lsft", "lalt", "lmet", "rctl", "rsft", "ralt", "rmet",
+            ]
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| modifier & (1 << bit) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+            match mods[..] {
+                [name] => Ok(format!("(one-shot 2000 {})", name)),
+                _ => Ok(format!("(one-shot 2000 (multi {}))", mods.join(" "))),
+            }
+        }
+        ChordOutput::Mouse(buttons) => {
+            let clicks: Vec<&str> = ["mlft", "mrgt", "mmid"]
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| buttons & (1 << bit) != 0)
+                .map(|(_, name)| *name)
+                .collect();
+            match clicks[..] {
+                [click] => Ok(click.to_string()),
+                _ => Ok(format!("(multi {})", clicks.join(" "))),
+            }
+        }
+        ChordOutput::Macro(steps) => {
+            let mut actions = vec![];
+            for step in steps {
+                actions.push(match step {
+                    MacroStep::Key { modifier, key_code } => {
+                        let key = kanata_key(*key_code).ok_or_else(|| {
+                            format!("Kanata has no name for key 0x{:02X}", key_code)
+                        })?;
+                        format!("{}{}", kanata_modifiers(*modifier), key)
+                    }
+                    MacroStep::Delay(ms) => ms.to_string(),
+                });
+            }
+            match &actions[..] {
+                [action] if !matches!(steps[..], [MacroStep::Delay(_)]) => Ok(action.clone()),
+                _ => Ok(format!("(macro {})", actions.join(" "))),
+            }
+        }
+    }
+}
+
+// Kanata writes held modifiers as prefixes, e.g. C-S-c
+fn kanata_modifiers(modifier: u8) -> String {
+    ["C-", "S-", "A-", "M-", "RC-", "RS-", "RA-", "RM-"]
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| modifier & (1 << bit) != 0)
+        .map(|(_, prefix)| *prefix)
+        .collect()
+}
+
+#[rustfmt::skip]
+fn kanata_key(key_code: u8) -> Option<String> {
+    let name = match key_code {
+        0x04..=0x1D => return Some(((b'a' + key_code - 0x04) as char).to_string()),
+        0x1E..=0x26 => return Some((key_code - 0x1D).to_string()),
+        0x3A..=0x45 => return Some(format!("f{}", key_code - 0x39)),
+        0x59..=0x61 => return Some(format!("kp{}", key_code - 0x58)),
+        0x27 => "0", 0x28 => "ret", 0x29 => "esc", 0x2A => "bspc",
+        0x2B => "tab", 0x2C => "spc", 0x2D => "min", 0x2E => "eql",
+        0x2F => "[", 0x30 => "]", 0x31 => "\\", 0x33 => ";",
+        0x34 => "'", 0x35 => "grv", 0x36 => ",", 0x37 => ".",
+        0x38 => "/", 0x39 => "caps", 0x46 => "prnt", 0x47 => "slck",
+        0x48 => "pause", 0x49 => "ins", 0x4A => "home", 0x4B => "pgup",
+        0x4C => "del", 0x4D => "end", 0x4E => "pgdn", 0x4F => "rght",
+        0x50 => "left", 0x51 => "down", 0x52 => "up", 0x53 => "nlck",
+        0x54 => "kp/", 0x55 => "kp*", 0x56 => "kp-", 0x57 => "kp+",
+        0x58 => "kprt", 0x62 => "kp0", 0x63 => "kp.", 0x64 => "102d",
+        0x65 => "menu", 0x7F => "mute", 0x80 => "volu", 0x81 => "voldwn",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// Karabiner-Elements complex modifications typing the chords on a Mac
+/// keyboard, pressing the [`PRACTICE_KEYS`] together like the Twiddler
+/// buttons. Karabiner can't pause in the middle of a macro so delays hold
+/// down the key before them instead, chords it can't type are left out.
+pub fn karabiner(chords: &[Chord]) -> String {
+    let buttons: Vec<ButtonState> = ButtonState::all().iter().collect();
+    let key = |button: ButtonState| {
+        let i = buttons
+            .iter()
+            .position(|b| *b == button)
+            .unwrap_or_default();
+        karabiner_key(PRACTICE_KEYS[i]).unwrap_or_default()
+    };
+
+    // Karabiner takes the first manipulator that matches so the chords with
+    // the most buttons have to come first
+    let mut chords: Vec<(ButtonState, Vec<serde_json::Value>)> = first_chords(chords)
+        .into_iter()
+        .filter(|(state, _)| !state.is_empty())
+        .filter_map(|(state, chord)| Some((state, karabiner_events(&chord.get_output().ok()?)?)))
+        .collect();
+    for button in &buttons {
+        if !chords.iter().any(|(state, _)| state == button) {
+            chords.push((*button, vec![json!({ "key_code": "vk_none" })]));
+        }
+    }
+    chords.sort_by_key(|(state, _)| std::cmp::Reverse(state.len()));
+
+    let manipulators: Vec<serde_json::Value> = chords
+        .into_iter()
+        .map(|(state, to)| {
+            let from = match state.len() {
+                1 => json!({ "key_code": key(state) }),
+                _ => json!({
+                    "simultaneous": state
+                        .iter()
+                        .map(|button| json!({ "key_code": key(button) }))
+                        .collect::<Vec<_>>(),
+                    "simultaneous_options": {
+                        "key_down_order": "insensitive",
+                        "key_up_order": "insensitive"
+                    }
+                }),
+            };
+            json!({
+                "type": "basic",
+                "from": from,
+                "to": to,
+                "parameters": {
+                    "basic.simultaneous_threshold_milliseconds": CHORD_TIMEOUT_MS
+                }
+            })
+        })
+        .collect();
+
+    let rules = json!({
+        "title": "Twiddler layout",
+        "rules": [{
+            "description": "Twiddler chords exported by twiddler-cfg, thumbs on F D S A and finger rows on the Y to P columns",
+            "manipulators": manipulators
+        }]
+    });
+    format!("{:#}\n", rules)
+}
+
+/// JSON for web chord trainers, the chords in tiers by how many buttons
+/// they press, so the single button chords are learnt first, and easiest
+/// first within a tier by [`effort`]. Each chord has a `prompt` to show,
+/// with spaces as ␣, the `output` as written in the CSV and the `buttons` to
+/// press, one name each:
+///
+/// ```json
+/// { "tier": 1, "prompt": "e", "output": "e", "buttons": ["1L"], "effort": 1.0 }
+/// ```
+pub fn trainer(chords: &[Chord]) -> String {
+    let mut chords: Vec<(ButtonState, &Chord)> = first_chords(chords)
+        .into_iter()
+        .filter(|(buttons, _)| !buttons.is_empty())
+        .collect();
+    chords.sort_by(|(a, ac), (b, bc)| {
+        (a.len(), effort(a))
+            .partial_cmp(&(b.len(), effort(b)))
+            .unwrap_or(core::cmp::Ordering::Equal)
+            .then_with(|| ac.output().cmp(bc.output()))
+    });
+
+    let chords: Vec<serde_json::Value> = chords
+        .into_iter()
+        .map(|(buttons, chord)| {
+            json!({
+                "tier": buttons.len(),
+                "prompt": chord.output().replace(' ', "␣"),
+                "output": chord.output(),
+                "buttons": buttons.iter().map(|b| b.to_string()).collect::<Vec<_>>(),
+                "effort": effort(&buttons),
+            })
+        })
+        .collect();
+    let trainer = json!({
+        "generator": "twiddler-cfg",
+        "version": 1,
+        "chords": chords,
+    });
+    format!("{:#}\n", trainer)
+}
+
+fn karabiner_events(output: &ChordOutput) -> Option<Vec<serde_json::Value>> {
+    let mut events: Vec<serde_json::Value> = vec![];
+    match output {
+        // Karabiner has no one-shot modifiers, and other layers aren't
+        // exported
+        ChordOutput::Sticky(_) | ChordOutput::SwitchLayer(_) => return None,
+        ChordOutput::Mouse(buttons) => {
+            for bit in 0..3 {
+                if buttons & (1 << bit) != 0 {
+                    events.push(json!({ "pointing_button": format!("button{}", bit + 1) }));
+                }
+            }
+        }
+        ChordOutput::Macro(steps) => {
+            for step in steps {
+                match step {
+                    MacroStep::Key { modifier, key_code } => {
+                        let mut event = json!({ "key_code": karabiner_key(*key_code)? });
+                        let modifiers: Vec<&str> = KARABINER_MODIFIERS
+                            .iter()
+                            .enumerate()
+                            .filter(|(bit, _)| modifier & (1 << bit) != 0)
+                            .map(|(_, name)| *name)
+                            .collect();
+                        if !modifiers.is_empty() {
+                            event["modifiers"] = json!(modifiers);
+                        }
+                        events.push(event);
+                    }
+                    MacroStep::Delay(ms) => {
+                        if let Some(event) = events.last_mut() {
+                            event["hold_down_milliseconds"] = json!(ms);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Some(events)
+}
+
+const KARABINER_MODIFIERS: [&str; 8] = [
+    "left_control",
+    "left_shift",
+    "left_option",
+    "left_command",
+    "right_control",
+    "right_shift",
+    "right_option",
+    "right_command",
+];
+
+#[rustfmt::skip]
+fn karabiner_key(key_code: u8) -> Option<String> {
+    let name = match key_code {
+        0x04..=0x1D => return Some(((b'a' + key_code - 0x04) as char).to_string()),
+        0x1E..=0x26 => return Some((key_code - 0x1D).to_string()),
+        0x3A..=0x45 => return Some(format!("f{}", key_code - 0x39)),
+        0x59..=0x61 => return Some(format!("keypad_{}", key_code - 0x58)),
+        0x27 => "0", 0x28 => "return_or_enter", 0x29 => "escape",
+        0x2A => "delete_or_backspace", 0x2B => "tab", 0x2C => "spacebar",
+        0x2D => "hyphen", 0x2E => "equal_sign", 0x2F => "open_bracket",
+        0x30 => "close_bracket", 0x31 => "backslash", 0x32 => "non_us_pound",
+        0x33 => "semicolon", 0x34 => "quote", 0x35 => "grave_accent_and_tilde",
+        0x36 => "comma", 0x37 => "period", 0x38 => "slash",
+        0x39 => "caps_lock", 0x46 => "print_screen", 0x47 => "scroll_lock",
+        0x48 => "pause", 0x49 => "insert", 0x4A => "home", 0x4B => "page_up",
+        0x4C => "delete_forward", 0x4D => "end", 0x4E => "page_down",
+        0x4F => "right_arrow", 0x50 => "left_arrow", 0x51 => "down_arrow",
+        0x52 => "up_arrow", 0x53 => "keypad_num_lock", 0x54 => "keypad_slash",
+        0x55 => "keypad_asterisk", 0x56 => "keypad_hyphen", 0x57 => "keypad_plus",
+        0x58 => "keypad_enter", 0x62 => "keypad_0", 0x63 => "keypad_period",
+        0x64 => "non_us_backslash", 0x65 => "application", 0x7F => "mute",
+        0x80 => "volume_increment", 0x81 => "volume_decrement",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qmk() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("", "1M", "<MouseLeft>"),
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "2R 3R", "<L-Ctrl><L-Shift>c</L-Shift></L-Ctrl>"),
+            Chord::new("", "1L", "x"),
+            Chord::new("", "4R", "<Nope>"),
+            Chord::new("", "4M", "<Sticky:L-Shift><Sticky:R-Alt>"),
+        ];
+        let qmk = qmk(&chords);
+        assert!(qmk.contains("    TW_T1 = SAFE_RANGE,\n    TW_T2,\n"));
+        assert!(qmk.contains("    TW_4R,\n};"));
+        assert!(qmk.contains("// Skipped 4R: "));
+        assert!(qmk.contains("    case TW_1L: // \"e\"\n        if (record->event.pressed) {\n            register_code16(KC_E);"));
+        assert!(qmk.contains("unregister_code16(KC_BTN1);"));
+        assert!(!qmk.contains("KC_X"));
+        assert!(qmk.contains("tw_combo_0[] = {TW_T1, TW_1L, TW_2M, COMBO_END};"));
+        assert!(qmk.contains("COMBO_ACTION(tw_combo_0), // \"the \""));
+        assert!(qmk.contains("    case 0:\n        tap_code16(KC_T);\n        tap_code16(KC_H);"));
+        assert!(qmk.contains("COMBO(tw_combo_1, LCTL(LSFT(KC_C))),"));
+        assert!(qmk.contains("add_oneshot_mods(MOD_BIT(KC_LSFT) | MOD_BIT(KC_RALT));"));
+    }
+
+    #[test]
+    fn test_kanata() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("", "1M", "<MouseLeft>"),
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "2R 3R", "<L-Ctrl><L-Shift>c</L-Shift></L-Ctrl>"),
+            Chord::new("", "4R", "<Delay:100>x"),
+            Chord::new("", "4M", "<Sticky:L-Shift>"),
+            Chord::new("", "4L", "<Layer:1>"),
+        ];
+        let kanata = kanata(&chords);
+        assert!(kanata.contains("(defsrc f d s a y h n u j m i k , o l . p ; /)"));
+        assert!(kanata.contains("(deflayer twiddler XX XX XX XX XX XX XX e mlft XX"));
+        assert!(kanata.contains(" (macro 100 x))"));
+        assert!(kanata.contains("  (f u k) (macro t h e spc) 50 all-released ()\n"));
+        assert!(kanata.contains("  (, .) C-S-c 50 all-released ()\n"));
+        assert!(kanata.contains(" (one-shot 2000 lsft)"));
+        assert!(kanata.contains(";; Skipped 4L: layer 1 isn't exported\n"));
+    }
+
+    #[test]
+    fn test_trainer() {
+        let chords = vec![
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "4L", "q"),
+            Chord::new("", "1L", "e"),
+            Chord::new("", "1M 1R", "<Return>"),
+            Chord::new("", "1L", "x"),
+        ];
+        let trainer: serde_json::Value = serde_json::from_str(&trainer(&chords)).unwrap();
+        assert!(trainer["version"] == 1);
+        let chords = trainer["chords"].as_array().unwrap();
+        let prompts: Vec<&str> = chords
+            .iter()
+            .map(|c| c["prompt"].as_str().unwrap())
+            .collect();
+        // The pinky is harder than the index finger, x is on e's buttons
+        assert!(prompts == ["e", "q", "<Return>", "the␣"]);
+        assert!(
+            chords[3]
+                == json!({
+                    "tier": 3,
+                    "prompt": "the␣",
+                    "output": "the ",
+                    "buttons": ["T1", "1L", "2M"],
+                    "effort": 3.0
+                })
+        );
+    }
+
+    #[test]
+    fn test_karabiner() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("1", "1L 2M", "x<Delay:100>Y"),
+            Chord::new("", "1R", "<Nope>"),
+        ];
+        let rules: serde_json::Value = serde_json::from_str(&karabiner(&chords)).unwrap();
+        let manipulators = rules["rules"][0]["manipulators"].as_array().unwrap();
+        assert!(manipulators.len() == 20);
+
+        let chord = &manipulators[0];
+        let keys: Vec<&str> = chord["from"]["simultaneous"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|key| key["key_code"].as_str().unwrap())
+            .collect();
+        assert!(keys == ["f", "u", "k"]);
+        assert!(
+            chord["to"]
+                == json!([
+                    { "key_code": "x", "hold_down_milliseconds": 100 },
+                    { "key_code": "y", "modifiers": ["left_shift"] }
+                ])
+        );
+
+        let single = |key: &str| {
+            manipulators
+                .iter()
+                .find(|m| m["from"]["key_code"] == key)
+                .unwrap()["to"][0]["key_code"]
+                .clone()
+        };
+        assert!(single("u") == "e");
+        assert!(single("m") == "vk_none");
+    }
+}
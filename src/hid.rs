@@ -1,9 +1,17 @@
-use bimap::BiMap;
-use std::collections::HashMap;
-use std::ops::RangeInclusive;
-use std::sync::OnceLock;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::ops::RangeInclusive;
+#[cfg(feature = "std")]
+use std::{collections::HashMap, sync::OnceLock};
 
-fn modifiers_hid() -> &'static HashMap<&'static str, usize> {
+use crate::layout::{self, Layout};
+
+#[cfg(feature = "std")]
+pub fn modifiers_hid() -> &'static HashMap<&'static str, usize> {
     static MODIFIERS: OnceLock<HashMap<&str, usize>> = OnceLock::new();
     MODIFIERS.get_or_init(|| {
         let mut m = HashMap::new();
@@ -19,81 +27,568 @@ fn modifiers_hid() -> &'static HashMap<&'static str, usize> {
     })
 }
 
+/// Tag names for the mouse button bits used by mouse chords
+pub const MOUSE_BUTTONS: [(&str, u8); 3] = [
+    ("MouseLeft", 0x01),
+    ("MouseRight", 0x02),
+    ("MouseMiddle", 0x04),
+];
+
+/// Consumer page usages for <Media:Name> tags
+#[rustfmt::skip]
+pub const CONSUMER_USAGES: [(&str, u16); 12] = [
+    ("PlayPause", 0xCD), ("NextTrack", 0xB5), ("PrevTrack", 0xB6),
+    ("Stop", 0xB7), ("Eject", 0xB8), ("Mute", 0xE2),
+    ("VolumeUp", 0xE9), ("VolumeDown", 0xEA),
+    ("BrightnessUp", 0x6F), ("BrightnessDown", 0x70),
+    ("Calculator", 0x192), ("Browser", 0x196),
+];
+
+pub fn consumer_usage(name: &str) -> Option<u16> {
+    CONSUMER_USAGES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, usage)| *usage)
+}
+
+/// How a [`consumer_usage`] name is written out, e.g. "PlayPause"
+pub fn consumer_name(usage: u16) -> Option<&'static str> {
+    CONSUMER_USAGES
+        .iter()
+        .find(|(_, u)| *u == usage)
+        .map(|(name, _)| *name)
+}
+
+/// Keyboard page keys that do the same thing as a consumer usage
+pub fn consumer_to_keyboard(usage: u16) -> Option<u8> {
+    match usage {
+        0xE2 => Some(0x7F),
+        0xE9 => Some(0x80),
+        0xEA => Some(0x81),
+        _ => None,
+    }
+}
+
 pub const ALPHA_HID_CODES: RangeInclusive<u8> = 0x04u8..=0x1Du8;
 
-pub fn keys_hid() -> &'static BiMap<u8, String> {
-    static KEYS: OnceLock<BiMap<u8, String>> = OnceLock::new();
-    KEYS.get_or_init(|| {
-        let mut elements: bimap::BiMap<u8, String> = BiMap::new();
+/// Every key code with a name, the names are what tags use
+#[rustfmt::skip]
+pub const KEYS: [(u8, &str); 214] = [
+    // Alpha keys
+    (0x04, "a"), (0x05, "b"), (0x06, "c"), (0x07, "d"), (0x08, "e"), (0x09, "f"), (0x0A, "g"),
+    (0x0B, "h"), (0x0C, "i"), (0x0D, "j"), (0x0E, "k"), (0x0F, "l"), (0x10, "m"), (0x11, "n"),
+    (0x12, "o"), (0x13, "p"), (0x14, "q"), (0x15, "r"), (0x16, "s"), (0x17, "t"), (0x18, "u"),
+    (0x19, "v"), (0x1A, "w"), (0x1B, "x"), (0x1C, "y"), (0x1D, "z"),
+    // numbers
+    (0x1E, "1"), (0x1F, "2"), (0x20, "3"), (0x21, "4"), (0x22, "5"), (0x23, "6"), (0x24, "7"),
+    (0x25, "8"), (0x26, "9"), (0x27, "0"),
+    // F keys
+    (0x3A, "F1"), (0x3B, "F2"), (0x3C, "F3"), (0x3D, "F4"), (0x3E, "F5"), (0x3F, "F6"),
+    (0x40, "F7"), (0x41, "F8"), (0x42, "F9"), (0x43, "F10"), (0x44, "F11"), (0x45, "F12"),
+    // F keys 2
+    (0x68, "F13"), (0x69, "F14"), (0x6A, "F15"), (0x6B, "F16"), (0x6C, "F17"), (0x6D, "F18"),
+    (0x6E, "F19"), (0x6F, "F20"), (0x70, "F21"), (0x71, "F22"), (0x72, "F23"), (0x73, "F24"),
+    // keypad
+    (0x54, "KP/"), (0x55, "KP*"), (0x56, "KP-"), (0x57, "KP+"), (0x58, "KPEnter"),
+    (0x59, "KP1"), (0x5A, "KP2"), (0x5B, "KP3"), (0x5C, "KP4"), (0x5D, "KP5"), (0x5E, "KP6"),
+    (0x5F, "KP7"), (0x60, "KP8"), (0x61, "KP9"), (0x62, "KP0"), (0x63, "KP."), (0x67, "KP="),
+    (0x85, "KP,"), (0x86, "KPEqualSign"),
+    // extended keypad
+    (0xB0, "KP00"), (0xB1, "KP000"), (0xB2, "ThousandsSeparator"), (0xB3, "DecimalSeparator"),
+    (0xB4, "CurrencyUnit"), (0xB5, "CurrencySubunit"), (0xB6, "KP("), (0xB7, "KP)"),
+    (0xB8, "KP{"), (0xB9, "KP}"), (0xBA, "KPTab"), (0xBB, "KPBackspace"), (0xBC, "KPA"),
+    (0xBD, "KPB"), (0xBE, "KPC"), (0xBF, "KPD"), (0xC0, "KPE"), (0xC1, "KPF"), (0xC2, "KPXor"),
+    (0xC3, "KP^"), (0xC4, "KP%"), (0xC5, "KP<"), (0xC6, "KP>"), (0xC7, "KP&"), (0xC8, "KP&&"),
+    (0xC9, "KP|"), (0xCA, "KP||"), (0xCB, "KP:"), (0xCC, "KP#"), (0xCD, "KPSpace"),
+    (0xCE, "KP@"), (0xCF, "KP!"), (0xD0, "KPMemStore"), (0xD1, "KPMemRecall"),
+    (0xD2, "KPMemClear"), (0xD3, "KPMemAdd"), (0xD4, "KPMemSubtract"), (0xD5, "KPMemMultiply"),
+    (0xD6, "KPMemDivide"), (0xD7, "KP+/-"), (0xD8, "KPClear"), (0xD9, "KPClearEntry"),
+    (0xDA, "KPBinary"), (0xDB, "KPOctal"), (0xDC, "KPDecimal"), (0xDD, "KPHexadecimal"),
+    // punctuation, typed as plain characters but these names work in tags
+    (0x2C, "Space"), (0x2D, "Minus"), (0x2E, "Equal"), (0x2F, "LeftBracket"),
+    (0x30, "RightBracket"), (0x31, "Backslash"), (0x32, "NonUSHash"), (0x33, "Semicolon"),
+    (0x34, "Quote"), (0x35, "Grave"), (0x36, "Comma"), (0x37, "Period"), (0x38, "Slash"),
+    (0x64, "NonUSBackslash"),
+    // international
+    (0x87, "International1"), (0x88, "International2"), (0x89, "International3"),
+    (0x8A, "International4"), (0x8B, "International5"), (0x8C, "International6"),
+    (0x8D, "International7"), (0x8E, "International8"), (0x8F, "International9"),
+    (0x90, "Lang1"), (0x91, "Lang2"), (0x92, "Lang3"), (0x93, "Lang4"), (0x94, "Lang5"),
+    (0x95, "Lang6"), (0x96, "Lang7"), (0x97, "Lang8"), (0x98, "Lang9"),
+    // modifiers as plain keys
+    (0xE0, "LeftCtrl"), (0xE1, "LeftShift"), (0xE2, "LeftAlt"), (0xE3, "LeftGUI"),
+    (0xE4, "RightCtrl"), (0xE5, "RightShift"), (0xE6, "RightAlt"), (0xE7, "RightGUI"),
+    // others
+    (0x28, "Return"), (0x29, "Escape"), (0x2A, "Backspace"), (0x2B, "Tab"), (0x39, "CapsLock"),
+    (0x46, "PrintScreen"), (0x47, "ScrollLock"), (0x48, "Pause"), (0x49, "Insert"),
+    (0x4A, "Home"), (0x4B, "PageUp"), (0x4C, "Delete"), (0x4D, "End"), (0x4E, "PageDown"),
+    (0x4F, "RightArrow"), (0x50, "LeftArrow"), (0x51, "DownArrow"), (0x52, "UpArrow"),
+    (0x53, "NumLock"), (0x65, "Application"), (0x66, "Power"), (0x74, "Execute"),
+    (0x75, "Help"), (0x76, "Menu"), (0x77, "Select"), (0x78, "Stop"), (0x79, "Again"),
+    (0x7A, "Undo"), (0x7B, "Cut"), (0x7C, "Copy"), (0x7D, "Paste"), (0x7E, "Find"),
+    (0x7F, "Mute"), (0x80, "VolumeUp"), (0x81, "VolumeDown"), (0x82, "LockingCapsLock"),
+    (0x83, "LockingNumLock"), (0x84, "LockingScrollLock"), (0x99, "AltErase"), (0x9A, "SysReq"),
+    (0x9B, "Cancel"), (0x9C, "Clear"), (0x9D, "Prior"), (0x9F, "Separator"), (0xA0, "Out"),
+    (0xA1, "Oper"), (0xA2, "ClearAgain"), (0xA3, "CrSel"), (0xA4, "ExSel"),
+];
 
-        // Alpha keys
-        for i in 0x04..=0x1D {
-            elements.insert(i, format!("{}", (i - 0x04 + b'a') as char));
-        }
+// Names by key code and indices into KEYS sorted by name, both built at
+// compile time so lookups don't hash or allocate. Building them fails the
+// build if a key code or name is in KEYS twice.
+const NAMES: [Option<&str>; 256] = {
+    let mut names = [None; 256];
+    let mut i = 0;
+    while i < KEYS.len() {
+        let (code, name) = KEYS[i];
+        assert!(names[code as usize].is_none(), "key code in KEYS twice");
+        names[code as usize] = Some(name);
+        i += 1;
+    }
+    names
+};
 
-        // numbers
-        elements.insert(0x1E, "1".to_owned());
-        elements.insert(0x1F, "2".to_owned());
-        elements.insert(0x20, "3".to_owned());
-        elements.insert(0x21, "4".to_owned());
-        elements.insert(0x22, "5".to_owned());
-        elements.insert(0x23, "6".to_owned());
-        elements.insert(0x24, "7".to_owned());
-        elements.insert(0x25, "8".to_owned());
-        elements.insert(0x26, "9".to_owned());
-        elements.insert(0x27, "0".to_owned());
-
-        // F keys
-        for i in 0x3A..=0x45 {
-            elements.insert(i, format!("F{}", i - 0x3A + 1));
+const BY_NAME: [u8; KEYS.len()] = {
+    let mut order = [0; KEYS.len()];
+    let mut i = 0;
+    while i < KEYS.len() {
+        order[i] = i as u8;
+        i += 1;
+    }
+    // Insertion sort, the only kind that's easy in a const
+    let mut i = 1;
+    while i < KEYS.len() {
+        let mut j = i;
+        while j > 0 && name_before(KEYS[order[j] as usize].1, KEYS[order[j - 1] as usize].1) {
+            let swap = order[j];
+            order[j] = order[j - 1];
+            order[j - 1] = swap;
+            j -= 1;
         }
+        i += 1;
+    }
+    let mut i = 1;
+    while i < KEYS.len() {
+        let previous = KEYS[order[i - 1] as usize].1;
+        assert!(
+            name_before(previous, KEYS[order[i] as usize].1),
+            "name in KEYS twice"
+        );
+        i += 1;
+    }
+    order
+};
 
-        // F keys 2
-        for i in 0x68..=0x73 {
-            elements.insert(i, format!("F{}", i - 0x68 + 1));
+// str's Ord, which isn't const
+const fn name_before(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let mut i = 0;
+    while i < a.len() && i < b.len() {
+        if a[i] != b[i] {
+            return a[i] < b[i];
         }
+        i += 1;
+    }
+    a.len() < b.len()
+}
 
-        // keypad
-        elements.insert(0x54, "KP/".to_owned());
-        elements.insert(0x55, "KP*".to_owned());
-        elements.insert(0x56, "KP-".to_owned());
-        elements.insert(0x57, "KP+".to_owned());
-        elements.insert(0x58, "KPEnter".to_owned());
-        elements.insert(0x59, "KP1".to_owned());
-        elements.insert(0x5A, "KP2".to_owned());
-        elements.insert(0x5B, "KP3".to_owned());
-        elements.insert(0x5C, "KP4".to_owned());
-        elements.insert(0x5D, "KP5".to_owned());
-        elements.insert(0x5E, "KP6".to_owned());
-        elements.insert(0x5F, "KP7".to_owned());
-        elements.insert(0x60, "KP8".to_owned());
-        elements.insert(0x61, "KP9".to_owned());
-        elements.insert(0x62, "KP0".to_owned());
-        elements.insert(0x63, "KP.".to_owned());
-        elements.insert(0x64, "KP=".to_owned());
-
-        // others
-        elements.insert(0x28, "Return".to_owned());
-        elements.insert(0x29, "Escape".to_owned());
-        elements.insert(0x2A, "Backspace".to_owned());
-        elements.insert(0x2B, "Tab".to_owned());
-        elements.insert(0x39, "CapsLock".to_owned());
-        elements.insert(0x46, "PrintScreen".to_owned());
-        elements.insert(0x47, "ScrollLock".to_owned());
-        elements.insert(0x48, "Pause".to_owned());
-        elements.insert(0x49, "Insert".to_owned());
-        elements.insert(0x4A, "Home".to_owned());
-        elements.insert(0x4B, "PageUp".to_owned());
-        elements.insert(0x4C, "Delete".to_owned());
-        elements.insert(0x4D, "End".to_owned());
-        elements.insert(0x4E, "PageDown".to_owned());
-        elements.insert(0x4F, "RightArrow".to_owned());
-        elements.insert(0x50, "LeftArrow".to_owned());
-        elements.insert(0x51, "DownArrow".to_owned());
-        elements.insert(0x52, "UpArrow".to_owned());
-        elements.insert(0x53, "NumLock".to_owned());
-        elements.insert(0x65, "Application".to_owned());
-
-        elements
+/// The name of a key code, e.g. 0x28 is "Return"
+pub fn key_name(key_code: u8) -> Option<&'static str> {
+    NAMES[key_code as usize]
+}
+
+/// The key code of a name in [`KEYS`], without the aliases [`named_key`]
+/// takes
+pub fn key_code(name: &str) -> Option<u8> {
+    let i = BY_NAME
+        .binary_search_by(|i| KEYS[*i as usize].1.cmp(name))
+        .ok()?;
+    Some(KEYS[BY_NAME[i] as usize].0)
+}
+
+/// Key code for a tag name like `Tab` or `F1`, including the short names
+/// Tuner users tend to write, in any case
+pub fn named_key(name: &str) -> Option<u8> {
+    const ALIASES: [(&str, u8); 9] = [
+        ("Enter", 0x28),
+        ("Esc", 0x29),
+        ("Del", 0x4C),
+        ("Ins", 0x49),
+        ("PrtSc", 0x46),
+        ("Right", 0x4F),
+        ("Left", 0x50),
+        ("Down", 0x51),
+        ("Up", 0x52),
+    ];
+    key_code(name).or_else(|| {
+        ALIASES
+            .iter()
+            .copied()
+            .chain(KEYS.iter().map(|&(code, name)| (name, code)))
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            .map(|(_, code)| code)
     })
 }
+
+const SHIFT: u8 = 0x2;
+
+// Printable characters that aren't covered by the key names above,
+// as they'd be typed on a US layout
+#[rustfmt::skip]
+const CHAR_KEYS: [(char, u8, u8); 35] = [
+    (' ', 0, 0x2C), ('\n', 0, 0x28), ('\t', 0, 0x2B),
+    ('-', 0, 0x2D), ('_', SHIFT, 0x2D),
+    ('=', 0, 0x2E), ('+', SHIFT, 0x2E),
+    ('[', 0, 0x2F), ('{', SHIFT, 0x2F),
+    (']', 0, 0x30), ('}', SHIFT, 0x30),
+    ('\\', 0, 0x31), ('|', SHIFT, 0x31),
+    (';', 0, 0x33), (':', SHIFT, 0x33),
+    ('\'', 0, 0x34), ('"', SHIFT, 0x34),
+    ('`', 0, 0x35), ('~', SHIFT, 0x35),
+    (',', 0, 0x36), ('<', SHIFT, 0x36),
+    ('.', 0, 0x37), ('>', SHIFT, 0x37),
+    ('/', 0, 0x38), ('?', SHIFT, 0x38),
+    ('!', SHIFT, 0x1E), ('@', SHIFT, 0x1F), ('#', SHIFT, 0x20),
+    ('$', SHIFT, 0x21), ('%', SHIFT, 0x22), ('^', SHIFT, 0x23),
+    ('&', SHIFT, 0x24), ('*', SHIFT, 0x25), ('(', SHIFT, 0x26),
+    (')', SHIFT, 0x27),
+];
+
+/// Modifier and key code that type `c`, e.g. 'T' is (L-Shift, 0x17)
+pub const fn char_to_hid(c: char) -> Option<(u8, u8)> {
+    match c {
+        'a'..='z' => Some((0, 0x04 + (c as u8 - b'a'))),
+        'A'..='Z' => Some((SHIFT, 0x04 + (c as u8 - b'A'))),
+        '1'..='9' => Some((0, 0x1E + (c as u8 - b'1'))),
+        '0' => Some((0, 0x27)),
+        _ => {
+            let mut i = 0;
+            while i < CHAR_KEYS.len() {
+                let (key, modifier, key_code) = CHAR_KEYS[i];
+                if key == c {
+                    return Some((modifier, key_code));
+                }
+                i += 1;
+            }
+            None
+        }
+    }
+}
+
+// The printable characters by key code, unshifted and shifted
+const CHARS: [[Option<char>; 256]; 2] = {
+    let mut chars = [[None; 256]; 2];
+    let mut c = b' ';
+    while c <= b'~' {
+        if let Some((modifier, key_code)) = char_to_hid(c as char) {
+            chars[(modifier == SHIFT) as usize][key_code as usize] = Some(c as char);
+        }
+        c += 1;
+    }
+    chars
+};
+
+/// The printable character typed by a modifier and key code, the reverse of
+/// [`char_to_hid`] except for Return and Tab which are better left as names
+pub fn hid_to_char(modifier: u8, key_code: u8) -> Option<char> {
+    match modifier {
+        0 => CHARS[0][key_code as usize],
+        SHIFT => CHARS[1][key_code as usize],
+        _ => None,
+    }
+}
+
+/// Modifier tag names, bit n of the modifier byte is MODIFIER_TAGS[n]
+pub const MODIFIER_TAGS: [&str; 8] = [
+    "L-Ctrl", "L-Shift", "L-Alt", "L-Gui", "R-Ctrl", "R-Shift", "R-Alt", "R-Gui",
+];
+
+/// Modifier bit for a tag name, e.g. "L-Shift" is 0x02. Case, dashes and
+/// underscores don't matter and the side defaults to left, so "lctrl",
+/// "L_CTRL" and "Ctrl" are all L-Ctrl. Win, Cmd, Super and Meta are other
+/// names for Gui, Option for Alt, and AltGr for R-Alt, the key European
+/// layouts type their third level with.
+pub fn modifier_tag(name: &str) -> Option<u8> {
+    // Lower case without separators, no modifier name is longer
+    let mut folded = [0; 16];
+    let mut len = 0;
+    for c in name.bytes().filter(|c| !matches!(c, b'-' | b'_' | b' ')) {
+        *folded.get_mut(len)? = c.to_ascii_lowercase();
+        len += 1;
+    }
+    let name = &folded[..len];
+
+    let bit = |name: &[u8]| match name {
+        b"ctrl" | b"control" => Some(0x01),
+        b"shift" => Some(0x02),
+        b"alt" | b"option" | b"opt" => Some(0x04),
+        b"gui" | b"win" | b"windows" | b"cmd" | b"command" | b"super" | b"meta" => Some(0x08),
+        _ => None,
+    };
+    match name {
+        b"altgr" => Some(0x40),
+        [b'r', rest @ ..] if bit(rest).is_some() => bit(rest).map(|bit| bit << 4),
+        [b'l', rest @ ..] if bit(rest).is_some() => bit(rest),
+        _ => bit(name),
+    }
+}
+
+/// How [`modifier_tag`] names are written out, e.g. "L-Ctrl" for "ctrl".
+/// AltGr stays AltGr.
+pub fn modifier_name(name: &str) -> Option<&'static str> {
+    if name.eq_ignore_ascii_case("AltGr") {
+        return Some("AltGr");
+    }
+    let bit = modifier_tag(name)?.trailing_zeros();
+    Some(MODIFIER_TAGS[bit as usize])
+}
+
+/// Key slot value for every key of a report with more keys held than fit
+pub const ERROR_ROLL_OVER: u8 = 0x01;
+
+/// A boot protocol keyboard report, the modifier byte, a reserved byte and
+/// the keys held. More than six keys is reported as the phantom state, every
+/// slot ErrorRollOver, key code 0 is no key.
+pub fn report(modifier: u8, keys: &[u8]) -> [u8; 8] {
+    let mut report = [modifier, 0, 0, 0, 0, 0, 0, 0];
+    let keys: Vec<u8> = keys.iter().copied().filter(|key| *key != 0).collect();
+    if keys.len() > 6 {
+        report[2..].fill(ERROR_ROLL_OVER);
+    } else {
+        report[2..2 + keys.len()].copy_from_slice(&keys);
+    }
+    report
+}
+
+/// The reports a host gets when the pairs are typed in order. Modifiers go
+/// down in a report of their own before the key and stay down while the
+/// next pairs use them too, each key is released before the next so
+/// repeated keys aren't one long press, and the last report releases
+/// everything.
+pub fn to_reports(pairs: &[(u8, u8)]) -> Vec<[u8; 8]> {
+    let mut reports = vec![];
+    let mut held = 0;
+    for &(modifier, key_code) in pairs {
+        if modifier != held {
+            reports.push(report(modifier, &[]));
+            held = modifier;
+        }
+        if key_code != 0 {
+            reports.push(report(modifier, &[key_code]));
+            reports.push(report(modifier, &[]));
+        }
+    }
+    if held != 0 {
+        reports.push(report(0, &[]));
+    }
+    reports
+}
+
+// < would start a tag and \ could escape whatever comes after it
+fn escape(c: char) -> String {
+    match c {
+        '<' | '\\' => format!("\\{}", c),
+        c => c.to_string(),
+    }
+}
+
+/// Render modifier/key code pairs in the CSV output notation, the reverse of
+/// `csv::Chord::get_hid_pairs`. [(0x10, 0x13)] becomes <R-Ctrl>p</R-Ctrl>
+/// and pairs sharing modifiers share a tag.
+pub fn pairs_to_output(pairs: &[(u8, u8)]) -> String {
+    pairs_to_output_in(pairs, Layout::Us)
+}
+
+/// Like [`pairs_to_output`] for pairs typed on a host using `layout`
+pub fn pairs_to_output_in(pairs: &[(u8, u8)], layout: Layout) -> String {
+    render_pairs(pairs, pairs.len() == 1, layout)
+}
+
+/// Like [`pairs_to_output`] for output that goes next to other output, a
+/// single < is escaped too
+pub fn pairs_to_output_part(pairs: &[(u8, u8)]) -> String {
+    pairs_to_output_part_in(pairs, Layout::Us)
+}
+
+pub fn pairs_to_output_part_in(pairs: &[(u8, u8)], layout: Layout) -> String {
+    render_pairs(pairs, false, layout)
+}
+
+fn render_pairs(pairs: &[(u8, u8)], lone: bool, layout: Layout) -> String {
+    let tags = |modifier: u8| {
+        MODIFIER_TAGS
+            .iter()
+            .enumerate()
+            .filter(move |(bit, _)| modifier & (1 << bit) != 0)
+            .map(|(_, tag)| *tag)
+    };
+
+    // A dead key and the key after it type one character between them
+    let mut typed = vec![];
+    let mut rest = pairs.iter();
+    while let Some(&(modifier, key_code)) = rest.next() {
+        let composed = layout.dead_key(modifier, key_code).and_then(|accent| {
+            let &(next_modifier, next_key) = rest.clone().next()?;
+            layout::compose(accent, layout.hid_to_char(next_modifier, next_key)?)
+        });
+        match composed {
+            Some(c) => {
+                rest.next();
+                typed.push((modifier, key_code, Some(c)));
+            }
+            None => typed.push((modifier, key_code, layout.hid_to_char(modifier, key_code))),
+        }
+    }
+
+    // Tags are opened and closed as modifiers are pressed and released, so
+    // <L-Ctrl><L-Shift>a</L-Shift>b</L-Ctrl> comes back the way it's
+    // written. `open` is innermost last.
+    let mut output = String::new();
+    let mut open: Vec<(u8, &str)> = vec![];
+    let close = |output: &mut String, open: &mut Vec<(u8, &str)>, released: u8| {
+        while let Some(i) = open.iter().rposition(|(bit, _)| released & bit != 0) {
+            output.push_str(&format!("</{}>", open.remove(i).1));
+        }
+    };
+    for (modifier, key_code, c) in typed {
+        // Just the modifiers, written as tags with nothing between them.
+        // A chord of only modifiers leaves them open, as in `<L-Shift>`.
+        if key_code == 0 && modifier != 0 {
+            close(&mut output, &mut open, 0xFF);
+            let held: Vec<&str> = tags(modifier).collect();
+            for tag in &held {
+                output.push_str(&format!("<{}>", tag));
+            }
+            if !lone {
+                for tag in held.iter().rev() {
+                    output.push_str(&format!("</{}>", tag));
+                }
+            }
+            continue;
+        }
+        let (modifier, text) = match c {
+            // Lone characters are always literal
+            Some(c) if lone => (0, c.to_string()),
+            Some(c) => (0, escape(c)),
+            None => match (layout.hid_to_char(0, key_code), key_name(key_code)) {
+                (Some(c), _) => (modifier, escape(c)),
+                (None, Some(key)) if key.chars().count() == 1 => (modifier, key.to_string()),
+                (None, Some(key)) => (modifier, format!("<{}>", key)),
+                (None, None) => continue,
+            },
+        };
+
+        let held = open.iter().fold(0, |held, (bit, _)| held | bit);
+        close(&mut output, &mut open, held & !modifier);
+        for (bit, tag) in MODIFIER_TAGS.iter().enumerate() {
+            if (modifier & !held) & (1 << bit) != 0 {
+                output.push_str(&format!("<{}>", tag));
+                open.push((1 << bit, tag));
+            }
+        }
+        output.push_str(&text);
+    }
+    close(&mut output, &mut open, 0xFF);
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_reports() {
+        // Ctrl+c Ctrl+v, then "aa"
+        let reports = to_reports(&[(0x01, 0x06), (0x01, 0x19), (0, 0x04), (0, 0x04)]);
+        assert!(
+            reports
+                == vec![
+                    [0x01, 0, 0, 0, 0, 0, 0, 0],
+                    [0x01, 0, 0x06, 0, 0, 0, 0, 0],
+                    [0x01, 0, 0, 0, 0, 0, 0, 0],
+                    [0x01, 0, 0x19, 0, 0, 0, 0, 0],
+                    [0x01, 0, 0, 0, 0, 0, 0, 0],
+                    [0; 8],
+                    [0, 0, 0x04, 0, 0, 0, 0, 0],
+                    [0; 8],
+                    [0, 0, 0x04, 0, 0, 0, 0, 0],
+                    [0; 8],
+                ]
+        );
+        // Just the modifier
+        assert!(to_reports(&[(0x02, 0)]) == vec![[0x02, 0, 0, 0, 0, 0, 0, 0], [0; 8]]);
+        assert!(to_reports(&[]).is_empty());
+
+        assert!(report(0x20, &[0x04, 0, 0x05]) == [0x20, 0, 0x04, 0x05, 0, 0, 0, 0]);
+        assert!(report(0, &[4, 5, 6, 7, 8, 9, 10]) == [0, 0, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_keys() {
+        for code in (0x04..=0xA4).chain(0xB0..=0xDD).chain(0xE0..=0xE7) {
+            // 0x9E is a second Return key and has no name of its own
+            if code != 0x9E {
+                let name = key_name(code).unwrap();
+                assert!(key_code(name) == Some(code), "{:#x}", code);
+            }
+        }
+        assert!(key_code("F13") == Some(0x68));
+        assert!(key_code("KP=") == Some(0x67));
+        assert!(key_code("KPHexadecimal") == Some(0xDD));
+        assert!(key_code("Enter").is_none() && named_key("Enter") == Some(0x28));
+        assert!(named_key("Menu") == Some(0x76));
+        assert!(named_key("TAB") == Some(0x2B) && named_key("esc") == Some(0x29));
+        assert!(named_key("LeftCtrl") == Some(0xE0));
+        assert!(named_key("Up") == Some(0x52) && named_key("up") == Some(0x52));
+        assert!(named_key("Down") == Some(0x51));
+        assert!(named_key("Left") == Some(0x50));
+        assert!(named_key("RIGHT") == Some(0x4F));
+        assert!(key_name(0x28) == Some("Return") && key_name(0x9E).is_none());
+    }
+
+    #[test]
+    fn test_modifier_tag() {
+        for name in ["L-Ctrl", "l-ctrl", "LCTRL", "Ctrl", "l_control"] {
+            assert!(modifier_tag(name) == Some(0x01), "{}", name);
+        }
+        for name in ["Win", "Cmd", "Super", "meta", "L-Gui"] {
+            assert!(modifier_tag(name) == Some(0x08), "{}", name);
+        }
+        assert!(modifier_tag("R-Cmd") == Some(0x80));
+        assert!(modifier_tag("ralt") == Some(0x40) && modifier_tag("ALTGR") == Some(0x40));
+        assert!(modifier_tag("Option") == Some(0x04));
+        assert!(modifier_tag("LeftCtrl").is_none() && modifier_tag("Hyper").is_none());
+        assert!(modifier_tag("ctrl-ctrl-ctrl-ctrl").is_none());
+
+        assert!(modifier_name("super") == Some("L-Gui"));
+        assert!(modifier_name("R_SHIFT") == Some("R-Shift"));
+        assert!(modifier_name("altgr") == Some("AltGr"));
+    }
+
+    #[test]
+    fn test_char_to_hid() {
+        for c in ' '..='~' {
+            let (modifier, key_code) = char_to_hid(c).unwrap();
+            assert!(hid_to_char(modifier, key_code) == Some(c));
+        }
+    }
+
+    #[test]
+    fn test_pairs_to_output() {
+        assert!(pairs_to_output(&[(0x10, 0x13)]) == "<R-Ctrl>p</R-Ctrl>");
+        assert!(pairs_to_output(&[(0x02, 0x0B), (0, 0x0C), (0x02, 0x1E)]) == "Hi!");
+        assert!(
+            pairs_to_output(&[(0x01, 0x06), (0x01, 0x19), (0, 0x28)])
+                == "<L-Ctrl>cv</L-Ctrl><Return>"
+        );
+        assert!(pairs_to_output(&[(0x05, 0x4C)]) == "<L-Ctrl><L-Alt><Delete></L-Alt></L-Ctrl>");
+        assert!(pairs_to_output(&[(0x02, 0x36)]) == "<");
+        assert!(pairs_to_output(&[(0, 0x04), (0x02, 0x36)]) == "a\\<");
+        assert!(pairs_to_output(&[(0, 0x31), (0x02, 0x2B)]) == "\\\\<L-Shift><Tab></L-Shift>");
+        assert!(pairs_to_output(&[(0x03, 0)]) == "<L-Ctrl><L-Shift>");
+        assert!(
+            pairs_to_output(&[(0x01, 0x06), (0x02, 0), (0, 0x04)])
+                == "<L-Ctrl>c</L-Ctrl><L-Shift></L-Shift>a"
+        );
+    }
+}
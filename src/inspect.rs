@@ -0,0 +1,418 @@
+use std::fmt::Write;
+use std::io::Cursor;
+
+use binrw::BinRead;
+
+use crate::{
+    buttons::ButtonState, config, convert::Format, hid, twiddler5, twiddler6, twiddler7, Error,
+    Result,
+};
+
+/// A run of bytes in a binary config and what the parser reads it as
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+    pub start: usize,
+    pub len: usize,
+    pub label: String,
+}
+
+impl Region {
+    fn new(start: usize, len: usize, label: impl Into<String>) -> Self {
+        Self {
+            start,
+            len,
+            label: label.into(),
+        }
+    }
+}
+
+/// Label the header fields, chords, command lists and strings of a v5, v6
+/// or v7 config, in the order they're in. Bytes the parser skips are left
+/// out, [`hexdump`] shows them as unknown.
+pub fn annotate(data: &[u8]) -> Result<Vec<Region>> {
+    match config::detect_version(&mut Cursor::new(data))?.format()? {
+        Format::Twiddler5 => annotate_v5(data),
+        Format::Twiddler6 => annotate_v6(data),
+        Format::Twiddler7 => annotate_v7(data, 0),
+        format => Err(Error::Unsupported(format!(
+            "{:?} configs are text, only binary configs can be inspected",
+            format
+        ))),
+    }
+}
+
+fn settings_regions(regions: &mut Vec<Region>, settings: &crate::Settings, flags: (usize, usize)) {
+    let set = [
+        ("key repeat", settings.key_repeat),
+        ("haptic", settings.haptic),
+        ("direct", settings.direct),
+        ("bluetooth", settings.bluetooth),
+        ("sticky num", settings.sticky_num),
+        ("sticky alt", settings.sticky_alt),
+        ("sticky ctrl", settings.sticky_ctrl),
+        ("sticky shift", settings.sticky_shift),
+        ("mouse on F0L", settings.left_mouse_pos),
+    ];
+    let set: Vec<&str> = set.iter().filter(|(_, on)| *on).map(|(n, _)| *n).collect();
+    regions.push(Region::new(
+        flags.0,
+        flags.1,
+        format!("flags: {}", set.join(", ")),
+    ));
+}
+
+fn annotate_v7(data: &[u8], base: usize) -> Result<Vec<Region>> {
+    let mut reader = Cursor::new(data);
+    let config = twiddler7::Config::read(&mut reader)?;
+    let settings = config.settings();
+
+    let mut regions = vec![Region::new(base + 0x04, 1, "version 7")];
+    settings_regions(&mut regions, &settings, (base + 0x05, 2));
+    regions.extend([
+        Region::new(
+            base + 0x08,
+            2,
+            format!("number of chords: {}", config.number_of_chords),
+        ),
+        Region::new(base + 0x0A, 2, format!("idle time: {}s", config.idle_time)),
+        Region::new(
+            base + 0x0C,
+            1,
+            format!("mouse sensitivity: {}", config.mouse_sensitivity),
+        ),
+        Region::new(
+            base + 0x0D,
+            1,
+            format!("key repeat delay: {}", config.key_repeat_delay),
+        ),
+    ]);
+
+    let mut offset = 0x80;
+    for (i, chord) in config.chords.iter().enumerate() {
+        let buttons = ButtonState::from(chord.buttons);
+        let command = describe_v7(&chord.command);
+        let label = format!("chord {}: {} -> {}", i, buttons, command);
+        regions.push(Region::new(base + offset, 8, label));
+        offset += 8;
+    }
+    let lists = config
+        .chords
+        .iter()
+        .filter_map(|chord| match chord.command.data {
+            twiddler7::CommandData::ListOfCommands(offset) => Some(usize::from(offset)),
+            _ => None,
+        });
+    let lists = lists.zip(
+        config
+            .command_lists
+            .iter()
+            .map(|list| list.0.iter().map(describe_v7).collect()),
+    );
+    offset += list_regions(&mut regions, base + offset, lists);
+
+    // The metadata block twiddler7::parse reads after the command lists
+    if let Some(header) = data
+        .get(offset..offset + 8)
+        .filter(|h| h.starts_with(b"TCMD"))
+    {
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let block = &data[(offset + 8).min(data.len())..(offset + 8 + len).min(data.len())];
+        let label = format!("metadata block, {} bytes", len);
+        regions.push(Region::new(base + offset, 8, label));
+        regions.push(Region::new(base + offset + 8, block.len(), "metadata JSON"));
+    }
+
+    Ok(regions)
+}
+
+// Label each command list at its offset from `start`, given with its
+// commands described. A list ending another one is already labelled as
+// part of it. Returns how many bytes the lists take.
+fn list_regions(
+    regions: &mut Vec<Region>,
+    start: usize,
+    lists: impl Iterator<Item = (usize, Vec<String>)>,
+) -> usize {
+    let mut lists: Vec<_> = lists.enumerate().collect();
+    lists.sort_by_key(|(_, (offset, _))| *offset);
+
+    let mut end = 0;
+    for (i, (offset, commands)) in lists {
+        if offset < end {
+            continue;
+        }
+        for (j, command) in commands.iter().enumerate() {
+            let label = format!("command list {}, {}: {}", i, j, command);
+            regions.push(Region::new(start + offset + j * 4, 4, label));
+        }
+        end = offset + commands.len() * 4;
+        let label = format!("end of command list {}", i);
+        regions.push(Region::new(start + end, 4, label));
+        end += 4;
+    }
+    end
+}
+
+fn describe_v7(command: &twiddler7::Command) -> String {
+    match &command.data {
+        twiddler7::CommandData::Keyboard(hid) => describe_key(hid.modifier, hid.key_code),
+        twiddler7::CommandData::Mouse(mouse) => format!("mouse buttons {:#04x}", mouse.buttons),
+        twiddler7::CommandData::System(command, argument) => {
+            format!("system {} {}", command, argument)
+        }
+        twiddler7::CommandData::Delay(ms) => format!("delay {}ms", ms),
+        twiddler7::CommandData::ListOfCommands(offset) => {
+            format!("command list at +{:#x}", offset)
+        }
+        twiddler7::CommandData::None(..) => "nothing".to_string(),
+    }
+}
+
+fn describe_key(modifier: u8, key_code: u8) -> String {
+    if key_code == 0 && modifier != 0 {
+        let tags: Vec<&str> = (0..8)
+            .filter(|bit| modifier & (1 << bit) != 0)
+            .map(|bit| hid::MODIFIER_TAGS[bit])
+            .collect();
+        return format!("modifiers {}", tags.join(" "));
+    }
+    format!(
+        "key {:02x} {:02x} {:?}",
+        modifier,
+        key_code,
+        hid::pairs_to_output(&[(modifier, key_code)])
+    )
+}
+
+fn annotate_v6(data: &[u8]) -> Result<Vec<Region>> {
+    let config = twiddler6::parse(&mut Cursor::new(data))?;
+    let settings = config.settings();
+
+    let mut regions = vec![Region::new(0x04, 1, "version 6")];
+    settings_regions(&mut regions, &settings, (0x05, 1));
+    regions.extend([
+        Region::new(
+            0x06,
+            2,
+            format!("number of chords: {}", config.number_of_chords),
+        ),
+        Region::new(0x08, 2, format!("idle time: {}s", config.idle_time)),
+        Region::new(
+            0x10,
+            1,
+            format!("mouse sensitivity: {}", config.mouse_sensitivity),
+        ),
+        Region::new(
+            0x11,
+            1,
+            format!("key repeat delay: {}", config.key_repeat_delay),
+        ),
+    ]);
+
+    let mut offset = 0x28;
+    for (i, chord) in config.chords.iter().enumerate() {
+        let buttons = ButtonState::from(chord.buttons);
+        let label = format!(
+            "chord {}: {} -> {}",
+            i,
+            buttons,
+            describe_v6(&chord.command)
+        );
+        regions.push(Region::new(offset, 8, label));
+        offset += 8;
+    }
+    let lists = config
+        .chords
+        .iter()
+        .filter_map(|chord| match chord.command.data {
+            twiddler6::CommandData::ListOfCommands(_, offset) => Some(usize::from(offset)),
+            _ => None,
+        });
+    let lists = lists.zip(
+        config
+            .command_lists
+            .iter()
+            .map(|list| list.0.iter().map(describe_v6).collect()),
+    );
+    list_regions(&mut regions, offset, lists);
+
+    Ok(regions)
+}
+
+fn describe_v6(command: &twiddler6::Command) -> String {
+    match &command.data {
+        twiddler6::CommandData::Keyboard(hid, _) => describe_key(hid.modifier, hid.key_code),
+        twiddler6::CommandData::Mouse(mouse, _) => {
+            format!("mouse buttons {:#04x}", mouse.buttons)
+        }
+        twiddler6::CommandData::System(command, argument, _) => {
+            format!("system {} {}", command, argument)
+        }
+        twiddler6::CommandData::Delay(ms, _) => format!("delay {}ms", ms),
+        twiddler6::CommandData::ListOfCommands(_, offset) => {
+            format!("command list at +{:#x}", offset)
+        }
+        twiddler6::CommandData::None(..) => "nothing".to_string(),
+    }
+}
+
+fn annotate_v5(data: &[u8]) -> Result<Vec<Region>> {
+    let config = twiddler5::parse(&mut Cursor::new(data))?;
+    let settings = config.settings();
+
+    let mut regions = vec![
+        Region::new(0x00, 1, "version 5"),
+        Region::new(0x01, 1, format!("options a: {:#010b}", settings.options_a)),
+        Region::new(
+            0x02,
+            2,
+            format!("number of chords: {}", config.chords.len()),
+        ),
+        Region::new(
+            0x04,
+            2,
+            format!("sleep timeout: {}s", settings.sleep_timeout),
+        ),
+        Region::new(0x06, 2, "mouse left click"),
+        Region::new(0x08, 2, "mouse middle click"),
+        Region::new(0x0A, 2, "mouse right click"),
+        Region::new(
+            0x0C,
+            1,
+            format!("mouse acceleration: {}", settings.mouse_accel_factor),
+        ),
+        Region::new(
+            0x0D,
+            1,
+            format!("key repeat delay: {}", settings.key_repeat_delay),
+        ),
+        Region::new(0x0E, 1, format!("options b: {:#010b}", settings.options_b)),
+        Region::new(0x0F, 1, format!("options c: {:#010b}", settings.options_c)),
+    ];
+
+    let mut offset = 0x10;
+    for (i, chord) in config.chords.iter().enumerate() {
+        let mapping = match chord.mapping {
+            twiddler5::ChordMapping::KeyMapping(modifier, key_code) => {
+                describe_key(modifier, key_code)
+            }
+            twiddler5::ChordMapping::StringMapping(_, index) => format!("string {}", index),
+        };
+        let label = format!("chord {}: {} -> {}", i, chord.button_state(), mapping);
+        regions.push(Region::new(offset, 4, label));
+        offset += 4;
+    }
+    for (i, location) in config.string_locations.iter().enumerate() {
+        let label = format!("string {} at {:#x}", i, location);
+        regions.push(Region::new(offset, 4, label));
+        offset += 4;
+    }
+    for contents in &config.string_contents {
+        let start = contents.pos as usize;
+        let index = config
+            .string_locations
+            .iter()
+            .position(|location| u64::from(*location) == contents.pos);
+        let keys: String = contents
+            .keys
+            .iter()
+            .filter_map(|key| match key {
+                twiddler5::ChordMapping::KeyMapping(modifier, key_code) => {
+                    Some((*modifier, *key_code))
+                }
+                twiddler5::ChordMapping::StringMapping(..) => None,
+            })
+            .map(|pair| hid::pairs_to_output(&[pair]))
+            .collect();
+        let label = match index {
+            Some(index) => format!("string {}: {:?}", index, keys),
+            None => format!("string: {:?}", keys),
+        };
+        regions.push(Region::new(start, 2 + contents.keys.len() * 2, label));
+    }
+
+    Ok(regions)
+}
+
+/// `data` as hex, 16 bytes to a line, each region starting a line of its
+/// own with its label next to it. Bytes no region covers are marked
+/// unknown.
+pub fn hexdump(data: &[u8], regions: &[Region]) -> String {
+    let mut regions: Vec<&Region> = regions.iter().collect();
+    regions.sort_by_key(|region| region.start);
+
+    let mut spans = vec![];
+    let mut offset = 0;
+    for region in regions {
+        let start = region.start.min(data.len());
+        let end = (region.start + region.len).min(data.len());
+        if start > offset {
+            spans.push((offset, start, "unknown"));
+        }
+        if end > start.max(offset) {
+            spans.push((start.max(offset), end, region.label.as_str()));
+            offset = end;
+        }
+    }
+    if offset < data.len() {
+        spans.push((offset, data.len(), "unknown"));
+    }
+
+    let mut dump = String::new();
+    for (start, end, label) in spans {
+        for (i, line) in data[start..end].chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+            let label = if i == 0 { label } else { "" };
+            let _ = writeln!(
+                dump,
+                "{:08x}  {:<47}  {}",
+                start + i * 16,
+                hex.join(" "),
+                label
+            );
+        }
+    }
+    dump
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert;
+
+    #[test]
+    fn test_annotate_v7() {
+        let csv = "thumbs,fingers,output\n1,1L,the\n,1M,<L-Shift>a</L-Shift>\n";
+        let config = convert::read(Format::Csv, &mut Cursor::new(csv)).unwrap();
+        let mut data = Cursor::new(vec![]);
+        convert::write(Format::Twiddler7, config, &mut data).unwrap();
+        let data = data.into_inner();
+
+        let regions = annotate(&data).unwrap();
+        assert!(regions[0] == Region::new(4, 1, "version 7"));
+        assert!(regions
+            .iter()
+            .any(|r| r.start == 0x80 && r.label == "chord 0: T1 1L -> command list at +0x0"));
+        assert!(regions
+            .iter()
+            .any(|r| r.label == "command list 0, 1: key 00 0b \"h\""));
+        assert!(regions
+            .iter()
+            .any(|r| r.label == "chord 1: 1M -> key 02 04 \"A\""));
+
+        let dump = hexdump(&data, &regions);
+        assert!(dump.starts_with("00000000  00 00 00 00"));
+        assert!(dump.lines().nth(1).unwrap().ends_with("version 7"));
+        assert!(dump.lines().map(|line| line.len()).max().unwrap() < 120);
+    }
+
+    #[test]
+    fn test_annotate_v5() {
+        let data = std::fs::read("configs/backspice2_v5.cfg").unwrap();
+        let regions = annotate(&data).unwrap();
+        assert!(regions.iter().any(|r| r.label.starts_with("chord 257: ")));
+        assert!(regions.iter().any(|r| r.label.starts_with("string 0: ")));
+        assert!(annotate(b"thumbs,fingers,output\n").is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Writing each command list of a v6 or v7 config once. Text macros repeat a
+//! lot, "the " and "The " both end in "he ", so instead of a list per chord
+//! like the Tuner writes, chords with the same list point at one copy and a
+//! list that ends another one points into it. Configs are only written this
+//! way when asked to, the Tuner's own configs never point into the middle of
+//! a list so it's not known whether every firmware reads them.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use binrw::{BinRead, BinResult, BinWrite, Endian};
+
+use crate::{Error, Result};
+
+/// Chords point at their command list with a u16 offset from the first one,
+/// so every list has to start in the first 64 KiB of them
+pub const OFFSET_LIMIT: usize = 0x10000;
+
+/// Where each command list goes
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Interned {
+    /// Indices of the lists that are written, in order
+    pub stored: Vec<usize>,
+    /// Offset of every list from the first one
+    pub offsets: Vec<usize>,
+    /// Bytes the written lists take
+    pub len: usize,
+    /// Bytes a list per chord would have taken
+    pub unshared_len: usize,
+}
+
+impl Interned {
+    /// Bytes saved by sharing lists
+    pub fn saved(&self) -> usize {
+        self.unshared_len - self.len
+    }
+
+    /// The lists that are written out of all of them, in order
+    pub fn take_stored<T>(&self, lists: Vec<T>) -> Vec<T> {
+        let mut lists: Vec<Option<T>> = lists.into_iter().map(Some).collect();
+        self.stored
+            .iter()
+            .map(|&i| lists[i].take().unwrap())
+            .collect()
+    }
+
+    /// Fails when a list starts past what a u16 offset can point to
+    pub fn check(&self) -> Result<()> {
+        match self.offsets.iter().max() {
+            Some(&offset) if offset >= OFFSET_LIMIT => Err(Error::Invalid(format!(
+                "The command lists take {} bytes even written once each, but chords can only point {} bytes in, make some macros shorter",
+                self.len, OFFSET_LIMIT
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Each command list as written, terminator included, for [`intern`]
+pub fn encode<T>(lists: &[T]) -> Result<Vec<Vec<u8>>>
+where
+    T: for<'a> BinWrite<Args<'a> = ()>,
+{
+    lists
+        .iter()
+        .map(|list| {
+            let mut data = std::io::Cursor::new(vec![]);
+            list.write_le(&mut data)?;
+            Ok(data.into_inner())
+        })
+        .collect()
+}
+
+/// Lay out encoded command lists shared with [`intern`] when `share` is
+/// set, otherwise a list per chord the way the Tuner writes them
+pub fn lay_out(lists: &[Vec<u8>], share: bool) -> Interned {
+    if share {
+        return intern(lists);
+    }
+    let mut interned = Interned {
+        stored: (0..lists.len()).collect(),
+        unshared_len: lists.iter().map(Vec::len).sum(),
+        ..Interned::default()
+    };
+    for list in lists {
+        interned.offsets.push(interned.len);
+        interned.len += list.len();
+    }
+    interned
+}
+
+/// Lay out encoded command lists, each with its terminator, so identical
+/// lists and lists ending a longer one are only written once. Lists that
+/// are written keep their order, so configs without repeats come out the
+/// way the Tuner writes them.
+pub fn intern(lists: &[Vec<u8>]) -> Interned {
+    // The longest list ending with each one, the first of equal ones. Every
+    // command is 4 bytes so a byte suffix is always a whole command suffix.
+    let hosts: Vec<usize> = lists
+        .iter()
+        .enumerate()
+        .map(|(i, list)| {
+            lists
+                .iter()
+                .enumerate()
+                .filter(|(_, other)| other.ends_with(list))
+                .max_by_key(|(j, other)| (other.len(), core::cmp::Reverse(*j)))
+                .map_or(i, |(j, _)| j)
+        })
+        .collect();
+
+    let mut interned = Interned {
+        offsets: vec![0; lists.len()],
+        unshared_len: lists.iter().map(Vec::len).sum(),
+        ..Interned::default()
+    };
+    for (i, list) in lists.iter().enumerate() {
+        if hosts[i] == i {
+            interned.stored.push(i);
+            interned.offsets[i] = interned.len;
+            interned.len += list.len();
+        }
+    }
+    for (i, list) in lists.iter().enumerate() {
+        let host = hosts[i];
+        interned.offsets[i] = interned.offsets[host] + lists[host].len() - list.len();
+    }
+    interned
+}
+
+/// Read the command lists chords point at, `offsets` has the index of each
+/// chord with a list and the list's offset from the first one, and
+/// `chord_table` is where the chords start for errors. The Tuner writes a
+/// list per chord in order but shared lists are read the same, so they're
+/// read by offset. Every list has to start inside or right after the ones
+/// before it, otherwise there's garbage in between.
+pub(crate) fn read_lists<R, T>(
+    reader: &mut R,
+    endian: Endian,
+    offsets: &[(usize, u64)],
+    chord_table: u64,
+) -> BinResult<Vec<T>>
+where
+    R: Read + Seek,
+    T: for<'a> BinRead<Args<'a> = ()> + Default + Clone,
+{
+    let start = reader.stream_position()?;
+    let mut order: Vec<usize> = (0..offsets.len()).collect();
+    order.sort_by_key(|&k| offsets[k].1);
+
+    let mut lists = vec![T::default(); offsets.len()];
+    let mut end = 0;
+    for k in order {
+        let (i, offset) = offsets[k];
+        let error = if offset > end {
+            format!("the lists before it end at +{:#x}", end)
+        } else if offset % 4 != 0 {
+            "that's not on a command".to_string()
+        } else {
+            reader.seek(SeekFrom::Start(start + offset))?;
+            lists[k] = T::read_options(reader, endian, ())?;
+            end = end.max(reader.stream_position()? - start);
+            continue;
+        };
+        let pos = chord_table + i as u64 * 8;
+        return Err(binrw::Error::AssertFail {
+            pos,
+            message: format!(
+                "Chord {} at {:#x} points to a command list at +{:#x} but {}",
+                i, pos, offset, error
+            ),
+        });
+    }
+    reader.seek(SeekFrom::Start(start + end))?;
+    Ok(lists)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern() {
+        let end = [0, 0, 0, 0];
+        let list = |commands: &[u8]| {
+            let mut data: Vec<u8> = commands.iter().flat_map(|&c| [2, 0, c, 0]).collect();
+            data.extend(end);
+            data
+        };
+        let lists = [
+            list(b"he"),
+            list(b"the"),
+            list(b"a"),
+            list(b"the"),
+            list(b""),
+        ];
+        let interned = intern(&lists);
+        assert!(interned.stored == [1, 2]);
+        assert!(interned.offsets == [4, 0, 16, 0, 12]);
+        assert!(interned.len == 24);
+        assert!(interned.saved() == 12 + 16 + 4);
+        assert!(interned.check().is_ok());
+
+        // Nothing repeats so nothing moves
+        let lists = [list(b"ab"), list(b"c")];
+        assert!(intern(&lists).offsets == [0, 12]);
+
+        // Unless asked to, repeats are written again
+        let lists = [list(b"he"), list(b"the"), list(b"he")];
+        let unshared = lay_out(&lists, false);
+        assert!(unshared.stored == [0, 1, 2] && unshared.offsets == [0, 12, 28]);
+        assert!(unshared.saved() == 0);
+        assert!(unshared.take_stored(vec!['a', 'b', 'c']) == ['a', 'b', 'c']);
+        let shared = lay_out(&lists, true);
+        assert!(shared.offsets == [4, 0, 4]);
+        assert!(shared.take_stored(vec!['a', 'b', 'c']) == ['b']);
+
+        let long = list(&[b'a'; OFFSET_LIMIT / 4]);
+        assert!(intern(&[long, list(b"b")]).check().is_err());
+    }
+}
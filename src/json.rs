@@ -0,0 +1,92 @@
+use std::io::{Read, Write};
+
+pub use crate::Metadata;
+use crate::{csv::Chord, Result, Settings};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A chord list as JSON. Everything but `chords` can be left out:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "metadata": { "name": "CoolHand", "author": "Jane", "version": "1.2" },
+///   "settings": { "key_repeat": true, "key_repeat_delay": 100, ... },
+///   "chords": [
+///     { "thumbs": "", "fingers": "1R", "output": "i" },
+///     { "thumbs": "1", "fingers": "1R", "output": "<R-Ctrl>p</R-Ctrl>" }
+///   ]
+/// }
+/// ```
+///
+/// `thumbs`, `fingers` and `output` use the same notation as the CSV
+/// columns, `settings` has the fields of [`Settings`] and `metadata` those
+/// of [`Metadata`].
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Config {
+    #[serde(default = "schema_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub settings: Settings,
+    pub chords: Vec<Chord>,
+}
+
+fn schema_version() -> u32 {
+    SCHEMA_VERSION
+}
+
+impl Config {
+    pub fn new(chords: Vec<Chord>) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            metadata: Metadata::default(),
+            settings: Settings::default(),
+            chords,
+        }
+    }
+}
+
+pub fn parse<R: Read>(reader: &mut R) -> Result<Config> {
+    Ok(serde_json::from_reader(reader)?)
+}
+
+pub fn export<W: Write>(writer: &mut W, config: &Config) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, config)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let data = r#"{"chords": [{"thumbs": "1", "fingers": "1R", "output": "a"}]}"#;
+        let config = parse(&mut data.as_bytes()).unwrap();
+
+        assert!(config.version == SCHEMA_VERSION);
+        assert!(config.settings == Settings::default());
+        assert!(config.chords.len() == 1);
+        assert!(config.chords[0].output == "a");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut file = std::fs::File::open("configs/twiddler_cfg_CoolHand.csv").unwrap();
+        let mut config = Config::new(crate::csv::parse(&mut file).unwrap());
+        config.metadata.name = Some("CoolHand".to_string());
+        config.settings.idle_time = 60;
+
+        let mut buffer = vec![];
+        export(&mut buffer, &config).unwrap();
+        let parsed = parse(&mut buffer.as_slice()).unwrap();
+
+        assert!(parsed.metadata == config.metadata);
+        assert!(parsed.settings == config.settings);
+        assert!(parsed.chords.len() == config.chords.len());
+        assert!(parsed.chords[1].output == "<R-Ctrl>p</R-Ctrl>");
+    }
+}
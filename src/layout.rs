@@ -0,0 +1,398 @@
+use alloc::{format, string::String, vec, vec::Vec};
+
+use crate::hid;
+
+const SHIFT: u8 = 0x02;
+const ALT_GR: u8 = 0x40;
+
+/// The keyboard layout the host the Twiddler is plugged into uses. The
+/// Twiddler sends key codes and the host picks the character, so the same
+/// output needs different key codes on a German host than on a US one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Layout {
+    #[default]
+    Us,
+    Uk,
+    /// German QWERTZ
+    De,
+    /// French AZERTY
+    Fr,
+}
+
+/// How the host is asked for a character no key types, by its code point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnicodeEntry {
+    /// Ctrl+Shift+U, the code point in hex and a space, for GTK and IBus
+    Linux,
+    /// The code point in decimal on the keypad while holding Alt, for
+    /// programs that take Unicode Alt codes
+    Windows,
+    /// The UTF-16 code units in hex while holding Option, with the Unicode
+    /// Hex Input source selected
+    Mac,
+}
+
+/// The operating system a config is made for, so one layout can be
+/// written for each computer it's used with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TargetOs {
+    Windows,
+    /// Shortcuts held with Ctrl are held with Cmd (Gui) and the other way
+    /// around, and the brightness keys are sent as F14 and F15
+    Mac,
+    Linux,
+}
+
+impl TargetOs {
+    /// How the OS takes characters by their code point
+    pub fn unicode_entry(self) -> UnicodeEntry {
+        match self {
+            TargetOs::Windows => UnicodeEntry::Windows,
+            TargetOs::Mac => UnicodeEntry::Mac,
+            TargetOs::Linux => UnicodeEntry::Linux,
+        }
+    }
+}
+
+/// The computer the Twiddler types into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Host {
+    pub layout: Layout,
+    /// Characters the layout has no key for are errors without one, or
+    /// without an OS to take its way of entering them from
+    pub unicode: Option<UnicodeEntry>,
+    /// Outputs are typed the way they're written when not given
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub os: Option<TargetOs>,
+}
+
+impl From<Layout> for Host {
+    fn from(layout: Layout) -> Self {
+        Host {
+            layout,
+            unicode: None,
+            os: None,
+        }
+    }
+}
+
+impl Host {
+    /// How characters without a key are entered, the OS's way unless one
+    /// is given
+    pub fn unicode_entry(self) -> Option<UnicodeEntry> {
+        self.unicode.or(self.os.map(TargetOs::unicode_entry))
+    }
+
+    /// The modifier bits to hold for an output holding `modifiers`, Ctrl
+    /// and Gui swapped on each side for a Mac
+    pub fn modifiers(self, modifiers: u8) -> u8 {
+        match self.os {
+            // Ctrl is bit 0 and Gui bit 3 of each side's nibble
+            Some(TargetOs::Mac) => {
+                let ctrl = modifiers & 0x11;
+                let gui = modifiers & 0x88;
+                modifiers & !0x99 | ctrl << 3 | gui >> 3
+            }
+            _ => modifiers,
+        }
+    }
+
+    /// The keyboard key sending a consumer usage like a media key, see
+    /// [`hid::consumer_to_keyboard`]
+    pub fn media_key(self, usage: u16) -> Option<u8> {
+        match (self.os, usage) {
+            // macOS takes F14 and F15 as brightness down and up
+            (Some(TargetOs::Mac), 0x70) => Some(0x69),
+            (Some(TargetOs::Mac), 0x6F) => Some(0x6A),
+            _ => hid::consumer_to_keyboard(usage),
+        }
+    }
+
+    /// Modifier/key code pairs that type `c`, a single pair unless it needs a
+    /// dead key or has to be entered by its code point
+    pub fn char_to_hid(self, c: char) -> Option<Vec<(u8, u8)>> {
+        if let Some(pairs) = self.layout.char_to_keys(c) {
+            return Some(pairs);
+        }
+
+        const ALT: u8 = 0x04;
+        let hex = |digits: String, held: u8| -> Option<Vec<(u8, u8)>> {
+            digits
+                .chars()
+                .map(|d| {
+                    let (modifier, key_code) = self.layout.char_to_hid(d)?;
+                    Some((held | modifier, key_code))
+                })
+                .collect()
+        };
+        match self.unicode_entry()? {
+            UnicodeEntry::Linux => {
+                let mut pairs = vec![(0x03, 0x18)];
+                pairs.extend(hex(format!("{:x}", c as u32), 0)?);
+                pairs.push((0, 0x2C));
+                Some(pairs)
+            }
+            // Keypad 1 to 9 are 0x59 to 0x61 and keypad 0 comes after them
+            UnicodeEntry::Windows => Some(
+                format!("0{}", c as u32)
+                    .chars()
+                    .map(|d| match d.to_digit(10) {
+                        Some(0) | None => (ALT, 0x62),
+                        Some(n) => (ALT, 0x58 + n as u8),
+                    })
+                    .collect(),
+            ),
+            UnicodeEntry::Mac => {
+                let units: String = c
+                    .encode_utf16(&mut [0; 2])
+                    .iter()
+                    .map(|unit| format!("{:04x}", unit))
+                    .collect();
+                hex(units, ALT)
+            }
+        }
+    }
+}
+
+// The letter on each key from 0x04 (A on US keyboards) to 0x1D (Z), \0 for
+// keys that aren't letters
+const US_LETTERS: &str = "abcdefghijklmnopqrstuvwxyz";
+const DE_LETTERS: &str = "abcdefghijklmnopqrstuvwxzy";
+const FR_LETTERS: &str = "qbcdefghijkl\0noparstuvzxyw";
+
+// The characters each key types on its own, with shift and with AltGr, \0
+// where there isn't one. Dead keys are in their own tables below as they don't
+// type anything until the next key.
+#[rustfmt::skip]
+const UK_KEYS: &[(u8, &str)] = &[
+    (0x1E, "1!"), (0x1F, "2\""), (0x20, "3£"), (0x21, "4$€"), (0x22, "5%"),
+    (0x23, "6^"), (0x24, "7&"), (0x25, "8*"), (0x26, "9("), (0x27, "0)"),
+    (0x2C, " "), (0x2D, "-_"), (0x2E, "=+"), (0x2F, "[{"), (0x30, "]}"),
+    (0x32, "#~"), (0x33, ";:"), (0x34, "'@"), (0x35, "`¬"), (0x36, ",<"),
+    (0x37, ".>"), (0x38, "/?"), (0x64, "\\|"),
+];
+
+#[rustfmt::skip]
+const DE_KEYS: &[(u8, &str)] = &[
+    (0x08, "\0\0€"), (0x10, "\0\0µ"), (0x14, "\0\0@"),
+    (0x1E, "1!"), (0x1F, "2\"²"), (0x20, "3§³"), (0x21, "4$"), (0x22, "5%"),
+    (0x23, "6&"), (0x24, "7/{"), (0x25, "8(["), (0x26, "9)]"), (0x27, "0=}"),
+    (0x2C, " "), (0x2D, "ß?\\"), (0x2F, "üÜ"), (0x30, "+*~"), (0x32, "#'"),
+    (0x33, "öÖ"), (0x34, "äÄ"), (0x35, "\0°"), (0x36, ",;"), (0x37, ".:"),
+    (0x38, "-_"), (0x64, "<>|"),
+];
+
+#[rustfmt::skip]
+const FR_KEYS: &[(u8, &str)] = &[
+    (0x08, "\0\0€"), (0x10, ",?"), (0x33, "mM"),
+    (0x1E, "&1"), (0x1F, "é2"), (0x20, "\"3#"), (0x21, "'4{"), (0x22, "(5["),
+    (0x23, "-6|"), (0x24, "è7"), (0x25, "_8\\"), (0x26, "ç9^"), (0x27, "à0@"),
+    (0x2C, " "), (0x2D, ")°]"), (0x2E, "=+}"), (0x30, "$£¤"), (0x32, "*µ"),
+    (0x34, "ù%"), (0x36, ";."), (0x37, ":/"), (0x38, "!§"), (0x64, "<>"),
+];
+
+// Keys that put an accent on the next key instead of typing, and the accent.
+// The accent on its own is the dead key then space.
+const DE_DEAD: &[(u8, u8, char)] = &[(0, 0x2E, '´'), (SHIFT, 0x2E, '`'), (0, 0x35, '^')];
+const FR_DEAD: &[(u8, u8, char)] = &[
+    (0, 0x2F, '^'),
+    (SHIFT, 0x2F, '¨'),
+    (ALT_GR, 0x1F, '~'),
+    (ALT_GR, 0x24, '`'),
+];
+
+// The letters each accent goes on and what they turn into
+const ACCENTS: &[(char, &str, &str)] = &[
+    ('´', "aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+    ('`', "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    ('^', "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    ('¨', "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+    ('~', "anoANO", "ãñõÃÑÕ"),
+];
+
+/// The character a dead key for `accent` followed by `base` types, None when
+/// the host would type both or nothing
+pub fn compose(accent: char, base: char) -> Option<char> {
+    if base == ' ' {
+        return Some(accent);
+    }
+    let (_, bases, composed) = ACCENTS.iter().find(|(a, _, _)| *a == accent)?;
+    let i = bases.chars().position(|b| b == base)?;
+    composed.chars().nth(i)
+}
+
+// The accent and letter `c` is composed from, the reverse of compose
+fn decompose(c: char) -> Option<(char, char)> {
+    ACCENTS.iter().find_map(|(accent, bases, composed)| {
+        let i = composed.chars().position(|k| k == c)?;
+        Some((*accent, bases.chars().nth(i)?))
+    })
+}
+
+const MODIFIERS: [u8; 3] = [0, SHIFT, ALT_GR];
+
+impl Layout {
+    pub const ALL: [Layout; 4] = [Layout::Us, Layout::Uk, Layout::De, Layout::Fr];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "US",
+            Layout::Uk => "UK",
+            Layout::De => "German",
+            Layout::Fr => "French",
+        }
+    }
+
+    fn tables(self) -> (&'static str, &'static [(u8, &'static str)]) {
+        match self {
+            Layout::Us => (US_LETTERS, &[]),
+            Layout::Uk => (US_LETTERS, UK_KEYS),
+            Layout::De => (DE_LETTERS, DE_KEYS),
+            Layout::Fr => (FR_LETTERS, FR_KEYS),
+        }
+    }
+
+    fn dead_keys(self) -> &'static [(u8, u8, char)] {
+        match self {
+            Layout::Us | Layout::Uk => &[],
+            Layout::De => DE_DEAD,
+            Layout::Fr => FR_DEAD,
+        }
+    }
+
+    /// The accent a modifier and key code put on the next key, if they are
+    /// a dead key on this layout
+    pub fn dead_key(self, modifier: u8, key_code: u8) -> Option<char> {
+        self.dead_keys()
+            .iter()
+            .find(|(m, k, _)| *m == modifier && *k == key_code)
+            .map(|(_, _, accent)| *accent)
+    }
+
+    /// Modifier/key code pairs that type `c` on this layout, going through a
+    /// dead key for accents and accented letters without a key of their own,
+    /// e.g. 'é' is ´ then e on a German host
+    pub fn char_to_keys(self, c: char) -> Option<Vec<(u8, u8)>> {
+        if let Some(pair) = self.char_to_hid(c) {
+            return Some(vec![pair]);
+        }
+        let dead = |accent: char| {
+            self.dead_keys()
+                .iter()
+                .find(|(_, _, a)| *a == accent)
+                .map(|(m, k, _)| (*m, *k))
+        };
+        if let Some(pair) = dead(c) {
+            return Some(vec![pair, (0, 0x2C)]);
+        }
+        let (accent, base) = decompose(c)?;
+        Some(vec![dead(accent)?, self.char_to_hid(base)?])
+    }
+
+    /// Modifier and key code that type `c` on this layout, e.g. 'z' is
+    /// (0, 0x1C) on a German host
+    pub fn char_to_hid(self, c: char) -> Option<(u8, u8)> {
+        // Return and Tab are the same keys everywhere
+        if self == Layout::Us || c.is_ascii_control() {
+            return hid::char_to_hid(c);
+        }
+
+        let (letters, keys) = self.tables();
+        for (key_code, chars) in keys {
+            if let Some(i) = chars.chars().position(|k| k == c && k != '\0') {
+                return Some((MODIFIERS[i], *key_code));
+            }
+        }
+        if !c.is_ascii_alphabetic() {
+            return None;
+        }
+        let i = letters.find(c.to_ascii_lowercase())?;
+        let modifier = if c.is_ascii_uppercase() { SHIFT } else { 0 };
+        Some((modifier, 0x04 + i as u8))
+    }
+
+    /// The character typed by a modifier and key code on this layout, the
+    /// reverse of [`Layout::char_to_hid`]
+    pub fn hid_to_char(self, modifier: u8, key_code: u8) -> Option<char> {
+        if self == Layout::Us {
+            return hid::hid_to_char(modifier, key_code);
+        }
+
+        let slot = MODIFIERS.iter().position(|m| *m == modifier)?;
+        let (letters, keys) = self.tables();
+        let symbol = keys
+            .iter()
+            .find(|(key, _)| *key == key_code)
+            .and_then(|(_, chars)| chars.chars().nth(slot))
+            .filter(|c| *c != '\0');
+        if symbol.is_some() {
+            return symbol;
+        }
+
+        let letter = letters
+            .chars()
+            .nth(key_code.checked_sub(0x04)? as usize)
+            .filter(|c| *c != '\0')?;
+        match slot {
+            0 => Some(letter),
+            1 => Some(letter.to_ascii_uppercase()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_to_hid() {
+        assert!(Layout::Us.char_to_hid('z') == Some((0, 0x1D)));
+        assert!(Layout::De.char_to_hid('z') == Some((0, 0x1C)));
+        assert!(Layout::De.char_to_hid('Y') == Some((SHIFT, 0x1D)));
+        assert!(Layout::De.char_to_hid('@') == Some((ALT_GR, 0x14)));
+        assert!(Layout::Uk.char_to_hid('"') == Some((SHIFT, 0x1F)));
+        assert!(Layout::Uk.char_to_hid('@') == Some((SHIFT, 0x34)));
+        assert!(Layout::Fr.char_to_hid('a') == Some((0, 0x14)));
+        assert!(Layout::Fr.char_to_hid('1') == Some((SHIFT, 0x1E)));
+        assert!(Layout::Fr.char_to_hid('m') == Some((0, 0x33)));
+        assert!(Layout::Us.char_to_hid('é').is_none());
+        assert!(Layout::De.char_to_hid('`').is_none());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for layout in Layout::ALL {
+            for c in (' '..='~').chain("£€§°²³µßäöüÄÖÜéèçàù¬¤".chars()) {
+                if let Some((modifier, key_code)) = layout.char_to_hid(c) {
+                    assert!(layout.hid_to_char(modifier, key_code) == Some(c));
+                }
+            }
+        }
+        assert!(Layout::Fr.hid_to_char(0, 0x10) == Some(','));
+        assert!(Layout::De.hid_to_char(ALT_GR, 0x1C).is_none());
+    }
+
+    #[test]
+    fn test_dead_keys() {
+        assert!(Layout::De.char_to_keys('é') == Some(vec![(0, 0x2E), (0, 0x08)]));
+        assert!(Layout::De.char_to_keys('`') == Some(vec![(SHIFT, 0x2E), (0, 0x2C)]));
+        assert!(Layout::Fr.char_to_keys('é') == Some(vec![(0, 0x1F)]));
+        assert!(Layout::Fr.char_to_keys('Ê') == Some(vec![(0, 0x2F), (SHIFT, 0x08)]));
+        assert!(Layout::Fr.char_to_keys('ñ') == Some(vec![(ALT_GR, 0x1F), (0, 0x11)]));
+        assert!(Layout::Us.char_to_keys('é').is_none());
+        assert!(Layout::De.char_to_keys('ñ').is_none());
+
+        assert!(Layout::De.dead_key(0, 0x2E) == Some('´'));
+        assert!(compose('´', 'e') == Some('é'));
+        assert!(compose('^', ' ') == Some('^'));
+        assert!(compose('¨', 'x').is_none());
+    }
+}
@@ -0,0 +1,105 @@
+// Buttons, HID tables and output parsing only need alloc, so firmware can
+// share the chord model with the std feature off
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+// A tracing event, e.g. `event!(debug, row, "Read chord")`, compiled out
+// without the tracing feature
+#[cfg(feature = "std")]
+macro_rules! event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)*);
+    };
+}
+
+#[cfg(feature = "std")]
+pub mod analysis;
+pub mod buttons;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "std")]
+pub mod charachorder;
+#[cfg(feature = "std")]
+pub mod chord_map;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod convert;
+#[cfg(feature = "std")]
+pub mod corpus;
+#[cfg(feature = "std")]
+pub mod csv;
+#[cfg(feature = "device")]
+pub mod device;
+#[cfg(feature = "std")]
+pub mod dido;
+#[cfg(feature = "std")]
+pub mod diff;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod export;
+pub mod hid;
+#[cfg(feature = "std")]
+pub mod inspect;
+#[cfg(feature = "std")]
+pub mod intern;
+#[cfg(feature = "std")]
+pub mod json;
+pub mod layout;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod merge;
+#[cfg(feature = "std")]
+pub mod metadata;
+#[cfg(feature = "std")]
+pub mod migrate;
+#[cfg(feature = "std")]
+pub mod optimize;
+pub mod output;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+#[cfg(feature = "std")]
+pub mod plan;
+#[cfg(feature = "std")]
+pub mod presets;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod query;
+#[cfg(feature = "std")]
+pub mod render;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod settings;
+#[cfg(feature = "std")]
+pub mod simulate;
+#[cfg(feature = "std")]
+pub mod template;
+#[cfg(feature = "std")]
+pub mod transform;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "std")]
+pub mod twiddler5;
+#[cfg(feature = "std")]
+pub mod twiddler6;
+#[cfg(feature = "std")]
+pub mod twiddler7;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "std")]
+pub use chord_map::ChordMap;
+#[cfg(feature = "std")]
+pub use convert::{convert, Format};
+pub use error::{Error, Result, Warning};
+#[cfg(feature = "std")]
+pub use metadata::Metadata;
+#[cfg(feature = "std")]
+pub use settings::Settings;
@@ -0,0 +1,388 @@
+//! Checks for a config's chords, each rule with an ID so a layout shared in
+//! a repository can turn off the ones it doesn't care about and fail CI on
+//! the rest:
+//!
+//! ```text
+//! twiddler-cfg lint layout.csv --allow too-many-buttons --deny warnings
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    buttons::ButtonState,
+    csv::Chord,
+    intern::OFFSET_LIMIT,
+    output::ChordOutput,
+    validate::{find_conflicts, find_duplicate_outputs, find_impossible, Conflict},
+    Error,
+};
+
+/// How bad a finding is, errors are chords that won't work as written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A check [`lint`] makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rule {
+    /// A row can't be read at all, only reported by `validate`, which skips
+    /// such rows and checks the rest
+    Unreadable,
+    /// An output has a `<` that doesn't start a closed tag
+    MalformedTag,
+    /// An output names a key or tag the tool doesn't know
+    UnknownKey,
+    /// Chords on the same buttons and layer type different outputs, only
+    /// one of them works
+    Conflict,
+    /// The exact same row is in the config more than once
+    Duplicate,
+    /// Chords on different buttons type the same output
+    DuplicateOutput,
+    /// A chord presses more than four buttons
+    TooManyButtons,
+    /// A chord presses both the left and right button of a row
+    Impossible,
+    /// A macro has more steps than the firmware can store, or than
+    /// [`LintOptions::max_macro_steps`]
+    MacroTooLong,
+    /// Chords are on a layer no chord switches to from the first layer
+    UnreachableLayer,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 10] = [
+        Rule::Unreadable,
+        Rule::MalformedTag,
+        Rule::UnknownKey,
+        Rule::Conflict,
+        Rule::Duplicate,
+        Rule::DuplicateOutput,
+        Rule::TooManyButtons,
+        Rule::Impossible,
+        Rule::MacroTooLong,
+        Rule::UnreachableLayer,
+    ];
+
+    /// The name used to allow or deny the rule
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::Unreadable => "unreadable-row",
+            Rule::MalformedTag => "malformed-tag",
+            Rule::UnknownKey => "unknown-key",
+            Rule::Conflict => "conflict",
+            Rule::Duplicate => "duplicate",
+            Rule::DuplicateOutput => "duplicate-output",
+            Rule::TooManyButtons => "too-many-buttons",
+            Rule::Impossible => "impossible",
+            Rule::MacroTooLong => "macro-too-long",
+            Rule::UnreachableLayer => "unreachable-layer",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Rule> {
+        Rule::ALL.into_iter().find(|rule| rule.id() == id)
+    }
+
+    /// Rules for chords that can't be written or typed are errors, the
+    /// others are warnings
+    pub fn severity(self) -> Severity {
+        match self {
+            Rule::Unreadable | Rule::MalformedTag | Rule::UnknownKey | Rule::Conflict => {
+                Severity::Error
+            }
+            _ => Severity::Warning,
+        }
+    }
+
+    /// What the rule checks, in a sentence
+    pub fn description(self) -> &'static str {
+        match self {
+            Rule::Unreadable => "Rows that can't be read",
+            Rule::MalformedTag => "Outputs with a < that doesn't start a closed tag",
+            Rule::UnknownKey => "Outputs naming a key or tag that doesn't exist",
+            Rule::Conflict => "Chords on the same buttons and layer typing different outputs",
+            Rule::Duplicate => "Rows that are in the config more than once",
+            Rule::DuplicateOutput => "Chords on different buttons typing the same output",
+            Rule::TooManyButtons => "Chords pressing more than four buttons",
+            Rule::Impossible => "Chords pressing the left and right button of a row",
+            Rule::MacroTooLong => "Macros with more steps than the limit",
+            Rule::UnreachableLayer => "Chords on a layer no chord switches to",
+        }
+    }
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+/// A v6 or v7 command takes 4 bytes and a list has to start within
+/// [`OFFSET_LIMIT`] of the first one, so no macro can be longer than this
+pub const MAX_MACRO_STEPS: usize = OFFSET_LIMIT / 4;
+
+/// Which rules [`lint`] checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LintOptions {
+    /// Rules that aren't checked
+    pub allowed: BTreeSet<Rule>,
+    /// Steps, key presses and delays, a macro can have before
+    /// [`Rule::MacroTooLong`] reports it
+    pub max_macro_steps: usize,
+}
+
+impl Default for LintOptions {
+    fn default() -> Self {
+        LintOptions {
+            allowed: BTreeSet::new(),
+            max_macro_steps: MAX_MACRO_STEPS,
+        }
+    }
+}
+
+/// Something a rule found. Chords are referred to by their index in the
+/// chords linted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Finding {
+    pub rule: Rule,
+    pub severity: Severity,
+    pub chords: Vec<usize>,
+    /// Byte offset in the output of the first chord, for problems with one
+    /// part of it
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+/// Check `chords` with every rule that isn't allowed, findings in the order
+/// of [`Rule::ALL`] and the chords for each rule
+pub fn lint(chords: &[Chord], options: &LintOptions) -> Vec<Finding> {
+    let mut findings = vec![];
+    let mut report = |rule: Rule, chords: Vec<usize>, column: Option<usize>, message: String| {
+        if !options.allowed.contains(&rule) {
+            findings.push(Finding {
+                rule,
+                severity: rule.severity(),
+                chords,
+                column,
+                message,
+            });
+        }
+    };
+
+    let outputs: Vec<_> = chords.iter().map(Chord::get_output).collect();
+    for (i, output) in outputs.iter().enumerate() {
+        match output {
+            Err(Error::MalformedTag { tag, column, .. }) => report(
+                Rule::MalformedTag,
+                vec![i],
+                Some(*column),
+                format!("{:?} isn't a closed tag", tag),
+            ),
+            Err(Error::UnknownKey { name, column, .. }) => report(
+                Rule::UnknownKey,
+                vec![i],
+                Some(*column),
+                format!("{:?} isn't a known key", name),
+            ),
+            // Outputs that can't be typed on the device for other reasons,
+            // a character with no key on the host layout
+            Err(e) => report(Rule::UnknownKey, vec![i], None, e.to_string()),
+            Ok(_) => (),
+        }
+    }
+
+    for conflict in find_conflicts(chords) {
+        match conflict {
+            Conflict::SameButtons { buttons, chords } => report(
+                Rule::Conflict,
+                chords,
+                None,
+                format!("{} is mapped to different outputs", buttons),
+            ),
+            Conflict::Duplicate { chords } => report(
+                Rule::Duplicate,
+                chords,
+                None,
+                "The same row repeats".to_string(),
+            ),
+        }
+    }
+
+    for duplicate in find_duplicate_outputs(chords) {
+        report(
+            Rule::DuplicateOutput,
+            duplicate.chords,
+            None,
+            format!("{:?} is typed by more than one chord", duplicate.output),
+        );
+    }
+
+    for (i, chord) in chords.iter().enumerate() {
+        let pressed = ButtonState::lossy(chord).len();
+        if pressed > 4 {
+            report(
+                Rule::TooManyButtons,
+                vec![i],
+                None,
+                format!("{} buttons are hard to press at once", pressed),
+            );
+        }
+    }
+
+    for i in find_impossible(chords) {
+        report(
+            Rule::Impossible,
+            vec![i],
+            None,
+            "A row's left and right buttons can't be pressed with one finger".to_string(),
+        );
+    }
+
+    for (i, output) in outputs.iter().enumerate() {
+        if let Ok(ChordOutput::Macro(steps)) = output {
+            if steps.len() > options.max_macro_steps {
+                report(
+                    Rule::MacroTooLong,
+                    vec![i],
+                    None,
+                    format!(
+                        "The macro has {} steps, more than {}",
+                        steps.len(),
+                        options.max_macro_steps
+                    ),
+                );
+            }
+        }
+    }
+
+    for (layer, indices) in unreachable_layers(chords) {
+        report(
+            Rule::UnreachableLayer,
+            indices,
+            None,
+            format!("No chord switches to layer {}", layer),
+        );
+    }
+    findings
+}
+
+// The chords on each layer that can't be reached by switching layers from
+// the first one
+fn unreachable_layers(chords: &[Chord]) -> BTreeMap<u8, Vec<usize>> {
+    let mut reachable = BTreeSet::from([0]);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for chord in chords {
+            if let Some(layer) = chord.get_layer_switch() {
+                if reachable.contains(&chord.layer()) {
+                    changed |= reachable.insert(layer);
+                }
+            }
+        }
+    }
+
+    let mut unreachable: BTreeMap<u8, Vec<usize>> = BTreeMap::new();
+    for (i, chord) in chords.iter().enumerate() {
+        if !reachable.contains(&chord.layer()) {
+            unreachable.entry(chord.layer()).or_default().push(i);
+        }
+    }
+    unreachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(findings: &[Finding]) -> Vec<(Rule, Vec<usize>)> {
+        findings
+            .iter()
+            .map(|finding| (finding.rule, finding.chords.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let mut chords = vec![
+            Chord::new("", "1L", "<Tab"),
+            Chord::new("", "1M", "<Nope>"),
+            Chord::new("", "2L", "a"),
+            Chord::new("", "2L", "b"),
+            Chord::new("1", "1L 2L 3L 4L", "e"),
+            Chord::new("", "1L 1R", "f"),
+            Chord::new("", "3M", "abcdef"),
+            Chord::new("", "4M", "<Layer:1>"),
+            Chord::new("", "1L", "x"),
+            Chord::new("", "1L", "y"),
+            Chord::new("", "1R", "z"),
+            Chord::new("", "3L", "a"),
+            Chord::new("", "2L 2R", "g"),
+        ];
+        chords[8].set_layer(1);
+        chords[9].set_layer(2);
+        chords[10].set_layer(2);
+
+        let options = LintOptions {
+            max_macro_steps: 5,
+            ..LintOptions::default()
+        };
+        let findings = lint(&chords, &options);
+        assert!(
+            rules(&findings)
+                == [
+                    (Rule::MalformedTag, vec![0]),
+                    (Rule::UnknownKey, vec![1]),
+                    (Rule::Conflict, vec![2, 3]),
+                    (Rule::DuplicateOutput, vec![2, 11]),
+                    (Rule::TooManyButtons, vec![4]),
+                    // A finding for each chord
+                    (Rule::Impossible, vec![5]),
+                    (Rule::Impossible, vec![12]),
+                    (Rule::MacroTooLong, vec![6]),
+                    (Rule::UnreachableLayer, vec![9, 10]),
+                ]
+        );
+        assert!(findings[2].severity == Severity::Error);
+        assert!(findings[3].severity == Severity::Warning);
+        assert!(findings[8].message == "No chord switches to layer 2");
+        // Where in the output the tag starts
+        assert!(findings[0].column == Some(0) && findings[2].column.is_none());
+
+        let options = LintOptions {
+            allowed: BTreeSet::from([Rule::Conflict, Rule::UnreachableLayer]),
+            ..options
+        };
+        let findings = lint(&chords, &options);
+        assert!(findings.len() == 7);
+        assert!(!findings.iter().any(|f| f.rule == Rule::Conflict));
+        // The default limit is the firmware's
+        assert!(!lint(&chords, &LintOptions::default())
+            .iter()
+            .any(|f| f.rule == Rule::MacroTooLong));
+    }
+
+    #[test]
+    fn test_rule_ids() {
+        for rule in Rule::ALL {
+            assert!(Rule::from_id(rule.id()) == Some(rule));
+        }
+        assert!(Rule::from_id("warnings").is_none());
+    }
+}
@@ -1,240 +1,2319 @@
-#[macro_use]
-extern crate simple_error;
+use std::io::{Cursor, IsTerminal, Read, Seek, Write};
+use std::ops::RangeInclusive;
+use std::process::ExitCode;
 
-use std::io::{Read, Seek, SeekFrom};
+use twiddler_cfg::{
+    analysis,
+    buttons::{self, ButtonState, Notation},
+    config,
+    convert::{self, SortOrder},
+    corpus, csv, diff, export, hid, inspect, intern, json,
+    layout::{Host, Layout, TargetOs, UnicodeEntry},
+    lint::{self, Rule, Severity},
+    migrate, optimize,
+    output::ParseOptions,
+    plan, presets,
+    query::{self, Pattern, Query},
+    render,
+    report::{self, MessageFormat},
+    simulate, template, transform, twiddler5, twiddler6, twiddler7, validate, Format, Metadata,
+};
 
-use byteorder::ReadBytesExt;
+#[cfg(feature = "device")]
+use twiddler_cfg::device::{self, Device};
+#[cfg(feature = "tui")]
+use twiddler_cfg::tui;
 
-mod buttons;
-mod csv;
-mod dido;
-mod hid;
-mod twiddler5;
-mod twiddler6;
-mod twiddler7;
-
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand};
 use clio::*;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    name = "twiddler-cfg",
+    version,
+    about,
+    long_about = None,
+    arg_required_else_help = true
+)]
 struct Opt {
-    #[clap(value_parser)]
-    input: Input,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print a man page, e.g. to /usr/local/share/man/man1/twiddler-cfg.1
+    #[clap(long, exclusive = true)]
+    generate_man: bool,
+
+    /// Log what reading, converting and flashing do on stderr, -vv for
+    /// every chord
+    #[clap(long, short, action = clap::ArgAction::Count, global = true)]
+    verbose: u8,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a config to another format, use - for stdin/stdout
+    Convert {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Format to write
+        #[clap(long, short, default_value = "v7")]
+        to: Format,
+
+        /// Generate upper case versions of chords with shift,
+        /// 1 2 3 or 4 for the thumb key that should act as shift
+        #[clap(long, short)]
+        generate_caps: Option<i32>,
+
+        /// Skip adding the default system chords to the output
+        #[clap(long, short)]
+        skip_system_chords: bool,
+
+        /// Add the chords Twiddler 2 firmware types by holding the Alt, Ctrl
+        /// or Shift thumb (T2, T3 or T4) with a single key chord, for a v5
+        /// layout on a Twiddler where thumbs are only chord buttons
+        #[clap(long)]
+        thumb_modifiers: bool,
+
+        /// Keyboard layout of the computer the Twiddler is used with, for
+        /// turning text into key codes and back
+        #[clap(long, short, default_value = "us")]
+        layout: Layout,
+
+        /// Type characters the layout has no key for by their code point,
+        /// the way the computer's operating system takes them
+        #[clap(long, short)]
+        unicode: Option<UnicodeEntry>,
+
+        /// Operating system to write the config for: on a Mac, shortcuts
+        /// held with Ctrl are held with Cmd and the other way around. Sets
+        /// --unicode to the OS's way unless it's given.
+        #[clap(long, value_enum)]
+        os: Option<TargetOs>,
+
+        /// Warn about unknown keys and tags in outputs and leave them out,
+        /// instead of stopping
+        #[clap(long)]
+        lenient: bool,
+
+        /// Order to write the chords in, buttons or output keep regenerated
+        /// configs stable under version control
+        #[clap(long, value_enum, default_value = "input")]
+        sort: SortOrder,
+
+        /// Warn when the config takes more bytes than this, for a device
+        /// with less room than the format allows
+        #[clap(long)]
+        capacity: Option<usize>,
+
+        /// Write repeated macros of v6 and v7 configs once, with chords
+        /// pointing into the middle of longer ones, for layouts too big to
+        /// fit otherwise. The Tuner never writes configs this way.
+        #[clap(long)]
+        share_command_lists: bool,
+
+        /// Keep the name, author and other metadata of the input in v7
+        /// configs, in a block after the macros that the Tuner and the
+        /// firmware don't know about
+        #[clap(long)]
+        binary_metadata: bool,
+
+        /// File of `name = value` lines filling in the {{name}} variables in
+        /// CSV and JSON outputs
+        #[clap(long, value_parser)]
+        vars: Option<Input>,
+    },
+
+    /// Start a new config from one of the bundled layouts
+    New {
+        /// Layout to start from: default, backspice2 or coolhand
+        #[clap(long, short)]
+        preset: String,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format to write
+        #[clap(long, short, default_value = "v7")]
+        to: Format,
+    },
+
+    /// Check a config for rows that can't be used and chords that conflict
+    Validate {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Don't report chords that type the same output
+        #[clap(long)]
+        allow_duplicate_outputs: bool,
+
+        /// Print the problems as text, or as JSON or SARIF for CI
+        #[clap(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Check a config with rules that can each be turned off, for CI
+    Lint {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Rule not to check, like too-many-buttons
+        #[clap(long, short = 'A', value_parser = parse_rule)]
+        allow: Vec<Rule>,
+
+        /// Rule to report as an error, or `warnings` for all of them
+        #[clap(long, short = 'D', value_parser = parse_denied)]
+        deny: Vec<Denied>,
+
+        /// Steps a macro can have before it's reported, the most a v7
+        /// config can store by default
+        #[clap(long, default_value_t = lint::MAX_MACRO_STEPS)]
+        max_macro_steps: usize,
+
+        /// Print the findings as text, or as JSON or SARIF for CI
+        #[clap(long, value_enum, default_value_t)]
+        message_format: MessageFormat,
+    },
+
+    /// Remove chords for outputs another chord already types, keeping the
+    /// easiest one
+    Dedupe {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Format to write
+        #[clap(long, short, default_value = "v7")]
+        to: Format,
+    },
+
+    /// Suggest chords for the most common words a layout has none for, as CSV
+    /// rows
+    Suggest {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Text file to take the words from
+        #[clap(long, short, value_parser)]
+        corpus: Option<Input>,
+
+        /// Take the words from a built in corpus instead
+        #[clap(long, value_enum, conflicts_with = "corpus")]
+        sample: Option<corpus::BuiltIn>,
+
+        /// How many chords to suggest at most
+        #[clap(long, short = 'n', default_value_t = 20)]
+        count: usize,
+    },
+
+    /// Split a layout into lessons to learn it by, the chords typing the
+    /// most text first
+    Plan {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Text file to order the chords by, common English words if not
+        /// given
+        #[clap(long, short, value_parser)]
+        corpus: Option<Input>,
+
+        /// Order the chords by a built in corpus
+        #[clap(long, value_enum, conflicts_with = "corpus")]
+        sample: Option<corpus::BuiltIn>,
+
+        /// Chords in each lesson
+        #[clap(long, short = 'n', default_value_t = 10)]
+        size: usize,
+
+        /// Write a Markdown page instead of CSV rows with a lesson column
+        #[clap(long)]
+        markdown: bool,
+    },
+
+    /// Rewrite every chord at once, to adapt a layout to another OS or hand
+    Transform {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Format to write
+        #[clap(long, short, default_value = "v7")]
+        to: Format,
+
+        /// Hold Gui where Ctrl was held and the other way around, for
+        /// shortcuts on macOS
+        #[clap(long)]
+        swap_ctrl_gui: bool,
+
+        /// Remove the chords typing more than one key
+        #[clap(long)]
+        strip_macros: bool,
+
+        /// Type the letters in outputs in upper case, besides shortcuts
+        #[clap(long)]
+        uppercase_letters: bool,
+
+        /// Swap the L and R finger columns, for the other hand
+        #[clap(long)]
+        mirror: bool,
+    },
+
+    /// Show the name, author, version and description a config was shared with
+    Info {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+    },
+
+    /// Print a binary config as hex with the header fields, chords, command
+    /// lists and strings labelled, for debugging format mismatches
+    Inspect {
+        #[clap(value_parser)]
+        input: Input,
+    },
+
+    /// Show how often each button and modifier is used
+    Stats {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Text file to score the layout against, lower effort is better
+        #[clap(long, short, value_parser)]
+        corpus: Option<Input>,
+
+        /// Score the layout against a built in corpus instead
+        #[clap(long, value_enum, conflicts_with = "corpus")]
+        sample: Option<corpus::BuiltIn>,
+
+        /// Only count the chords in this category
+        #[clap(long)]
+        category: Option<String>,
+    },
+
+    /// Draw an SVG cheat sheet of every chord
+    Chart {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Write a searchable HTML page instead
+        #[clap(long)]
+        html: bool,
+
+        /// Write tab separated notes to import into Anki instead, the output
+        /// on the front and the buttons on the back
+        #[clap(long, conflicts_with = "html")]
+        anki: bool,
+
+        /// Write a Graphviz graph instead, of the chords a button apart or
+        /// of the modifiers chords hold
+        #[clap(long, value_enum, conflicts_with_all = ["html", "anki"])]
+        dot: Option<render::DotGraph>,
+
+        /// Only draw the chords in this category
+        #[clap(long)]
+        category: Option<String>,
+    },
+
+    /// Write a one page PDF cheat sheet to print
+    #[cfg(feature = "pdf")]
+    Pdf {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Chords side by side
+        #[clap(long, default_value_t = 4)]
+        columns: usize,
+
+        /// Size of the outputs in points, made smaller when the chords
+        /// wouldn't fit on the page
+        #[clap(long, default_value_t = 8.0)]
+        font_size: f64,
+
+        /// Only print the chords in this category
+        #[clap(long)]
+        category: Option<String>,
+    },
+
+    /// List chords a single mispressed button apart that type very
+    /// different things, riskiest first
+    Confusable {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// How many pairs to show
+        #[clap(long, short = 'n', default_value = "20")]
+        limit: usize,
+    },
+
+    /// Show how much each button is used, colored from white to red
+    Heatmap {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Count letter chords by how often English text has the letter,
+        /// and nothing else
+        #[clap(long, short)]
+        letters: bool,
+
+        /// Write an SVG here instead of printing to the terminal
+        #[clap(long, short, value_parser)]
+        svg: Option<Output>,
+    },
+
+    /// Draw the buttons of a chord in the terminal
+    Show {
+        /// Buttons to draw, like "T1 1R 2M" or "<Num> RM00"
+        #[clap(required_unless_present = "output")]
+        buttons: Option<String>,
+
+        /// Draw the chords typing this output in --config instead
+        #[clap(long, requires = "config", conflicts_with = "buttons")]
+        output: Option<String>,
+
+        /// Config to look up --output in
+        #[clap(long, short, value_parser)]
+        config: Option<Input>,
+
+        /// Format of the config, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+    },
+
+    /// List the chords matching every filter given, to see what a layout
+    /// already binds
+    Search {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Outputs exactly this, in the CSV notation
+        #[clap(long, short)]
+        output: Option<String>,
+
+        /// Outputs containing this
+        #[clap(long, short)]
+        contains: Option<String>,
+
+        /// Outputs a regular expression matches, e.g. "^[a-z]+ $"
+        #[clap(long, short, value_parser = Pattern::new)]
+        regex: Option<Pattern>,
+
+        /// Chords on exactly these buttons, e.g. "T1 1R"
+        #[clap(long, short)]
+        buttons: Option<ButtonState>,
+
+        /// Chords pressing at least these buttons
+        #[clap(long, short)]
+        pressing: Option<ButtonState>,
+
+        /// Chords holding a modifier, e.g. L-Ctrl or Ctrl for either side
+        #[clap(long, short, value_parser = parse_modifier)]
+        modifier: Option<u8>,
+
+        /// Chords pressing this many buttons, e.g. 2, 2-4 or 3+
+        #[clap(long, short = 'n', value_parser = parse_count)]
+        count: Option<RangeInclusive<usize>>,
 
-    #[clap(value_parser)]
-    output: Output,
+        /// Chords in this category, from the category column of a CSV file
+        /// or field of a JSON one
+        #[clap(long)]
+        category: Option<String>,
+    },
 
-    /// Generate upper case versions of chords with shift,
-    /// 1 2 3 or 4 for the thumb key that should act as shift
-    #[clap(long, short)]
+    /// List the fewest chords that type some text, and the characters no
+    /// chord types
+    Encode {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Text to type
+        text: String,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+    },
+
+    /// Write the chords as a config for other keyboards, to practice a
+    /// layout without the Twiddler
+    Export {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// What to export for
+        #[clap(long, short)]
+        to: export::Target,
+    },
+
+    /// Write a CSV config with every chord's buttons in the same order and
+    /// its tags spelled the same way, so diffs only show real changes
+    Fmt {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Write every chord's buttons in this notation instead of the one
+        /// it's in
+        #[clap(long, short)]
+        notation: Option<Notation>,
+    },
+
+    /// Carry a binary config over to the version other firmware reads,
+    /// listing anything the new version can't hold
+    Migrate {
+        #[clap(value_parser)]
+        input: Input,
+
+        #[clap(value_parser)]
+        output: Output,
+
+        /// Version to migrate to, v6 or v7
+        #[clap(long, short, default_value = "v6")]
+        to: Format,
+    },
+
+    /// Write a config to the Twiddler plugged in over USB, backing up the
+    /// one on it
+    #[cfg(feature = "device")]
+    Flash {
+        #[clap(value_parser)]
+        input: Input,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Where the Twiddler's drive is mounted, found by its name if not
+        /// given
+        #[clap(long, short)]
+        mount: Option<std::path::PathBuf>,
+
+        /// Keyboard layout of the computer the Twiddler is used with
+        #[clap(long, short, default_value = "us")]
+        layout: Layout,
+
+        /// Operating system of the computer the Twiddler is used with, see
+        /// convert --os
+        #[clap(long, value_enum)]
+        os: Option<TargetOs>,
+
+        /// Leave the drive mounted afterwards
+        #[clap(long)]
+        no_eject: bool,
+
+        /// Warn when the config takes more bytes than this, for a device
+        /// with less room than the format allows
+        #[clap(long)]
+        capacity: Option<usize>,
+
+        /// Write repeated macros of v6 and v7 configs once, with chords
+        /// pointing into the middle of longer ones, for layouts too big to
+        /// fit otherwise. The Tuner never writes configs this way.
+        #[clap(long)]
+        share_command_lists: bool,
+
+        /// File of `name = value` lines filling in the {{name}} variables in
+        /// CSV and JSON outputs
+        #[clap(long, value_parser)]
+        vars: Option<Input>,
+    },
+
+    /// Copy the config off the Twiddler into the backup history
+    #[cfg(feature = "device")]
+    Backup {
+        /// Where the Twiddler's drive is mounted, found by its name if not
+        /// given
+        #[clap(long, short)]
+        mount: Option<std::path::PathBuf>,
+
+        /// List the backups instead, oldest first
+        #[clap(long)]
+        list: bool,
+    },
+
+    /// Put a config from the backup history back on the Twiddler
+    #[cfg(feature = "device")]
+    Restore {
+        /// Name or path of the backup, the latest one if not given
+        backup: Option<String>,
+
+        /// Where the Twiddler's drive is mounted, found by its name if not
+        /// given
+        #[clap(long, short)]
+        mount: Option<std::path::PathBuf>,
+
+        /// Show how the backup differs from the config on the Twiddler
+        /// instead of restoring it
+        #[clap(long)]
+        diff: bool,
+
+        /// Leave the drive mounted afterwards
+        #[clap(long)]
+        no_eject: bool,
+    },
+
+    /// Convert a config again every time it's saved, until stopped with
+    /// Ctrl+C
+    Watch {
+        input: std::path::PathBuf,
+
+        /// Where to write the converted config
+        #[clap(long, short)]
+        out: std::path::PathBuf,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Format to write
+        #[clap(long, short, default_value = "v7")]
+        to: Format,
+
+        /// Keyboard layout of the computer the Twiddler is used with, for
+        /// turning text into key codes and back
+        #[clap(long, short, default_value = "us")]
+        layout: Layout,
+
+        /// File of `name = value` lines filling in the {{name}} variables in
+        /// CSV and JSON outputs, read again for every conversion
+        #[clap(long)]
+        vars: Option<std::path::PathBuf>,
+
+        /// Also write every v7 config to the Twiddler plugged in over USB,
+        /// mounted at the given path or found by its name. Its config is
+        /// backed up first, see backup.
+        #[cfg(feature = "device")]
+        #[clap(long)]
+        flash: Option<Option<std::path::PathBuf>>,
+    },
+
+    /// Edit a config in the terminal, with the buttons of each chord drawn
+    /// and conflicts shown as they happen
+    #[cfg(feature = "tui")]
+    Edit {
+        input: std::path::PathBuf,
+
+        /// Format of the input, detected from the file contents if not given
+        #[clap(long, short)]
+        from: Option<Format>,
+
+        /// Where to save, the input file if not given
+        #[clap(long, short)]
+        out: Option<std::path::PathBuf>,
+
+        /// Format to save as, CSV for CSV input and v7 otherwise if not
+        /// given
+        #[clap(long, short)]
+        to: Option<Format>,
+    },
+
+    /// Show chords that were added, removed or changed between two configs
+    Diff {
+        #[clap(value_parser)]
+        old: Input,
+
+        #[clap(value_parser)]
+        new: Input,
+    },
+
+    /// Print a completion script for a shell
+    ///
+    /// e.g. `twiddler-cfg completions bash > /etc/bash_completion.d/twiddler-cfg`
+    Completions {
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+fn main() -> ExitCode {
+    let opt = Opt::parse();
+    let level = match opt.verbose {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .without_time()
+        .init();
+
+    let Some(command) = opt.command else {
+        return match run_generate_man() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    };
+
+    let res = match command {
+        Command::Convert {
+            mut input,
+            mut output,
+            from,
+            to,
+            generate_caps,
+            skip_system_chords,
+            thumb_modifiers,
+            layout,
+            unicode,
+            os,
+            lenient,
+            sort,
+            capacity,
+            share_command_lists,
+            binary_metadata,
+            vars,
+        } => load_variables(vars)
+            .and_then(|variables| {
+                load_config_with(
+                    &mut input,
+                    from,
+                    Host {
+                        layout,
+                        unicode,
+                        os,
+                    },
+                    ParseOptions { strict: !lenient },
+                    Some(&variables),
+                )
+            })
+            .and_then(|mut config| {
+                if thumb_modifiers {
+                    let added = config.add_thumb_modifier_chords();
+                    eprintln!("Adding {} chords held with modifier thumbs", added);
+                }
+                convert::sort_chords(&mut config, sort, layout);
+                run_convert(
+                    config,
+                    &mut output,
+                    to,
+                    generate_caps,
+                    !skip_system_chords,
+                    layout,
+                    Fit {
+                        capacity,
+                        share_command_lists,
+                        binary_metadata,
+                    },
+                )
+            }),
+        Command::New {
+            preset,
+            mut output,
+            to,
+        } => run_new(&preset, &mut output, to),
+        Command::Validate {
+            mut input,
+            from,
+            allow_duplicate_outputs,
+            message_format,
+        } => run_validate(&mut input, from, allow_duplicate_outputs, message_format),
+        Command::Lint {
+            mut input,
+            from,
+            allow,
+            deny,
+            max_macro_steps,
+            message_format,
+        } => run_lint(
+            &mut input,
+            from,
+            allow,
+            &deny,
+            max_macro_steps,
+            message_format,
+        ),
+        Command::Dedupe {
+            mut input,
+            mut output,
+            from,
+            to,
+        } => run_dedupe(&mut input, &mut output, from, to),
+        Command::Suggest {
+            mut input,
+            mut output,
+            from,
+            corpus,
+            sample,
+            count,
+        } => run_suggest(&mut input, &mut output, from, corpus, sample, count),
+        Command::Plan {
+            mut input,
+            mut output,
+            from,
+            corpus,
+            sample,
+            size,
+            markdown,
+        } => run_plan(
+            &mut input,
+            &mut output,
+            from,
+            corpus,
+            sample,
+            size,
+            markdown,
+        ),
+        Command::Transform {
+            mut input,
+            mut output,
+            from,
+            to,
+            swap_ctrl_gui,
+            strip_macros,
+            uppercase_letters,
+            mirror,
+        } => {
+            let transforms = Transforms {
+                swap_ctrl_gui,
+                strip_macros,
+                uppercase_letters,
+                mirror,
+            };
+            run_transform(&mut input, &mut output, from, to, transforms)
+        }
+        Command::Info { mut input, from } => run_info(&mut input, from),
+        Command::Inspect { mut input } => run_inspect(&mut input),
+        Command::Stats {
+            mut input,
+            from,
+            corpus,
+            sample,
+            category,
+        } => run_stats(&mut input, from, corpus, sample, category),
+        Command::Chart {
+            mut input,
+            mut output,
+            from,
+            html,
+            anki,
+            dot,
+            category,
+        } => run_chart(&mut input, &mut output, from, html, anki, dot, category),
+        #[cfg(feature = "pdf")]
+        Command::Pdf {
+            mut input,
+            mut output,
+            from,
+            columns,
+            font_size,
+            category,
+        } => {
+            let options = twiddler_cfg::pdf::PdfOptions { columns, font_size };
+            run_pdf(&mut input, &mut output, from, &options, category)
+        }
+        Command::Confusable {
+            mut input,
+            from,
+            limit,
+        } => run_confusable(&mut input, from, limit),
+        Command::Heatmap {
+            mut input,
+            from,
+            letters,
+            svg,
+        } => run_heatmap(&mut input, from, letters, svg),
+        Command::Show {
+            buttons,
+            output,
+            config,
+            from,
+        } => run_show(buttons.as_deref(), output.as_deref(), config, from),
+        Command::Search {
+            mut input,
+            from,
+            output,
+            contains,
+            regex,
+            buttons,
+            pressing,
+            modifier,
+            count,
+            category,
+        } => {
+            let queries = [
+                output.map(Query::Output),
+                contains.map(Query::Contains),
+                regex.map(Query::Matches),
+                buttons.map(Query::Buttons),
+                pressing.map(Query::Pressing),
+                modifier.map(Query::Modifier),
+                count.map(Query::ButtonCount),
+                category.map(Query::Category),
+            ];
+            run_search(
+                &mut input,
+                from,
+                Query::All(queries.into_iter().flatten().collect()),
+            )
+        }
+        Command::Encode {
+            mut input,
+            text,
+            from,
+        } => run_encode(&mut input, &text, from),
+        Command::Export {
+            mut input,
+            mut output,
+            from,
+            to,
+        } => run_export(&mut input, &mut output, from, to),
+        Command::Fmt {
+            mut input,
+            mut output,
+            notation,
+        } => run_fmt(&mut input, &mut output, notation),
+        Command::Migrate {
+            mut input,
+            mut output,
+            to,
+        } => run_migrate(&mut input, &mut output, to),
+        #[cfg(feature = "device")]
+        Command::Flash {
+            mut input,
+            from,
+            mount,
+            layout,
+            os,
+            no_eject,
+            capacity,
+            share_command_lists,
+            vars,
+        } => run_flash(
+            &mut input,
+            from,
+            mount,
+            Host {
+                layout,
+                unicode: None,
+                os,
+            },
+            !no_eject,
+            Fit {
+                capacity,
+                share_command_lists,
+                ..Default::default()
+            },
+            vars,
+        ),
+        #[cfg(feature = "device")]
+        Command::Backup { mount, list } => run_backup(mount, list),
+        #[cfg(feature = "device")]
+        Command::Restore {
+            backup,
+            mount,
+            diff,
+            no_eject,
+        } => run_restore(backup.as_deref(), mount, diff, !no_eject),
+        Command::Watch {
+            input,
+            out,
+            from,
+            to,
+            layout,
+            vars,
+            #[cfg(feature = "device")]
+            flash,
+        } => run_watch(
+            &input,
+            &out,
+            from,
+            to,
+            layout,
+            vars.as_deref(),
+            #[cfg(feature = "device")]
+            flash,
+        ),
+        #[cfg(feature = "tui")]
+        Command::Edit {
+            input,
+            from,
+            out,
+            to,
+        } => run_edit(&input, from, out, to),
+        Command::Diff { mut old, mut new } => run_diff(&mut old, &mut new),
+        Command::Completions { shell } => run_completions(shell),
+    };
+
+    match res {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_convert(
+    config: twiddler7::Config,
+    output: &mut Output,
+    to: Format,
     generate_caps: Option<i32>,
+    ensure_system_chords: bool,
+    layout: Layout,
+    fit: Fit,
+) -> twiddler_cfg::Result<bool> {
+    write_config_in(
+        config,
+        output,
+        to,
+        generate_caps,
+        ensure_system_chords,
+        layout,
+        fit,
+    )?;
 
-    /// Skip adding the default system chords to the output
-    #[clap(long, short)]
-    skip_system_chords: bool,
+    eprintln!("Done");
+    Ok(true)
 }
 
-fn main() {
-    let mut opt = Opt::parse();
+fn run_new(preset: &str, output: &mut Output, to: Format) -> twiddler_cfg::Result<bool> {
+    write_config(presets::config(preset)?, output, to, None, true)?;
+    Ok(true)
+}
 
-    match load_config(&mut opt.input) {
-        Ok(config) => {
-            let res = twiddler7::write(
-                config,
-                &mut opt.output,
-                opt.generate_caps,
-                !opt.skip_system_chords,
+fn write_config(
+    config: twiddler7::Config,
+    output: &mut Output,
+    to: Format,
+    generate_caps: Option<i32>,
+    ensure_system_chords: bool,
+) -> twiddler_cfg::Result<()> {
+    write_config_in(
+        config,
+        output,
+        to,
+        generate_caps,
+        ensure_system_chords,
+        Layout::Us,
+        Fit::default(),
+    )
+}
+
+fn write_config_in(
+    config: twiddler7::Config,
+    output: &mut Output,
+    to: Format,
+    generate_caps: Option<i32>,
+    ensure_system_chords: bool,
+    layout: Layout,
+    fit: Fit,
+) -> twiddler_cfg::Result<()> {
+    let data = config_bytes(config, to, generate_caps, ensure_system_chords, layout, fit)?;
+    output.write_all(&data)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn config_bytes(
+    mut config: twiddler7::Config,
+    to: Format,
+    generate_caps: Option<i32>,
+    ensure_system_chords: bool,
+    layout: Layout,
+    fit: Fit,
+) -> twiddler_cfg::Result<Vec<u8>> {
+    // Only CSV and JSON files keep it unless asked for
+    if to == Format::Twiddler7 && !fit.binary_metadata {
+        config.metadata = Metadata::default();
+    }
+    // Before writing, which fails when chords can't reach their macros
+    if matches!(to, Format::Twiddler6 | Format::Twiddler7) {
+        warn_size(&config, to, layout, fit)?;
+    }
+
+    // Stdout can't seek so build the whole file in memory first
+    let mut buffer = Cursor::new(vec![]);
+    match to {
+        Format::Twiddler7 => print_lists(&twiddler7::write(
+            config,
+            &mut buffer,
+            generate_caps,
+            ensure_system_chords,
+            fit.share_command_lists,
+        )?),
+        _ if generate_caps.is_some() => {
+            return Err(twiddler_cfg::Error::Unsupported(
+                "--generate-caps is only supported for v7 output".to_string(),
+            ))
+        }
+        Format::Twiddler6 => {
+            let lists = convert::interned(&config, to, fit.share_command_lists)?;
+            let options = convert::ExportOptions {
+                share_command_lists: fit.share_command_lists,
+                ..Default::default()
+            };
+            convert::write_with(to, config, &mut buffer, layout, options)?;
+            print_lists(&lists);
+        }
+        _ => convert::write_in(to, config, &mut buffer, layout)?,
+    }
+    Ok(buffer.into_inner())
+}
+
+fn print_lists(lists: &intern::Interned) {
+    let saved = match lists.saved() {
+        0 => String::new(),
+        saved => format!(" ({} saved by sharing repeats)", saved),
+    };
+    eprintln!(
+        "Wrote config, the command lists take {} of the {} bytes chords can point into{}",
+        lists.len,
+        intern::OFFSET_LIMIT,
+        saved
+    );
+}
+
+/// How v6 and v7 configs have to fit, see convert --capacity,
+/// --share-command-lists and --binary-metadata
+#[derive(Debug, Default, Clone, Copy)]
+struct Fit {
+    capacity: Option<usize>,
+    share_command_lists: bool,
+    binary_metadata: bool,
+}
+
+fn warn_size(
+    config: &twiddler7::Config,
+    to: Format,
+    layout: Layout,
+    fit: Fit,
+) -> twiddler_cfg::Result<()> {
+    match fit.capacity {
+        Some(capacity) => {
+            let size = convert::encoded_size(config, to, fit.share_command_lists)?;
+            if size <= capacity {
+                return Ok(());
+            }
+            eprintln!(
+                "Warning: the config takes {} bytes but only {} fit, {} too many. The largest macros are",
+                size,
+                capacity,
+                size - capacity
             );
-            match res {
-                Ok(_) => {
-                    println!("Done");
-                }
-                Err(e) => {
-                    println!("Failed to write output config: {:?}", e);
-                }
+        }
+        // Writing fails saying why
+        None => {
+            let lists = convert::interned(config, to, fit.share_command_lists)?;
+            if lists.check().is_ok() {
+                return Ok(());
             }
+            eprintln!("The largest macros are");
         }
-        Err(e) => {
-            println!("Failed to load input config: {:?}", e);
+    }
+    for size in convert::largest_macros(config, layout).iter().take(10) {
+        let layer = match size.layer {
+            0 => String::new(),
+            layer => format!(" on layer {}", layer),
+        };
+        let mut output: String = size.output.chars().take(60).collect();
+        if output.len() < size.output.len() {
+            output.push('…');
         }
+        eprintln!(
+            "  {:>5} bytes  {}{}  {:?}",
+            size.bytes, size.buttons, layer, output
+        );
     }
+    Ok(())
 }
 
-fn load_config<R: Read + Seek>(
-    reader: &mut R,
-) -> std::result::Result<twiddler7::Config, Box<dyn std::error::Error>> {
-    reader.seek(SeekFrom::Start(0))?;
-    if reader.read_u8()? == 0x05 {
-        println!("Reading input as Twiddler 5 config");
-        reader.seek(SeekFrom::Start(0))?;
-        let config = twiddler5::parse(reader)?;
-        return Ok(twiddler5_to_twiddler7(&config));
+fn run_validate(
+    input: &mut Input,
+    from: Option<Format>,
+    allow_duplicate_outputs: bool,
+    message_format: MessageFormat,
+) -> twiddler_cfg::Result<bool> {
+    let mut reader = read_input(input)?;
+    let from = match from {
+        Some(format) => format,
+        None => detect_format(&mut reader)?,
+    };
+
+    // CSV chords keep their lines so the reported rows match the file, even
+    // after rows that were skipped
+    let mut diagnostics = vec![];
+    let mut lines = vec![];
+    let mut warnings = vec![];
+    let chords = match from {
+        Format::Csv => {
+            let (rows, csv_diagnostics) = csv::parse_lenient_lines(&mut reader);
+            diagnostics = csv_diagnostics;
+            lines = rows.iter().map(|(line, _)| *line).collect();
+            rows.into_iter().map(|(_, chord)| chord).collect()
+        }
+        // Only CharaChorder backups warn in a strict read, about the chords
+        // they left out
+        _ => {
+            let config;
+            (config, warnings) =
+                convert::read_with(from, &mut reader, Host::default(), ParseOptions::STRICT)?;
+            to_chords(config)?
+        }
+    };
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
     }
 
-    reader.seek(SeekFrom::Start(4))?;
-    if reader.read_u8()? == 0x06 {
-        println!("Twiddler 6 config detected");
-        bail!("Twiddler 6 config not supported yet");
+    if message_format != MessageFormat::Text {
+        // The same checks as the lint rules
+        let checked = [
+            Rule::Conflict,
+            Rule::Duplicate,
+            Rule::Impossible,
+            Rule::DuplicateOutput,
+        ];
+        let allowed = Rule::ALL
+            .into_iter()
+            .filter(|rule| {
+                !checked.contains(rule)
+                    || (*rule == Rule::DuplicateOutput && allow_duplicate_outputs)
+            })
+            .collect();
+        let options = lint::LintOptions {
+            allowed,
+            ..Default::default()
+        };
+        let findings = lint::lint(&chords, &options);
+        let file = input.path().display().to_string();
+        let unreadable = diagnostics.iter().map(|diagnostic| {
+            let rule = match diagnostic.problem {
+                csv::Problem::Unreadable => Rule::Unreadable,
+                csv::Problem::MalformedTag => Rule::MalformedTag,
+                csv::Problem::UnknownKey => Rule::UnknownKey,
+            };
+            report::Diagnostic {
+                file: file.clone(),
+                line: Some(diagnostic.line),
+                column: diagnostic.column,
+                chord: None,
+                rule,
+                severity: rule.severity(),
+                message: diagnostic.reason.clone(),
+            }
+        });
+        let source = report::Source {
+            file: &file,
+            csv: (from == Format::Csv).then(|| (text(&reader), lines.as_slice())),
+        };
+        let all: Vec<report::Diagnostic> = unreadable
+            .chain(report::diagnostics(&findings, &chords, source))
+            .collect();
+        print_report(message_format, &all);
+        return Ok(all.is_empty() && warnings.is_empty());
     }
 
-    reader.seek(SeekFrom::Start(4))?;
-    if reader.read_u8()? == 0x07 {
-        println!("Twiddler 7 config detected");
-        println!("Running through twiddler 7 parser to ensure it's valid");
-        reader.seek(SeekFrom::Start(0))?;
-        let conf = twiddler7::parse(reader)?;
-        return Ok(conf);
+    for diagnostic in &diagnostics {
+        match diagnostic.column {
+            Some(column) => println!(
+                "Line {}, column {}: {}",
+                diagnostic.line, column, diagnostic.reason
+            ),
+            None => println!("Line {}: {}", diagnostic.line, diagnostic.reason),
+        }
     }
 
-    reader.seek(SeekFrom::Start(0))?;
-    if reader.read_u8()? == '#' as u8 {
-        println!("Starts with a #, assuming Dido config");
-        let res = dido::parse(reader);
-        match res {
-            Ok(config) => {
-                let config7 = dido_to_twiddler7(config);
-                return Ok(config7);
+    let conflicts = validate::find_conflicts(&chords);
+    for conflict in &conflicts {
+        match conflict {
+            validate::Conflict::SameButtons { buttons, chords } => {
+                println!(
+                    "{} map {} to different outputs",
+                    rows(chords, &lines),
+                    buttons
+                );
             }
-            Err(e) => {
-                bail!("Failed parsing dido config: {:?}", e);
+            validate::Conflict::Duplicate { chords } => {
+                println!("{} are duplicates", rows(chords, &lines));
             }
         }
     }
-    // CSV implementation configured according to the structure
-    println!("Reading input as CSV config");
-    reader.seek(SeekFrom::Start(0))?;
-    let chords = csv::parse(reader)?;
-    println!("Parsed CSV data successfully!");
-
-    let mut config7 = twiddler7::Config::new();
-    for chord in chords {
-        let button_state: buttons::ButtonState = chord.clone().into();
-        let hid_pairs = chord.get_hid_pairs();
-        let command = twiddler7::Command {
-            command_type: twiddler7::CommandType::Keyboard,
-            data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
-                modifier: hid_pairs.first().map_or(0, |p| p.0),
-                key_code: hid_pairs.first().map_or(0, |p| p.1),
-            }),
+
+    let impossible = validate::find_impossible(&chords);
+    if !impossible.is_empty() {
+        println!(
+            "{} can't be pressed with one hand",
+            rows(&impossible, &lines)
+        );
+    }
+
+    let duplicates = if allow_duplicate_outputs {
+        vec![]
+    } else {
+        validate::find_duplicate_outputs(&chords)
+    };
+    for duplicate in &duplicates {
+        println!(
+            "{} all type {:?}",
+            rows(&duplicate.chords, &lines),
+            duplicate.output
+        );
+    }
+
+    let ok = conflicts.is_empty()
+        && diagnostics.is_empty()
+        && warnings.is_empty()
+        && impossible.is_empty()
+        && duplicates.is_empty();
+    if ok {
+        eprintln!("No problems found");
+    }
+    Ok(ok)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Denied {
+    Warnings,
+    Rule(Rule),
+}
+
+fn parse_denied(name: &str) -> std::result::Result<Denied, String> {
+    match name {
+        "warnings" => Ok(Denied::Warnings),
+        _ => parse_rule(name).map(Denied::Rule),
+    }
+}
+
+fn parse_rule(id: &str) -> std::result::Result<Rule, String> {
+    Rule::from_id(id).ok_or_else(|| {
+        let ids: Vec<&str> = Rule::ALL.iter().map(|rule| rule.id()).collect();
+        format!("unknown rule {:?}, the rules are {}", id, ids.join(", "))
+    })
+}
+
+fn run_lint(
+    input: &mut Input,
+    from: Option<Format>,
+    allow: Vec<Rule>,
+    deny: &[Denied],
+    max_macro_steps: usize,
+    message_format: MessageFormat,
+) -> twiddler_cfg::Result<bool> {
+    let mut reader = read_input(input)?;
+    let from = match from {
+        Some(format) => format,
+        None => detect_format(&mut reader)?,
+    };
+    // CSV rows are read without checking the outputs, the rules do that
+    let (lines, chords) = match from {
+        Format::Csv => csv::parse_lines(&mut reader)?.into_iter().unzip(),
+        _ => (vec![], to_chords(convert::read(from, &mut reader)?)?),
+    };
+
+    let options = lint::LintOptions {
+        allowed: allow.into_iter().collect(),
+        max_macro_steps,
+    };
+    let mut findings = lint::lint(&chords, &options);
+    for finding in &mut findings {
+        if deny.contains(&Denied::Warnings) || deny.contains(&Denied::Rule(finding.rule)) {
+            finding.severity = Severity::Error;
+        }
+    }
+    // Counted as printed, JSON and SARIF have an entry for each chord
+    let severities: Vec<Severity> = if message_format == MessageFormat::Text {
+        for finding in &findings {
+            println!(
+                "{}: {}[{}]: {}",
+                rows(&finding.chords, &lines),
+                finding.severity,
+                finding.rule,
+                finding.message
+            );
+        }
+        findings.iter().map(|finding| finding.severity).collect()
+    } else {
+        let source = report::Source {
+            file: &input.path().display().to_string(),
+            csv: (from == Format::Csv).then(|| (text(&reader), lines.as_slice())),
         };
-        config7.chords.push(twiddler7::Chord {
-            buttons: button_state.into(),
-            command,
-        });
+        let diagnostics = report::diagnostics(&findings, &chords, source);
+        print_report(message_format, &diagnostics);
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.severity)
+            .collect()
+    };
+    let errors = severities
+        .iter()
+        .filter(|severity| **severity == Severity::Error)
+        .count();
+    let warnings = severities.len() - errors;
+
+    if errors + warnings == 0 {
+        eprintln!("No problems found");
+    } else {
+        eprintln!(
+            "{}, {}",
+            count(errors, "error", "errors"),
+            count(warnings, "warning", "warnings")
+        );
     }
+    Ok(errors == 0)
+}
 
-    Ok(config7)
+// The input as text, for finding columns in CSV rows
+fn text(reader: &Cursor<Vec<u8>>) -> &str {
+    std::str::from_utf8(reader.get_ref()).unwrap_or_default()
 }
 
-fn dido_to_twiddler7(config: dido::Config) -> twiddler7::Config {
-    let mut config7 = twiddler7::Config::new();
-    config.chords.iter().for_each(|c| {
-        let command = match &c.output {
-            dido::ChordOutput::HidCode(key_code) => {
-                let key_code = key_code.parse().unwrap();
-                twiddler7::Command {
-                    command_type: twiddler7::CommandType::Keyboard,
-                    data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
-                        modifier: c.modifiers,
-                        key_code,
-                    }),
-                }
-            }
-            dido::ChordOutput::StringIndex(index) => {
-                let index = index.parse::<usize>().unwrap();
-                let command = twiddler7::Command {
-                    command_type: twiddler7::CommandType::ListOfCommands,
-                    data: twiddler7::CommandData::ListOfCommands(0),
-                };
+fn print_report(format: MessageFormat, diagnostics: &[report::Diagnostic]) {
+    match format {
+        MessageFormat::Text => (),
+        MessageFormat::Json => print!("{}", report::json(diagnostics)),
+        MessageFormat::Sarif => print!("{}", report::sarif(diagnostics)),
+    }
+}
 
-                let out_string_hids = &config.strings[index];
+fn run_dedupe(
+    input: &mut Input,
+    output: &mut Output,
+    from: Option<Format>,
+    to: Format,
+) -> twiddler_cfg::Result<bool> {
+    let config = load_config(input, from)?;
+    let settings = config.settings();
+    let chords = to_chords(config)?;
 
-                let mut command_list = vec![];
+    let deduped = transform::dedupe(&chords);
+    eprintln!("Removed {} chords", chords.len() - deduped.len());
 
-                for hids in out_string_hids {
-                    command_list.push(twiddler7::Command {
-                        command_type: twiddler7::CommandType::Keyboard,
-                        data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
-                            key_code: hids.0,
-                            modifier: hids.1,
-                        }),
-                    });
-                }
+    let mut config = from_chords(&deduped)?;
+    config.set_settings(&settings);
+    write_config(config, output, to, None, true)?;
+    Ok(true)
+}
+
+fn run_suggest(
+    input: &mut Input,
+    output: &mut Output,
+    from: Option<Format>,
+    corpus: Option<Input>,
+    sample: Option<corpus::BuiltIn>,
+    count: usize,
+) -> twiddler_cfg::Result<bool> {
+    let chords = load_chords(input, from)?;
+    let corpus = match (corpus, sample) {
+        (Some(mut corpus), _) => {
+            let mut text = String::new();
+            corpus.read_to_string(&mut text)?;
+            corpus::from_text(&text)
+        }
+        (None, Some(sample)) => sample.corpus(),
+        (None, None) => {
+            return Err(twiddler_cfg::Error::Invalid(
+                "Give a --corpus or --sample to take the words from".to_string(),
+            ))
+        }
+    };
+
+    let suggested = optimize::suggest_words(&chords, &corpus, count, &analysis::Weights::default());
+    eprintln!("Suggested {} chords", suggested.len());
+    csv::export(output, &suggested)?;
+    Ok(true)
+}
+
+fn run_plan(
+    input: &mut Input,
+    output: &mut Output,
+    from: Option<Format>,
+    corpus: Option<Input>,
+    sample: Option<corpus::BuiltIn>,
+    size: usize,
+    markdown: bool,
+) -> twiddler_cfg::Result<bool> {
+    let chords = load_chords(input, from)?;
+    let text = match corpus {
+        Some(mut corpus) => {
+            let mut text = String::new();
+            corpus.read_to_string(&mut text)?;
+            text
+        }
+        None => sample
+            .unwrap_or(corpus::BuiltIn::English)
+            .corpus()
+            .sample(SAMPLE_WORDS),
+    };
+
+    let lessons = plan::plan(&chords, &text, size);
+    if markdown {
+        output.write_all(plan::markdown(&chords, &lessons).as_bytes())?;
+    } else {
+        csv::export(&mut *output, &plan::lesson_chords(&chords, &lessons))?;
+    }
+    output.flush()?;
+    if let Some(last) = lessons.last() {
+        eprintln!(
+            "{} lessons, typing {:.0}% of the text",
+            lessons.len(),
+            last.coverage * 100.0
+        );
+    }
+    Ok(true)
+}
+
+struct Transforms {
+    swap_ctrl_gui: bool,
+    strip_macros: bool,
+    uppercase_letters: bool,
+    mirror: bool,
+}
+
+fn run_transform(
+    input: &mut Input,
+    output: &mut Output,
+    from: Option<Format>,
+    to: Format,
+    transforms: Transforms,
+) -> twiddler_cfg::Result<bool> {
+    let config = load_config(input, from)?;
+    let settings = config.settings();
+    let mut chords = to_chords(config)?;
+
+    if transforms.strip_macros {
+        let count = chords.len();
+        chords = transform::strip_macros(&chords);
+        eprintln!("Removed {} macros", count - chords.len());
+    }
+    if transforms.swap_ctrl_gui {
+        let changed = transform::map_outputs(&mut chords, transform::swap_ctrl_gui);
+        eprintln!("Swapped Ctrl and Gui in {} chords", changed);
+    }
+    if transforms.uppercase_letters {
+        let changed = transform::map_outputs(&mut chords, transform::uppercase_letters);
+        eprintln!("Upper cased {} chords", changed);
+    }
+    if transforms.mirror {
+        transform::mirror(&mut chords);
+        eprintln!("Mirrored {} chords", chords.len());
+    }
+
+    let mut config = from_chords(&chords)?;
+    config.set_settings(&settings);
+    write_config(config, output, to, None, true)?;
+    Ok(true)
+}
+
+fn run_info(input: &mut Input, from: Option<Format>) -> twiddler_cfg::Result<bool> {
+    let config = load_config(input, from)?;
+    if config.metadata.is_empty() {
+        println!("No metadata");
+    }
+    for (key, value) in config.metadata.fields() {
+        if let Some(value) = value {
+            let mut key = key.to_string();
+            key[..1].make_ascii_uppercase();
+            println!("{}: {}", key, value.replace('\n', "\n  "));
+        }
+    }
+    println!("Chords: {}", config.chords.len());
+    Ok(true)
+}
+
+fn run_inspect(input: &mut Input) -> twiddler_cfg::Result<bool> {
+    let data = read_input(input)?.into_inner();
+    let regions = inspect::annotate(&data)?;
+    print!("{}", inspect::hexdump(&data, &regions));
+    Ok(true)
+}
+
+// Words of a built in corpus to score against, enough that every common
+// word comes up
+const SAMPLE_WORDS: usize = 10_000;
+
+fn run_stats(
+    input: &mut Input,
+    from: Option<Format>,
+    corpus: Option<Input>,
+    sample: Option<corpus::BuiltIn>,
+    category: Option<String>,
+) -> twiddler_cfg::Result<bool> {
+    let chords = in_category(load_chords(input, from)?, category);
+    let stats = analysis::stats(&chords);
+
+    println!(
+        "Chords: {} ({} single button, {} multi button, {:.0}% single)",
+        stats.chords,
+        stats.single_button,
+        stats.multi_button,
+        stats.single_button_ratio() * 100.0
+    );
+    let thumbs: Vec<String> = stats
+        .thumbs
+        .iter()
+        .enumerate()
+        .map(|(i, count)| format!("T{} {}", i + 1, count))
+        .collect();
+    println!("Thumbs: {}", thumbs.join(", "));
+    let fingers: Vec<String> = stats
+        .fingers
+        .iter()
+        .enumerate()
+        .map(|(row, count)| format!("{} {}", row, count))
+        .collect();
+    println!("Finger rows: {}", fingers.join(", "));
+    let modifiers: Vec<String> = hid::MODIFIER_TAGS
+        .iter()
+        .zip(stats.modifiers)
+        .filter(|(_, count)| *count > 0)
+        .map(|(tag, count)| format!("{} {}", tag, count))
+        .collect();
+    if !modifiers.is_empty() {
+        println!("Modifiers: {}", modifiers.join(", "));
+    }
+    if !stats.unassigned_letters.is_empty() {
+        let letters: String = stats.unassigned_letters.iter().collect();
+        println!("Letters without a chord: {}", letters);
+    }
+    if !stats.categories.is_empty() {
+        let categories: Vec<String> = stats
+            .categories
+            .iter()
+            .map(|(category, count)| format!("{} {}", category, count))
+            .collect();
+        println!("Categories: {}", categories.join(", "));
+    }
+
+    let text = match (corpus, sample) {
+        (Some(mut corpus), _) => {
+            let mut text = String::new();
+            corpus.read_to_string(&mut text)?;
+            Some(text)
+        }
+        (None, Some(sample)) => Some(sample.corpus().sample(SAMPLE_WORDS)),
+        (None, None) => None,
+    };
+    if let Some(text) = text {
+        let score = analysis::score(&chords, &text);
+        println!(
+            "Effort: {:.1} ({:.2} per character, {} characters without a chord)",
+            score.effort,
+            score.per_char(),
+            score.untyped
+        );
+    }
+
+    Ok(true)
+}
+
+fn run_chart(
+    input: &mut Input,
+    output: &mut Output,
+    from: Option<Format>,
+    html: bool,
+    anki: bool,
+    dot: Option<render::DotGraph>,
+    category: Option<String>,
+) -> twiddler_cfg::Result<bool> {
+    let chords = in_category(load_chords(input, from)?, category);
+    let chart = if html {
+        render::html(&chords)
+    } else if anki {
+        render::anki(&chords)
+    } else if let Some(graph) = dot {
+        render::dot(&chords, graph)
+    } else {
+        render::svg(&chords)
+    };
+    output.write_all(chart.as_bytes())?;
+    output.flush()?;
+    Ok(true)
+}
+
+#[cfg(feature = "pdf")]
+fn run_pdf(
+    input: &mut Input,
+    output: &mut Output,
+    from: Option<Format>,
+    options: &twiddler_cfg::pdf::PdfOptions,
+    category: Option<String>,
+) -> twiddler_cfg::Result<bool> {
+    let chords = in_category(load_chords(input, from)?, category);
+    output.write_all(&twiddler_cfg::pdf::pdf(&chords, options))?;
+    output.flush()?;
+    Ok(true)
+}
+
+fn run_confusable(
+    input: &mut Input,
+    from: Option<Format>,
+    limit: usize,
+) -> twiddler_cfg::Result<bool> {
+    let chords = to_chords(load_config(input, from)?)?;
+    let confusions = analysis::confusions(&chords);
+    for confusion in confusions.iter().take(limit) {
+        let (a, b) = (&chords[confusion.chords.0], &chords[confusion.chords.1]);
+        println!(
+            "{:.2} {} {:?} and {} {:?}",
+            confusion.risk,
+            a.buttons(),
+            a.output(),
+            b.buttons(),
+            b.output()
+        );
+    }
+    eprintln!("{} pairs a button apart", confusions.len());
+    Ok(true)
+}
+
+fn run_heatmap(
+    input: &mut Input,
+    from: Option<Format>,
+    letters: bool,
+    svg: Option<Output>,
+) -> twiddler_cfg::Result<bool> {
+    let chords = to_chords(load_config(input, from)?)?;
+    let usage = if letters {
+        analysis::button_usage(&chords, analysis::letter_frequency)
+    } else {
+        analysis::button_usage(&chords, |_| 1.0)
+    };
+    match svg {
+        Some(mut output) => {
+            output.write_all(render::heatmap_svg(&usage).as_bytes())?;
+            output.flush()?;
+        }
+        None => print!("{}", render::heatmap_terminal(&usage)),
+    }
+    Ok(true)
+}
+
+fn run_show(
+    buttons: Option<&str>,
+    output: Option<&str>,
+    config: Option<Input>,
+    from: Option<Format>,
+) -> twiddler_cfg::Result<bool> {
+    if let Some(buttons) = buttons {
+        print!(
+            "{}",
+            render::diagram_terminal(&buttons::parse_any(buttons)?)
+        );
+        return Ok(true);
+    }
+
+    let (Some(output), Some(mut config)) = (output, config) else {
+        return Ok(false);
+    };
+    let chords = load_chords(&mut config, from)?;
+    let output = twiddler_cfg::output::normalize(output);
+    let found: Vec<&csv::Chord> = chords
+        .iter()
+        .filter(|chord| twiddler_cfg::output::normalize(chord.output()) == output)
+        .collect();
+    for (i, chord) in found.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", chord.buttons());
+        print!("{}", render::diagram_terminal(&chord.buttons()));
+    }
+    if found.is_empty() {
+        eprintln!("No chord types {:?}", output);
+    }
+    Ok(!found.is_empty())
+}
+
+fn run_search(input: &mut Input, from: Option<Format>, query: Query) -> twiddler_cfg::Result<bool> {
+    let chords = load_chords(input, from)?;
+    let found = query::find(&chords, &query);
+    for &i in &found {
+        println!("{} {}", chords[i].buttons(), chords[i].output());
+    }
+    eprintln!("{} of {} chords", found.len(), chords.len());
+    Ok(!found.is_empty())
+}
+
+fn in_category(chords: Vec<csv::Chord>, category: Option<String>) -> Vec<csv::Chord> {
+    match category.map(Query::Category) {
+        Some(query) => chords.into_iter().filter(|c| query.matches(c)).collect(),
+        None => chords,
+    }
+}
+
+fn parse_modifier(name: &str) -> std::result::Result<u8, String> {
+    query::modifier_mask(name).ok_or_else(|| format!("unknown modifier {:?}", name))
+}
+
+// 2, 2-4 or 3+
+fn parse_count(count: &str) -> std::result::Result<RangeInclusive<usize>, String> {
+    let number = |n: &str| {
+        n.parse()
+            .map_err(|_| format!("expected 2, 2-4 or 3+, not {:?}", count))
+    };
+    match (count.strip_suffix('+'), count.split_once('-')) {
+        (Some(min), _) => Ok(number(min)?..=usize::MAX),
+        (None, Some((min, max))) => Ok(number(min)?..=number(max)?),
+        (None, None) => Ok(number(count)?..=number(count)?),
+    }
+}
+
+fn run_encode(input: &mut Input, text: &str, from: Option<Format>) -> twiddler_cfg::Result<bool> {
+    let chords = to_chords(load_config(input, from)?)?;
+    let encoding = simulate::encode(text, &chords);
+    for &i in &encoding.chords {
+        println!("{} {}", chords[i].buttons(), chords[i].output());
+    }
+    if !encoding.is_complete() {
+        let unreachable: String = encoding.unreachable.iter().map(|(_, c)| c).collect();
+        eprintln!("No chord types {:?}", unreachable);
+    }
+    eprintln!("{} chords", encoding.chords.len());
+    Ok(encoding.is_complete())
+}
+
+fn run_export(
+    input: &mut Input,
+    output: &mut Output,
+    from: Option<Format>,
+    to: export::Target,
+) -> twiddler_cfg::Result<bool> {
+    let chords = to_chords(load_config(input, from)?)?;
+    output.write_all(export::export(to, &chords).as_bytes())?;
+    output.flush()?;
+    Ok(true)
+}
+
+fn run_fmt(
+    input: &mut Input,
+    output: &mut Output,
+    notation: Option<Notation>,
+) -> twiddler_cfg::Result<bool> {
+    let mut chords = csv::parse(&mut read_input(input)?)?;
+    let changed = csv::canonicalize(&mut chords, notation)?;
+
+    let mut buffer = vec![];
+    csv::export(&mut buffer, &chords)?;
+    output.write_all(&buffer)?;
+    output.flush()?;
+    eprintln!("Reformatted {} chords", changed);
+    Ok(true)
+}
+
+fn run_migrate(input: &mut Input, output: &mut Output, to: Format) -> twiddler_cfg::Result<bool> {
+    let mut reader = read_input(input)?;
+    let from = detect_format(&mut reader)?;
+
+    let mut buffer = Cursor::new(vec![]);
+    let dropped = match (from, to) {
+        (Format::Twiddler5, Format::Twiddler6) => {
+            let migrated = migrate::upgrade(&twiddler5::parse(&mut reader)?)?;
+            print_lists(&twiddler6::write(
+                migrated.config,
+                &mut buffer,
+                None,
+                false,
+            )?);
+            migrated.dropped
+        }
+        (Format::Twiddler5, Format::Twiddler7) => {
+            let migrated = migrate::upgrade(&twiddler5::parse(&mut reader)?)?;
+            let config = migrate::upgrade_to_v7(&migrated.config);
+            print_lists(&twiddler7::write(config, &mut buffer, None, false, false)?);
+            migrated.dropped
+        }
+        (Format::Twiddler6, Format::Twiddler7) => {
+            let config = migrate::upgrade_to_v7(&twiddler6::parse(&mut reader)?);
+            print_lists(&twiddler7::write(config, &mut buffer, None, false, false)?);
+            vec![]
+        }
+        (Format::Twiddler7, Format::Twiddler6) => {
+            let migrated = migrate::downgrade(&twiddler7::parse(&mut reader)?)?;
+            print_lists(&twiddler6::write(
+                migrated.config,
+                &mut buffer,
+                None,
+                false,
+            )?);
+            migrated.dropped
+        }
+        _ => {
+            return Err(twiddler_cfg::Error::Unsupported(format!(
+            "Can't migrate {:?} configs to {:?}, only between v5, v6 and v7 and never down to v5",
+            from, to
+        )))
+        }
+    };
+
+    output.write_all(buffer.get_ref())?;
+    output.flush()?;
+    for dropped in &dropped {
+        eprintln!("Dropped {}", dropped);
+    }
+    Ok(true)
+}
+
+#[cfg(feature = "device")]
+fn run_flash(
+    input: &mut Input,
+    from: Option<Format>,
+    mount: Option<std::path::PathBuf>,
+    host: Host,
+    eject: bool,
+    fit: Fit,
+    vars: Option<Input>,
+) -> twiddler_cfg::Result<bool> {
+    let variables = load_variables(vars)?;
+    let config = load_config_with(input, from, host, ParseOptions::STRICT, Some(&variables))?;
+    let data = config_bytes(config, Format::Twiddler7, None, true, host.layout, fit)?;
+
+    let device = find_device(mount)?;
+    if let Some(backup) = device.flash(&data)? {
+        eprintln!("Backed up the old config to {}", backup.display());
+    }
+    eprintln!("Wrote {}", device.config_path().display());
+    if eject {
+        device.eject()?;
+        eprintln!("Ejected, the Twiddler can be unplugged");
+    }
+    Ok(true)
+}
+
+#[cfg(feature = "device")]
+fn run_backup(mount: Option<std::path::PathBuf>, list: bool) -> twiddler_cfg::Result<bool> {
+    let dir = device::backup_dir()?;
+    if list {
+        for backup in device::list_backups(&dir)? {
+            println!("{}", backup.display());
+        }
+        return Ok(true);
+    }
 
-                config7
-                    .command_lists
-                    .push(twiddler7::CommandList(command_list));
+    let config = find_device(mount)?.read_config()?;
+    let backup = device::save_backup(&dir, &config)?;
+    eprintln!("Backed up to {}", backup.display());
+    Ok(true)
+}
+
+#[cfg(feature = "device")]
+fn run_restore(
+    backup: Option<&str>,
+    mount: Option<std::path::PathBuf>,
+    diff: bool,
+    eject: bool,
+) -> twiddler_cfg::Result<bool> {
+    let backup = device::find_backup(&device::backup_dir()?, backup)?;
+    let data = std::fs::read(&backup)?;
+    let device = find_device(mount)?;
+
+    if diff {
+        let read = |data: Vec<u8>| convert::read(Format::Twiddler7, &mut Cursor::new(data));
+        let current = to_chords(read(device.read_config()?)?)?;
+        return Ok(print_diff(&current, &to_chords(read(data)?)?));
+    }
+
+    device.flash(&data)?;
+    eprintln!("Restored {}", backup.display());
+    if eject {
+        device.eject()?;
+        eprintln!("Ejected, the Twiddler can be unplugged");
+    }
+    Ok(true)
+}
 
-                command
+#[cfg(feature = "device")]
+fn find_device(mount: Option<std::path::PathBuf>) -> twiddler_cfg::Result<Device> {
+    match mount {
+        Some(mount) => Ok(Device::at(mount)),
+        None => Device::find(),
+    }
+}
+
+fn run_watch(
+    input: &std::path::Path,
+    out: &std::path::Path,
+    from: Option<Format>,
+    to: Format,
+    layout: Layout,
+    vars: Option<&std::path::Path>,
+    #[cfg(feature = "device")] flash: Option<Option<std::path::PathBuf>>,
+) -> twiddler_cfg::Result<bool> {
+    // Each flash overwrites the device's 0.cfg.bak, so the config from
+    // before watching is kept with the timestamped backups
+    #[cfg(feature = "device")]
+    if let Some(mount) = &flash {
+        let config = find_device(mount.clone())?.read_config()?;
+        let backup = device::save_backup(&device::backup_dir()?, &config)?;
+        eprintln!("Backed up the Twiddler's config to {}", backup.display());
+    }
+
+    let modified = || std::fs::metadata(input).and_then(|m| m.modified()).ok();
+    let mut last = None;
+    eprintln!("Watching {}, Ctrl+C to stop", input.display());
+    loop {
+        let now = modified();
+        if now.is_none() || now == last {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            continue;
+        }
+        last = now;
+
+        // Errors are printed and the next save tries again
+        let converted = std::fs::read(input)
+            .map_err(twiddler_cfg::Error::from)
+            .and_then(|data| {
+                let mut reader = Cursor::new(data);
+                let from = match from {
+                    Some(format) => format,
+                    None => detect_format(&mut reader)?,
+                };
+                let variables = match vars {
+                    Some(vars) => template::parse(&mut std::fs::File::open(vars)?)?,
+                    None => template::Variables::new(),
+                };
+                let mut reader = expand_variables(reader, from, &variables)?;
+                let config = convert::read_in(from, &mut reader, layout)?;
+                config_bytes(config, to, None, true, layout, Fit::default())
+            });
+        let data = match converted {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
             }
         };
+        if let Err(e) = std::fs::write(out, &data) {
+            eprintln!("Error: {}", e);
+            continue;
+        }
+        eprintln!("Wrote {}", out.display());
 
-        config7.chords.push(twiddler7::Chord {
-            buttons: twiddler7::ButtonData::from(&c.buttons),
-            command,
-        });
+        #[cfg(feature = "device")]
+        if let Some(mount) = &flash {
+            let flashed = match to {
+                Format::Twiddler7 => find_device(mount.clone()).and_then(|d| d.flash(&data)),
+                _ => Err(twiddler_cfg::Error::Unsupported(
+                    "--flash needs v7 output".to_string(),
+                )),
+            };
+            match flashed {
+                Ok(_) => eprintln!("Flashed the Twiddler"),
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+fn run_edit(
+    input: &std::path::Path,
+    from: Option<Format>,
+    out: Option<std::path::PathBuf>,
+    to: Option<Format>,
+) -> twiddler_cfg::Result<bool> {
+    let mut reader = Cursor::new(std::fs::read(input)?);
+    let from = match from {
+        Some(format) => format,
+        None => detect_format(&mut reader)?,
+    };
+    // CSV rows are kept as they are so rows with problems can be fixed
+    let (chords, settings) = match from {
+        Format::Csv => (csv::parse(&mut reader)?, None),
+        _ => {
+            let config = convert::read(from, &mut reader)?;
+            let settings = config.settings();
+            (to_chords(config)?, Some(settings))
+        }
+    };
+    let to = to.unwrap_or(match from {
+        Format::Csv => Format::Csv,
+        _ => Format::Twiddler7,
     });
+    let out = out.unwrap_or_else(|| input.to_path_buf());
 
-    config7
+    let mut editor = tui::Editor::new(out.display().to_string(), chords);
+    tui::run(&mut editor, |chords| {
+        let data = match to {
+            Format::Csv => {
+                let mut buffer = vec![];
+                csv::export(&mut buffer, chords)?;
+                buffer
+            }
+            _ => {
+                let mut config = from_chords(chords)?;
+                if let Some(settings) = &settings {
+                    config.set_settings(settings);
+                }
+                config_bytes(config, to, None, true, Layout::Us, Fit::default())?
+            }
+        };
+        Ok(std::fs::write(&out, data)?)
+    })?;
+    Ok(true)
 }
 
-fn twiddler5_to_twiddler7(config: &twiddler5::Config) -> twiddler7::Config {
-    let mut config7 = twiddler7::Config::new();
-    config.chords.iter().for_each(|c| {
-        let button_state = c.button_state();
+fn run_diff(old: &mut Input, new: &mut Input) -> twiddler_cfg::Result<bool> {
+    let old = to_chords(load_config(old, None)?)?;
+    let new = to_chords(load_config(new, None)?)?;
+    Ok(print_diff(&old, &new))
+}
 
-        let command = match c.mapping {
-            twiddler5::ChordMapping::KeyMapping(modifier, key_code) => twiddler7::Command {
-                command_type: twiddler7::CommandType::Keyboard,
-                data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
-                    modifier,
-                    key_code,
-                }),
-            },
-            twiddler5::ChordMapping::StringMapping(_, index) => {
-                let command = twiddler7::Command {
-                    command_type: twiddler7::CommandType::ListOfCommands,
-                    data: twiddler7::CommandData::ListOfCommands(0),
-                };
+fn print_diff(old: &[csv::Chord], new: &[csv::Chord]) -> bool {
+    let diff = diff::compare(old, new);
+    for remap in &diff.remapped {
+        println!("~ {} {} -> {}", remap.buttons, remap.old, remap.new);
+    }
+    for (buttons, output) in &diff.removed {
+        println!("- {} {}", buttons, output);
+    }
+    for (buttons, output) in &diff.added {
+        println!("+ {} {}", buttons, output);
+    }
+    diff.is_empty()
+}
 
-                let string_pos = config.string_locations[index as usize];
-                let contents = config
-                    .string_contents
-                    .iter()
-                    .find(|sc| sc.pos == string_pos.into())
-                    .unwrap();
-
-                let mut command_list = vec![];
-
-                for c in &contents.keys {
-                    match c {
-                        twiddler5::ChordMapping::KeyMapping(modifier, key_code) => {
-                            command_list.push(twiddler7::Command {
-                                command_type: twiddler7::CommandType::Keyboard,
-                                data: twiddler7::CommandData::Keyboard(twiddler7::HidCommand {
-                                    modifier: *modifier,
-                                    key_code: *key_code,
-                                }),
-                            });
-                        }
-                        _ => {}
-                    }
-                }
+fn run_completions(shell: clap_complete::Shell) -> twiddler_cfg::Result<bool> {
+    // generate panics when it can't write, so a closed pipe is an error
+    // here instead
+    let mut script = vec![];
+    clap_complete::generate(shell, &mut Opt::command(), "twiddler-cfg", &mut script);
+    std::io::stdout().write_all(&script)?;
+    Ok(true)
+}
 
-                config7
-                    .command_lists
-                    .push(twiddler7::CommandList(command_list));
+fn run_generate_man() -> twiddler_cfg::Result<()> {
+    clap_mangen::Man::new(Opt::command()).render(&mut std::io::stdout())?;
+    Ok(())
+}
+
+fn read_input(input: &mut Input) -> twiddler_cfg::Result<Cursor<Vec<u8>>> {
+    // Stdin can't seek either, format detection needs to
+    let mut data = vec![];
+    input.read_to_end(&mut data)?;
+    Ok(Cursor::new(data))
+}
+
+fn load_config(input: &mut Input, from: Option<Format>) -> twiddler_cfg::Result<twiddler7::Config> {
+    load_config_in(input, from, Host::default())
+}
+
+fn load_config_in(
+    input: &mut Input,
+    from: Option<Format>,
+    host: Host,
+) -> twiddler_cfg::Result<twiddler7::Config> {
+    load_config_with(input, from, host, ParseOptions::STRICT, None)
+}
+
+// Warnings from a lenient parse are printed
+fn load_config_with(
+    input: &mut Input,
+    from: Option<Format>,
+    host: Host,
+    options: ParseOptions,
+    variables: Option<&template::Variables>,
+) -> twiddler_cfg::Result<twiddler7::Config> {
+    let mut reader = read_input(input)?;
+    let from = match from {
+        Some(format) => format,
+        None => detect_format(&mut reader)?,
+    };
+    if let Some(variables) = variables {
+        reader = expand_variables(reader, from, variables)?;
+    }
+    let (config, warnings) = convert::read_with(from, &mut reader, host, options)?;
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(config)
+}
 
-                command
+// Without a file every variable is undefined, rather than typed as braces
+fn load_variables(vars: Option<Input>) -> twiddler_cfg::Result<template::Variables> {
+    match vars {
+        Some(mut vars) => template::parse(&mut vars),
+        None => Ok(template::Variables::new()),
+    }
+}
+
+fn expand_variables(
+    mut reader: Cursor<Vec<u8>>,
+    from: Format,
+    variables: &template::Variables,
+) -> twiddler_cfg::Result<Cursor<Vec<u8>>> {
+    match template::expand_config(&mut reader, from, variables)? {
+        Some((expanded, count)) => {
+            if count > 0 {
+                eprintln!("Filled in the variables of {} chords", count);
             }
-        };
+            Ok(Cursor::new(expanded))
+        }
+        None => {
+            if !variables.is_empty() {
+                eprintln!("Only CSV and JSON configs have variables to fill in");
+            }
+            reader.rewind()?;
+            Ok(reader)
+        }
+    }
+}
 
-        config7.chords.push(twiddler7::Chord {
-            buttons: button_state.into(),
-            command,
-        });
-    });
+fn detect_format<R: Read + Seek>(reader: &mut R) -> twiddler_cfg::Result<Format> {
+    let format = config::detect_version(reader)?.format()?;
+    match format {
+        Format::Twiddler5 => eprintln!("Reading input as Twiddler 5 config"),
+        Format::Twiddler6 => eprintln!("Twiddler 6 config detected"),
+        Format::Twiddler7 => eprintln!("Twiddler 7 config detected"),
+        Format::Dido => eprintln!("Starts with a #, assuming Dido config"),
+        Format::Charachorder => eprintln!("Reading input as CharaChorder chords"),
+        Format::Json => eprintln!("Reading input as JSON config"),
+        Format::Csv => eprintln!("Reading input as CSV config"),
+    }
+    Ok(format)
+}
+
+// The chords of a config with the categories CSV and JSON files give them,
+// which binary configs have nowhere to keep
+fn load_chords(input: &mut Input, from: Option<Format>) -> twiddler_cfg::Result<Vec<csv::Chord>> {
+    let mut reader = read_input(input)?;
+    let from = match from {
+        Some(format) => format,
+        None => detect_format(&mut reader)?,
+    };
+    let (config, warnings) =
+        convert::read_with(from, &mut reader, Host::default(), ParseOptions::STRICT)?;
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    let mut chords = to_chords(config)?;
+
+    reader.rewind()?;
+    let source = match from {
+        Format::Csv => csv::parse(&mut reader)?,
+        Format::Json => json::parse(&mut reader)?.chords,
+        _ => return Ok(chords),
+    };
+    let categories: std::collections::HashMap<_, _> = source
+        .iter()
+        .filter_map(|chord| Some(((chord.buttons(), chord.layer()), chord.category()?)))
+        .collect();
+    for chord in &mut chords {
+        if let Some(category) = categories.get(&(chord.buttons(), chord.layer())) {
+            chord.set_category(*category);
+        }
+    }
+    Ok(chords)
+}
+
+fn to_chords(config: twiddler7::Config) -> twiddler_cfg::Result<Vec<csv::Chord>> {
+    let mut buffer = Cursor::new(vec![]);
+    convert::write(Format::Csv, config, &mut buffer)?;
+    buffer.set_position(0);
+    csv::parse(&mut buffer)
+}
+
+fn from_chords(chords: &[csv::Chord]) -> twiddler_cfg::Result<twiddler7::Config> {
+    let mut buffer = Cursor::new(vec![]);
+    csv::export(&mut buffer, chords)?;
+    buffer.set_position(0);
+    convert::read(Format::Csv, &mut buffer)
+}
+
+// "1 error" or "2 errors"
+fn count(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
 
-    config7
+fn rows(indices: &[usize], lines: &[usize]) -> String {
+    // Without lines, the chords are numbered from 1
+    let (noun, rows): (_, Vec<String>) = if lines.is_empty() {
+        (
+            "Chord",
+            indices.iter().map(|i| (i + 1).to_string()).collect(),
+        )
+    } else {
+        (
+            "Row",
+            indices.iter().map(|&i| lines[i].to_string()).collect(),
+        )
+    };
+    let plural = if rows.len() == 1 { "" } else { "s" };
+    format!("{}{} {}", noun, plural, rows.join(", "))
 }
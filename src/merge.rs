@@ -0,0 +1,118 @@
+use crate::{
+    buttons::ButtonState,
+    csv::Chord,
+    diff::{first_chords, same_output},
+};
+
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Merge {
+    /// Our chords with their changes applied, conflicts keep our side
+    pub chords: Vec<Chord>,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Buttons changed differently on both sides, `None` means the chord isn't
+/// there
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MergeConflict {
+    pub buttons: ButtonState,
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+/// Merge the changes both sides made to `base`, chords are matched by their
+/// buttons like in [`crate::diff::compare`]. Our chords keep their order and
+/// chords only they added go at the end.
+pub fn three_way(base: &[Chord], ours: &[Chord], theirs: &[Chord]) -> Merge {
+    let base = first_chords(base);
+    let ours = first_chords(ours);
+    let theirs = first_chords(theirs);
+    // Chords on other layers are other buttons
+    let mut keys: Vec<(u8, ButtonState)> = vec![];
+    for (b, c) in ours.iter().chain(&theirs).chain(&base) {
+        if !keys.contains(&(c.layer(), *b)) {
+            keys.push((c.layer(), *b));
+        }
+    }
+
+    let mut merge = Merge::default();
+    for key in keys {
+        let buttons = key.1;
+        let (b, o, t) = (find(&base, key), find(&ours, key), find(&theirs, key));
+        let merged = if same(o, t) || same(b, t) {
+            o
+        } else if same(b, o) {
+            t
+        } else {
+            merge.conflicts.push(MergeConflict {
+                buttons,
+                base: b.map(|c| c.output().to_string()),
+                ours: o.map(|c| c.output().to_string()),
+                theirs: t.map(|c| c.output().to_string()),
+            });
+            o
+        };
+        merge.chords.extend(merged.cloned());
+    }
+
+    merge
+}
+
+fn find<'a>(chords: &[(ButtonState, &'a Chord)], key: (u8, ButtonState)) -> Option<&'a Chord> {
+    chords
+        .iter()
+        .find(|(b, c)| (c.layer(), *b) == key)
+        .map(|(_, c)| *c)
+}
+
+fn same(a: Option<&Chord>, b: Option<&Chord>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => same_output(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_three_way() {
+        let base = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("", "2L", "b"),
+            Chord::new("", "3L", "c"),
+            Chord::new("", "4L", "d"),
+        ];
+        // We change b and remove d, they change c, add e and change d
+        let ours = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("", "2L", "B"),
+            Chord::new("", "3L", "c"),
+        ];
+        let theirs = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("", "2L", "b"),
+            Chord::new("", "3L", "x"),
+            Chord::new("", "4L", "dd"),
+            Chord::new("", "1M", "e"),
+        ];
+
+        let merge = three_way(&base, &ours, &theirs);
+        let outputs: Vec<&str> = merge.chords.iter().map(Chord::output).collect();
+        assert!(outputs == vec!["a", "B", "x", "e"]);
+        assert!(
+            merge.conflicts
+                == vec![MergeConflict {
+                    buttons: "4L".parse().unwrap(),
+                    base: Some("d".to_string()),
+                    ours: None,
+                    theirs: Some("dd".to_string()),
+                }]
+        );
+    }
+}
@@ -0,0 +1,97 @@
+/// Where a shared config came from. JSON configs have it as `metadata`,
+/// CSV files as `# name: CoolHand` comment lines before the header row, and
+/// v7 configs after the command lists only when written with
+/// [`ExportOptions::binary_metadata`](crate::convert::ExportOptions::binary_metadata).
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+    pub description: Option<String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.fields().iter().all(|(_, value)| value.is_none())
+    }
+
+    pub fn fields(&self) -> [(&'static str, Option<&str>); 4] {
+        [
+            ("name", self.name.as_deref()),
+            ("author", self.author.as_deref()),
+            ("version", self.version.as_deref()),
+            ("description", self.description.as_deref()),
+        ]
+    }
+
+    fn field_mut(&mut self, key: &str) -> Option<&mut Option<String>> {
+        match key {
+            "name" => Some(&mut self.name),
+            "author" => Some(&mut self.author),
+            "version" => Some(&mut self.version),
+            "description" => Some(&mut self.description),
+            _ => None,
+        }
+    }
+
+    /// The CSV comments, without the #. A value over several lines gets a
+    /// comment for each.
+    pub fn to_comments(&self) -> Vec<String> {
+        let mut comments = vec![];
+        for (key, value) in self.fields() {
+            for line in value.into_iter().flat_map(str::lines) {
+                comments.push(format!(" {}: {}", key, line));
+            }
+        }
+        comments
+    }
+
+    /// Takes the `key: value` comments out of `comments`, the others are
+    /// left. Repeated keys are lines of the same value.
+    pub fn take_comments(comments: &mut Vec<String>) -> Metadata {
+        let mut metadata = Metadata::default();
+        comments.retain(|comment| {
+            let Some((key, value)) = comment.split_once(':') else {
+                return true;
+            };
+            let Some(field) = metadata.field_mut(key.trim()) else {
+                return true;
+            };
+            let value = value.trim();
+            match field {
+                Some(lines) => {
+                    lines.push('\n');
+                    lines.push_str(value);
+                }
+                None => *field = Some(value.to_string()),
+            }
+            false
+        });
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comments() {
+        let metadata = Metadata {
+            name: Some("CoolHand".to_string()),
+            version: Some("1.2".to_string()),
+            description: Some("Letters first\nthen words".to_string()),
+            ..Default::default()
+        };
+        let mut comments = metadata.to_comments();
+        assert!(comments[0] == " name: CoolHand");
+        assert!(comments.len() == 4);
+
+        comments.push(" keep: this".to_string());
+        comments.insert(0, " a note".to_string());
+        assert!(Metadata::take_comments(&mut comments) == metadata);
+        assert!(comments == [" a note", " keep: this"]);
+        assert!(Metadata::default().is_empty() && !metadata.is_empty());
+    }
+}
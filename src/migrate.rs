@@ -0,0 +1,167 @@
+use std::fmt;
+
+use crate::{
+    buttons::ButtonState,
+    convert::{twiddler5_to_twiddler7, twiddler6_to_twiddler7, twiddler7_to_twiddler6},
+    error::Result,
+    twiddler5, twiddler6, twiddler7,
+};
+
+/// Something a config had that the version it was migrated to can't hold
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Dropped {
+    /// A v5 setting that isn't at its default and has no place in v6, like
+    /// "mouse acceleration"
+    Setting { name: String },
+    /// Name, author, version and description
+    Metadata,
+    /// Every chord on a layer, layer 1 first
+    Layer { layer: u8, chords: usize },
+    /// A chord switching layers, by its index in the base layer
+    LayerSwitch { chord: usize, buttons: ButtonState },
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dropped::Setting { name } => write!(f, "the {} setting", name),
+            Dropped::Metadata => write!(f, "the name, author, version and description"),
+            Dropped::Layer { layer, chords } => write!(f, "layer {} with {} chords", layer, chords),
+            Dropped::LayerSwitch { chord, buttons } => {
+                write!(f, "chord {} on {} switching layers", chord, buttons)
+            }
+        }
+    }
+}
+
+/// A migrated config and what it lost on the way
+#[derive(Debug)]
+pub struct Migrated<T> {
+    pub config: T,
+    pub dropped: Vec<Dropped>,
+}
+
+/// A v5 config for firmware that reads v6 ones. The chords and strings all
+/// carry over, the idle time and key repeat delay are the only settings
+/// that do. Errors for a corrupt string in the config.
+pub fn upgrade(config: &twiddler5::Config) -> Result<Migrated<twiddler6::Config>> {
+    let settings = config.settings();
+    let dropped = [
+        ("options a", settings.options_a != 0),
+        ("mouse left click", settings.mouse_left_click != 0),
+        ("mouse middle click", settings.mouse_middle_click != 0),
+        ("mouse right click", settings.mouse_right_click != 0),
+        ("mouse acceleration", settings.mouse_accel_factor != 0),
+        ("options b", settings.options_b != 0),
+        ("options c", settings.options_c != 0),
+    ]
+    .into_iter()
+    .filter(|(_, set)| *set)
+    .map(|(name, _)| Dropped::Setting {
+        name: name.to_string(),
+    })
+    .collect();
+
+    Ok(Migrated {
+        config: twiddler7_to_twiddler6(&twiddler5_to_twiddler7(config)?)?,
+        dropped,
+    })
+}
+
+/// A v6 config as a v7 one, v7 holds everything v6 does
+pub fn upgrade_to_v7(config: &twiddler6::Config) -> twiddler7::Config {
+    twiddler6_to_twiddler7(config)
+}
+
+/// A v7 config for firmware that only reads v6 ones. v6 has no metadata or
+/// layers, and chords switching layers would go to the next config on the
+/// device instead, so they're left out.
+pub fn downgrade(config: &twiddler7::Config) -> Result<Migrated<twiddler6::Config>> {
+    let mut dropped = vec![];
+    if !config.metadata.is_empty() {
+        dropped.push(Dropped::Metadata);
+    }
+
+    let mut base = twiddler7::Config::new();
+    base.set_settings(&config.settings());
+    let mut command_lists = config.command_lists.iter();
+    for (i, chord) in config.chords.iter().enumerate() {
+        let command_list = match chord.command.command_type {
+            twiddler7::CommandType::ListOfCommands => command_lists.next(),
+            _ => None,
+        };
+        if chord.command.switched_layer().is_some() {
+            dropped.push(Dropped::LayerSwitch {
+                chord: i,
+                buttons: chord.buttons.into(),
+            });
+            continue;
+        }
+        base.chords.push(chord.clone());
+        base.command_lists.extend(command_list.cloned());
+    }
+
+    for (i, layer) in config.layers.iter().enumerate() {
+        dropped.push(Dropped::Layer {
+            layer: i as u8 + 1,
+            chords: layer.chords.len(),
+        });
+    }
+
+    Ok(Migrated {
+        config: twiddler7_to_twiddler6(&base)?,
+        dropped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convert::{self, Format};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_upgrade() {
+        let mut file = std::fs::File::open("configs/backspice2_v5.cfg").unwrap();
+        let config = twiddler5::parse(&mut file).unwrap();
+        let migrated = upgrade(&config).unwrap();
+        assert!(migrated.config.chords.len() == 258);
+        assert!(migrated.config.command_lists.len() == config.string_locations.len());
+        assert!(migrated.config.idle_time == 3600);
+        assert!(migrated.dropped.contains(&Dropped::Setting {
+            name: "mouse acceleration".to_string()
+        }));
+
+        let mut data = Cursor::new(vec![]);
+        twiddler6::export(&mut data, &migrated.config).unwrap();
+        data.set_position(0);
+        assert!(twiddler6::parse(&mut data).unwrap().chords.len() == 258);
+    }
+
+    #[test]
+    fn test_downgrade() {
+        let csv = "thumbs,fingers,output,layer\n,1L,the,\n,0R,<Layer:1>,\n,1M,b,\n,1M,z,1\n";
+        let mut config = convert::read(Format::Csv, &mut Cursor::new(csv)).unwrap();
+        config.metadata.name = Some("Layers".to_string());
+
+        let migrated = downgrade(&config).unwrap();
+        assert!(migrated.config.chords.len() == 2);
+        assert!(migrated.config.command_lists.len() == 1);
+        assert!(
+            migrated.dropped
+                == [
+                    Dropped::Metadata,
+                    Dropped::LayerSwitch {
+                        chord: 1,
+                        buttons: ButtonState::F0R
+                    },
+                    Dropped::Layer {
+                        layer: 1,
+                        chords: 1
+                    },
+                ]
+        );
+        assert!(upgrade_to_v7(&migrated.config).chords.len() == 2);
+    }
+}
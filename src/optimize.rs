@@ -0,0 +1,410 @@
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{
+    analysis::{chord_sequence, output_counts, ChordCostModel, Weights},
+    buttons::ButtonState,
+    corpus::Corpus,
+    csv::Chord,
+    Error, Result,
+};
+
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Constraints {
+    /// Chords, by index, that keep their buttons
+    pub pinned: Vec<usize>,
+    /// Buttons no chord may be moved to, chords already on them are moved
+    /// off unless pinned
+    pub forbidden: Vec<ButtonState>,
+}
+
+/// Move outputs between chords so typing `corpus` takes as little effort as
+/// possible, keeping the constraints. The result has the same outputs in the
+/// same order as `chords`, only their buttons change.
+///
+/// Besides the buttons already in use, outputs can be moved to any chord of
+/// one or two finger buttons, optionally with a thumb. The effort score is a
+/// sum of frequency times chord effort so the best layout is found exactly by
+/// giving the most typed outputs the easiest chords, there's no need for a
+/// search like simulated annealing.
+pub fn optimize(chords: &[Chord], corpus: &str, constraints: &Constraints) -> Result<Vec<Chord>> {
+    optimize_with(chords, corpus, constraints, &Weights::default())
+}
+
+/// [`optimize`] with the effort of each chord from `model`
+pub fn optimize_with(
+    chords: &[Chord],
+    corpus: &str,
+    constraints: &Constraints,
+    model: &dyn ChordCostModel,
+) -> Result<Vec<Chord>> {
+    let (counts, _) = output_counts(chords, corpus);
+    let is_pinned = |i: usize| constraints.pinned.contains(&i);
+
+    let slots = free_slots(chords, constraints);
+    #[cfg(feature = "rayon")]
+    let mut slots: Vec<(f64, ButtonState)> =
+        slots.into_par_iter().map(|b| (model.cost(&b), b)).collect();
+    #[cfg(not(feature = "rayon"))]
+    let mut slots: Vec<(f64, ButtonState)> =
+        slots.into_iter().map(|b| (model.cost(&b), b)).collect();
+    slots.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut movable: Vec<usize> = (0..chords.len()).filter(|i| !is_pinned(*i)).collect();
+    if movable.len() > slots.len() {
+        return Err(Error::Invalid(format!(
+            "{} chords to place but only {} free button combinations",
+            movable.len(),
+            slots.len()
+        )));
+    }
+    let count = |i: &usize| counts.get(chords[*i].output()).copied().unwrap_or(0);
+    movable.sort_by_key(|i| std::cmp::Reverse(count(i)));
+
+    let mut optimized = chords.to_vec();
+    for (i, (_, buttons)) in movable.into_iter().zip(slots) {
+        optimized[i].set_buttons(&buttons);
+    }
+
+    Ok(optimized)
+}
+
+/// How often one chord follows another typing a corpus, by chord index, see
+/// [`analysis::chord_sequence`](crate::analysis::chord_sequence) for how
+/// chords are picked. Characters no chord types break the sequence.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NGrams {
+    /// Times each chord is pressed
+    pub unigrams: Vec<usize>,
+    /// Chords pressed one right after the other
+    pub bigrams: HashMap<(usize, usize), usize>,
+    /// The first and last of three chords in a row
+    pub trigrams: HashMap<(usize, usize), usize>,
+}
+
+impl NGrams {
+    pub fn count(chords: &[Chord], corpus: &str) -> NGrams {
+        let mut ngrams = NGrams {
+            unigrams: vec![0; chords.len()],
+            ..Default::default()
+        };
+        for run in chord_sequence(chords, corpus).split(Option::is_none) {
+            let run: Vec<usize> = run.iter().flatten().copied().collect();
+            for &i in &run {
+                ngrams.unigrams[i] += 1;
+            }
+            for pair in run.windows(2) {
+                *ngrams.bigrams.entry((pair[0], pair[1])).or_default() += 1;
+            }
+            for triple in run.windows(3) {
+                *ngrams.trigrams.entry((triple[0], triple[2])).or_default() += 1;
+            }
+        }
+        ngrams
+    }
+}
+
+/// Transitions over a chord in between cost this much of one straight after,
+/// the finger has had the middle chord's time to move
+const TRIGRAM_WEIGHT: f64 = 0.5;
+
+/// Passes over every move before settling for the layout found so far
+const MAX_PASSES: usize = 50;
+
+/// [`optimize_with`] taking [`ChordCostModel::transition`] into account
+/// too, so chords typed one after the other share buttons or at least keep
+/// off each other's fingers. Transitions make the best layout too costly to
+/// find exactly, so the independent optimum is improved by swapping chords
+/// and moving them to free buttons for as long as that lowers the total of
+/// chord and transition costs.
+pub fn optimize_ngrams(
+    chords: &[Chord],
+    corpus: &str,
+    constraints: &Constraints,
+    model: &dyn ChordCostModel,
+) -> Result<Vec<Chord>> {
+    let mut optimized = optimize_with(chords, corpus, constraints, model)?;
+    let ngrams = NGrams::count(chords, corpus);
+
+    // Transitions to or from each chord: the other chord, whether the
+    // transition goes out to it, and how often it's made
+    let mut edges: Vec<Vec<(usize, bool, f64)>> = vec![vec![]; chords.len()];
+    let bigrams = ngrams.bigrams.iter().map(|(&pair, &n)| (pair, n as f64));
+    let trigrams = (ngrams.trigrams.iter()).map(|(&pair, &n)| (pair, n as f64 * TRIGRAM_WEIGHT));
+    for ((from, to), weight) in bigrams.chain(trigrams) {
+        edges[from].push((to, true, weight));
+        if from != to {
+            edges[to].push((from, false, weight));
+        }
+    }
+
+    let mut buttons: Vec<ButtonState> = optimized.iter().map(Chord::buttons).collect();
+    // The cost of chord i and its transitions, leaving out those to `skip`
+    // so a pair of chords can be costed without counting them twice
+    let local = |buttons: &[ButtonState], i: usize, skip: Option<usize>| {
+        let mut cost = ngrams.unigrams[i] as f64 * model.cost(&buttons[i]);
+        for &(other, outgoing, weight) in edges[i].iter().filter(|e| Some(e.0) != skip) {
+            cost += weight
+                * if outgoing {
+                    model.transition(&buttons[i], &buttons[other])
+                } else {
+                    model.transition(&buttons[other], &buttons[i])
+                };
+        }
+        cost
+    };
+    let pair = |buttons: &[ButtonState], i: usize, j: usize| {
+        local(buttons, i, None) + local(buttons, j, Some(i))
+    };
+
+    let movable: Vec<usize> = (0..chords.len())
+        .filter(|i| !constraints.pinned.contains(i))
+        .collect();
+    // Only chords typed in the corpus can lower the cost by moving
+    let typed: Vec<usize> = movable
+        .iter()
+        .copied()
+        .filter(|&i| ngrams.unigrams[i] > 0)
+        .collect();
+    let mut free: Vec<ButtonState> = free_slots(chords, constraints)
+        .into_iter()
+        .filter(|slot| !movable.iter().any(|&i| buttons[i] == *slot))
+        .collect();
+
+    for _ in 0..MAX_PASSES {
+        let mut improved = false;
+        for &i in &typed {
+            for &j in &movable {
+                if i == j {
+                    continue;
+                }
+                let before = pair(&buttons, i, j);
+                buttons.swap(i, j);
+                if pair(&buttons, i, j) < before - 1e-9 {
+                    improved = true;
+                } else {
+                    buttons.swap(i, j);
+                }
+            }
+            for slot in free.iter_mut() {
+                let before = local(&buttons, i, None);
+                let current = core::mem::replace(&mut buttons[i], *slot);
+                if local(&buttons, i, None) < before - 1e-9 {
+                    *slot = current;
+                    improved = true;
+                } else {
+                    buttons[i] = current;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    for (chord, buttons) in optimized.iter_mut().zip(&buttons) {
+        chord.set_buttons(buttons);
+    }
+    Ok(optimized)
+}
+
+/// Chords for the most common words of `corpus` that no chord types yet, up
+/// to `limit` of them, on the easiest buttons by `model` that no chord on
+/// the first layer uses. Words of one character are left to letter chords.
+/// Each suggestion has how often its word appears in a `count` column, to
+/// help pick which ones to keep.
+pub fn suggest_words(
+    chords: &[Chord],
+    corpus: &Corpus,
+    limit: usize,
+    model: &dyn ChordCostModel,
+) -> Vec<Chord> {
+    let typed: HashSet<&str> = chords.iter().map(Chord::output).collect();
+    let words = corpus
+        .top_words()
+        .into_iter()
+        .filter(|(word, _)| word.chars().count() > 1 && !typed.contains(word));
+
+    let taken: HashSet<ButtonState> = chords
+        .iter()
+        .filter(|chord| chord.layer() == 0)
+        .map(ButtonState::lossy)
+        .collect();
+    let mut slots: Vec<(f64, ButtonState)> = candidates()
+        .into_iter()
+        .filter(|buttons| !taken.contains(buttons))
+        .map(|buttons| (model.cost(&buttons), buttons))
+        .collect();
+    slots.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    words
+        .zip(slots)
+        .take(limit)
+        .map(|((word, count), (_, buttons))| {
+            let mut chord = Chord::from_buttons(&buttons, word);
+            chord.set_extra("count", count.to_string());
+            chord
+        })
+        .collect()
+}
+
+// Buttons chords can go on, the ones in use first so ties leave chords
+// where they are
+fn free_slots(chords: &[Chord], constraints: &Constraints) -> Vec<ButtonState> {
+    let mut taken: HashSet<ButtonState> = chords
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| constraints.pinned.contains(i))
+        .map(|(_, chord)| chord.buttons())
+        .collect();
+    taken.extend(constraints.forbidden.iter().copied());
+
+    let mut slots = vec![];
+    for buttons in chords.iter().map(Chord::buttons).chain(candidates()) {
+        if taken.insert(buttons) {
+            slots.push(buttons);
+        }
+    }
+    slots
+}
+
+fn candidates() -> Vec<ButtonState> {
+    let fingers: Vec<String> = (0..5)
+        .flat_map(|row| ["L", "M", "R"].map(|col| format!("{}{}", row, col)))
+        .collect();
+
+    let mut combinations = vec![];
+    for (i, first) in fingers.iter().enumerate() {
+        combinations.push(first.clone());
+        for second in &fingers[i + 1..] {
+            combinations.push(format!("{} {}", first, second));
+        }
+    }
+
+    let mut candidates = vec![];
+    for thumb in ["", "T1 ", "T2 ", "T3 ", "T4 "] {
+        for fingers in &combinations {
+            match format!("{}{}", thumb, fingers).parse::<ButtonState>() {
+                Ok(buttons) if buttons.is_physically_possible() => candidates.push(buttons),
+                _ => (),
+            }
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::{effort, score};
+
+    #[test]
+    fn test_optimize() {
+        let chords = vec![
+            Chord::new("1", "2L 2M", "e"),
+            Chord::new("", "1L", "z"),
+            Chord::new("", "1M", "q"),
+        ];
+        let corpus = "eeeeez";
+        let constraints = Constraints {
+            pinned: vec![2],
+            forbidden: vec!["2L".parse().unwrap()],
+        };
+        let optimized = optimize(&chords, corpus, &constraints).unwrap();
+
+        assert!(optimized[2] == chords[2]);
+        assert!(optimized.iter().all(|c| c.fingers() != "2L"));
+        assert!(optimized[0].output() == "e" && optimized[0].fingers() == "1L");
+        assert!(score(&optimized, corpus).effort < score(&chords, corpus).effort);
+    }
+
+    #[test]
+    fn test_optimize_with() {
+        let chords = vec![Chord::new("", "1L", "z"), Chord::new("", "2L", "e")];
+        let constraints = Constraints::default();
+        let optimized = optimize(&chords, "eez", &constraints).unwrap();
+        assert!(optimized[1].fingers() == "1L");
+
+        // Row 1 is hard to reach, so neither goes on it
+        let weights = Weights {
+            fingers: [1.5, 5.0, 1.0, 1.0, 1.5],
+            ..Default::default()
+        };
+        let optimized = optimize_with(&chords, "eez", &constraints, &weights).unwrap();
+        assert!(optimized.iter().all(|c| !c.fingers().contains('1')));
+    }
+
+    #[test]
+    fn test_ngrams() {
+        let chords = vec![
+            Chord::new("", "1L", "t"),
+            Chord::new("", "1M", "h"),
+            Chord::new("", "1R", "e"),
+        ];
+        let ngrams = NGrams::count(&chords, "the tea");
+        assert!(ngrams.unigrams == [2, 1, 2]);
+        assert!(ngrams.bigrams.len() == 3 && ngrams.bigrams[&(0, 1)] == 1);
+        // The space between the words isn't typed, so e doesn't lead to t
+        assert!(!ngrams.bigrams.contains_key(&(2, 0)));
+        assert!(ngrams.trigrams.len() == 1 && ngrams.trigrams[&(0, 2)] == 1);
+    }
+
+    #[test]
+    fn test_optimize_ngrams() {
+        // t and h are typed as often as each other and a, so on their own
+        // any three easy chords do, but th keeps them on different fingers
+        let chords = vec![
+            Chord::new("", "1L", "t"),
+            Chord::new("", "1M", "h"),
+            Chord::new("", "2L", "a"),
+        ];
+        let corpus = "th th th a a a";
+        let constraints = Constraints::default();
+        let weights = Weights::default();
+        let independent = optimize_with(&chords, corpus, &constraints, &weights).unwrap();
+        let optimized = optimize_ngrams(&chords, corpus, &constraints, &weights).unwrap();
+
+        let transitions =
+            |chords: &[Chord]| weights.transition(&chords[0].buttons(), &chords[1].buttons());
+        assert!(transitions(&independent) == 1.0);
+        assert!(transitions(&optimized) == 0.0);
+        let effort = |chords: &[Chord]| score(chords, corpus).effort;
+        assert!(effort(&optimized) == effort(&independent));
+    }
+
+    #[test]
+    fn test_suggest_words() {
+        let chords = vec![
+            Chord::new("", "1L", "t"),
+            Chord::new("", "1M", "the"),
+            Chord::new("", "2L", "a"),
+        ];
+        let corpus = crate::corpus::from_text("the cat and the hat and a cat, a dog");
+        let suggested = suggest_words(&chords, &corpus, 2, &Weights::default());
+
+        // the has a chord and a is a letter
+        assert!(suggested.len() == 2);
+        assert!(suggested[0].output() == "and" && suggested[0].extra("count") == Some("2"));
+        assert!(suggested[1].output() == "cat");
+        assert!(suggested.iter().all(|s| effort(&s.buttons()) == 1.0));
+        assert!(suggested
+            .iter()
+            .all(|s| chords.iter().all(|c| c.buttons() != s.buttons())));
+    }
+
+    #[test]
+    fn test_not_enough_slots() {
+        let chords = vec![Chord::new("", "1L", "a"), Chord::new("", "1M", "b")];
+        let constraints = Constraints {
+            pinned: vec![],
+            forbidden: candidates(),
+        };
+        assert!(matches!(
+            optimize(&chords, "ab", &constraints),
+            Err(Error::Invalid(_))
+        ));
+    }
+}
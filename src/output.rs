@@ -0,0 +1,858 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    hid,
+    layout::{Host, Layout},
+    Error, Result, Warning,
+};
+
+/// A parsed Keyboard Output string, e.g. `<L-Ctrl>c</L-Ctrl><Tab>` is
+/// a Ctrl span holding the text "c" followed by the Tab key
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputToken {
+    /// Characters typed as they are
+    Text(String),
+    /// A key named in a tag, e.g. <Tab>
+    Key { name: String, key_code: u8 },
+    /// A consumer page usage, e.g. <Media:PlayPause>
+    Media { name: String, usage: u16 },
+    /// A pause in milliseconds, e.g. <Delay:100>
+    Delay(u16),
+    /// Everything between an opening and closing modifier tag is typed with
+    /// `modifier` held
+    Modifier {
+        name: String,
+        modifier: u8,
+        tokens: Vec<OutputToken>,
+    },
+}
+
+struct Span {
+    name: String,
+    modifier: u8,
+    tokens: Vec<OutputToken>,
+    /// Carried on after a span it was opened in was closed
+    reopened: bool,
+}
+
+impl Span {
+    fn push_char(&mut self, c: char) {
+        match self.tokens.last_mut() {
+            Some(OutputToken::Text(text)) => text.push(c),
+            _ => self.tokens.push(OutputToken::Text(c.to_string())),
+        }
+    }
+
+    // A span with nothing in it only presses its modifier when it was
+    // written that way, like <L-Shift></L-Shift>
+    fn close(self) -> Option<OutputToken> {
+        (!self.tokens.is_empty() || !self.reopened).then_some(OutputToken::Modifier {
+            name: self.name,
+            modifier: self.modifier,
+            tokens: self.tokens,
+        })
+    }
+}
+
+/// How [`parse_with`] treats tags and characters it doesn't know
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParseOptions {
+    /// Fail on them, otherwise they type nothing and are returned as
+    /// warnings
+    pub strict: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { strict: true }
+    }
+}
+
+impl ParseOptions {
+    pub const STRICT: ParseOptions = ParseOptions { strict: true };
+    pub const LENIENT: ParseOptions = ParseOptions { strict: false };
+
+    // The error in strict mode, a warning and carrying on otherwise
+    pub(crate) fn problem(self, error: Error, warnings: &mut Vec<Warning>) -> Result<()> {
+        if self.strict {
+            return Err(error);
+        }
+        warnings.push(Warning { error });
+        Ok(())
+    }
+}
+
+/// Parse an output string, error columns are byte offsets into `output`.
+/// Modifiers that are never closed are held until the end, and `\<` types
+/// a < rather than starting a tag.
+pub fn parse(output: &str) -> Result<Vec<OutputToken>> {
+    parse_with(output, ParseOptions::STRICT).map(|(tokens, _)| tokens)
+}
+
+/// Like [`parse`], a lenient parse leaves out unknown keys and malformed
+/// tags and returns why with the tokens
+pub fn parse_with(output: &str, options: ParseOptions) -> Result<(Vec<OutputToken>, Vec<Warning>)> {
+    let mut warnings = vec![];
+    // The bottom span holds the top level tokens
+    let mut spans = vec![Span {
+        name: String::new(),
+        modifier: 0,
+        tokens: vec![],
+        reopened: false,
+    }];
+
+    let mut chars = output.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        // \<, \> and \\ type the character itself, any other \ is literal
+        if let ('\\', Some(&(_, escaped @ ('<' | '>' | '\\')))) = (c, chars.peek()) {
+            chars.next();
+            spans.last_mut().unwrap().push_char(escaped);
+            continue;
+        }
+
+        if c != '<' {
+            // Whether other characters can be typed depends on the host
+            if c.is_control() && hid::char_to_hid(c).is_none() {
+                let error = Error::UnknownKey {
+                    name: c.to_string(),
+                    row: None,
+                    column: i,
+                };
+                options.problem(error, &mut warnings)?;
+                continue;
+            }
+            spans.last_mut().unwrap().push_char(c);
+            continue;
+        }
+
+        // A < before the > starts the next tag
+        let mut end = None;
+        while let Some(&(j, c)) = chars.peek() {
+            match c {
+                '>' => {
+                    chars.next();
+                    end = Some(j);
+                    break;
+                }
+                '<' => break,
+                _ => {
+                    chars.next();
+                }
+            }
+        }
+        let Some(end) = end else {
+            let until = chars.peek().map_or(output.len(), |&(j, _)| j);
+            let error = Error::MalformedTag {
+                tag: output[i..until].to_string(),
+                row: None,
+                column: i,
+            };
+            options.problem(error, &mut warnings)?;
+            continue;
+        };
+
+        let tag = &output[i + 1..end];
+        let malformed = || Error::MalformedTag {
+            tag: output[i..=end].to_string(),
+            row: None,
+            column: i,
+        };
+        if let Some(name) = tag.strip_prefix('/') {
+            if close_span(&mut spans, name).is_none() {
+                options.problem(malformed(), &mut warnings)?;
+            }
+        } else if let Some(modifier) = hid::modifier_tag(tag) {
+            spans.push(Span {
+                name: hid::modifier_name(tag).unwrap_or(tag).to_string(),
+                modifier,
+                tokens: vec![],
+                reopened: false,
+            });
+        } else {
+            let unknown_key = || Error::UnknownKey {
+                name: tag.to_string(),
+                row: None,
+                column: i + 1,
+            };
+            let token = if let Some(ms) = strip_tag_prefix(tag, "Delay:") {
+                ms.parse().map(OutputToken::Delay).map_err(|_| malformed())
+            } else if let Some(media) = strip_tag_prefix(tag, "Media:") {
+                hid::consumer_usage(media)
+                    .map(|usage| OutputToken::Media {
+                        name: hid::consumer_name(usage).unwrap_or(media).to_string(),
+                        usage,
+                    })
+                    .ok_or_else(unknown_key)
+            } else {
+                hid::named_key(tag)
+                    .map(|key_code| OutputToken::Key {
+                        name: hid::key_name(key_code).unwrap_or(tag).to_string(),
+                        key_code,
+                    })
+                    .ok_or_else(unknown_key)
+            };
+            match token {
+                Ok(token) => spans.last_mut().unwrap().tokens.push(token),
+                Err(error) => options.problem(error, &mut warnings)?,
+            }
+        }
+    }
+
+    // Modifiers never closed are held to the end, and with nothing typed
+    // at all they're a modifier-only chord like <L-Shift>
+    let modifiers_only = spans.iter().all(|span| span.tokens.is_empty());
+    while spans.len() > 1 {
+        let span = spans.pop().unwrap();
+        if !modifiers_only && span.tokens.is_empty() {
+            continue;
+        }
+        if let Some(token) = span.close() {
+            spans.last_mut().unwrap().tokens.push(token);
+        }
+    }
+    Ok((spans.pop().unwrap().tokens, warnings))
+}
+
+// Tag prefixes like Delay: are matched in any case too
+pub(crate) fn strip_tag_prefix<'a>(tag: &'a str, prefix: &str) -> Option<&'a str> {
+    let start = tag.get(..prefix.len())?;
+    start
+        .eq_ignore_ascii_case(prefix)
+        .then(|| &tag[prefix.len()..])
+}
+
+/// `output` with every tag [`parse`] knows written the way exports write
+/// it, e.g. `<lctrl>c</CTRL><enter>` is `<L-Ctrl>c</L-Ctrl><Return>`, and
+/// `<Sticky:win>` is `<Sticky:L-Gui>`. Text and tags it doesn't know are
+/// left as they are.
+pub fn normalize(output: &str) -> String {
+    map_tags(output, canonical_tag)
+}
+
+/// `output` with each tag, without its angle brackets, replaced by what `f`
+/// returns for it, or left as it is for `None`. Text and escapes are kept.
+pub fn map_tags(output: &str, f: impl FnMut(&str) -> Option<String>) -> String {
+    map_parts(output, |text, mapped| mapped.push_str(text), f)
+}
+
+/// `output` with each run of text between tags and escapes replaced by what
+/// `f` returns for it
+pub fn map_text(output: &str, mut f: impl FnMut(&str) -> String) -> String {
+    map_parts(output, |text, mapped| mapped.push_str(&f(text)), |_| None)
+}
+
+/// Like [`map_text`], `f` also gets the modifier bits held around the text
+/// by the tags before it
+pub fn map_text_held(output: &str, mut f: impl FnMut(&str, u8) -> String) -> String {
+    let held = core::cell::Cell::new(0u8);
+    map_parts(
+        output,
+        |text, mapped| mapped.push_str(&f(text, held.get())),
+        |tag| {
+            match tag.strip_prefix('/') {
+                Some(name) => held.set(held.get() & !hid::modifier_tag(name).unwrap_or(0)),
+                None => held.set(held.get() | hid::modifier_tag(tag).unwrap_or(0)),
+            }
+            None
+        },
+    )
+}
+
+fn map_parts(
+    output: &str,
+    mut text: impl FnMut(&str, &mut String),
+    mut tag: impl FnMut(&str) -> Option<String>,
+) -> String {
+    let mut mapped = String::with_capacity(output.len());
+    let mut rest = output;
+    while let Some(i) = rest.find(['\\', '<']) {
+        let (before, from) = rest.split_at(i);
+        text(before, &mut mapped);
+        if let Some(escaped) = from.strip_prefix('\\') {
+            // An escape and what it escapes are copied as they are
+            let len = escaped.chars().next().map_or(0, char::len_utf8);
+            mapped.push_str(&from[..1 + len]);
+            rest = &from[1 + len..];
+            continue;
+        }
+        let Some(end) = from[1..]
+            .find(['<', '>'])
+            .filter(|&end| from[1 + end..].starts_with('>'))
+        else {
+            mapped.push('<');
+            rest = &from[1..];
+            continue;
+        };
+        match tag(&from[1..1 + end]) {
+            Some(tag) => {
+                mapped.push('<');
+                mapped.push_str(&tag);
+                mapped.push('>');
+            }
+            None => mapped.push_str(&from[..end + 2]),
+        }
+        rest = &from[end + 2..];
+    }
+    text(rest, &mut mapped);
+    mapped
+}
+
+fn canonical_tag(tag: &str) -> Option<String> {
+    if let Some(name) = tag.strip_prefix('/') {
+        return Some(format!("/{}", hid::modifier_name(name)?));
+    }
+    if let Some(name) = hid::modifier_name(tag) {
+        return Some(name.to_string());
+    }
+    if let Some(ms) = strip_tag_prefix(tag, "Delay:") {
+        return Some(format!("Delay:{}", ms));
+    }
+    if let Some(media) = strip_tag_prefix(tag, "Media:") {
+        let usage = hid::consumer_usage(media)?;
+        return Some(format!("Media:{}", hid::consumer_name(usage)?));
+    }
+    if let Some(name) = strip_tag_prefix(tag, "Sticky:") {
+        return Some(format!("Sticky:{}", hid::modifier_name(name)?));
+    }
+    if let Some(layer) = strip_tag_prefix(tag, "Layer:") {
+        return Some(format!("Layer:{}", layer));
+    }
+    if let Some((name, _)) = hid::MOUSE_BUTTONS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+    {
+        return Some(name.to_string());
+    }
+    hid::named_key(tag)
+        .and_then(hid::key_name)
+        .map(str::to_string)
+}
+
+// Close the innermost open span called `name`. Spans opened inside it, like
+// Alt in <L-Ctrl><L-Alt>a</L-Ctrl>b</L-Alt>, carry on after it.
+fn close_span(spans: &mut Vec<Span>, name: &str) -> Option<()> {
+    // AltGr and R-Alt hold the same key but are closed by their own tags
+    let name = hid::modifier_name(name)?;
+    let index = spans.iter().rposition(|s| s.name == name)?;
+    if index == 0 {
+        return None;
+    }
+
+    let mut reopen = vec![];
+    while spans.len() > index {
+        let span = spans.pop().unwrap();
+        reopen.push((span.name.clone(), span.modifier));
+        if let Some(token) = span.close() {
+            spans.last_mut().unwrap().tokens.push(token);
+        }
+    }
+    // The closed span itself was popped last
+    reopen.pop();
+    for (name, modifier) in reopen.into_iter().rev() {
+        spans.push(Span {
+            name,
+            modifier,
+            tokens: vec![],
+            reopened: true,
+        });
+    }
+
+    Some(())
+}
+
+/// What a chord does, as the binary formats store it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChordOutput {
+    /// Mouse buttons held while the chord is pressed, see `hid::MOUSE_BUTTONS`
+    Mouse(u8),
+    /// Modifiers held for the next chord only, stored as a keyboard command
+    /// with no key
+    Sticky(u8),
+    /// Switches to another keymap, see
+    /// [`twiddler7::Config::layers`](crate::twiddler7::Config::layers)
+    SwitchLayer(u8),
+    /// Keys typed in order, more than one step goes in a command list
+    Macro(Vec<MacroStep>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MacroStep {
+    Key {
+        modifier: u8,
+        key_code: u8,
+    },
+    /// Milliseconds to wait before the next step
+    Delay(u16),
+}
+
+/// The steps to type the tokens in order on a US host
+pub fn to_macro(tokens: &[OutputToken]) -> Result<Vec<MacroStep>> {
+    to_macro_in(tokens, Layout::Us)
+}
+
+/// The steps to type the tokens in order on `host`, a [`Layout`] or a
+/// [`Host`] that can enter other characters by their code point
+pub fn to_macro_in(tokens: &[OutputToken], host: impl Into<Host>) -> Result<Vec<MacroStep>> {
+    let mut steps = vec![];
+    push_steps(tokens, 0, host.into(), &mut steps)?;
+    Ok(steps)
+}
+
+/// Modifier/key code pairs to type the tokens in order, delays need
+/// [`to_macro`]
+pub fn to_hid_pairs(tokens: &[OutputToken]) -> Result<Vec<(u8, u8)>> {
+    to_macro(tokens)?
+        .into_iter()
+        .map(|step| match step {
+            MacroStep::Key { modifier, key_code } => Ok((modifier, key_code)),
+            MacroStep::Delay(ms) => Err(Error::Unsupported(format!(
+                "<Delay:{}> can only be written as part of a macro",
+                ms
+            ))),
+        })
+        .collect()
+}
+
+fn push_steps(
+    tokens: &[OutputToken],
+    held: u8,
+    host: Host,
+    steps: &mut Vec<MacroStep>,
+) -> Result<()> {
+    let key = |modifier, key_code| MacroStep::Key { modifier, key_code };
+    for token in tokens {
+        match token {
+            OutputToken::Text(text) => {
+                for c in text.chars() {
+                    let pairs = host.char_to_hid(c).ok_or_else(|| {
+                        Error::Unsupported(format!(
+                            "{:?} can't be typed on a {} keyboard layout",
+                            c,
+                            host.layout.name()
+                        ))
+                    })?;
+                    for (modifier, key_code) in pairs {
+                        steps.push(key(held | modifier, key_code));
+                    }
+                }
+            }
+            OutputToken::Key { key_code, .. } => steps.push(key(held, *key_code)),
+            // The binary formats only have keyboard reports so far
+            OutputToken::Media { name, usage } => match host.media_key(*usage) {
+                Some(key_code) => steps.push(key(held, key_code)),
+                None => {
+                    return Err(Error::Unsupported(format!(
+                        "<Media:{}> can't be written to a config yet, only volume and mute can, and brightness for a Mac",
+                        name
+                    )))
+                }
+            },
+            OutputToken::Delay(ms) => steps.push(MacroStep::Delay(*ms)),
+            // Pressing just the modifier, key code 0 is no key
+            OutputToken::Modifier {
+                modifier, tokens, ..
+            } if tokens.is_empty() => steps.push(key(held | host.modifiers(*modifier), 0)),
+            OutputToken::Modifier {
+                modifier, tokens, ..
+            } => push_steps(tokens, held | host.modifiers(*modifier), host, steps)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Render steps in the CSV output notation, the reverse of [`parse`] and
+/// [`to_macro`]
+pub fn macro_to_output(steps: &[MacroStep]) -> String {
+    macro_to_output_in(steps, Layout::Us)
+}
+
+/// Like [`macro_to_output`] for steps typed on a host using `layout`
+pub fn macro_to_output_in(steps: &[MacroStep], layout: Layout) -> String {
+    let mut output = String::new();
+    let mut pairs = vec![];
+    for step in steps {
+        match *step {
+            MacroStep::Key { modifier, key_code } => pairs.push((modifier, key_code)),
+            MacroStep::Delay(ms) => {
+                output.push_str(&hid::pairs_to_output_part_in(&pairs, layout));
+                output.push_str(&format!("<Delay:{}>", ms));
+                pairs.clear();
+            }
+        }
+    }
+
+    if output.is_empty() {
+        hid::pairs_to_output_in(&pairs, layout)
+    } else {
+        output + &hid::pairs_to_output_part_in(&pairs, layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{TargetOs, UnicodeEntry};
+
+    fn text(text: &str) -> OutputToken {
+        OutputToken::Text(text.to_string())
+    }
+
+    fn modifier(name: &str, tokens: Vec<OutputToken>) -> OutputToken {
+        OutputToken::Modifier {
+            name: name.to_string(),
+            modifier: hid::modifier_tag(name).unwrap(),
+            tokens,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let tokens = parse("<L-Ctrl>c</L-Ctrl><Tab>ok").unwrap();
+        assert!(
+            tokens
+                == vec![
+                    modifier("L-Ctrl", vec![text("c")]),
+                    OutputToken::Key {
+                        name: "Tab".to_string(),
+                        key_code: 0x2B
+                    },
+                    text("ok"),
+                ]
+        );
+        assert!(
+            to_hid_pairs(&tokens).unwrap() == vec![(0x01, 0x06), (0, 0x2B), (0, 0x12), (0, 0x0E)]
+        );
+    }
+
+    #[test]
+    fn test_aliases() {
+        let tokens = parse("<ctrl>c</LCTRL><Win>r</Super><enter>").unwrap();
+        assert!(
+            tokens
+                == vec![
+                    modifier("L-Ctrl", vec![text("c")]),
+                    modifier("L-Gui", vec![text("r")]),
+                    OutputToken::Key {
+                        name: "Return".to_string(),
+                        key_code: 0x28
+                    },
+                ]
+        );
+        assert!(
+            parse("<delay:5><media:playpause>").unwrap()[1]
+                == OutputToken::Media {
+                    name: "PlayPause".to_string(),
+                    usage: 0xCD
+                }
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert!(normalize("<lctrl>c</CTRL><enter>") == "<L-Ctrl>c</L-Ctrl><Return>");
+        assert!(normalize("<Sticky:win>") == "<Sticky:L-Gui>");
+        assert!(normalize("<AltGr>q</altgr><delay:10>") == "<AltGr>q</AltGr><Delay:10>");
+        // Escapes, unknown and broken tags are left alone
+        assert!(normalize("\\<tab\\> <Nope> <Ta<tab>") == "\\<tab\\> <Nope> <Ta<Tab>");
+        assert!(normalize("<Layer:1>") == "<Layer:1>");
+        assert!(normalize("<layer:1><mouseleft>") == "<Layer:1><MouseLeft>");
+        assert!(normalize("é<esc>") == "é<Escape>");
+    }
+
+    #[test]
+    fn test_unclosed_modifier() {
+        let tokens = parse("<R-Ctrl>p").unwrap();
+        assert!(tokens == vec![modifier("R-Ctrl", vec![text("p")])]);
+    }
+
+    #[test]
+    fn test_overlapping_modifiers() {
+        let tokens = parse("<L-Ctrl><L-Alt>a</L-Ctrl>b</L-Alt>").unwrap();
+        assert!(
+            tokens
+                == vec![
+                    modifier("L-Ctrl", vec![modifier("L-Alt", vec![text("a")])]),
+                    modifier("L-Alt", vec![text("b")]),
+                ]
+        );
+        assert!(to_hid_pairs(&tokens).unwrap() == vec![(0x05, 0x04), (0x04, 0x05)]);
+    }
+
+    #[test]
+    fn test_spans() {
+        let pairs = |output| to_hid_pairs(&parse(output).unwrap()).unwrap();
+        // A closed modifier stops applying
+        assert!(
+            pairs("<L-Shift>ab</L-Shift>cd")
+                == vec![(0x02, 0x04), (0x02, 0x05), (0, 0x06), (0, 0x07)]
+        );
+        // Nested, Ctrl is still held after Shift is let go
+        assert!(
+            pairs("<L-Ctrl><L-Shift>a</L-Shift>b</L-Ctrl>c")
+                == vec![(0x03, 0x04), (0x01, 0x05), (0, 0x06)]
+        );
+        // Overlapping the other way round
+        assert!(
+            pairs("<L-Shift><L-Ctrl>a</L-Shift>b</L-Ctrl>c")
+                == vec![(0x03, 0x04), (0x01, 0x05), (0, 0x06)]
+        );
+        // Closing a modifier that isn't held is an error, the same one twice
+        // only closes the inner one
+        assert!(parse("a</L-Shift>").is_err());
+        assert!(
+            pairs("<L-Shift><L-Shift>a</L-Shift>b</L-Shift>c")
+                == vec![(0x02, 0x04), (0x02, 0x05), (0, 0x06)]
+        );
+    }
+
+    #[test]
+    fn test_spans_round_trip() {
+        for output in [
+            "<L-Ctrl>abc</L-Ctrl>def",
+            "<L-Ctrl><L-Shift>a</L-Shift>b</L-Ctrl>c",
+            "<L-Ctrl><L-Alt>a</L-Ctrl>b</L-Alt>c",
+            "<R-Shift><R-Alt>,s</R-Shift>hift<UpArrow></R-Alt>",
+            "<L-Ctrl>x</L-Ctrl><L-Shift></L-Shift>y",
+        ] {
+            let pairs = to_hid_pairs(&parse(output).unwrap()).unwrap();
+            assert!(hid::pairs_to_output(&pairs) == output, "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_modifier_only() {
+        let pairs = |output| to_hid_pairs(&parse(output).unwrap()).unwrap();
+        assert!(pairs("<L-Shift>") == vec![(0x02, 0)]);
+        assert!(pairs("<L-Ctrl><L-Shift>") == vec![(0x03, 0)]);
+        assert!(pairs("<L-Ctrl><L-Shift></L-Shift></L-Ctrl>") == vec![(0x03, 0)]);
+        assert!(pairs("a<R-Alt></R-Alt>b") == vec![(0, 0x04), (0x40, 0), (0, 0x05)]);
+        // Modifiers left open after the text don't press anything more
+        assert!(pairs("a<L-Shift>") == vec![(0, 0x04)]);
+        assert!(pairs("<L-Ctrl><L-Alt>a</L-Ctrl></L-Alt>") == vec![(0x05, 0x04)]);
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert!(parse("\\<Tab\\>").unwrap() == vec![text("<Tab>")]);
+        assert!(parse("a\\\\<Tab>").unwrap()[0] == text("a\\"));
+        assert!(parse("\\n").unwrap() == vec![text("\\n")]);
+        assert!(
+            to_hid_pairs(&parse("<L-Ctrl>\\<</L-Ctrl>").unwrap()).unwrap() == vec![(0x03, 0x36)]
+        );
+    }
+
+    #[test]
+    fn test_macro() {
+        let steps = to_macro(&parse("<L-Ctrl>c</L-Ctrl><Delay:250>\\<").unwrap()).unwrap();
+        assert!(
+            steps
+                == vec![
+                    MacroStep::Key {
+                        modifier: 0x01,
+                        key_code: 0x06
+                    },
+                    MacroStep::Delay(250),
+                    MacroStep::Key {
+                        modifier: 0x02,
+                        key_code: 0x36
+                    },
+                ]
+        );
+        assert!(macro_to_output(&steps) == "<L-Ctrl>c</L-Ctrl><Delay:250>\\<");
+        assert!(to_hid_pairs(&parse("a<Delay:10>").unwrap()).is_err());
+        assert!(matches!(
+            parse("<Delay:soon>"),
+            Err(Error::MalformedTag { column: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            parse("ab<Tab"),
+            Err(Error::MalformedTag { column: 2, .. })
+        ));
+        assert!(matches!(
+            parse("<Ta<b>"),
+            Err(Error::MalformedTag { column: 0, .. })
+        ));
+        assert!(matches!(
+            parse("a</L-Ctrl>"),
+            Err(Error::MalformedTag { column: 1, .. })
+        ));
+        assert!(matches!(
+            parse("-<Nope>"),
+            Err(Error::UnknownKey { column: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_layout() {
+        let tokens = parse("<L-Ctrl>z</L-Ctrl>ß").unwrap();
+        let steps = to_macro_in(&tokens, Layout::De).unwrap();
+        assert!(macro_to_output_in(&steps, Layout::De) == "<L-Ctrl>z</L-Ctrl>ß");
+        assert!(
+            steps[0]
+                == MacroStep::Key {
+                    modifier: 0x01,
+                    key_code: 0x1C
+                }
+        );
+        assert!(matches!(to_macro(&tokens), Err(Error::Unsupported(_))));
+        assert!(matches!(
+            parse("\u{7}"),
+            Err(Error::UnknownKey { column: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_dead_keys() {
+        let tokens = parse("Café <AltGr>q</AltGr>").unwrap();
+        let steps = to_macro_in(&tokens, Layout::De).unwrap();
+        let pairs: Vec<(u8, u8)> = steps
+            .iter()
+            .map(|step| match step {
+                MacroStep::Key { modifier, key_code } => (*modifier, *key_code),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert!(pairs[3..] == [(0, 0x2E), (0, 0x08), (0, 0x2C), (0x40, 0x14)]);
+        assert!(macro_to_output_in(&steps, Layout::De) == "Café @");
+        assert!(macro_to_output_in(&steps, Layout::Us) == "Caf=e <R-Alt>q</R-Alt>");
+        assert!(parse("<AltGr>q</R-Alt>").is_err());
+        assert!(parse("<R-Alt>q</AltGr>").is_err());
+    }
+
+    #[test]
+    fn test_unicode_entry() {
+        let tokens = parse("é→").unwrap();
+        let host = |unicode| Host {
+            layout: Layout::Us,
+            unicode: Some(unicode),
+            os: None,
+        };
+        let pairs = |unicode| -> Vec<(u8, u8)> {
+            to_macro_in(&tokens, host(unicode))
+                .unwrap()
+                .into_iter()
+                .map(|step| match step {
+                    MacroStep::Key { modifier, key_code } => (modifier, key_code),
+                    MacroStep::Delay(_) => unreachable!(),
+                })
+                .collect()
+        };
+
+        // Ctrl+Shift+U e9 space, then 2192
+        let linux = pairs(UnicodeEntry::Linux);
+        assert!(linux[..4] == [(0x03, 0x18), (0, 0x08), (0, 0x26), (0, 0x2C)]);
+        assert!(linux.len() == 4 + 6);
+        // Alt and keypad 0 2 3 3
+        let windows = pairs(UnicodeEntry::Windows);
+        assert!(windows[..4] == [(0x04, 0x62), (0x04, 0x5A), (0x04, 0x5B), (0x04, 0x5B)]);
+        // Option and 00e9
+        let mac = pairs(UnicodeEntry::Mac);
+        assert!(mac[..4] == [(0x04, 0x27), (0x04, 0x27), (0x04, 0x08), (0x04, 0x26)]);
+        // French keyboards have é but still no arrow
+        assert!(to_macro_in(&tokens, Layout::Fr).is_err());
+    }
+
+    #[test]
+    fn test_target_os() {
+        let mac = Host {
+            os: Some(TargetOs::Mac),
+            ..Host::default()
+        };
+        let tokens =
+            parse("<L-Ctrl>c</L-Ctrl><R-Gui><L-Shift>t</L-Shift></R-Gui><Media:BrightnessUp>")
+                .unwrap();
+        let steps = to_macro_in(&tokens, mac).unwrap();
+        let key = |modifier, key_code| MacroStep::Key { modifier, key_code };
+        assert!(steps == [key(0x08, 0x06), key(0x12, 0x17), key(0, 0x6A)]);
+        // Other systems type it as written, brightness has no key there
+        let linux = Host {
+            os: Some(TargetOs::Linux),
+            ..Host::default()
+        };
+        assert!(to_macro_in(&tokens[..2], linux).unwrap() == [key(0x01, 0x06), key(0x82, 0x17)]);
+        assert!(to_macro_in(&tokens, linux).is_err());
+
+        // The OS's way of entering characters unless another one is given
+        let tokens = parse("é").unwrap();
+        assert!(to_macro_in(&tokens, linux).unwrap()[0] == key(0x03, 0x18));
+        let windows = Host {
+            unicode: Some(UnicodeEntry::Windows),
+            ..linux
+        };
+        assert!(to_macro_in(&tokens, windows).unwrap()[0] == key(0x04, 0x62));
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let tokens = parse("<L-Alt>é</L-Alt>ü").unwrap();
+        assert!(tokens == vec![modifier("L-Alt", vec![text("é")]), text("ü")]);
+        let steps = to_macro_in(&tokens[..1], Layout::Fr).unwrap();
+        assert!(
+            steps
+                == vec![MacroStep::Key {
+                    modifier: 0x04,
+                    key_code: 0x1F
+                }]
+        );
+        assert!(matches!(to_macro(&tokens), Err(Error::Unsupported(_))));
+
+        // Columns are byte offsets, past the multi-byte characters
+        assert!(matches!(
+            parse("é<Nope>"),
+            Err(Error::UnknownKey { column: 3, .. })
+        ));
+        assert!(matches!(
+            parse("日本<Tab"),
+            Err(Error::MalformedTag { column: 6, ref tag, .. }) if tag == "<Tab"
+        ));
+        assert!(matches!(
+            parse("<é>"),
+            Err(Error::UnknownKey { ref name, .. }) if name == "é"
+        ));
+        assert!(parse("<Delay:½>").is_err() && parse("<L-Alt>🎉</L-Alt>").is_ok());
+
+        let (tokens, warnings) = parse_with("é<Ta<Tab>ü", ParseOptions::LENIENT).unwrap();
+        assert!(tokens.len() == 3 && tokens[2] == text("ü"));
+        assert!(matches!(
+            &warnings[0].error,
+            Error::MalformedTag { tag, column: 2, .. } if tag == "<Ta"
+        ));
+        assert!(normalize("<ctrl>é</ctrl>\\ü") == "<L-Ctrl>é</L-Ctrl>\\ü");
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let (tokens, warnings) =
+            parse_with("a<Nope>b</L-Ctrl><Tab", ParseOptions::LENIENT).unwrap();
+        assert!(tokens == vec![text("ab")]);
+        assert!(warnings.len() == 3);
+        assert!(matches!(
+            warnings[0].error,
+            Error::UnknownKey { column: 2, .. }
+        ));
+        assert!(matches!(
+            &warnings[2].error,
+            Error::MalformedTag { tag, column: 17, .. } if tag == "<Tab"
+        ));
+        assert!(parse_with("a<Nope>", ParseOptions::STRICT).is_err());
+
+        // The < of the next tag isn't swallowed by the broken one
+        let (tokens, warnings) = parse_with("<Ta<Tab><Delay:x>", ParseOptions::LENIENT).unwrap();
+        assert!(tokens.len() == 1 && matches!(tokens[0], OutputToken::Key { key_code: 0x2B, .. }));
+        assert!(warnings.len() == 2);
+    }
+}
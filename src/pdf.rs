@@ -0,0 +1,310 @@
+//! A one page PDF cheat sheet to print and keep next to the Twiddler. The
+//! PDF is written by hand, text in the Courier and Helvetica fonts every
+//! reader has and button diagrams as rectangles, so nothing is embedded.
+
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::{
+    buttons::ButtonState,
+    csv::Chord,
+    render::{finger_rows, sorted},
+};
+
+// A4 in points
+const PAGE_WIDTH: f64 = 595.0;
+const PAGE_HEIGHT: f64 = 842.0;
+const MARGIN: f64 = 28.0;
+// Width of a Courier character in ems
+const CHAR_WIDTH: f64 = 0.6;
+// Smallest font size the sheet shrinks to, below that it runs off the page
+const MIN_FONT_SIZE: f64 = 3.0;
+
+/// How the sheet is laid out
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PdfOptions {
+    /// Chords side by side
+    pub columns: usize,
+    /// Size of the outputs in points, made smaller when the chords wouldn't
+    /// fit on the page
+    pub font_size: f64,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        PdfOptions {
+            columns: 4,
+            font_size: 8.0,
+        }
+    }
+}
+
+/// A cheat sheet of every chord on one A4 page, grouped like
+/// [`render::svg`](crate::render::svg), each chord's buttons drawn next to
+/// its output. Characters Latin-1 doesn't have print as `?`.
+pub fn pdf(chords: &[Chord], options: &PdfOptions) -> Vec<u8> {
+    let mut sheet = Sheet {
+        columns: options.columns.max(1),
+        font_size: options.font_size,
+        rows: finger_rows(chords),
+    };
+    let usable = PAGE_HEIGHT - 2.0 * MARGIN;
+    while sheet.font_size > MIN_FONT_SIZE && sheet.layout(chords).1 > usable {
+        sheet.font_size *= 0.95;
+    }
+    document(&sheet.draw(chords))
+}
+
+enum Cell<'a> {
+    Header(f64, &'a str),
+    Chord(f64, f64, &'a Chord),
+}
+
+struct Sheet {
+    columns: usize,
+    font_size: f64,
+    rows: Range<usize>,
+}
+
+impl Sheet {
+    fn button(&self) -> f64 {
+        self.font_size * 0.9
+    }
+
+    fn gap(&self) -> f64 {
+        self.button() * 0.25
+    }
+
+    fn cell_width(&self) -> f64 {
+        (PAGE_WIDTH - 2.0 * MARGIN) / self.columns as f64
+    }
+
+    fn cell_height(&self) -> f64 {
+        let thumbs = self.button() / 2.0 + self.gap();
+        thumbs + self.rows.len() as f64 * (self.button() + self.gap()) + self.gap()
+    }
+
+    // Where each group header and chord goes, from the top of the page, and
+    // the height they take
+    fn layout<'a>(&self, chords: &'a [Chord]) -> (Vec<Cell<'a>>, f64) {
+        let mut cells = vec![];
+        let mut y = 0.0;
+        let mut last_group = None;
+        let mut column = 0;
+        for (group, chord) in sorted(chords) {
+            if last_group != Some(group) {
+                if last_group.is_some() {
+                    y += self.cell_height();
+                }
+                cells.push(Cell::Header(y, group.title()));
+                y += self.font_size * 2.0;
+                column = 0;
+                last_group = Some(group);
+            } else if column == self.columns {
+                y += self.cell_height();
+                column = 0;
+            }
+            cells.push(Cell::Chord(column as f64 * self.cell_width(), y, chord));
+            column += 1;
+        }
+        if last_group.is_some() {
+            y += self.cell_height();
+        }
+        (cells, y)
+    }
+
+    // The page's content stream
+    fn draw(&self, chords: &[Chord]) -> String {
+        let mut content = String::from("0.5 w 0.27 0.27 0.27 RG 0.16 0.44 0.86 rg\n");
+        for cell in self.layout(chords).0 {
+            match cell {
+                Cell::Header(y, title) => text(
+                    &mut content,
+                    "F2",
+                    self.font_size * 1.2,
+                    MARGIN,
+                    MARGIN + y + self.font_size * 1.4,
+                    title,
+                ),
+                Cell::Chord(x, y, chord) => {
+                    self.draw_chord(&mut content, MARGIN + x, MARGIN + y, chord)
+                }
+            }
+        }
+        content
+    }
+
+    fn draw_chord(&self, content: &mut String, x: f64, y: f64, chord: &Chord) {
+        let (button, gap) = (self.button(), self.gap());
+        self.draw_buttons(content, x + gap, y + gap, &chord.buttons());
+
+        let diagram = 3.0 * button + 5.0 * gap;
+        let length = ((self.cell_width() - diagram) / (self.font_size * CHAR_WIDTH)) as usize;
+        // Spaces would be invisible, so they're shown as middle dots
+        let mut label: String = chord
+            .output()
+            .chars()
+            .take(length.max(2) - 1)
+            .map(|c| if c == ' ' { '·' } else { c })
+            .collect();
+        if chord.output().chars().count() >= length.max(2) {
+            label.pop();
+            label.push('~');
+        }
+        let baseline = y + gap + button / 2.0 + gap + self.font_size;
+        text(content, "F1", self.font_size, x + diagram, baseline, &label);
+    }
+
+    fn draw_buttons(&self, content: &mut String, left: f64, mut top: f64, buttons: &ButtonState) {
+        let (button, gap) = (self.button(), self.gap());
+        for (i, pressed) in buttons.thumbs().iter().enumerate() {
+            let x = left + i as f64 * (button + gap) * 3.0 / 4.0;
+            rect(content, x, top, button * 3.0 / 4.0, button / 2.0, *pressed);
+        }
+        top += button / 2.0 + gap;
+
+        let fingers = buttons.fingers();
+        for row in self.rows.clone() {
+            for (col, pressed) in fingers[row].iter().enumerate() {
+                let x = left + col as f64 * (button + gap);
+                rect(content, x, top, button, button, *pressed);
+            }
+            top += button + gap;
+        }
+    }
+}
+
+// Filled in blue when pressed, outlined either way
+fn rect(content: &mut String, x: f64, y: f64, width: f64, height: f64, pressed: bool) {
+    let _ = writeln!(
+        content,
+        "{:.2} {:.2} {:.2} {:.2} re {}",
+        x,
+        PAGE_HEIGHT - y - height,
+        width,
+        height,
+        if pressed { "B" } else { "S" }
+    );
+}
+
+fn text(content: &mut String, font: &str, size: f64, x: f64, baseline: f64, text: &str) {
+    let _ = writeln!(
+        content,
+        "BT 0 g /{} {:.2} Tf {:.2} {:.2} Td ({}) Tj ET 0.16 0.44 0.86 rg",
+        font,
+        size,
+        x,
+        PAGE_HEIGHT - baseline,
+        escape(text)
+    );
+}
+
+// A PDF string in WinAnsiEncoding
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\\' | '(' | ')' => format!("\\{}", c),
+            ' '..='~' => c.to_string(),
+            // Latin-1 is the same in WinAnsiEncoding, written as octal so
+            // the file stays ASCII
+            '\u{a0}'..='\u{ff}' => format!("\\{:03o}", c as u32),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
+
+// The document around a page's content stream, with the cross-reference
+// table readers use to find each object
+fn document(content: &str) -> Vec<u8> {
+    let font = |name: &str| {
+        format!(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+            name
+        )
+    };
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+        format!(
+            concat!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] ",
+                "/Resources << /Font << /F1 4 0 R /F2 5 0 R >> >> /Contents 6 0 R >>"
+            ),
+            PAGE_WIDTH, PAGE_HEIGHT
+        ),
+        font("Courier"),
+        font("Helvetica-Bold"),
+        format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ),
+    ];
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        let _ = write!(pdf, "{} 0 obj\n{}\nendobj\n", i + 1, object);
+    }
+    let xref = pdf.len();
+    let _ = writeln!(pdf, "xref\n0 {}\n0000000000 65535 f ", objects.len() + 1);
+    for offset in offsets {
+        let _ = writeln!(pdf, "{:010} 00000 n ", offset);
+    }
+    let _ = write!(
+        pdf,
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    pdf.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdf() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("1", "1M 2M", "the (end) café"),
+            Chord::new("", "2R", "→"),
+        ];
+        let pdf = String::from_utf8(pdf(&chords, &PdfOptions::default())).unwrap();
+        assert!(pdf.starts_with("%PDF-1.4\n") && pdf.ends_with("%%EOF\n"));
+        assert!(pdf.contains("(Letters) Tj") && pdf.contains("(Words) Tj"));
+        assert!(pdf.contains(r"(the\267\(end\)\267caf\351) Tj"));
+        assert!(pdf.contains("(?) Tj"));
+        // A rectangle per button, thumbs and rows 1 to 4
+        assert!(pdf.matches(" re ").count() == 3 * (4 + 4 * 3));
+        assert!(pdf.matches(" re B").count() == 1 + 3 + 1);
+
+        // Every object is where the cross-reference table says
+        let xref = pdf.find("\nxref\n").unwrap() + 1;
+        let startxref: usize = pdf.lines().rev().nth(1).unwrap().parse().unwrap();
+        assert!(startxref == xref);
+        for (i, line) in pdf[xref..].lines().skip(3).take(6).enumerate() {
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(&format!("{} 0 obj\n", i + 1)));
+        }
+    }
+
+    #[test]
+    fn test_fits_on_a_page() {
+        let chords: Vec<Chord> = (0..150)
+            .map(|i| Chord::new("", "1L", format!("chord {}", i)))
+            .collect();
+        let pdf = String::from_utf8(pdf(&chords, &PdfOptions::default())).unwrap();
+        // Shrunk from 8 points to fit
+        assert!(!pdf.contains("/F1 8.00 Tf"));
+        let lowest = pdf
+            .lines()
+            .filter_map(|line| line.strip_suffix(" re S"))
+            .map(|rect| rect.split(' ').nth(1).unwrap().parse::<f64>().unwrap())
+            .fold(f64::INFINITY, f64::min);
+        assert!(lowest >= MARGIN - 0.01);
+    }
+}
@@ -0,0 +1,131 @@
+//! Lessons for learning a new layout, the chords that type the most of a
+//! corpus first so every lesson makes more text typeable.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::{
+    analysis::{effort, output_counts},
+    csv::Chord,
+};
+
+/// A group of chords to learn together
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Lesson {
+    /// Indices of the chords, in the order to learn them
+    pub chords: Vec<usize>,
+    /// Share of the corpus' characters typed with the chords of this lesson
+    /// and the ones before it
+    pub coverage: f64,
+}
+
+/// Split the chords into lessons of `size`, ordered by how many characters
+/// of `corpus` each one types, see [`output_counts`], and the easiest by
+/// [`effort`] first when that's the same. Chords the corpus doesn't use go
+/// in the last lessons.
+pub fn plan(chords: &[Chord], corpus: &str, size: usize) -> Vec<Lesson> {
+    let (counts, untyped) = output_counts(chords, corpus);
+    let typed = |chord: &Chord| {
+        counts.get(chord.output()).copied().unwrap_or(0) * chord.output().chars().count()
+    };
+    let total = chords.iter().map(typed).sum::<usize>() + untyped;
+
+    let mut order: Vec<usize> = (0..chords.len()).collect();
+    order.sort_by(|&a, &b| {
+        let effort = |i: usize| effort(&chords[i].buttons());
+        typed(&chords[b])
+            .cmp(&typed(&chords[a]))
+            .then(effort(a).total_cmp(&effort(b)))
+    });
+
+    // Chords with the same output share the characters, only the first
+    // one learnt counts
+    let mut seen = HashSet::new();
+    let mut covered = 0;
+    order
+        .chunks(size.max(1))
+        .map(|lesson| {
+            for &i in lesson {
+                if seen.insert(chords[i].output()) {
+                    covered += typed(&chords[i]);
+                }
+            }
+            Lesson {
+                chords: lesson.to_vec(),
+                coverage: if total == 0 {
+                    0.0
+                } else {
+                    covered as f64 / total as f64
+                },
+            }
+        })
+        .collect()
+}
+
+/// The chords in lesson order, each with its lesson number in a `lesson`
+/// column
+pub fn lesson_chords(chords: &[Chord], lessons: &[Lesson]) -> Vec<Chord> {
+    let mut ordered = vec![];
+    for (number, lesson) in lessons.iter().enumerate() {
+        for &i in &lesson.chords {
+            let mut chord = chords[i].clone();
+            chord.set_extra("lesson", (number + 1).to_string());
+            ordered.push(chord);
+        }
+    }
+    ordered
+}
+
+/// A Markdown page with a table of chords for each lesson and how much of
+/// the corpus can be typed after it
+pub fn markdown(chords: &[Chord], lessons: &[Lesson]) -> String {
+    let mut page = String::from("# Learning plan\n");
+    for (number, lesson) in lessons.iter().enumerate() {
+        let _ = write!(
+            page,
+            "\n## Lesson {}\n\n{:.0}% of the text typed after this lesson\n\n| Output | Buttons |\n| --- | --- |\n",
+            number + 1,
+            lesson.coverage * 100.0
+        );
+        for &i in &lesson.chords {
+            // Pipes would end the cell and spaces would be invisible
+            let output = chords[i].output().replace('|', "\\|").replace(' ', "␣");
+            let _ = writeln!(page, "| `{}` | {} |", output, chords[i].buttons());
+        }
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan() {
+        let chords = vec![
+            Chord::new("", "4L", "z"),
+            Chord::new("", "0L", "e"),
+            Chord::new("", "1L", "t"),
+            Chord::new("1", "1L 2M", "the"),
+            Chord::new("", "2L", "a"),
+        ];
+        let lessons = plan(&chords, "the tea ate", 2);
+        assert!(lessons.len() == 3);
+        // the 3, then t, e and a 2 each, easiest first, and z untyped
+        assert!(lessons[0].chords == [3, 2]);
+        assert!(lessons[1].chords == [4, 1]);
+        assert!(lessons[2].chords == [0]);
+        // Two spaces aren't typed
+        assert!(lessons[0].coverage == 5.0 / 11.0);
+        assert!(lessons[2].coverage == 9.0 / 11.0);
+
+        let ordered = lesson_chords(&chords, &lessons);
+        assert!(ordered[0].output() == "the" && ordered[0].extra("lesson") == Some("1"));
+        assert!(ordered[4].output() == "z" && ordered[4].extra("lesson") == Some("3"));
+
+        let page = markdown(&chords, &lessons);
+        assert!(page.starts_with("# Learning plan\n\n## Lesson 1\n\n45% of the text"));
+        assert!(page.contains("| `the` | T1 1L 2M |\n| `t` | 1L |\n\n## Lesson 2"));
+    }
+}
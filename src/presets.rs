@@ -0,0 +1,68 @@
+use std::io::Cursor;
+
+use crate::{
+    convert::{self, Format},
+    csv::Chord,
+    layout::Layout,
+    twiddler7, Error, Result,
+};
+
+// There's no copy of TabSpace in the repo yet so it can't be bundled
+const PRESETS: [(&str, Format, &[u8]); 3] = [
+    (
+        "default",
+        Format::Twiddler7,
+        include_bytes!("../test/configs/v7/default.cfg"),
+    ),
+    (
+        "backspice2",
+        Format::Twiddler5,
+        include_bytes!("../configs/backspice2_v5.cfg"),
+    ),
+    (
+        "coolhand",
+        Format::Csv,
+        include_bytes!("../configs/twiddler_cfg_CoolHand.csv"),
+    ),
+];
+
+/// Names accepted by [`get`] and [`config`]
+pub fn names() -> impl Iterator<Item = &'static str> {
+    PRESETS.iter().map(|(name, _, _)| *name)
+}
+
+/// A bundled layout with its settings, e.g. `config("backspice2")`
+pub fn config(name: &str) -> Result<twiddler7::Config> {
+    let (_, format, data) = PRESETS
+        .iter()
+        .find(|(preset, _, _)| *preset == name)
+        .ok_or_else(|| {
+            Error::Invalid(format!(
+                "No preset called {:?}, try one of {}",
+                name,
+                names().collect::<Vec<_>>().join(", ")
+            ))
+        })?;
+    convert::read(*format, &mut Cursor::new(data))
+}
+
+/// The chords of a bundled layout, `None` when there's no preset called
+/// `name`. Every bundled one converts, the tests check.
+pub fn get(name: &str) -> Option<Vec<Chord>> {
+    convert::twiddler7_to_csv(&config(name).ok()?, Layout::Us).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        for name in names() {
+            assert!(!get(name).unwrap().is_empty(), "{}", name);
+        }
+        assert!(get("default").unwrap()[0] == Chord::new("", "1R", "a"));
+        assert!(get("qwerty").is_none());
+        assert!(matches!(config("qwerty"), Err(Error::Invalid(_))));
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::{
+    buttons::ButtonState,
+    config,
+    convert::{self, Format},
+    csv, json,
+    layout::Layout,
+    output::ParseOptions,
+    validate::{self, Conflict},
+    Error,
+};
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> Self {
+        PyValueError::new_err(error.to_string())
+    }
+}
+
+/// Buttons in the CLI's notation, e.g. `ButtonState("T1 1R 2M")`
+#[pyclass(
+    name = "ButtonState",
+    module = "twiddler_cfg",
+    eq,
+    hash,
+    frozen,
+    from_py_object
+)]
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct PyButtonState(ButtonState);
+
+#[pymethods]
+impl PyButtonState {
+    #[new]
+    fn new(notation: &str) -> PyResult<Self> {
+        Ok(Self(notation.parse()?))
+    }
+
+    #[staticmethod]
+    fn from_bits(bits: u32) -> Option<Self> {
+        ButtonState::from_bits(bits).map(Self)
+    }
+
+    #[getter]
+    fn bits(&self) -> u32 {
+        self.0.bits()
+    }
+
+    /// Each button on its own, e.g. `["T1", "1R"]`
+    fn buttons(&self) -> Vec<String> {
+        self.0.iter().map(|button| button.to_string()).collect()
+    }
+
+    fn mirrored(&self) -> Self {
+        Self(self.0.mirrored())
+    }
+
+    fn __contains__(&self, other: &Self) -> bool {
+        self.0.contains(other.0)
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+
+    fn __or__(&self, other: &Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    fn __and__(&self, other: &Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("ButtonState({:?})", self.0.to_string())
+    }
+}
+
+/// Either a [`PyButtonState`] or its notation
+#[derive(FromPyObject)]
+enum Buttons {
+    State(PyButtonState),
+    Notation(String),
+}
+
+impl Buttons {
+    fn state(self) -> PyResult<ButtonState> {
+        match self {
+            Buttons::State(state) => Ok(state.0),
+            Buttons::Notation(notation) => Ok(notation.parse()?),
+        }
+    }
+}
+
+/// A CSV row, e.g. `Chord("T1 1R", "the ")`. Outputs use the CSV tags.
+#[pyclass(name = "Chord", module = "twiddler_cfg", eq, from_py_object)]
+#[derive(Clone, PartialEq)]
+struct PyChord(csv::Chord);
+
+#[pymethods]
+impl PyChord {
+    #[new]
+    #[pyo3(signature = (buttons, output, layer = 0))]
+    fn new(buttons: Buttons, output: String, layer: u8) -> PyResult<Self> {
+        let mut chord = csv::Chord::from_buttons(&buttons.state()?, output);
+        chord.set_layer(layer);
+        Ok(Self(chord))
+    }
+
+    #[getter]
+    fn buttons(&self) -> PyResult<PyButtonState> {
+        Ok(PyButtonState(ButtonState::try_from(&self.0)?))
+    }
+
+    #[setter]
+    fn set_buttons(&mut self, buttons: Buttons) -> PyResult<()> {
+        self.0.set_buttons(&buttons.state()?);
+        Ok(())
+    }
+
+    #[getter]
+    fn thumbs(&self) -> &str {
+        self.0.thumbs()
+    }
+
+    #[getter]
+    fn fingers(&self) -> &str {
+        self.0.fingers()
+    }
+
+    #[getter]
+    fn output(&self) -> &str {
+        self.0.output()
+    }
+
+    #[setter]
+    fn set_output(&mut self, output: String) {
+        self.0.set_output(output);
+    }
+
+    #[getter]
+    fn layer(&self) -> u8 {
+        self.0.layer()
+    }
+
+    #[setter]
+    fn set_layer(&mut self, layer: u8) {
+        self.0.set_layer(layer);
+    }
+
+    #[getter]
+    fn category(&self) -> Option<&str> {
+        self.0.category()
+    }
+
+    #[setter]
+    fn set_category(&mut self, category: Option<String>) {
+        self.0.set_category(category.unwrap_or_default());
+    }
+
+    /// The (modifier, key code) pairs the chord sends on a US host
+    fn hid_pairs(&self) -> PyResult<Vec<(u8, u8)>> {
+        Ok(self.0.get_hid_pairs()?)
+    }
+
+    fn __repr__(&self) -> String {
+        let buttons = ButtonState::lossy(&self.0);
+        match self.0.layer() {
+            0 => format!("Chord({:?}, {:?})", buttons.to_string(), self.0.output()),
+            layer => format!(
+                "Chord({:?}, {:?}, layer={})",
+                buttons.to_string(),
+                self.0.output(),
+                layer
+            ),
+        }
+    }
+}
+
+/// A config in any format, as the chords, settings and metadata of a JSON
+/// config. Formats are named as on the command line, e.g. "csv" or "v7".
+#[pyclass(name = "Config", module = "twiddler_cfg", from_py_object)]
+#[derive(Clone)]
+struct PyConfig(json::Config);
+
+fn format(name: Option<&str>, reader: &mut Cursor<&[u8]>) -> PyResult<Format> {
+    match name {
+        Some(name) => Ok(name.parse()?),
+        None => Ok(config::detect_version(reader)?.format()?),
+    }
+}
+
+#[pymethods]
+impl PyConfig {
+    #[new]
+    #[pyo3(signature = (chords = vec![]))]
+    fn new(chords: Vec<PyChord>) -> Self {
+        Self(json::Config::new(chords.into_iter().map(|c| c.0).collect()))
+    }
+
+    /// The format is detected when left out
+    #[staticmethod]
+    #[pyo3(signature = (data, format = None))]
+    fn from_bytes(data: &[u8], format: Option<&str>) -> PyResult<Self> {
+        let mut reader = Cursor::new(data);
+        let format = self::format(format, &mut reader)?;
+        let config = convert::read(format, &mut reader)?;
+        Ok(Self(convert::to_json(config, Layout::Us)?))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (path, format = None))]
+    fn read(path: PathBuf, format: Option<&str>) -> PyResult<Self> {
+        let data = std::fs::read(path).map_err(Error::from)?;
+        Self::from_bytes(&data, format)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>, format: &str) -> PyResult<Bound<'py, PyBytes>> {
+        let (config, _) = convert::from_json(self.0.clone(), Layout::Us, ParseOptions::STRICT)?;
+        let mut writer = Cursor::new(vec![]);
+        convert::write(format.parse()?, config, &mut writer)?;
+        Ok(PyBytes::new(py, writer.get_ref()))
+    }
+
+    fn write(&self, py: Python<'_>, path: PathBuf, format: &str) -> PyResult<()> {
+        let bytes = self.to_bytes(py, format)?;
+        std::fs::write(path, bytes.as_bytes()).map_err(Error::from)?;
+        Ok(())
+    }
+
+    /// Copies of the chords, assign a list back to change them
+    #[getter]
+    fn chords(&self) -> Vec<PyChord> {
+        self.0.chords.iter().cloned().map(PyChord).collect()
+    }
+
+    #[setter]
+    fn set_chords(&mut self, chords: Vec<PyChord>) {
+        self.0.chords = chords.into_iter().map(|c| c.0).collect();
+    }
+
+    /// `{"name": ..., "author": ..., "version": ..., "description": ...}`
+    #[getter]
+    fn metadata(&self) -> BTreeMap<&'static str, Option<&str>> {
+        self.0.metadata.fields().into_iter().collect()
+    }
+
+    /// Indices of chords that can't all be used at once, like `validate`
+    fn conflicts(&self) -> Vec<Vec<usize>> {
+        validate::find_conflicts(&self.0.chords)
+            .into_iter()
+            .map(|conflict| match conflict {
+                Conflict::SameButtons { chords, .. } | Conflict::Duplicate { chords } => chords,
+            })
+            .collect()
+    }
+
+    /// `(output, indices)` for outputs typed by more than one chord
+    fn duplicate_outputs(&self) -> Vec<(String, Vec<usize>)> {
+        validate::find_duplicate_outputs(&self.0.chords)
+            .into_iter()
+            .map(|duplicate| (duplicate.output, duplicate.chords))
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.chords.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Config with {} chords>", self.0.chords.len())
+    }
+}
+
+#[pymodule]
+pub fn twiddler_cfg(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyButtonState>()?;
+    module.add_class::<PyChord>()?;
+    module.add_class::<PyConfig>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_module() {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "twiddler_cfg").unwrap();
+            twiddler_cfg(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("tc", module).unwrap();
+            py.run(
+                cr#"
+state = tc.ButtonState("T1 1R")
+assert state.buttons() == ["T1", "1R"] and len(state) == 2
+assert tc.ButtonState("1R") in state
+assert tc.ButtonState.from_bits(state.bits) == state
+
+chord = tc.Chord(state, "<L-Shift>a</L-Shift>")
+assert chord.hid_pairs() == [(2, 4)] and chord.thumbs == "1"
+assert repr(chord) == "Chord(\"T1 1R\", \"<L-Shift>a</L-Shift>\")"
+
+config = tc.Config([chord, tc.Chord("T1 1R", "b")])
+assert config.conflicts() == [[0, 1]]
+config.chords = config.chords[1:]
+config = tc.Config.from_bytes(config.to_bytes("v7"))
+assert [c.output for c in config.chords] == ["b"]
+assert config.metadata["name"] is None
+
+try:
+    tc.ButtonState("T9")
+    assert False
+except ValueError:
+    pass
+"#,
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}
@@ -0,0 +1,571 @@
+use std::ops::RangeInclusive;
+
+use crate::{analysis, buttons::ButtonState, csv::Chord, hid, output, Error, Result};
+
+/// What to look for in [`find`]
+#[derive(Debug, Clone)]
+pub enum Query {
+    /// Outputs exactly this, in the CSV notation
+    Output(String),
+    /// Outputs containing this
+    Contains(String),
+    /// Outputs a pattern matches anywhere in
+    Matches(Pattern),
+    /// Chords on exactly these buttons
+    Buttons(ButtonState),
+    /// Chords pressing at least these buttons
+    Pressing(ButtonState),
+    /// Chords holding any of these modifier bits, see [`modifier_mask`]
+    Modifier(u8),
+    /// Chords pressing this many buttons
+    ButtonCount(RangeInclusive<usize>),
+    /// Chords in this category, in any case
+    Category(String),
+    /// Chords matching every query
+    All(Vec<Query>),
+}
+
+impl Query {
+    pub fn matches(&self, chord: &Chord) -> bool {
+        match self {
+            Query::Output(output) => chord.output() == output,
+            Query::Contains(text) => chord.output().contains(text.as_str()),
+            Query::Matches(pattern) => pattern.is_match(chord.output()),
+            Query::Buttons(buttons) => chord.buttons() == *buttons,
+            Query::Pressing(buttons) => chord.buttons().contains(*buttons),
+            Query::Modifier(mask) => output::parse(chord.output())
+                .is_ok_and(|tokens| analysis::held_modifiers(&tokens) & mask != 0),
+            Query::ButtonCount(count) => count.contains(&chord.buttons().len()),
+            Query::Category(category) => chord
+                .category()
+                .is_some_and(|name| name.eq_ignore_ascii_case(category)),
+            Query::All(queries) => queries.iter().all(|query| query.matches(chord)),
+        }
+    }
+}
+
+/// Indices of the chords matching `query`, in order
+pub fn find(chords: &[Chord], query: &Query) -> Vec<usize> {
+    chords
+        .iter()
+        .enumerate()
+        .filter(|(_, chord)| query.matches(chord))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Modifier bits for a tag name like "L-Ctrl", or both sides for "Ctrl",
+/// "Shift", "Alt" and "Gui"
+pub fn modifier_mask(name: &str) -> Option<u8> {
+    match name {
+        "Ctrl" => Some(0x11),
+        "Shift" => Some(0x22),
+        "Alt" => Some(0x44),
+        "Gui" => Some(0x88),
+        _ => hid::modifier_tag(name),
+    }
+}
+
+/// A regular expression over outputs. Supports literals, `.`, classes like
+/// `[a-z]` and `[^0-9]`, `\d` `\w` `\s`, `^` and `$`, groups with `|` and
+/// the `*` `+` `?` `{n,m}` repeats. Matching takes time linear in the text
+/// whatever the pattern, every way it could match is followed at once.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    program: Vec<Inst>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+    },
+}
+
+// The pattern compiled for matching, Split and Jump go to other instructions
+// and the rest read a character or check where the text is
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+// Counted repeats are written out, so `(a{100}){100}` takes 10000
+// instructions, this keeps a pattern from taking all the memory
+const MAX_INSTRUCTIONS: usize = 100_000;
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut parser = Parser {
+            source,
+            chars: &chars,
+            pos: 0,
+        };
+        let alternatives = parser.alternatives()?;
+        if parser.pos < chars.len() {
+            return Err(parser.error("unmatched )"));
+        }
+
+        let mut program = vec![];
+        compile_alternatives(&alternatives, &mut program);
+        program.push(Inst::Match);
+        if program.len() > MAX_INSTRUCTIONS {
+            return Err(parser.error("repeats too large"));
+        }
+        Ok(Pattern {
+            source: source.to_string(),
+            program,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        for pos in 0..=text.len() {
+            // A match can start anywhere in the text
+            if self.add_thread(&mut current, 0, pos, text.len()) {
+                return true;
+            }
+            let Some(&c) = text.get(pos) else {
+                break;
+            };
+            next.clear();
+            for &pc in &current.list {
+                let read = match &self.program[pc] {
+                    Inst::Char(expected) => *expected == c,
+                    Inst::Any => true,
+                    Inst::Class { ranges, negated } => in_class(ranges, *negated, c),
+                    _ => false,
+                };
+                if read && self.add_thread(&mut next, pc + 1, pos + 1, text.len()) {
+                    return true;
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        false
+    }
+
+    // Follow the jumps and checks from pc, adding the instructions waiting
+    // for the next character. True if the pattern can end here.
+    fn add_thread(&self, threads: &mut Threads, pc: usize, pos: usize, len: usize) -> bool {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if threads.seen[pc] {
+                continue;
+            }
+            threads.seen[pc] = true;
+            match self.program[pc] {
+                Inst::Jump(to) => stack.push(to),
+                Inst::Split(first, second) => stack.extend([second, first]),
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == len => stack.push(pc + 1),
+                Inst::Start | Inst::End => (),
+                Inst::Match => return true,
+                Inst::Char(_) | Inst::Any | Inst::Class { .. } => threads.list.push(pc),
+            }
+        }
+        false
+    }
+}
+
+// The instructions waiting for the character at one position, each once
+struct Threads {
+    list: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads {
+            list: vec![],
+            seen: vec![false; len],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.fill(false);
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: &'a [char],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, reason: &str) -> Error {
+        Error::Invalid(format!(
+            "Bad pattern {:?} at {}: {}",
+            self.source, self.pos, reason
+        ))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let node = self.atom()?;
+            nodes.push(self.repeat(node)?);
+        }
+        Ok(nodes)
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        let node = match self.next() {
+            Some('(') => {
+                if self.chars[self.pos..].starts_with(&['?', ':']) {
+                    self.pos += 2;
+                }
+                let group = self.alternatives()?;
+                if self.next() != Some(')') {
+                    return Err(self.error("unclosed ("));
+                }
+                Node::Group(group)
+            }
+            Some('[') => self.class()?,
+            Some('.') => Node::Any,
+            Some('^') => Node::Start,
+            Some('$') => Node::End,
+            Some('\\') => self.escape()?,
+            Some('*' | '+' | '?' | '{') => return Err(self.error("nothing to repeat")),
+            Some(c) => Node::Char(c),
+            None => return Err(self.error("unexpected end")),
+        };
+        Ok(node)
+    }
+
+    fn repeat(&mut self, node: Node) -> Result<Node> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => return self.counted(node),
+            _ => return Ok(node),
+        };
+        self.pos += 1;
+        self.repeated(node, min, max)
+    }
+
+    // {n}, {n,} or {n,m}
+    fn counted(&mut self, node: Node) -> Result<Node> {
+        let bad = |parser: &Self| parser.error("expected {n}, {n,} or {n,m}");
+        self.pos += 1;
+        let min = self.number().ok_or_else(|| bad(self))?;
+        let max = match self.peek() {
+            Some(',') => {
+                self.pos += 1;
+                match self.peek() {
+                    Some('}') => None,
+                    _ => Some(self.number().ok_or_else(|| bad(self))?),
+                }
+            }
+            _ => Some(min),
+        };
+        if self.next() != Some('}') || max.is_some_and(|max| max < min) {
+            return Err(bad(self));
+        }
+        self.repeated(node, min, max)
+    }
+
+    fn repeated(&self, node: Node, min: usize, max: Option<usize>) -> Result<Node> {
+        if matches!(node, Node::Start | Node::End) {
+            return Err(self.error("nothing to repeat"));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        })
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn escape(&mut self) -> Result<Node> {
+        let c = self.next().ok_or_else(|| self.error("\\ at the end"))?;
+        let (ranges, negated) = match c {
+            'd' | 'D' => (DIGITS.to_vec(), c == 'D'),
+            'w' | 'W' => (WORD.to_vec(), c == 'W'),
+            's' | 'S' => (SPACE.to_vec(), c == 'S'),
+            'n' => return Ok(Node::Char('\n')),
+            't' => return Ok(Node::Char('\t')),
+            c => return Ok(Node::Char(c)),
+        };
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn class(&mut self) -> Result<Node> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut ranges = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.next() {
+                None => return Err(self.error("unclosed [")),
+                Some(']') if !first => break,
+                Some('\\') => match self.next() {
+                    Some('d') => {
+                        ranges.extend(DIGITS);
+                        continue;
+                    }
+                    Some('w') => {
+                        ranges.extend(WORD);
+                        continue;
+                    }
+                    Some('s') => {
+                        ranges.extend(SPACE);
+                        continue;
+                    }
+                    Some(c) => c,
+                    None => return Err(self.error("unclosed [")),
+                },
+                Some(c) => c,
+            };
+            first = false;
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|c| *c != ']') {
+                self.pos += 1;
+                let end = self.next().unwrap();
+                if end < c {
+                    return Err(self.error("range out of order"));
+                }
+                ranges.push((c, end));
+            } else {
+                ranges.push((c, c));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+}
+
+const DIGITS: [(char, char); 1] = [('0', '9')];
+const WORD: [(char, char); 4] = [('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: [(char, char); 4] = [(' ', ' '), ('\t', '\t'), ('\n', '\n'), ('\r', '\r')];
+
+fn in_class(ranges: &[(char, char)], negated: bool, c: char) -> bool {
+    ranges
+        .iter()
+        .any(|(start, end)| (*start..=*end).contains(&c))
+        != negated
+}
+
+fn compile_alternatives(alternatives: &[Vec<Node>], program: &mut Vec<Inst>) {
+    let mut jumps = vec![];
+    for (i, sequence) in alternatives.iter().enumerate() {
+        if i + 1 == alternatives.len() {
+            compile_sequence(sequence, program);
+            break;
+        }
+        let split = program.len();
+        program.push(Inst::Split(split + 1, 0));
+        compile_sequence(sequence, program);
+        jumps.push(program.len());
+        program.push(Inst::Jump(0));
+        program[split] = Inst::Split(split + 1, program.len());
+    }
+    for jump in jumps {
+        program[jump] = Inst::Jump(program.len());
+    }
+}
+
+fn compile_sequence(sequence: &[Node], program: &mut Vec<Inst>) {
+    for node in sequence {
+        // Stop writing out repeats once the pattern is too large, new
+        // rejects it
+        if program.len() > MAX_INSTRUCTIONS {
+            return;
+        }
+        compile_node(node, program);
+    }
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Char(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class { ranges, negated } => program.push(Inst::Class {
+            ranges: ranges.clone(),
+            negated: *negated,
+        }),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Group(alternatives) => compile_alternatives(alternatives, program),
+        Node::Repeat { node, min, max } => {
+            let node = core::slice::from_ref(node.as_ref());
+            for _ in 0..*min {
+                if program.len() > MAX_INSTRUCTIONS {
+                    return;
+                }
+                compile_sequence(node, program);
+            }
+            match max {
+                // x* as a loop back to a split
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile_sequence(node, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                // x{0,n} as n optional copies, each one skipping to the end
+                Some(max) => {
+                    let mut splits = vec![];
+                    for _ in *min..*max {
+                        if program.len() > MAX_INSTRUCTIONS {
+                            break;
+                        }
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile_sequence(node, program);
+                    }
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, program.len());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern() {
+        let matches = |pattern: &str, text: &str| Pattern::new(pattern).unwrap().is_match(text);
+        assert!(matches("th", "the"));
+        assert!(matches("^th(e|at)$", "that"));
+        assert!(!matches("^th(e|at)$", "them"));
+        assert!(matches("^[a-z]+ $", "and "));
+        assert!(!matches("^[^<]*$", "<Tab>"));
+        assert!(matches("\\d{2,3}", "a12"));
+        assert!(!matches("^\\d{2}$", "123"));
+        assert!(matches("^(ab)*c?$", "abab"));
+        assert!(matches("^a.*z$", "abcz"));
+        assert!(matches("\\<", "\\<"));
+        assert!(Pattern::new("(a").is_err());
+        assert!(Pattern::new("*a").is_err());
+        assert!(Pattern::new("[z-a]").is_err());
+        assert!(Pattern::new("a{3,1}").is_err());
+        assert!(matches("^a{2,}$", "aaaa") && !matches("^a{2,}$", "a"));
+    }
+
+    #[test]
+    fn test_pattern_repeats() {
+        let matches = |pattern: &str, text: &str| Pattern::new(pattern).unwrap().is_match(text);
+        assert!(matches("^(a|bc)+$", "abca") && !matches("^(a|bc)+$", "abcb"));
+        assert!(matches("^(ab|a)(bc)?$", "abc"));
+        assert!(matches("^x(a|)y$", "xy") && matches("^x(a|)y$", "xay"));
+        assert!(matches("^(a*)*$", "") && matches("^(a*)*$", "aaa"));
+        assert!(matches("^(a?){3}b$", "ab") && !matches("^(a?){3}b$", "aaaab"));
+        assert!(matches("^(ab){2}$", "abab") && !matches("^(ab){2}$", "ab"));
+        assert!(matches("^a{0}b$", "b") && !matches("^a{0}b$", "ab"));
+        assert!(matches("^[^a-c]{1,2}$", "xy") && !matches("^[^a-c]{1,2}$", "xb"));
+        assert!(matches("é+$", "café") && matches("^\\w\\s\\d$", "a 1"));
+        assert!(!matches("a$b", "ab") && !matches("a^", "a"));
+        assert!(Pattern::new("(a{1000}){1000}").is_err());
+        assert!(Pattern::new("a{99999999999}").is_err());
+    }
+
+    #[test]
+    fn test_pattern_time() {
+        // A backtracking matcher tries every way of splitting the a's
+        // between the alternatives, 2^28 of them
+        let text = "a".repeat(28);
+        let start = std::time::Instant::now();
+        assert!(!Pattern::new("^(a|a)*b$").unwrap().is_match(&text));
+        assert!(!Pattern::new("^(a*)*b$").unwrap().is_match(&text));
+        assert!(!Pattern::new("^(a+a+)+b$").unwrap().is_match(&text));
+        assert!(Pattern::new("^(a|a)*$").unwrap().is_match(&text));
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_find() {
+        let mut chords = vec![
+            Chord::new("", "1L", "the "),
+            Chord::new("1", "1M", "<L-Ctrl>c</L-Ctrl>"),
+            Chord::new("", "1L 2M", "<R-Ctrl>v</R-Ctrl>"),
+            Chord::new("", "1R 2R 3R", "them"),
+        ];
+        assert!(find(&chords, &Query::Output("them".to_string())) == [3]);
+        assert!(find(&chords, &Query::Contains("the".to_string())) == [0, 3]);
+        let pattern = Pattern::new("^the.$").unwrap();
+        assert!(find(&chords, &Query::Matches(pattern)) == [0, 3]);
+        assert!(find(&chords, &Query::Buttons("1L".parse().unwrap())) == [0]);
+        assert!(find(&chords, &Query::Pressing("1L".parse().unwrap())) == [0, 2]);
+        assert!(find(&chords, &Query::Modifier(modifier_mask("Ctrl").unwrap())) == [1, 2]);
+        assert!(find(&chords, &Query::Modifier(modifier_mask("L-Ctrl").unwrap())) == [1]);
+        assert!(find(&chords, &Query::ButtonCount(2..=2)) == [1, 2]);
+        chords[1].set_category("Shortcuts");
+        assert!(find(&chords, &Query::Category("shortcuts".to_string())) == [1]);
+
+        let query = Query::All(vec![
+            Query::Contains("the".to_string()),
+            Query::ButtonCount(3..=usize::MAX),
+        ]);
+        assert!(find(&chords, &query) == [3]);
+    }
+}
@@ -0,0 +1,711 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::{
+    analysis::{held_modifiers, ButtonUsage},
+    buttons::ButtonState,
+    csv::Chord,
+    hid::MODIFIER_TAGS,
+    output,
+};
+
+const COLUMNS: usize = 8;
+const CELL_WIDTH: usize = 96;
+const HEADER_HEIGHT: usize = 28;
+const BUTTON: usize = 16;
+const GAP: usize = 4;
+const LABEL_LENGTH: usize = 12;
+
+// Chords with a category are grouped by it, after the groups of the ones
+// without
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Group<'a> {
+    Letters,
+    Numbers,
+    Symbols,
+    Words,
+    Keys,
+    Category(&'a str),
+}
+
+impl<'a> Group<'a> {
+    fn of(chord: &'a Chord) -> Group<'a> {
+        if let Some(category) = chord.category() {
+            return Group::Category(category);
+        }
+        let output = chord.output();
+        let mut chars = output.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => Group::Letters,
+            (Some(c), None) if c.is_ascii_digit() => Group::Numbers,
+            (Some(_), None) => Group::Symbols,
+            _ if output.contains('<') => Group::Keys,
+            _ => Group::Words,
+        }
+    }
+
+    pub(crate) fn title(self) -> &'a str {
+        match self {
+            Group::Letters => "Letters",
+            Group::Numbers => "Numbers",
+            Group::Symbols => "Symbols",
+            Group::Words => "Words",
+            Group::Keys => "Keys and shortcuts",
+            Group::Category(category) => category,
+        }
+    }
+}
+
+/// An SVG cheat sheet with a button diagram for every chord, grouped by
+/// category, or into letters, numbers, symbols, words and everything else
+/// for chords without one
+pub fn svg(chords: &[Chord]) -> String {
+    let rows = finger_rows(chords);
+    let cell_height = 24 + (BUTTON / 2 + GAP) + rows.len() * (BUTTON + GAP) + GAP;
+
+    let mut body = String::new();
+    let mut y = 0;
+    let mut last_group = None;
+    let mut column = 0;
+    for (group, chord) in sorted(chords) {
+        if last_group != Some(group) {
+            if last_group.is_some() {
+                y += cell_height;
+            }
+            let _ = writeln!(
+                body,
+                r#"<text x="8" y="{}" class="group">{}</text>"#,
+                y + 20,
+                escape(group.title())
+            );
+            y += HEADER_HEIGHT;
+            column = 0;
+            last_group = Some(group);
+        } else if column == COLUMNS {
+            y += cell_height;
+            column = 0;
+        }
+
+        draw_chord(&mut body, column * CELL_WIDTH, y, chord, rows.clone());
+        column += 1;
+    }
+    if last_group.is_some() {
+        y += cell_height;
+    }
+
+    let width = COLUMNS * CELL_WIDTH;
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            "\n<style>",
+            "text{{font-family:sans-serif;font-size:12px}}",
+            ".group{{font-size:16px;font-weight:bold}}",
+            "rect{{fill:#fff;stroke:#444}}",
+            "rect.on{{fill:#2a6fdb}}",
+            "</style>\n",
+            r#"<rect width="100%" height="100%" style="stroke:none"/>"#,
+            "\n{body}</svg>\n"
+        ),
+        w = width,
+        h = y,
+        body = body
+    )
+}
+
+/// A standalone HTML page listing every chord with its buttons drawn
+/// inline, searchable by output or notation and filterable by group
+pub fn html(chords: &[Chord]) -> String {
+    let rows = finger_rows(chords);
+    let width = 2 * GAP + 3 * (BUTTON + GAP);
+    let height = 2 * GAP + BUTTON / 2 + rows.len() * (BUTTON + GAP);
+
+    let mut groups: Vec<Group> = chords.iter().map(Group::of).collect();
+    groups.sort();
+    groups.dedup();
+    let options: String = groups
+        .iter()
+        .map(|g| format!(r#"<option>{}</option>"#, escape(g.title())))
+        .collect();
+
+    let mut table = String::new();
+    for (group, chord) in sorted(chords) {
+        let mut diagram = String::new();
+        draw_buttons(&mut diagram, GAP, GAP, &chord.buttons(), rows.clone());
+        let _ = writeln!(
+            table,
+            concat!(
+                r#"<tr data-group="{}"><td><code>{}</code></td><td>{}</td>"#,
+                r#"<td><svg width="{}" height="{}">{}</svg></td></tr>"#
+            ),
+            escape(group.title()),
+            escape(chord.output()),
+            chord.buttons(),
+            width,
+            height,
+            diagram.replace('\n', "")
+        );
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Twiddler chords</title>
+<style>
+body{{font-family:sans-serif;margin:2em}}
+input,select{{font-size:1.2em;margin-bottom:1em}}
+td{{padding:2px 12px;border-bottom:1px solid #ddd}}
+code{{white-space:pre;font-size:1.2em}}
+rect{{fill:#fff;stroke:#444}}
+rect.on{{fill:#2a6fdb}}
+</style>
+</head>
+<body>
+<input id="search" type="search" placeholder="Search outputs or buttons" autofocus>
+<select id="group"><option value="">All</option>{options}</select>
+<table>
+<thead><tr><th>Output</th><th>Buttons</th><th></th></tr></thead>
+<tbody>
+{table}</tbody>
+</table>
+<script>
+const search = document.getElementById("search");
+const group = document.getElementById("group");
+function filter() {{
+  const query = search.value.toLowerCase();
+  for (const row of document.querySelectorAll("tbody tr")) {{
+    const text = (row.cells[0].textContent + "\n" + row.cells[1].textContent).toLowerCase();
+    row.hidden = !text.includes(query) || (group.value !== "" && row.dataset.group !== group.value);
+  }}
+}}
+search.oninput = filter;
+group.onchange = filter;
+</script>
+</body>
+</html>
+"#,
+        options = options,
+        table = table
+    )
+}
+
+/// An Anki deck to import as tab separated notes, the output on the front
+/// and the buttons drawn on the back, tagged with the chord's group
+pub fn anki(chords: &[Chord]) -> String {
+    let rows = finger_rows(chords);
+    let width = 2 * GAP + 3 * (BUTTON + GAP);
+    let height = 2 * GAP + BUTTON / 2 + rows.len() * (BUTTON + GAP);
+
+    let mut deck = String::from("#separator:tab\n#html:true\n#columns:Front\tBack\tTags\n");
+    for (group, chord) in sorted(chords) {
+        let mut diagram = String::new();
+        draw_buttons(&mut diagram, GAP, GAP, &chord.buttons(), rows.clone());
+        let svg = format!(
+            concat!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
+                "<style>rect{{fill:#fff;stroke:#444}}rect.on{{fill:#2a6fdb}}</style>{}</svg>"
+            ),
+            width,
+            height,
+            diagram.replace('\n', "")
+        );
+        // Tabs and newlines would end the field or the note
+        let front = escape(chord.output())
+            .replace('\t', "&#9;")
+            .replace('\n', "<br>");
+        let _ = writeln!(
+            deck,
+            "<code>{}</code>\t<img src=\"data:image/svg+xml,{}\"><br>{}\t{}",
+            front,
+            percent_encode(&svg),
+            chord.buttons(),
+            group.title().to_lowercase().replace(' ', "_")
+        );
+    }
+    deck
+}
+
+/// What [`dot`] connects chords by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DotGraph {
+    /// An arrow from each chord to the chords on the same layer pressing
+    /// one more button, so families of chords built on the same buttons
+    /// hang together
+    Buttons,
+    /// A tree of the modifiers chords hold, each combination under the one
+    /// without its last modifier, with the chords holding it as leaves.
+    /// Chords without modifiers are left out.
+    Modifiers,
+}
+
+/// A Graphviz DOT graph of the layout's structure, to render with
+/// `dot -Tsvg`. Chords are labeled with their output and buttons.
+pub fn dot(chords: &[Chord], graph: DotGraph) -> String {
+    let mut dot = String::from("digraph layout {\n    rankdir=LR;\n    node [shape=box];\n");
+    let node = |dot: &mut String, i: usize, chord: &Chord| {
+        let _ = writeln!(
+            dot,
+            "    chord{} [label=\"{}\\n{}\"];",
+            i,
+            dot_escape(chord.output()),
+            chord.buttons()
+        );
+    };
+
+    match graph {
+        DotGraph::Buttons => {
+            let buttons: Vec<ButtonState> = chords.iter().map(Chord::buttons).collect();
+            for (i, chord) in chords.iter().enumerate() {
+                node(&mut dot, i, chord);
+            }
+            for (i, from) in chords.iter().enumerate() {
+                for (j, to) in chords.iter().enumerate() {
+                    if from.layer() == to.layer()
+                        && buttons[j].len() == buttons[i].len() + 1
+                        && buttons[j].contains(buttons[i])
+                    {
+                        let _ = writeln!(dot, "    chord{} -> chord{};", i, j);
+                    }
+                }
+            }
+        }
+        DotGraph::Modifiers => {
+            // Modifier combinations by their bits, each with its parent
+            let mut held: Vec<(usize, u8)> = vec![];
+            let mut combinations = BTreeSet::new();
+            for (i, chord) in chords.iter().enumerate() {
+                let modifiers = output::parse(chord.output())
+                    .map(|tokens| held_modifiers(&tokens))
+                    .unwrap_or_default();
+                if modifiers != 0 {
+                    held.push((i, modifiers));
+                    let mut combination = modifiers;
+                    while combination != 0 && combinations.insert(combination) {
+                        combination &= !(1 << (7 - combination.leading_zeros()));
+                    }
+                }
+            }
+
+            for &combination in &combinations {
+                let names: Vec<&str> = (0..8)
+                    .filter(|bit| combination & (1 << bit) != 0)
+                    .map(|bit| MODIFIER_TAGS[bit])
+                    .collect();
+                let _ = writeln!(
+                    dot,
+                    "    mods{} [label=\"{}\", shape=ellipse];",
+                    combination,
+                    names.join(" + ")
+                );
+                let parent = combination & !(1 << (7 - combination.leading_zeros()));
+                if parent != 0 {
+                    let _ = writeln!(dot, "    mods{} -> mods{};", parent, combination);
+                }
+            }
+            for (i, modifiers) in held {
+                node(&mut dot, i, &chords[i]);
+                let _ = writeln!(dot, "    mods{} -> chord{};", modifiers, i);
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// An SVG of the buttons colored by how much they're used, each labeled
+/// with its share of the chords
+pub fn heatmap_svg(usage: &ButtonUsage) -> String {
+    const CELL: usize = 48;
+    let mut body = String::new();
+    let mut cell = |x: usize, y: usize, name: &str, value: f64| {
+        let (r, g, b) = heat(value / usage.max());
+        let _ = writeln!(
+            body,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" rx="4" fill="rgb({},{},{})"/>"#,
+            x,
+            y,
+            CELL - GAP,
+            CELL - GAP,
+            r,
+            g,
+            b
+        );
+        let _ = writeln!(
+            body,
+            r#"<text x="{}" y="{}">{}</text><text x="{}" y="{}" class="share">{:.0}%</text>"#,
+            x + 4,
+            y + 14,
+            name,
+            x + 4,
+            y + 34,
+            usage.share(value) * 100.0
+        );
+    };
+
+    for (i, value) in usage.thumbs.iter().enumerate() {
+        cell(i * CELL, 0, &format!("T{}", i + 1), *value);
+    }
+    let rows = heatmap_rows(usage);
+    let top = CELL + GAP * 2;
+    for (y, row) in rows.clone().enumerate() {
+        for (col, value) in usage.fingers[row].iter().enumerate() {
+            let name = format!("{}{}", row, ['L', 'M', 'R'][col]);
+            cell(col * CELL, top + y * CELL, &name, *value);
+        }
+    }
+
+    let width = 4 * CELL;
+    let height = top + rows.len() * CELL;
+    format!(
+        concat!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}" viewBox="0 0 {w} {h}">"#,
+            "\n<style>",
+            "text{{font-family:sans-serif;font-size:11px}}",
+            ".share{{font-size:14px;font-weight:bold}}",
+            "rect{{stroke:#444}}",
+            "</style>\n",
+            "{body}</svg>\n"
+        ),
+        w = width,
+        h = height,
+        body = body
+    )
+}
+
+/// The buttons as colored blocks for a terminal with 24-bit color, each
+/// labeled with its share of the chords
+pub fn heatmap_terminal(usage: &ButtonUsage) -> String {
+    let block = |value: f64| {
+        let (r, g, b) = heat(value / usage.max());
+        let share = format!("{:.0}%", usage.share(value) * 100.0);
+        format!("\x1b[48;2;{};{};{}m\x1b[30m{:^5}\x1b[0m ", r, g, b, share)
+    };
+
+    let mut lines = String::from("Thumbs\n  T1    T2    T3    T4\n");
+    let thumbs: String = usage.thumbs.iter().map(|value| block(*value)).collect();
+    let _ = writeln!(lines, "{}\n", thumbs.trim_end());
+    lines.push_str("Fingers\n    L     M     R\n");
+    for row in heatmap_rows(usage) {
+        let cols: String = usage.fingers[row]
+            .iter()
+            .map(|value| block(*value))
+            .collect();
+        let _ = writeln!(lines, "{} {}", row, cols.trim_end());
+    }
+    lines
+}
+
+/// The buttons of a chord drawn with box-drawing characters for a terminal,
+/// the pressed ones filled in. Row 0 is only drawn when it's pressed.
+pub fn diagram_terminal(buttons: &ButtonState) -> String {
+    const PRESSED: &str = "███";
+    let cells = |pressed: &[bool]| -> String {
+        let cells: Vec<&str> = pressed
+            .iter()
+            .map(|p| if *p { PRESSED } else { "   " })
+            .collect();
+        format!("│{}│", cells.join("│"))
+    };
+    let border = |left: &str, middle: &str, right: &str, count: usize| {
+        format!("{}{}{}", left, vec!["───"; count].join(middle), right)
+    };
+
+    let mut lines = String::from("  T1  T2  T3  T4\n");
+    let _ = writeln!(lines, " {}", border("┌", "┬", "┐", 4));
+    let _ = writeln!(lines, " {}", cells(&buttons.thumbs()));
+    let _ = writeln!(lines, " {}", border("└", "┴", "┘", 4));
+
+    let fingers = buttons.fingers();
+    let first = if fingers[0].contains(&true) { 0 } else { 1 };
+    lines.push_str("    L   M   R\n");
+    let _ = writeln!(lines, "  {}", border("┌", "┬", "┐", 3));
+    for (row, pressed) in fingers.iter().enumerate().skip(first) {
+        if row > first {
+            let _ = writeln!(lines, "  {}", border("├", "┼", "┤", 3));
+        }
+        let _ = writeln!(lines, "{} {}", row, cells(pressed));
+    }
+    let _ = writeln!(lines, "  {}", border("└", "┴", "┘", 3));
+    lines
+}
+
+// Row 0 is only drawn when a chord uses it, like in the cheat sheets
+fn heatmap_rows(usage: &ButtonUsage) -> Range<usize> {
+    if usage.fingers[0].iter().any(|value| *value > 0.0) {
+        0..5
+    } else {
+        1..5
+    }
+}
+
+// White for unused up to red for the most used, `heat` is 0 to 1
+fn heat(heat: f64) -> (u8, u8, u8) {
+    let heat = if heat.is_finite() {
+        heat.clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let mix = |from: f64, to: f64| (from + (to - from) * heat).round() as u8;
+    (mix(255.0, 220.0), mix(255.0, 50.0), mix(255.0, 32.0))
+}
+
+pub(crate) fn sorted(chords: &[Chord]) -> Vec<(Group<'_>, &Chord)> {
+    let mut sorted: Vec<(Group, &Chord)> = chords.iter().map(|c| (Group::of(c), c)).collect();
+    sorted.sort_by(|(a, ac), (b, bc)| (a, ac.output()).cmp(&(b, bc.output())));
+    sorted
+}
+
+// Row 0 is only drawn when a chord uses it
+pub(crate) fn finger_rows(chords: &[Chord]) -> Range<usize> {
+    if chords
+        .iter()
+        .any(|c| c.buttons().fingers()[0].contains(&true))
+    {
+        0..5
+    } else {
+        1..5
+    }
+}
+
+fn draw_chord(svg: &mut String, x: usize, y: usize, chord: &Chord, rows: Range<usize>) {
+    // Spaces would be invisible, Twiddler Tuner shows them the same way
+    let mut label: String = chord
+        .output()
+        .chars()
+        .take(LABEL_LENGTH)
+        .map(|c| if c == ' ' { '␣' } else { c })
+        .collect();
+    if chord.output().chars().count() > LABEL_LENGTH {
+        label.push('…');
+    }
+    let _ = writeln!(
+        svg,
+        r#"<text x="{}" y="{}">{}</text>"#,
+        x + 8,
+        y + 16,
+        escape(&label)
+    );
+
+    draw_buttons(svg, x + 8, y + 24, &chord.buttons(), rows);
+}
+
+fn draw_buttons(
+    svg: &mut String,
+    left: usize,
+    mut top: usize,
+    buttons: &ButtonState,
+    rows: Range<usize>,
+) {
+    for (i, pressed) in buttons.thumbs().iter().enumerate() {
+        rect(
+            svg,
+            left + i * (BUTTON + GAP) * 3 / 4,
+            top,
+            BUTTON * 3 / 4,
+            BUTTON / 2,
+            *pressed,
+        );
+    }
+    top += BUTTON / 2 + GAP;
+
+    let fingers = buttons.fingers();
+    for row in rows {
+        for (col, pressed) in fingers[row].iter().enumerate() {
+            rect(
+                svg,
+                left + col * (BUTTON + GAP),
+                top,
+                BUTTON,
+                BUTTON,
+                *pressed,
+            );
+        }
+        top += BUTTON + GAP;
+    }
+}
+
+fn rect(svg: &mut String, x: usize, y: usize, width: usize, height: usize, pressed: bool) {
+    let class = if pressed { r#" class="on""# } else { "" };
+    let _ = writeln!(
+        svg,
+        r#"<rect x="{}" y="{}" width="{}" height="{}" rx="2"{}/>"#,
+        x, y, width, height, class
+    );
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Everything but unreserved URI characters, for data URIs
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", byte);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "2R", "<L-Ctrl>c</L-Ctrl>"),
+        ];
+        let svg = svg(&chords);
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(">Letters</text>") && svg.contains(">Words</text>"));
+        assert!(!svg.contains(">Numbers</text>"));
+        assert!(svg.contains("&lt;L-Ctrl&gt;c&lt;/L…"));
+        assert!(svg.contains(">the␣</text>"));
+        // Five buttons pressed over the three chords
+        assert!(svg.matches(r#"class="on""#).count() == 5);
+    }
+
+    #[test]
+    fn test_html() {
+        let chords = vec![Chord::new("", "1L", "{"), Chord::new("1", "1L 2M", "the ")];
+        let html = html(&chords);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(r#"<tr data-group="Symbols"><td><code>{</code></td><td>1L</td>"#));
+        assert!(html.contains("<code>the </code></td><td>T1 1L 2M</td>"));
+        assert!(html.contains("<option>Words</option>") && !html.contains("<option>Keys"));
+        assert!(html.matches(r#"class="on""#).count() == 4);
+    }
+
+    #[test]
+    fn test_categories() {
+        let mut chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("", "2R", "<Left>"),
+            Chord::new("", "2M", "<Home>"),
+            Chord::new("1", "1L 2M", "the "),
+        ];
+        chords[1].set_category("navigation");
+        chords[2].set_category("navigation");
+        chords[3].set_category("R&D");
+
+        let svg = svg(&chords);
+        assert!(svg.matches(">navigation</text>").count() == 1);
+        assert!(!svg.contains(">Keys and shortcuts</text>") && !svg.contains(">Words</text>"));
+        // After the groups of chords without a category
+        let (letters, navigation) = (svg.find(">Letters<"), svg.find(">navigation<"));
+        assert!(letters < navigation);
+        assert!(svg.contains(">R&amp;D</text>"));
+
+        let html = html(&chords);
+        assert!(html.contains(r#"<tr data-group="navigation"><td><code>&lt;Home&gt;</code>"#));
+        assert!(html.contains("<option>R&amp;D</option>"));
+        let deck = anki(&chords);
+        assert!(
+            deck.lines()
+                .filter(|line| line.ends_with("\tnavigation"))
+                .count()
+                == 2
+        );
+    }
+
+    #[test]
+    fn test_anki() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("", "2R", "<L-Ctrl>c</L-Ctrl>"),
+        ];
+        let deck = anki(&chords);
+        let lines: Vec<&str> = deck.lines().collect();
+        assert!(lines.len() == 5 && lines[2] == "#columns:Front\tBack\tTags");
+        let fields: Vec<&str> = lines[3].split('\t').collect();
+        assert!(fields[0] == "<code>e</code>" && fields[2] == "letters");
+        assert!(fields[1].starts_with(r#"<img src="data:image/svg+xml,%3Csvg%20xmlns"#));
+        assert!(fields[1].ends_with(r#""><br>1L"#));
+        // One button pressed, with the class in the style not counting
+        assert!(fields[1].matches("class%3D%22on%22").count() == 1);
+        assert!(lines[4].starts_with("<code>&lt;L-Ctrl&gt;c&lt;/L-Ctrl&gt;</code>\t"));
+        assert!(lines[4].ends_with("\tkeys_and_shortcuts"));
+    }
+
+    #[test]
+    fn test_diagram_terminal() {
+        let diagram = diagram_terminal(&"T1 1R 2M".parse().unwrap());
+        let lines: Vec<&str> = diagram.lines().collect();
+        assert!(lines.len() == 4 + 1 + 4 * 2 + 1);
+        assert!(lines[2] == " │███│   │   │   │");
+        assert!(lines[6] == "1 │   │   │███│");
+        assert!(lines[8] == "2 │   │███│   │");
+        assert!(lines[13] == "  └───┴───┴───┘");
+        // Row 0 when it's pressed
+        assert!(diagram_terminal(&"0L".parse().unwrap()).contains("\n0 │███│   │   │\n"));
+    }
+
+    #[test]
+    fn test_dot() {
+        let chords = vec![
+            Chord::new("", "1L", "t"),
+            Chord::new("1", "1L", "T"),
+            Chord::new("1", "1L 2M", "the"),
+            Chord::new("", "2R", "<L-Ctrl>c</L-Ctrl>"),
+            Chord::new("", "3R", "<L-Ctrl><L-Shift>\"</L-Shift></L-Ctrl>"),
+        ];
+
+        let graph = dot(&chords, DotGraph::Buttons);
+        assert!(graph.starts_with("digraph layout {\n") && graph.ends_with("}\n"));
+        assert!(graph.contains("    chord2 [label=\"the\\nT1 1L 2M\"];\n"));
+        assert!(graph.contains("    chord0 -> chord1;\n    chord1 -> chord2;\n"));
+        assert!(graph.matches("->").count() == 2);
+
+        let graph = dot(&chords, DotGraph::Modifiers);
+        assert!(!graph.contains("chord0 "));
+        assert!(graph.contains("    mods1 [label=\"L-Ctrl\", shape=ellipse];\n"));
+        assert!(graph.contains("    mods3 [label=\"L-Ctrl + L-Shift\", shape=ellipse];\n"));
+        assert!(graph.contains("    mods1 -> mods3;\n"));
+        assert!(graph.contains("    mods1 -> chord3;\n    chord4"));
+        assert!(graph.contains("mods3 -> chord4;"));
+        assert!(graph.contains(r#"label="<L-Ctrl><L-Shift>\"</L-Shift></L-Ctrl>\n3R""#));
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let chords = vec![
+            Chord::new("", "1L", "e"),
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "2R", "x"),
+            Chord::new("", "2R 3R", "y"),
+        ];
+        let usage = crate::analysis::button_usage(&chords, |_| 1.0);
+        let svg = heatmap_svg(&usage);
+        assert!(svg.starts_with("<svg "));
+        // Thumbs and four finger rows, row 0 has no chords
+        assert!(svg.matches("<rect ").count() == 4 + 4 * 3);
+        assert!(svg.contains(r#">1L</text><text x="4" y="90" class="share">50%</text>"#));
+        assert!(svg.contains(r#"fill="rgb(220,50,32)""#));
+        assert!(svg.contains(r#"fill="rgb(255,255,255)""#));
+
+        let terminal = heatmap_terminal(&usage);
+        assert!(terminal.lines().count() == 2 + 1 + 1 + 2 + 4);
+        assert!(terminal.contains("\x1b[48;2;220;50;32m\x1b[30m 50% \x1b[0m"));
+        assert!(terminal.contains("\x1b[48;2;238;153;144m\x1b[30m 25% \x1b[0m"));
+    }
+}
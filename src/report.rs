@@ -0,0 +1,223 @@
+//! What `validate` and `lint` find as JSON or SARIF, for CI to read. SARIF
+//! is what GitHub code scanning takes to annotate the rows of a layout in
+//! pull requests:
+//!
+//! ```text
+//! twiddler-cfg lint layout.csv --message-format sarif > lint.sarif
+//! ```
+
+use crate::{
+    csv::{output_column, Chord},
+    lint::{Finding, Rule, Severity},
+};
+
+/// How findings are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MessageFormat {
+    /// A line per finding
+    #[default]
+    Text,
+    /// A list of diagnostics with their file, line and column
+    Json,
+    /// SARIF 2.1.0, for GitHub code scanning
+    Sarif,
+}
+
+/// A finding at one place in a file. A finding about several chords is a
+/// diagnostic for each of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnostic {
+    pub file: String,
+    /// 1-based line in a CSV file, binary configs and JSON have none
+    pub line: Option<usize>,
+    /// 1-based character in the line where the problem starts, when it's
+    /// one part of the output
+    pub column: Option<usize>,
+    /// 1-based position of the chord in the config, rows that can't be read
+    /// aren't chords
+    pub chord: Option<usize>,
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Where the chords were read from
+#[derive(Debug, Clone, Copy)]
+pub struct Source<'a> {
+    pub file: &'a str,
+    /// The text of a CSV file and the line each chord starts on, see
+    /// [`csv::parse_lines`](crate::csv::parse_lines)
+    pub csv: Option<(&'a str, &'a [usize])>,
+}
+
+/// A diagnostic for each chord of each finding
+pub fn diagnostics(findings: &[Finding], chords: &[Chord], source: Source) -> Vec<Diagnostic> {
+    let text: Vec<&str> = source
+        .csv
+        .map(|(text, _)| text.lines().collect())
+        .unwrap_or_default();
+    let mut diagnostics = vec![];
+    for finding in findings {
+        for (n, &i) in finding.chords.iter().enumerate() {
+            let line = source
+                .csv
+                .and_then(|(_, lines)| lines.get(i).copied())
+                .filter(|line| *line > 0);
+            // The column is in the output, found in the row
+            let row = line.and_then(|line| text.get(line - 1));
+            let column = match (n, finding.column, row) {
+                (0, Some(column), Some(row)) => output_column(row, chords[i].output(), column),
+                _ => None,
+            };
+            diagnostics.push(Diagnostic {
+                file: source.file.to_string(),
+                line,
+                column,
+                chord: Some(i + 1),
+                rule: finding.rule,
+                severity: finding.severity,
+                message: finding.message.clone(),
+            });
+        }
+    }
+    diagnostics
+}
+
+/// `{"version": 1, "diagnostics": [...]}`, each diagnostic with its file,
+/// line, column, rule ID, severity and message
+pub fn json(diagnostics: &[Diagnostic]) -> String {
+    let diagnostics: Vec<serde_json::Value> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            serde_json::json!({
+                "file": diagnostic.file,
+                "line": diagnostic.line,
+                "column": diagnostic.column,
+                "chord": diagnostic.chord,
+                "rule": diagnostic.rule.id(),
+                "severity": diagnostic.severity.to_string(),
+                "message": diagnostic.message,
+            })
+        })
+        .collect();
+    let report = serde_json::json!({
+        "version": 1,
+        "diagnostics": diagnostics,
+    });
+    format!("{:#}\n", report)
+}
+
+/// A SARIF log of one run with every rule and a result per diagnostic
+pub fn sarif(diagnostics: &[Diagnostic]) -> String {
+    let rules: Vec<serde_json::Value> = Rule::ALL
+        .iter()
+        .map(|rule| {
+            serde_json::json!({
+                "id": rule.id(),
+                "shortDescription": { "text": rule.description() },
+                "defaultConfiguration": { "level": rule.severity().to_string() },
+            })
+        })
+        .collect();
+    let results: Vec<serde_json::Value> = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            // Without a line the result is about the whole file, the
+            // message says which chord
+            let message = match (diagnostic.line, diagnostic.chord) {
+                (None, Some(chord)) => format!("Chord {}: {}", chord, diagnostic.message),
+                _ => diagnostic.message.clone(),
+            };
+            let mut location = serde_json::json!({
+                "artifactLocation": { "uri": diagnostic.file },
+            });
+            if let Some(line) = diagnostic.line {
+                location["region"] = serde_json::json!({ "startLine": line });
+                if let Some(column) = diagnostic.column {
+                    location["region"]["startColumn"] = column.into();
+                }
+            }
+            serde_json::json!({
+                "ruleId": diagnostic.rule.id(),
+                "ruleIndex": Rule::ALL.iter().position(|rule| *rule == diagnostic.rule),
+                "level": diagnostic.severity.to_string(),
+                "message": { "text": message },
+                "locations": [{ "physicalLocation": location }],
+            })
+        })
+        .collect();
+    let log = serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "twiddler-cfg",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    });
+    format!("{:#}\n", log)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::{lint, LintOptions};
+
+    #[test]
+    fn test_diagnostics() {
+        let csv = "Thumbs,Fingers,Keyboard Output\n,1L,a\n,1M,ab<Nope>\n,2L,c\n,2L,d\n";
+        let (lines, chords): (Vec<usize>, Vec<Chord>) = crate::csv::parse_lines(csv.as_bytes())
+            .unwrap()
+            .into_iter()
+            .unzip();
+        let findings = lint(&chords, &LintOptions::default());
+        let source = Source {
+            file: "layout.csv",
+            csv: Some((csv, &lines)),
+        };
+        let diagnostics = diagnostics(&findings, &chords, source);
+        assert!(diagnostics.len() == 3);
+        assert!(diagnostics[0].line == Some(3) && diagnostics[0].column == Some(8));
+        assert!(diagnostics[0].rule == Rule::UnknownKey);
+        // A conflict is reported on both rows
+        assert!(diagnostics[1].line == Some(4) && diagnostics[2].line == Some(5));
+        assert!(diagnostics[2].column.is_none());
+
+        let json: serde_json::Value = serde_json::from_str(&json(&diagnostics)).unwrap();
+        let first = &json["diagnostics"][0];
+        assert!(first["file"] == "layout.csv" && first["line"] == 3 && first["column"] == 8);
+        assert!(first["rule"] == "unknown-key" && first["severity"] == "error");
+
+        let sarif: serde_json::Value = serde_json::from_str(&sarif(&diagnostics)).unwrap();
+        let run = &sarif["runs"][0];
+        assert!(run["tool"]["driver"]["rules"].as_array().unwrap().len() == Rule::ALL.len());
+        let result = &run["results"][1];
+        assert!(result["ruleId"] == "conflict" && result["level"] == "error");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert!(location["artifactLocation"]["uri"] == "layout.csv");
+        assert!(location["region"]["startLine"] == 4);
+        assert!(location["region"].get("startColumn").is_none());
+
+        // Binary configs have no lines, the message gives the chord
+        let source = Source {
+            file: "layout.cfg",
+            csv: None,
+        };
+        let diagnostics = super::diagnostics(&findings, &chords, source);
+        assert!(diagnostics[0].line.is_none() && diagnostics[0].chord == Some(2));
+        let log: serde_json::Value = serde_json::from_str(&super::sarif(&diagnostics)).unwrap();
+        let result = &log["runs"][0]["results"][0];
+        assert!(result["message"]["text"] == "Chord 2: \"Nope\" isn't a known key");
+        assert!(result["locations"][0]["physicalLocation"]
+            .get("region")
+            .is_none());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::twiddler5;
+
+/// Device settings outside of the chord table. CSV and Dido configs don't
+/// have any so they get the Tuner defaults
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub key_repeat: bool,
+    pub key_repeat_delay: u8, // 10ms steps
+    pub mouse_sensitivity: u8,
+    pub idle_time: u16, // seconds
+    pub haptic: bool,
+    pub direct: bool,
+    pub bluetooth: bool, // v7 only
+    pub sticky_num: bool,
+    pub sticky_alt: bool,
+    pub sticky_ctrl: bool,
+    pub sticky_shift: bool,
+    pub left_mouse_pos: bool, // F0L instead of F0R
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            key_repeat: true,
+            key_repeat_delay: 100,
+            mouse_sensitivity: 0x7f,
+            idle_time: 600,
+            haptic: true,
+            direct: false,
+            bluetooth: false,
+            sticky_num: false,
+            sticky_alt: false,
+            sticky_ctrl: false,
+            sticky_shift: false,
+            left_mouse_pos: false,
+        }
+    }
+}
+
+impl From<twiddler5::Settings> for Settings {
+    fn from(settings: twiddler5::Settings) -> Self {
+        // The v5 option bits don't line up with the later flags
+        Self {
+            key_repeat_delay: settings.key_repeat_delay,
+            idle_time: settings.sleep_timeout,
+            ..Default::default()
+        }
+    }
+}
+
+// `Config::settings` and `set_settings` for v6 and v7 configs, which keep
+// the settings in header fields of the same names. The flags are given as
+// setting, flag getter and flag builder, v6 has no Bluetooth flag.
+macro_rules! config_settings {
+    ($($setting:ident $flag:ident $with:ident,)*) => {
+        impl Config {
+            pub fn settings(&self) -> Settings {
+                let mut settings = Settings {
+                    key_repeat_delay: self.key_repeat_delay,
+                    mouse_sensitivity: self.mouse_sensitivity,
+                    idle_time: self.idle_time,
+                    ..Default::default()
+                };
+                $(settings.$setting = self.flags.$flag();)*
+                settings
+            }
+
+            pub fn set_settings(&mut self, settings: &Settings) {
+                self.key_repeat_delay = settings.key_repeat_delay;
+                self.mouse_sensitivity = settings.mouse_sensitivity;
+                self.idle_time = settings.idle_time;
+                self.flags = self.flags$(.$with(settings.$setting))*;
+            }
+        }
+    };
+}
+pub(crate) use config_settings;
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Settings {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            key_repeat: u.arbitrary()?,
+            key_repeat_delay: u.arbitrary()?,
+            mouse_sensitivity: u.arbitrary()?,
+            idle_time: u.arbitrary()?,
+            haptic: u.arbitrary()?,
+            direct: u.arbitrary()?,
+            bluetooth: u.arbitrary()?,
+            sticky_num: u.arbitrary()?,
+            sticky_alt: u.arbitrary()?,
+            sticky_ctrl: u.arbitrary()?,
+            sticky_shift: u.arbitrary()?,
+            left_mouse_pos: u.arbitrary()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{twiddler6, twiddler7};
+
+    #[test]
+    fn test_defaults() {
+        assert!(twiddler7::Config::new().settings() == Settings::default());
+        let mut file = std::fs::File::open("test/configs/v7/default.cfg").unwrap();
+        assert!(twiddler7::parse(&mut file).unwrap().settings() == Settings::default());
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut file = std::fs::File::open("test/configs/v7/sticky_ctrl.cfg").unwrap();
+        let settings = twiddler7::parse(&mut file).unwrap().settings();
+        assert!(settings.sticky_ctrl);
+        assert!(!settings.sticky_alt);
+
+        // Each file turns one setting from the defaults
+        for (name, changed) in [
+            (
+                "direct",
+                Settings {
+                    direct: true,
+                    ..Default::default()
+                },
+            ),
+            (
+                "haptic_off",
+                Settings {
+                    haptic: false,
+                    ..Default::default()
+                },
+            ),
+        ] {
+            let path = format!("test/configs/v7/{}.cfg", name);
+            let mut file = std::fs::File::open(path).unwrap();
+            assert!(
+                twiddler7::parse(&mut file).unwrap().settings() == changed,
+                "{}",
+                name
+            );
+        }
+
+        let mut config7 = twiddler7::Config::new();
+        config7.set_settings(&Settings {
+            bluetooth: true,
+            ..Default::default()
+        });
+        assert!(config7.flags.bluetooth() && config7.settings().bluetooth);
+
+        let mut file = std::fs::File::open("test/configs/v6/sticky_shift.cfg").unwrap();
+        let config6 = twiddler6::parse(&mut file).unwrap();
+        let mut config7 = twiddler7::Config::new();
+        config7.set_settings(&config6.settings());
+        assert!(config7.flags.sticky_shift());
+        assert!(config7.settings() == config6.settings());
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+
+use crate::{
+    analysis,
+    buttons::ButtonState,
+    chord_map::ChordMap,
+    csv::Chord,
+    layout::{self, Layout},
+    output::{self, ChordOutput, MacroStep, OutputToken},
+};
+
+/// The chords that type some text, from [`encode`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Encoding {
+    /// Indices of the chords to press, in order
+    pub chords: Vec<usize>,
+    /// Characters no chord types where they are, with their byte offset
+    /// into the text. They're left out of `chords`.
+    pub unreachable: Vec<(usize, char)>,
+}
+
+impl Encoding {
+    pub fn is_complete(&self) -> bool {
+        self.unreachable.is_empty()
+    }
+}
+
+// Cost of typing up to a point: characters skipped, then chords pressed,
+// then the effort of pressing them
+type Cost = (usize, usize, f64);
+
+#[derive(Clone, Copy)]
+enum Step {
+    Chord { index: usize, len: usize },
+    Skip(char),
+}
+
+/// The fewest chords that type `text`, the easiest ones by
+/// [`analysis::effort`] when there's a choice. Only chords typing plain
+/// text are used, and characters none of them can type are skipped and
+/// reported.
+pub fn encode(text: &str, chords: &[Chord]) -> Encoding {
+    // The easiest chord for each output, grouped by its first character
+    let mut easiest: HashMap<String, (usize, f64)> = HashMap::new();
+    for (i, chord) in chords.iter().enumerate() {
+        let Some(typed) = typed_text(chord).filter(|typed| !typed.is_empty()) else {
+            continue;
+        };
+        let effort = analysis::effort(&chord.buttons());
+        match easiest.get(&typed) {
+            Some(&(_, best)) if best <= effort => (),
+            _ => {
+                easiest.insert(typed, (i, effort));
+            }
+        }
+    }
+    let mut by_first: HashMap<char, Vec<(String, usize, f64)>> = HashMap::new();
+    for (typed, (i, effort)) in easiest {
+        let first = typed.chars().next().unwrap();
+        by_first.entry(first).or_default().push((typed, i, effort));
+    }
+
+    // best[i] is the cheapest way to type text[..i] and its last step
+    let mut best: Vec<Option<(Cost, Step)>> = vec![None; text.len() + 1];
+    for (start, c) in text.char_indices() {
+        let (skipped, pressed, effort) = match start {
+            0 => (0, 0, 0.0),
+            _ => best[start].expect("every character can be skipped").0,
+        };
+        let rest = &text[start..];
+        for (typed, index, chord_effort) in by_first.get(&c).into_iter().flatten() {
+            if rest.starts_with(typed.as_str()) {
+                let step = Step::Chord {
+                    index: *index,
+                    len: typed.len(),
+                };
+                let cost = (skipped, pressed + 1, effort + chord_effort);
+                reach(&mut best, start + typed.len(), cost, step);
+            }
+        }
+        let cost = (skipped + 1, pressed, effort);
+        reach(&mut best, start + c.len_utf8(), cost, Step::Skip(c));
+    }
+
+    let mut encoding = Encoding::default();
+    let mut end = text.len();
+    while end > 0 {
+        let (_, step) = best[end].unwrap();
+        match step {
+            Step::Chord { index, len } => {
+                encoding.chords.push(index);
+                end -= len;
+            }
+            Step::Skip(c) => {
+                end -= c.len_utf8();
+                encoding.unreachable.push((end, c));
+            }
+        }
+    }
+    encoding.chords.reverse();
+    encoding.unreachable.reverse();
+    encoding
+}
+
+fn reach(best: &mut [Option<(Cost, Step)>], end: usize, cost: Cost, step: Step) {
+    if best[end].is_none_or(|(old, _)| cost < old) {
+        best[end] = Some((cost, step));
+    }
+}
+
+// The text a chord types when it's nothing but text, escapes included
+fn typed_text(chord: &Chord) -> Option<String> {
+    match output::parse(chord.output()).ok()?.as_slice() {
+        [OutputToken::Text(text)] => Some(text.clone()),
+        _ => None,
+    }
+}
+
+const SHIFT: u8 = 0x22;
+const ALT_GR: u8 = 0x40;
+const RETURN: u8 = 0x28;
+const BACKSPACE: u8 = 0x2A;
+const TAB: u8 = 0x2B;
+const SPACE: u8 = 0x2C;
+const CAPS_LOCK: u8 = 0x39;
+
+/// Replays chord presses on a US host, see [`Typist`]
+pub fn decode(presses: &[ButtonState], chords: &[Chord]) -> String {
+    decode_in(presses, chords, Layout::Us)
+}
+
+/// Like [`decode`] for a host using `layout`
+pub fn decode_in(presses: &[ButtonState], chords: &[Chord], layout: Layout) -> String {
+    let mut typist = Typist::new(chords, layout);
+    for buttons in presses {
+        typist.press(buttons);
+    }
+    typist.into_text()
+}
+
+/// The text a host ends up with as chords are pressed one at a time.
+/// Shift and AltGr change the character typed, Caps Lock and dead keys
+/// carry over to the following chords, sticky modifiers to the next one,
+/// layer switches change which chords the buttons press, and Backspace
+/// deletes. Keys held
+/// with Ctrl, Alt or Gui are shortcuts and type nothing, as do mouse
+/// chords and buttons no chord is on.
+#[derive(Debug, Clone)]
+pub struct Typist {
+    /// The chords of each layer
+    chords: Vec<ChordMap>,
+    layer: u8,
+    layout: Layout,
+    text: String,
+    caps_lock: bool,
+    /// Accent of a dead key waiting for the next character
+    dead_key: Option<char>,
+    /// Modifiers from sticky chords waiting for the next chord
+    sticky: u8,
+}
+
+impl Typist {
+    /// Later chords on buttons already mapped are ignored, like on the
+    /// device
+    pub fn new(chords: &[Chord], layout: Layout) -> Self {
+        let layers = chords.iter().map(Chord::layer).max().unwrap_or_default();
+        Typist {
+            chords: (0..=layers)
+                .map(|layer| {
+                    let on_layer = chords.iter().filter(|chord| chord.layer() == layer);
+                    on_layer.cloned().collect()
+                })
+                .collect(),
+            layer: 0,
+            layout,
+            text: String::new(),
+            caps_lock: false,
+            dead_key: None,
+            sticky: 0,
+        }
+    }
+
+    /// Presses a chord, returning whether one is mapped to the buttons
+    pub fn press(&mut self, buttons: &ButtonState) -> bool {
+        let Some(chord) = self.chords[usize::from(self.layer)].get(buttons) else {
+            return false;
+        };
+        // Outputs that can't be typed on the layout do nothing
+        let steps = match chord.get_output_in(self.layout) {
+            Ok(ChordOutput::Macro(steps)) => steps,
+            Ok(ChordOutput::Sticky(modifier)) => vec![MacroStep::Key {
+                modifier,
+                key_code: 0,
+            }],
+            // Layers without chords have nothing to press
+            Ok(ChordOutput::SwitchLayer(layer)) => {
+                if usize::from(layer) < self.chords.len() {
+                    self.layer = layer;
+                }
+                return true;
+            }
+            _ => vec![],
+        };
+        // A chord of just modifiers holds them for the next one
+        if let [MacroStep::Key {
+            modifier,
+            key_code: 0,
+        }] = steps[..]
+        {
+            self.sticky |= modifier;
+            return true;
+        }
+        let sticky = std::mem::take(&mut self.sticky);
+        for step in steps {
+            if let MacroStep::Key { modifier, key_code } = step {
+                self.key(modifier | sticky, key_code);
+            }
+        }
+        true
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn into_text(self) -> String {
+        self.text
+    }
+
+    pub fn caps_lock(&self) -> bool {
+        self.caps_lock
+    }
+
+    pub fn layer(&self) -> u8 {
+        self.layer
+    }
+
+    fn key(&mut self, modifier: u8, key_code: u8) {
+        if modifier & !(SHIFT | ALT_GR) != 0 {
+            return;
+        }
+        // Layouts only know the left shift
+        let modifier = match modifier & SHIFT {
+            0 => modifier,
+            _ => (modifier & ALT_GR) | 0x02,
+        };
+
+        match (modifier, key_code) {
+            (_, CAPS_LOCK) => self.caps_lock = !self.caps_lock,
+            (_, BACKSPACE) => {
+                if self.dead_key.take().is_none() {
+                    self.text.pop();
+                }
+            }
+            (0 | 0x02, RETURN) => self.type_char('\n'),
+            (0 | 0x02, TAB) => self.type_char('\t'),
+            (0 | 0x02, SPACE) => self.type_char(' '),
+            _ => {
+                if let Some(accent) = self.layout.dead_key(modifier, key_code) {
+                    match self.dead_key.take() {
+                        // The same dead key twice types the accent
+                        Some(pending) => {
+                            self.text.push(pending);
+                            if pending != accent {
+                                self.dead_key = Some(accent);
+                            }
+                        }
+                        None => self.dead_key = Some(accent),
+                    }
+                } else if let Some(c) = self.layout.hid_to_char(modifier, key_code) {
+                    self.type_char(c);
+                }
+            }
+        }
+    }
+
+    fn type_char(&mut self, c: char) {
+        let c = match self.caps_lock && c.is_alphabetic() {
+            true if c.is_lowercase() => c.to_uppercase().next().unwrap_or(c),
+            true => c.to_lowercase().next().unwrap_or(c),
+            false => c,
+        };
+        match self.dead_key.take() {
+            Some(accent) => match layout::compose(accent, c) {
+                Some(composed) => self.text.push(composed),
+                None => {
+                    self.text.push(accent);
+                    self.text.push(c);
+                }
+            },
+            None => self.text.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let chords = vec![
+            Chord::new("", "1L", "t"),
+            Chord::new("", "2L", "h"),
+            Chord::new("", "3L", "e"),
+            Chord::new("", "4L", " "),
+            Chord::new("1", "1L 2M", "the "),
+            Chord::new("", "1M", "th"),
+            Chord::new("", "2L 2M", "he"),
+            Chord::new("", "3M", "\\<"),
+            Chord::new("", "1R", "<L-Shift>t</L-Shift>"),
+        ];
+        let encoding = encode("the theta <3", &chords);
+        // "th" "e" is easier than "t" "he", and nothing types the a or 3
+        assert!(encoding.chords == [4, 5, 2, 0, 3, 7]);
+        assert!(encoding.unreachable == [(8, 'a'), (11, '3')]);
+        assert!(!encoding.is_complete());
+
+        let encoding = encode("then", &chords);
+        assert!(encoding.chords == [5, 2]);
+        assert!(encoding.unreachable == [(3, 'n')]);
+
+        assert!(encode("", &chords) == Encoding::default());
+        assert!(encode("é", &chords).unreachable == [(0, 'é')]);
+    }
+
+    #[test]
+    fn test_decode() {
+        let chords = vec![
+            Chord::new("", "1L", "the "),
+            Chord::new("", "1M", "<R-Shift>t</R-Shift>"),
+            Chord::new("", "1R", "<CapsLock>"),
+            Chord::new("", "2L", "<Backspace>"),
+            Chord::new("", "2M", "<L-Ctrl>c</L-Ctrl>ok<Return>"),
+            Chord::new("", "2R", "é"),
+            Chord::new("", "3M", "<Sticky:L-Shift>"),
+            Chord::new("", "3R", "<L-Shift>"),
+            Chord::new("", "1L", "ignored"),
+        ];
+        let press = |notation: &str| notation.parse::<ButtonState>().unwrap();
+        let presses = ["1L", "1M", "1R", "1L", "1R", "2L", "2L", "2M", "3L", "2R"].map(press);
+        assert!(decode(&presses, &chords) == "the TTHok\n");
+        // Only the chord after a sticky one is shifted
+        let presses = ["3M", "1L", "1L", "3M", "3M", "2M"].map(press);
+        assert!(decode(&presses, &chords) == "THE the OK\n");
+        let presses = ["3R", "1L", "1L"].map(press);
+        assert!(decode(&presses, &chords) == "THE the ");
+
+        let mut typist = Typist::new(&chords, Layout::Us);
+        assert!(typist.press(&press("1R")) && typist.caps_lock());
+        assert!(typist.layer() == 0);
+        assert!(!typist.press(&press("3L")));
+        typist.press(&press("1M"));
+        assert!(typist.text() == "t");
+    }
+
+    #[test]
+    fn test_decode_layers() {
+        let mut chords = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("", "0R", "<Layer:1>"),
+            Chord::new("", "1L", "1"),
+            Chord::new("", "0R", "<Layer:0>"),
+            Chord::new("", "0M", "<Layer:2>"),
+        ];
+        chords[2].set_layer(1);
+        chords[3].set_layer(1);
+        let presses: Vec<ButtonState> = ["1L", "0R", "1L", "0M", "1L", "0R", "1L"]
+            .iter()
+            .map(|notation| notation.parse().unwrap())
+            .collect();
+        // Layer 2 has no chords so it isn't switched to
+        assert!(decode(&presses, &chords) == "a11a");
+    }
+
+    #[test]
+    fn test_decode_dead_keys() {
+        // ´ is a dead key on German hosts
+        let chords = vec![
+            Chord::new("", "1L", "<Equal>"),
+            Chord::new("", "1M", "e"),
+            Chord::new("", "1R", "Café"),
+        ];
+        let presses: Vec<ButtonState> = ["1L", "1M", "1R", "1L", "1L"]
+            .iter()
+            .map(|notation| notation.parse().unwrap())
+            .collect();
+        assert!(decode_in(&presses, &chords, Layout::De) == "éCafé´");
+        assert!(decode(&presses[..2], &chords) == "=e");
+    }
+}
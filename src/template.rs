@@ -0,0 +1,231 @@
+//! Variables in outputs, so a shared layout can have a chord typing
+//! `{{email}}` that each user fills in from a file of their own:
+//!
+//! ```text
+//! # Mine, not in the layout's repository
+//! email = jane@example.com
+//! signature = Cheers,<Return>Jane
+//! ```
+//!
+//! Values use the output notation of the CSV files, tags and all.
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use crate::{csv, csv::Chord, json, Error, Format, Result};
+
+/// Values by variable name
+pub type Variables = BTreeMap<String, String>;
+
+/// Reads `name = value` lines, skipping empty ones and `#` comments. Space
+/// around the name and value is trimmed.
+pub fn parse<R: Read>(reader: &mut R) -> Result<Variables> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let mut variables = Variables::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, value) = line
+            .split_once('=')
+            .filter(|(name, _)| is_name(name.trim()))
+            .ok_or_else(|| {
+                Error::Invalid(format!(
+                    "Line {} of the variables isn't name = value: {:?}",
+                    i + 1,
+                    line
+                ))
+            })?;
+        variables.insert(name.trim().to_string(), value.trim().to_string());
+    }
+    Ok(variables)
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// The names of the `{{name}}` variables in an output, in order
+pub fn variables(output: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = output;
+    while let Some((_, name, after)) = next_variable(rest) {
+        names.push(name);
+        rest = after;
+    }
+    names
+}
+
+// The text before the next variable and its name, and what follows it
+fn next_variable(text: &str) -> Option<(&str, &str, &str)> {
+    let mut from = 0;
+    loop {
+        let start = from + text[from..].find("{{")?;
+        let end = start + 2 + text[start + 2..].find("}}")?;
+        let name = text[start + 2..end].trim();
+        if is_name(name) {
+            return Some((&text[..start], name, &text[end + 2..]));
+        }
+        // Not a variable, like `{{` typed on its own
+        from = start + 1;
+    }
+}
+
+/// `output` with each `{{name}}` replaced by its value. Braces around
+/// anything that isn't a name are left as they are.
+pub fn expand(output: &str, variables: &Variables) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = output;
+    while let Some((before, name, after)) = next_variable(rest) {
+        let value = variables.get(name).ok_or_else(|| {
+            Error::Invalid(format!(
+                "No value for the variable {:?} in {:?}",
+                name, output
+            ))
+        })?;
+        expanded.push_str(before);
+        expanded.push_str(value);
+        rest = after;
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expand the variables in the output of every chord, returning how many
+/// had any. Errors give the line each chord starts on from `lines`, or the
+/// chord's number without them.
+pub fn expand_chords(
+    chords: &mut [Chord],
+    lines: Option<&[usize]>,
+    variables: &Variables,
+) -> Result<usize> {
+    let mut expanded = 0;
+    for (i, chord) in chords.iter_mut().enumerate() {
+        let output = expand(chord.output(), variables).map_err(|e| match e {
+            Error::Invalid(message) => match lines.and_then(|lines| lines.get(i)) {
+                Some(line) => Error::Invalid(format!("{} at line {}", message, line)),
+                None => Error::Invalid(format!("{} in chord {}", message, i + 1)),
+            },
+            e => e,
+        })?;
+        if output != chord.output() {
+            chord.set_output(output);
+            expanded += 1;
+        }
+    }
+    Ok(expanded)
+}
+
+/// A CSV or JSON config with its variables filled in, as it would have been
+/// written by hand, and how many chords had any. Outputs are turned into key
+/// codes as they're read, so this comes before reading it. Other formats
+/// have no variables and give `None`.
+pub fn expand_config<R: Read>(
+    reader: &mut R,
+    from: Format,
+    variables: &Variables,
+) -> Result<Option<(Vec<u8>, usize)>> {
+    let mut expanded = vec![];
+    let count = match from {
+        Format::Csv => {
+            let (lines, mut chords): (Vec<_>, Vec<_>) =
+                csv::parse_lines(reader)?.into_iter().unzip();
+            let count = expand_chords(&mut chords, Some(&lines), variables)?;
+            csv::export(&mut expanded, &chords)?;
+            count
+        }
+        Format::Json => {
+            let mut config = json::parse(reader)?;
+            let count = expand_chords(&mut config.chords, None, variables)?;
+            json::export(&mut expanded, &config)?;
+            count
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((expanded, count)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "# Mine\n\nemail = jane@example.com\nsignature=Cheers,<Return>Jane\n";
+        let variables = parse(&mut text.as_bytes()).unwrap();
+        assert!(variables.len() == 2);
+        assert!(variables["email"] == "jane@example.com");
+        assert!(variables["signature"] == "Cheers,<Return>Jane");
+        assert!(parse(&mut "email jane@example.com".as_bytes()).is_err());
+        assert!(parse(&mut "my email = jane".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut variables = Variables::new();
+        variables.insert("name".to_string(), "Jane".to_string());
+        variables.insert("email".to_string(), "jane@example.com".to_string());
+
+        assert!(expand("{{name}} <{{ email }}>", &variables).unwrap() == "Jane <jane@example.com>");
+        assert!(expand("{{name}}{{name}}", &variables).unwrap() == "JaneJane");
+        // Only names are variables
+        assert!(expand("{{ }} {{a b}} {{{name}}}", &variables).unwrap() == "{{ }} {{a b}} {Jane}");
+        assert!(expand("{{", &variables).unwrap() == "{{");
+        assert!(super::variables("{{b}} x {{a}} {{ b }}") == ["b", "a", "b"]);
+        assert!(matches!(
+            expand("{{phone}}", &variables),
+            Err(Error::Invalid(message)) if message.contains("\"phone\"")
+        ));
+
+        let mut chords = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("1", "1L", "{{email}}"),
+            Chord::new("1", "1M", "{{phone}}"),
+        ];
+        assert!(matches!(
+            expand_chords(&mut chords, None, &variables),
+            Err(Error::Invalid(message)) if message.ends_with("in chord 3")
+        ));
+        assert!(matches!(
+            expand_chords(&mut chords, Some(&[2, 5, 9]), &variables),
+            Err(Error::Invalid(message)) if message.ends_with("at line 9")
+        ));
+        let mut chords = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("1", "1L", "{{email}}"),
+        ];
+        assert!(expand_chords(&mut chords, None, &variables).unwrap() == 1);
+        assert!(chords[1].output() == "jane@example.com");
+    }
+
+    #[test]
+    fn test_expand_config() {
+        let mut variables = Variables::new();
+        variables.insert("email".to_string(), "jane@example.com".to_string());
+
+        let text = "Thumbs,Fingers,Keyboard Output\n# Mail\n,1L,{{email}}\n\n,1M,{{phone}}\n";
+        assert!(matches!(
+            expand_config(&mut text.as_bytes(), Format::Csv, &variables),
+            Err(Error::Invalid(message)) if message.ends_with("\"phone\" in \"{{phone}}\" at line 5")
+        ));
+        let text = "Thumbs,Fingers,Keyboard Output\n,1L,{{email}}\n,1M,b\n";
+        let (expanded, count) = expand_config(&mut text.as_bytes(), Format::Csv, &variables)
+            .unwrap()
+            .unwrap();
+        assert!(count == 1);
+        assert!(String::from_utf8(expanded)
+            .unwrap()
+            .contains(",1L,jane@example.com\n"));
+        // Undefined without a file of variables too
+        assert!(expand_config(&mut text.as_bytes(), Format::Csv, &Variables::new()).is_err());
+        assert!(expand_config(&mut &b""[..], Format::Twiddler7, &variables)
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{
+    analysis::effort,
+    csv::Chord,
+    hid,
+    output::{self, ChordOutput},
+};
+
+/// Swap the L and R finger columns of every chord so a layout learned in one
+/// hand works in the other. The thumbs and fingers columns are rewritten in
+/// T4 notation.
+pub fn mirror(chords: &mut [Chord]) {
+    for chord in chords {
+        chord.set_buttons(&chord.buttons().mirrored());
+    }
+}
+
+/// Keep only the easiest chord for each output by [`effort`], the first one
+/// when there's a tie. Chords stay in their order.
+pub fn dedupe(chords: &[Chord]) -> Vec<Chord> {
+    let mut easiest: Vec<(&str, usize)> = vec![];
+    for (i, chord) in chords.iter().enumerate() {
+        match easiest
+            .iter_mut()
+            .find(|(output, _)| *output == chord.output())
+        {
+            Some((_, best)) => {
+                if effort(&chord.buttons()) < effort(&chords[*best].buttons()) {
+                    *best = i;
+                }
+            }
+            None => easiest.push((chord.output(), i)),
+        }
+    }
+
+    let mut keep: Vec<usize> = easiest.into_iter().map(|(_, i)| i).collect();
+    keep.sort();
+    keep.into_iter().map(|i| chords[i].clone()).collect()
+}
+
+/// Rewrite the output of every chord with `f`, returning how many changed
+pub fn map_outputs(chords: &mut [Chord], f: impl Fn(&str) -> String) -> usize {
+    let mut changed = 0;
+    for chord in chords {
+        let output = f(chord.output());
+        if output != chord.output() {
+            chord.set_output(output);
+            changed += 1;
+        }
+    }
+    changed
+}
+
+/// `output` with Ctrl held where it held Gui and the other way around, on
+/// the same side, for taking shortcuts between Windows or Linux and macOS.
+/// Sticky modifiers are swapped too.
+pub fn swap_ctrl_gui(output: &str) -> String {
+    output::map_tags(output, |tag| {
+        let (prefix, name) = match tag.strip_prefix('/') {
+            Some(name) => ("/", name),
+            None => match tag.split_once(':') {
+                Some((prefix, name)) if prefix.eq_ignore_ascii_case("Sticky") => ("Sticky:", name),
+                _ => ("", tag),
+            },
+        };
+        let bit = hid::modifier_tag(name)?.trailing_zeros() as usize;
+        // Ctrl is bit 0 and Gui bit 3 of each side's nibble
+        let swapped = match bit % 4 {
+            0 => bit + 3,
+            3 => bit - 3,
+            _ => return None,
+        };
+        Some(format!("{}{}", prefix, hid::MODIFIER_TAGS[swapped]))
+    })
+}
+
+/// `output` with the letters it types as text in upper case. Tags, escapes
+/// and text typed with modifiers held, like the c of `<L-Ctrl>c</L-Ctrl>`,
+/// are left alone.
+pub fn uppercase_letters(output: &str) -> String {
+    output::map_text_held(output, |text, held| match held {
+        0 => text.to_uppercase(),
+        _ => text.to_string(),
+    })
+}
+
+/// The chords that press a single key, with or without modifiers, or do
+/// something besides typing. Chords typing more than one key, text like
+/// "the " included, are left out, and so are the ones with outputs that
+/// don't parse.
+pub fn strip_macros(chords: &[Chord]) -> Vec<Chord> {
+    chords
+        .iter()
+        .filter(|chord| match chord.get_output() {
+            Ok(ChordOutput::Macro(steps)) => steps.len() <= 1,
+            Ok(_) => true,
+            Err(_) => false,
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror() {
+        let mut chords = vec![
+            Chord::new("1", "1L 2R", "the "),
+            Chord::new("N", "LM00", "x"),
+        ];
+        mirror(&mut chords);
+        assert!(chords[0].thumbs() == "1" && chords[0].fingers() == "1R 2L");
+        assert!(chords[0].output() == "the ");
+        // Legacy notation comes out as T4
+        assert!(chords[1].thumbs() == "1" && chords[1].fingers() == "1R 2M");
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let chords = vec![
+            Chord::new("1", "1L 2L", "the "),
+            Chord::new("", "1L", "a"),
+            Chord::new("", "3L 3M", "the "),
+            Chord::new("", "1M 2M", "the "),
+        ];
+        assert!(dedupe(&chords) == vec![chords[1].clone(), chords[3].clone()]);
+    }
+
+    #[test]
+    fn test_map_outputs() {
+        let mut chords = vec![
+            Chord::new("", "1L", "<L-Ctrl>c</L-Ctrl>"),
+            Chord::new("", "1M", "<R-Gui><Tab></R-Gui><Sticky:L-Ctrl>"),
+            Chord::new("", "1R", "<L-Shift>a</L-Shift>\\<L-Ctrl>"),
+        ];
+        assert!(map_outputs(&mut chords, swap_ctrl_gui) == 2);
+        assert!(chords[0].output() == "<L-Gui>c</L-Gui>");
+        assert!(chords[1].output() == "<R-Ctrl><Tab></R-Ctrl><Sticky:L-Gui>");
+        assert!(chords[2].output() == "<L-Shift>a</L-Shift>\\<L-Ctrl>");
+        assert!(swap_ctrl_gui("<cmd>q</cmd>") == "<L-Ctrl>q</L-Ctrl>");
+
+        assert!(uppercase_letters("the <Tab>\\<é") == "THE <Tab>\\<É");
+        assert!(uppercase_letters("a<L-Ctrl>c</L-Ctrl>b") == "A<L-Ctrl>c</L-Ctrl>B");
+        assert!(
+            uppercase_letters("<LCtrl><L-Alt>x</L-Alt>y</lctrl>z")
+                == "<LCtrl><L-Alt>x</L-Alt>y</lctrl>Z"
+        );
+        // Shortcuts and sticky modifiers stay as they are
+        assert!(map_outputs(&mut chords, uppercase_letters) == 1);
+        assert!(chords[0].output() == "<L-Gui>c</L-Gui>");
+        assert!(chords[2].output() == "<L-Shift>a</L-Shift>\\<L-CTRL>");
+    }
+
+    #[test]
+    fn test_strip_macros() {
+        let chords = vec![
+            Chord::new("", "1L", "a"),
+            Chord::new("", "1M", "the "),
+            Chord::new("", "1R", "<L-Ctrl>c</L-Ctrl>"),
+            Chord::new("", "2L", "<L-Ctrl>c</L-Ctrl>v"),
+            Chord::new("", "2M", "<Sticky:L-Shift>"),
+            Chord::new("", "2R", "<Nope>"),
+        ];
+        let kept = strip_macros(&chords);
+        assert!(kept == [chords[0].clone(), chords[2].clone(), chords[4].clone()]);
+    }
+}
@@ -0,0 +1,758 @@
+use std::collections::BTreeMap;
+
+use crate::{buttons::ButtonState, csv::Chord, validate, Result};
+
+/// A key press the editor understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Esc,
+    Backspace,
+    Delete,
+    /// Ctrl+C
+    Interrupt,
+    Char(char),
+}
+
+/// What the caller of [`Editor::handle`] should do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Save,
+    Quit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Buttons,
+    Output,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Browse,
+    /// Editing one field of the selected chord, `cursor` counts characters
+    Edit {
+        field: Field,
+        text: String,
+        cursor: usize,
+    },
+}
+
+const HELP: &str = "↑↓ move  Enter output  b buttons  a add  d delete  s save  q quit";
+const GRID_WIDTH: usize = 20;
+
+/// The state of the layout editor, kept apart from the terminal so it can be
+/// driven by key presses in tests
+#[derive(Debug, Clone)]
+pub struct Editor {
+    title: String,
+    chords: Vec<Chord>,
+    selected: usize,
+    scroll: usize,
+    mode: Mode,
+    // Why each chord can't be used as it is, by index
+    problems: BTreeMap<usize, String>,
+    dirty: bool,
+    quitting: bool,
+    message: String,
+}
+
+impl Editor {
+    pub fn new(title: impl Into<String>, chords: Vec<Chord>) -> Self {
+        let mut editor = Editor {
+            title: title.into(),
+            chords,
+            selected: 0,
+            scroll: 0,
+            mode: Mode::Browse,
+            problems: BTreeMap::new(),
+            dirty: false,
+            quitting: false,
+            message: String::new(),
+        };
+        editor.check();
+        editor
+    }
+
+    pub fn chords(&self) -> &[Chord] {
+        &self.chords
+    }
+
+    /// Whether there are changes since the last save
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn saved(&mut self) {
+        self.dirty = false;
+        self.message = "Saved".to_string();
+    }
+
+    pub fn set_message(&mut self, message: impl Into<String>) {
+        self.message = message.into();
+    }
+
+    /// The problem with a chord, if any, like conflicting with another chord
+    /// or an output that can't be typed
+    pub fn problem(&self, i: usize) -> Option<&str> {
+        self.problems.get(&i).map(String::as_str)
+    }
+
+    pub fn handle(&mut self, key: Key) -> Action {
+        let quitting = std::mem::take(&mut self.quitting);
+        match std::mem::replace(&mut self.mode, Mode::Browse) {
+            Mode::Browse => self.browse(key, quitting),
+            Mode::Edit {
+                field,
+                text,
+                cursor,
+            } => {
+                self.edit(field, text, cursor, key);
+                Action::Continue
+            }
+        }
+    }
+
+    fn browse(&mut self, key: Key, quitting: bool) -> Action {
+        self.message.clear();
+        let last = self.chords.len().saturating_sub(1);
+        match key {
+            Key::Up | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.selected = (self.selected + 1).min(last),
+            Key::PageUp => self.selected = self.selected.saturating_sub(10),
+            Key::PageDown => self.selected = (self.selected + 10).min(last),
+            Key::Home | Key::Char('g') => self.selected = 0,
+            Key::End | Key::Char('G') => self.selected = last,
+            Key::Enter | Key::Char('e') if !self.chords.is_empty() => {
+                let text = self.chords[self.selected].output().to_string();
+                self.start_edit(Field::Output, text);
+            }
+            Key::Char('b') if !self.chords.is_empty() => {
+                let chord = &self.chords[self.selected];
+                let text = match ButtonState::try_from(chord) {
+                    Ok(buttons) => buttons.to_string(),
+                    Err(_) => format!("{} {}", chord.thumbs(), chord.fingers())
+                        .trim()
+                        .to_string(),
+                };
+                self.start_edit(Field::Buttons, text);
+            }
+            Key::Char('a') => {
+                let at = (self.selected + 1).min(self.chords.len());
+                self.chords.insert(at, Chord::new("", "", ""));
+                self.selected = at;
+                self.changed();
+                self.start_edit(Field::Buttons, String::new());
+            }
+            Key::Char('d') | Key::Delete if !self.chords.is_empty() => {
+                self.chords.remove(self.selected);
+                self.selected = self.selected.min(self.chords.len().saturating_sub(1));
+                self.changed();
+            }
+            Key::Char('s') => return Action::Save,
+            Key::Char('q') | Key::Esc | Key::Interrupt => {
+                if !self.dirty || quitting {
+                    return Action::Quit;
+                }
+                self.quitting = true;
+                self.message = "Unsaved changes, press q again to quit anyway".to_string();
+            }
+            _ => (),
+        }
+        Action::Continue
+    }
+
+    fn start_edit(&mut self, field: Field, text: String) {
+        let cursor = text.chars().count();
+        self.mode = Mode::Edit {
+            field,
+            text,
+            cursor,
+        };
+    }
+
+    fn edit(&mut self, field: Field, mut text: String, mut cursor: usize, key: Key) {
+        self.message.clear();
+        let byte = |text: &str, cursor: usize| {
+            text.char_indices()
+                .nth(cursor)
+                .map_or(text.len(), |(i, _)| i)
+        };
+        match key {
+            Key::Esc | Key::Interrupt => return,
+            Key::Enter => match self.commit(field, &text) {
+                Ok(()) => return,
+                Err(e) => self.message = e.to_string(),
+            },
+            Key::Left => cursor = cursor.saturating_sub(1),
+            Key::Right => cursor = (cursor + 1).min(text.chars().count()),
+            Key::Home => cursor = 0,
+            Key::End => cursor = text.chars().count(),
+            Key::Backspace if cursor > 0 => {
+                cursor -= 1;
+                text.remove(byte(&text, cursor));
+            }
+            Key::Delete if cursor < text.chars().count() => {
+                text.remove(byte(&text, cursor));
+            }
+            Key::Char(c) => {
+                text.insert(byte(&text, cursor), c);
+                cursor += 1;
+            }
+            _ => (),
+        }
+        self.mode = Mode::Edit {
+            field,
+            text,
+            cursor,
+        };
+    }
+
+    fn commit(&mut self, field: Field, text: &str) -> Result<()> {
+        let chord = &mut self.chords[self.selected];
+        match field {
+            Field::Buttons => chord.set_buttons(&text.parse()?),
+            Field::Output => chord.set_output(text),
+        }
+        self.changed();
+        Ok(())
+    }
+
+    fn changed(&mut self) {
+        self.dirty = true;
+        self.check();
+    }
+
+    fn check(&mut self) {
+        self.problems.clear();
+        for conflict in validate::find_conflicts(&self.chords) {
+            let chords = match &conflict {
+                validate::Conflict::SameButtons { chords, .. } => chords,
+                validate::Conflict::Duplicate { chords } => chords,
+            };
+            for &i in chords {
+                let others: Vec<String> = chords
+                    .iter()
+                    .filter(|&&j| j != i)
+                    .map(|j| (j + 1).to_string())
+                    .collect();
+                let reason = match conflict {
+                    validate::Conflict::SameButtons { .. } => "On the same buttons as",
+                    validate::Conflict::Duplicate { .. } => "The same as",
+                };
+                self.problems
+                    .insert(i, format!("{} {}", reason, others.join(", ")));
+            }
+        }
+        for (i, chord) in self.chords.iter().enumerate() {
+            let problem = match ButtonState::try_from(chord) {
+                Ok(buttons) if buttons.is_empty() => Some("No buttons".to_string()),
+                Ok(_) => chord.get_output().err().map(|e| e.to_string()),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(problem) = problem {
+                self.problems.insert(i, problem);
+            }
+        }
+    }
+
+    /// The whole screen as lines of text with ANSI colours, the chord list
+    /// next to the buttons of the selected chord
+    pub fn render(&mut self, width: usize, height: usize) -> Vec<String> {
+        let width = width.max(GRID_WIDTH + 20);
+        let height = height.max(8);
+        let rows = height - 3;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        let mut lines = vec![];
+        let modified = if self.dirty { " [modified]" } else { "" };
+        let title = format!(
+            " {}{}  {} chords, {} with problems",
+            self.title,
+            modified,
+            self.chords.len(),
+            self.problems.len()
+        );
+        lines.push(format!("\x1b[7m{}\x1b[0m", pad(&title, width)));
+
+        let list_width = width - GRID_WIDTH - 1;
+        let grid = self
+            .chords
+            .get(self.selected)
+            .map(|chord| grid(&chord.buttons()))
+            .unwrap_or_default();
+        for row in 0..rows {
+            let i = self.scroll + row;
+            let item = match self.chords.get(i) {
+                Some(chord) => {
+                    let marker = if self.problems.contains_key(&i) {
+                        '!'
+                    } else {
+                        ' '
+                    };
+                    let buttons = format!("{} {}", chord.thumbs(), chord.fingers());
+                    let text = format!(
+                        "{}{:>4} {:<18} {}",
+                        marker,
+                        i + 1,
+                        buttons.trim(),
+                        chord.output()
+                    );
+                    let text = pad(&text, list_width);
+                    match (i == self.selected, self.problems.contains_key(&i)) {
+                        (true, _) => format!("\x1b[7m{}\x1b[0m", text),
+                        (false, true) => format!("\x1b[31m{}\x1b[0m", text),
+                        (false, false) => text,
+                    }
+                }
+                None => pad("", list_width),
+            };
+            let side = grid.get(row).map(String::as_str).unwrap_or_default();
+            lines.push(format!("{} {}", item, side));
+        }
+
+        let status = match &self.mode {
+            Mode::Edit {
+                field,
+                text,
+                cursor,
+            } => {
+                let label = match field {
+                    Field::Buttons => "Buttons",
+                    Field::Output => "Output",
+                };
+                let (before, after) = text.split_at(
+                    text.char_indices()
+                        .nth(*cursor)
+                        .map_or(text.len(), |(i, _)| i),
+                );
+                let mut after = after.chars();
+                let at = after.next().unwrap_or(' ');
+                format!(
+                    "{}: {}\x1b[7m{}\x1b[0m{}",
+                    label,
+                    before,
+                    at,
+                    after.as_str()
+                )
+            }
+            Mode::Browse if !self.message.is_empty() => self.message.clone(),
+            Mode::Browse => self
+                .problem(self.selected)
+                .map(|problem| format!("\x1b[31m{}\x1b[0m", problem))
+                .unwrap_or_default(),
+        };
+        lines.push(status);
+        let footer = match &self.mode {
+            Mode::Edit { .. } if !self.message.is_empty() => {
+                format!("\x1b[31m{}\x1b[0m", self.message)
+            }
+            Mode::Edit { .. } => "Enter to keep, Esc to cancel".to_string(),
+            Mode::Browse => HELP.to_string(),
+        };
+        lines.push(footer);
+        lines
+    }
+}
+
+// ■ for pressed buttons, · for the rest, laid out like the Twiddler
+fn grid(buttons: &ButtonState) -> Vec<String> {
+    let dot = |pressed: bool| if pressed { '■' } else { '·' };
+    let mut lines = vec!["Thumbs".to_string(), " T1 T2 T3 T4".to_string()];
+    let thumbs: Vec<String> = buttons
+        .thumbs()
+        .iter()
+        .map(|pressed| format!("  {}", dot(*pressed)))
+        .collect();
+    lines.push(thumbs.concat());
+    lines.push(String::new());
+    lines.push("Fingers".to_string());
+    lines.push("     L  M  R".to_string());
+    for (row, cols) in buttons.fingers().iter().enumerate() {
+        let cols: Vec<String> = cols.iter().map(|p| format!("  {}", dot(*p))).collect();
+        lines.push(format!("  {}{}", row, cols.concat()));
+    }
+    lines
+}
+
+// The key the bytes from `next` start with, None for ones the editor doesn't
+// use. `ready` is whether another byte follows an escape soon enough to be
+// part of the same key.
+fn decode_key(
+    mut next: impl FnMut() -> std::io::Result<u8>,
+    ready: impl FnMut() -> bool,
+) -> std::io::Result<Option<Key>> {
+    let byte = next()?;
+    let key = match byte {
+        0x1B => return decode_escape(next, ready),
+        0x03 => Key::Interrupt,
+        b'\r' | b'\n' => Key::Enter,
+        0x7F | 0x08 => Key::Backspace,
+        0x00..=0x1F => return Ok(None),
+        0x20..=0x7E => Key::Char(byte as char),
+        _ => {
+            // The rest of a UTF-8 character
+            let len = match byte {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                _ => 4,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.push(next()?);
+            }
+            match std::str::from_utf8(&bytes)
+                .ok()
+                .and_then(|s| s.chars().next())
+            {
+                Some(c) => Key::Char(c),
+                None => return Ok(None),
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+// Escape on its own, or the start of an arrow or other special key
+fn decode_escape(
+    mut next: impl FnMut() -> std::io::Result<u8>,
+    mut ready: impl FnMut() -> bool,
+) -> std::io::Result<Option<Key>> {
+    if !ready() {
+        return Ok(Some(Key::Esc));
+    }
+    if !matches!(next()?, b'[' | b'O') {
+        return Ok(None);
+    }
+    let mut params = String::new();
+    let last = loop {
+        match next()? {
+            b @ b'0'..=b'9' | b @ b';' => params.push(b as char),
+            b => break b,
+        }
+    };
+    let key = match (last, params.as_str()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', "1" | "7") => Key::Home,
+        (b'F', _) | (b'~', "4" | "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        (b'~', "5") => Key::PageUp,
+        (b'~', "6") => Key::PageDown,
+        _ => return Ok(None),
+    };
+    Ok(Some(key))
+}
+
+// Cut or fill `text` to exactly `width` characters
+fn pad(text: &str, width: usize) -> String {
+    let mut padded: String = text.chars().take(width).collect();
+    let len = padded.chars().count();
+    padded.extend(std::iter::repeat_n(' ', width - len));
+    padded
+}
+
+/// Run the editor in the terminal until it's quit, `save` is called with
+/// the chords whenever they're saved
+#[cfg(unix)]
+pub fn run(editor: &mut Editor, mut save: impl FnMut(&[Chord]) -> Result<()>) -> Result<()> {
+    use std::io::Write;
+
+    let terminal = terminal::Raw::enable()?;
+    let mut stdout = std::io::stdout();
+    loop {
+        let (width, height) = terminal::size();
+        let lines = editor.render(width, height);
+        write!(stdout, "\x1b[H\x1b[2J{}", lines.join("\r\n"))?;
+        stdout.flush()?;
+
+        let Some(key) = terminal.read_key()? else {
+            continue;
+        };
+        match editor.handle(key) {
+            Action::Continue => (),
+            Action::Save => match save(editor.chords()) {
+                Ok(()) => editor.saved(),
+                Err(e) => editor.set_message(format!("Error: {}", e)),
+            },
+            Action::Quit => return Ok(()),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn run(_editor: &mut Editor, _save: impl FnMut(&[Chord]) -> Result<()>) -> Result<()> {
+    Err(crate::Error::Unsupported(
+        "The editor only runs in Unix terminals".to_string(),
+    ))
+}
+
+#[cfg(unix)]
+mod terminal {
+    use std::io::{self, Write};
+
+    use super::Key;
+
+    /// Raw mode on the alternate screen, put back the way it was on drop
+    pub struct Raw {
+        original: libc::termios,
+    }
+
+    impl Raw {
+        pub fn enable() -> io::Result<Self> {
+            // SAFETY: termios is plain data, all zeros is a valid value
+            let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+            // SAFETY: the pointer is to a termios that lives past the call,
+            // a bad file descriptor is an error rather than undefined
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut termios) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let original = termios;
+            // SAFETY: only writes the flags of the termios it's given
+            unsafe { libc::cfmakeraw(&mut termios) };
+            // SAFETY: reads the termios through a pointer valid for the call
+            if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            print!("\x1b[?1049h\x1b[?25l");
+            io::stdout().flush()?;
+            Ok(Raw { original })
+        }
+
+        pub fn read_key(&self) -> io::Result<Option<Key>> {
+            super::decode_key(read_byte, || ready(50))
+        }
+    }
+
+    impl Drop for Raw {
+        fn drop(&mut self) {
+            print!("\x1b[?25h\x1b[?1049l");
+            let _ = io::stdout().flush();
+            // SAFETY: the termios was filled in by tcgetattr in enable, a
+            // failure leaves the terminal raw but is nothing worse
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+        }
+    }
+
+    /// Columns and rows, 80 by 24 if the terminal doesn't say
+    pub fn size() -> (usize, usize) {
+        // SAFETY: winsize is plain data, all zeros is a valid value
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: TIOCGWINSZ writes one winsize through the pointer, which is
+        // to a live one
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+        match ok && size.ws_col > 0 && size.ws_row > 0 {
+            true => (size.ws_col as usize, size.ws_row as usize),
+            false => (80, 24),
+        }
+    }
+
+    // Straight from the file descriptor, stdin's buffer would hide the rest
+    // of an escape sequence from poll
+    fn read_byte() -> io::Result<u8> {
+        let mut byte = 0u8;
+        loop {
+            // SAFETY: reads at most one byte into `byte`, which outlives the
+            // call
+            match unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) } {
+                1 => return Ok(byte),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                _ => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether there's input within `ms` milliseconds
+    fn ready(ms: i32) -> bool {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: the count of 1 matches the one pollfd pointed to
+        unsafe { libc::poll(&mut fd, 1, ms) > 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            editor.handle(Key::Char(c));
+        }
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut editor = Editor::new(
+            "test",
+            vec![Chord::new("", "1L", "a"), Chord::new("", "1M", "b")],
+        );
+        assert!(editor.problem(0).is_none());
+
+        // Put the second chord on the first one's buttons
+        editor.handle(Key::Down);
+        editor.handle(Key::Char('b'));
+        for _ in 0..2 {
+            editor.handle(Key::Backspace);
+        }
+        type_text(&mut editor, "1L");
+        editor.handle(Key::Enter);
+        assert!(editor.chords()[1].fingers() == "1L");
+        assert!(editor.problem(0) == Some("On the same buttons as 2"));
+        assert!(editor.is_dirty());
+
+        editor.handle(Key::Enter);
+        editor.handle(Key::Home);
+        type_text(&mut editor, "<Nope>");
+        editor.handle(Key::Enter);
+        assert!(editor.chords()[1].output() == "<Nope>b");
+        assert!(editor.problem(1).unwrap().contains("Nope"));
+
+        assert!(editor.handle(Key::Char('d')) == Action::Continue);
+        assert!(editor.chords().len() == 1 && editor.problem(0).is_none());
+        assert!(editor.handle(Key::Char('s')) == Action::Save);
+        editor.saved();
+        assert!(editor.handle(Key::Char('q')) == Action::Quit);
+    }
+
+    #[test]
+    fn test_add() {
+        let mut editor = Editor::new("test", vec![]);
+        editor.handle(Key::Char('a'));
+        type_text(&mut editor, "T1 9Z");
+        editor.handle(Key::Enter);
+        // Bad buttons keep the edit open with the error shown
+        let screen = editor.render(80, 24).join("\n");
+        assert!(screen.contains("9Z"));
+        for _ in 0..2 {
+            editor.handle(Key::Backspace);
+        }
+        type_text(&mut editor, "0M");
+        editor.handle(Key::Enter);
+        assert!(editor.chords() == [Chord::new("1", "0M", "")]);
+
+        // Quitting with changes asks first
+        assert!(editor.handle(Key::Char('q')) == Action::Continue);
+        assert!(editor.handle(Key::Char('q')) == Action::Quit);
+    }
+
+    #[test]
+    fn test_move() {
+        let chords = (0..30)
+            .map(|i| Chord::new("", "1L", i.to_string()))
+            .collect();
+        let mut editor = Editor::new("test", chords);
+        editor.handle(Key::PageDown);
+        editor.handle(Key::Char('j'));
+        assert!(editor.selected == 11);
+        editor.handle(Key::End);
+        assert!(editor.selected == 29);
+        editor.handle(Key::Down);
+        assert!(editor.selected == 29);
+
+        // The list scrolls to keep the selected chord on screen
+        let screen = editor.render(80, 10);
+        assert!(screen.len() == 10);
+        assert!(screen[7].contains("  30 1L"));
+        editor.handle(Key::Char('g'));
+        assert!(editor.render(80, 10)[1].contains("   1 1L"));
+    }
+
+    #[test]
+    fn test_edit_cursor() {
+        let mut editor = Editor::new("test", vec![Chord::new("", "1L", "é")]);
+        editor.handle(Key::Enter);
+        editor.handle(Key::Home);
+        type_text(&mut editor, "ç");
+        editor.handle(Key::End);
+        editor.handle(Key::Left);
+        editor.handle(Key::Delete);
+        type_text(&mut editor, "ü");
+        editor.handle(Key::Enter);
+        assert!(editor.chords()[0].output() == "çü");
+
+        // Esc leaves the chord as it was
+        editor.handle(Key::Enter);
+        editor.handle(Key::Backspace);
+        editor.handle(Key::Esc);
+        assert!(editor.chords()[0].output() == "çü");
+    }
+
+    #[test]
+    fn test_render() {
+        let mut editor = Editor::new("test", vec![Chord::new("1", "2M", "a")]);
+        let screen = editor.render(60, 12);
+        assert!(screen.len() == 12);
+        assert!(screen[0].contains("test  1 chords, 0 with problems"));
+        assert!(screen[3].ends_with("  ■  ·  ·  ·"));
+        assert!(screen[9].ends_with("  2  ·  ■  ·"));
+        assert!(screen[11] == HELP);
+        assert!(pad("abc", 2) == "ab" && pad("é", 3) == "é  ");
+    }
+
+    fn keys(input: &[u8]) -> Vec<Option<Key>> {
+        let at = Cell::new(0);
+        let next = || {
+            let byte = input.get(at.get()).copied();
+            at.set(at.get() + 1);
+            byte.ok_or_else(|| std::io::ErrorKind::UnexpectedEof.into())
+        };
+        let mut keys = vec![];
+        while at.get() < input.len() {
+            keys.push(decode_key(next, || at.get() < input.len()).unwrap());
+        }
+        keys
+    }
+
+    #[test]
+    fn test_decode_key() {
+        assert!(
+            keys(b"a\r\x7f\x03\x01")
+                == [
+                    Some(Key::Char('a')),
+                    Some(Key::Enter),
+                    Some(Key::Backspace),
+                    Some(Key::Interrupt),
+                    None
+                ]
+        );
+        assert!(keys("é€".as_bytes()) == [Some(Key::Char('é')), Some(Key::Char('€'))]);
+        assert!(
+            keys(b"\x1b[A\x1bOD\x1b[3~\x1b[1;5H\x1b[9~")
+                == [
+                    Some(Key::Up),
+                    Some(Key::Left),
+                    Some(Key::Delete),
+                    Some(Key::Home),
+                    None
+                ]
+        );
+        // Nothing after the escape in time is the Esc key itself
+        assert!(keys(b"\x1b") == [Some(Key::Esc)]);
+    }
+}
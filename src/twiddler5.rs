@@ -1,16 +1,19 @@
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
 
-use binrw::{BinRead, PosValue};
+use binrw::{binread, BinRead, PosValue};
 use modular_bitfield::prelude::*;
 
-use crate::buttons::ButtonState;
+use crate::{buttons::ButtonState, Error, Result};
 
-#[derive(BinRead)]
+#[binread]
 #[br(little)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug)]
 pub struct Config {
+    #[br(temp, assert(version == 5, "Not a version 5 config file"))]
     version: u8,
     options_a: u8,
+    #[br(temp)]
     number_of_chords: u16,
     sleep_timeout: u16,
     mouse_left_click: u16,   // todo: these can also be actions and probably
@@ -24,22 +27,83 @@ pub struct Config {
     #[br(count = number_of_chords)]
     pub chords: Vec<Chord>,
 
-    #[br(calc = chords.iter().filter(|c| c.mapping.is_string()).count())]
+    #[br(temp, calc = chords.iter().filter(|c| c.mapping.is_string()).count())]
     number_of_strings: usize,
 
     #[br(count = number_of_strings)]
     pub string_locations: Vec<u32>,
 
     #[br(count = number_of_strings)]
+    #[cfg_attr(feature = "serde", serde(with = "positioned"))]
     pub string_contents: Vec<PosValue<StringContents>>,
 }
 
-#[derive(BinRead)]
+// binrw's PosValue has no serde, each string goes with the position it was
+// read from as a pair
+#[cfg(feature = "serde")]
+mod positioned {
+    use binrw::PosValue;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::StringContents;
+
+    pub fn serialize<S: Serializer>(
+        strings: &[PosValue<StringContents>],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let pairs: Vec<(u64, &StringContents)> = strings.iter().map(|s| (s.pos, &s.val)).collect();
+        pairs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<PosValue<StringContents>>, D::Error> {
+        let pairs = Vec::<(u64, StringContents)>::deserialize(deserializer)?;
+        Ok(pairs
+            .into_iter()
+            .map(|(pos, val)| PosValue { val, pos })
+            .collect())
+    }
+}
+
+/// Global device settings from the v5 header
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub options_a: u8,
+    pub sleep_timeout: u16, // seconds
+    pub mouse_left_click: u16,
+    pub mouse_middle_click: u16,
+    pub mouse_right_click: u16,
+    pub mouse_accel_factor: u8,
+    pub key_repeat_delay: u8, // 10ms steps
+    pub options_b: u8,
+    pub options_c: u8,
+}
+
+impl Config {
+    pub fn settings(&self) -> Settings {
+        Settings {
+            options_a: self.options_a,
+            sleep_timeout: self.sleep_timeout,
+            mouse_left_click: self.mouse_left_click,
+            mouse_middle_click: self.mouse_middle_click,
+            mouse_right_click: self.mouse_right_click,
+            mouse_accel_factor: self.mouse_accel_factor,
+            key_repeat_delay: self.key_repeat_delay,
+            options_b: self.options_b,
+            options_c: self.options_c,
+        }
+    }
+}
+
+#[binread]
 #[br(little)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug)]
 pub struct Chord {
     chord: ButtonData,
-    #[br(restore_position)]
+    #[br(temp, restore_position)]
     modifier: u8,
     #[br(args { modifier })]
     pub mapping: ChordMapping,
@@ -51,10 +115,11 @@ impl Chord {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Eq, PartialEq, Hash, Debug, BinRead)]
 #[br(little)]
 #[br(import { modifier: u8 })]
-pub(crate) enum ChordMapping {
+pub enum ChordMapping {
     #[br(assert(modifier == 0xFF))]
     StringMapping(u8, u8),
     KeyMapping(u8, u8),
@@ -66,17 +131,22 @@ impl ChordMapping {
     }
 }
 
-#[derive(Debug, BinRead)]
+#[binread]
 #[br(little)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug)]
 pub struct StringContents {
+    #[br(temp)]
     size: u16,
 
-    #[br(count = size / 2 - 1, args { inner: ChordMappingBinReadArgs { modifier: 0 } })]
+    // The size counts itself, a corrupt one under 2 is an empty string
+    #[br(count = (size / 2).saturating_sub(1), args { inner: ChordMappingBinReadArgs { modifier: 0 } })]
     pub keys: Vec<ChordMapping>,
 }
 
 #[bitfield]
 #[derive(BinRead, Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[br(map = Self::from_bytes)]
 pub struct ButtonData {
     num: bool,
@@ -100,39 +170,152 @@ pub struct ButtonData {
     ent: bool,
 }
 
-impl Into<ButtonState> for ButtonData {
-    fn into(self) -> ButtonState {
-        ButtonState {
-            t1: self.num(),
-            t2: self.alt(),
-            t3: self.ctrl(),
-            t4: self.shift(),
-            f0l: false,
-            f0m: false,
-            f0r: false,
-            f1r: self.a(),
-            f1m: self.e(),
-            f1l: self.sp(),
-            f2r: self.b(),
-            f2m: self.f(),
-            f2l: self.del(),
-            f3r: self.c(),
-            f3m: self.g(),
-            f3l: self.bs(),
-            f4r: self.d(),
-            f4m: self.h(),
-            f4l: self.ent(),
-        }
+impl From<ButtonData> for ButtonState {
+    fn from(data: ButtonData) -> Self {
+        ButtonState::empty()
+            .with(ButtonState::T1, data.num())
+            .with(ButtonState::T2, data.alt())
+            .with(ButtonState::T3, data.ctrl())
+            .with(ButtonState::T4, data.shift())
+            .with(ButtonState::F1R, data.a())
+            .with(ButtonState::F1M, data.e())
+            .with(ButtonState::F1L, data.sp())
+            .with(ButtonState::F2R, data.b())
+            .with(ButtonState::F2M, data.f())
+            .with(ButtonState::F2L, data.del())
+            .with(ButtonState::F3R, data.c())
+            .with(ButtonState::F3M, data.g())
+            .with(ButtonState::F3L, data.bs())
+            .with(ButtonState::F4R, data.d())
+            .with(ButtonState::F4M, data.h())
+            .with(ButtonState::F4L, data.ent())
+    }
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config> {
+    check_size(reader)?;
+    let config = Config::read(reader)?;
+    check_strings(&config)?;
+    event!(
+        debug,
+        chords = config.chords.len(),
+        end = ?reader.stream_position().ok(),
+        "Read v5 config"
+    );
+    Ok(config)
+}
+
+// The header has to be there and the chord table has to fit
+fn check_size<R: Read + Seek>(reader: &mut R) -> Result<()> {
+    let start = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))? - start;
+    reader.seek(SeekFrom::Start(start))?;
+    if len < 0x10 {
+        return Err(Error::Invalid(format!(
+            "The config is {} bytes, too short for the 16 byte v5 header",
+            len
+        )));
     }
+
+    let mut count = [0; 2];
+    reader.seek(SeekFrom::Start(start + 0x02))?;
+    reader.read_exact(&mut count)?;
+    reader.seek(SeekFrom::Start(start))?;
+    let chords = u64::from(u16::from_le_bytes(count));
+    if 0x10 + chords * 4 > len {
+        return Err(Error::Invalid(format!(
+            "The header at 0x02 says there are {} chords but the {} byte config only has room for {}",
+            chords,
+            len,
+            (len - 0x10) / 4
+        )));
+    }
+    Ok(())
 }
 
-pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn std::error::Error>> {
-    let res = Config::read(reader);
-    match res {
-        Ok(config) => {
-            assert!(config.version == 5, "Not a version 5 config file");
-            Ok(config)
+// String chords refer to the string table by index and the table to the
+// strings by offset, converting looks both up
+fn check_strings(config: &Config) -> Result<()> {
+    for (i, chord) in config.chords.iter().enumerate() {
+        if let ChordMapping::StringMapping(_, index) = chord.mapping {
+            if usize::from(index) >= config.string_locations.len() {
+                return Err(Error::Invalid(format!(
+                    "Chord {} at {:#x} types string {} but there are only {}",
+                    i,
+                    0x10 + i * 4,
+                    index,
+                    config.string_locations.len()
+                )));
+            }
         }
-        Err(e) => Err(Box::new(e)),
+    }
+    for (i, location) in config.string_locations.iter().enumerate() {
+        if !config
+            .string_contents
+            .iter()
+            .any(|contents| contents.pos == u64::from(*location))
+        {
+            return Err(Error::Invalid(format!(
+                "String {} is at {:#x} in the string table but no string starts there",
+                i, location
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut file = std::fs::File::open("configs/backspice2_v5.cfg").unwrap();
+        let conf = parse(&mut file).unwrap();
+        assert!(conf.chords.len() == 258);
+        assert!(conf.string_contents.len() == conf.string_locations.len());
+
+        let settings = conf.settings();
+        assert!(settings.sleep_timeout == 3600);
+        assert!(settings.key_repeat_delay == 100);
+        assert!(settings.mouse_accel_factor == 0xff);
+
+        let mut file = std::fs::File::open("test/configs/v7/empty.cfg").unwrap();
+        assert!(parse(&mut file).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let mut file = std::fs::File::open("configs/backspice2_v5.cfg").unwrap();
+        let conf = parse(&mut file).unwrap();
+        let json = serde_json::to_string(&conf).unwrap();
+        let read: Config = serde_json::from_str(&json).unwrap();
+        assert!(read.settings() == conf.settings());
+        assert!(serde_json::to_string(&read).unwrap() == json);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let data = std::fs::read("configs/backspice2_v5.cfg").unwrap();
+        let error = |data: &[u8]| {
+            parse(&mut std::io::Cursor::new(data))
+                .unwrap_err()
+                .to_string()
+        };
+
+        let mut count = data.clone();
+        count[0x03] = 0x10;
+        assert!(error(&count).contains("says there are 4098 chords"));
+
+        // The first chord typing a string
+        let string = (0x10..0x10 + 258 * 4)
+            .step_by(4)
+            .find(|i| data[i + 2] == 0xFF)
+            .unwrap();
+        let mut index = data.clone();
+        index[string + 3] = 200;
+        assert!(error(&index).contains("types string 200"));
     }
 }
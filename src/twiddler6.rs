@@ -1,14 +1,18 @@
-use std::{
-    default,
-    io::{Read, Seek, SeekFrom, Write},
-};
+use std::io::{Read, Seek, SeekFrom, Write};
 
 use binrw::{binrw, BinRead, BinResult, BinWrite, Endian};
 use modular_bitfield::{bitfield, prelude::B4};
 use std::convert::From;
 
-use crate::{buttons::ButtonState, hid};
+use crate::{
+    buttons::ButtonState,
+    hid,
+    intern::{self, Interned},
+    settings::config_settings,
+    Error, Result, Settings,
+};
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Eq, PartialEq, Clone)]
 #[binrw]
 #[brw(big, repr = u8)]
@@ -23,38 +27,51 @@ pub enum CommandType {
 
 #[bitfield]
 #[derive(BinRead, BinWrite, Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[br(map = Self::from_bytes)]
 pub struct ConfigFlags {
-    repeat_delay_enable: bool,
-    haptic: bool,
-    left_mouse_pos: bool, // FOL or FOR
-    direct: bool,
-    sticky_num: bool,
-    sticky_alt: bool,
-    sticky_ctrl: bool,
-    sticky_shift: bool,
+    pub repeat_delay_enable: bool,
+    pub haptic: bool,
+    pub left_mouse_pos: bool, // FOL or FOR
+    pub direct: bool,
+    pub sticky_num: bool,
+    pub sticky_alt: bool,
+    pub sticky_ctrl: bool,
+    pub sticky_shift: bool,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[binrw]
 #[brw(little)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     #[brw(pad_before = 0x4)]
     version: u8,
-    flags: ConfigFlags,
+    pub flags: ConfigFlags,
     pub number_of_chords: u16,
-    #[brw(pad_before = 0x4)]
     pub idle_time: u16,
+    #[brw(pad_before = 0x6)]
     pub mouse_sensitivity: u8,
     pub key_repeat_delay: u8,
+    // TODO: Figure out more config format details, first byte changes with haptic
+    #[brw(pad_before = 0x1)]
+    unknown: [u8; 0x15],
     #[brw(seek_before = SeekFrom::Start(0x28))]
     #[br(count = number_of_chords)]
     pub chords: Vec<Chord>,
 
-    #[br(count = chords.iter().filter(|c| c.command.command_type == CommandType::ListOfCommands).count())]
+    /// One per chord with a [`CommandType::ListOfCommands`] command, in
+    /// order, even when chords share one in the file
+    #[br(parse_with = read_command_lists, args(&chords))]
     pub command_lists: Vec<CommandList>,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Config {
     pub fn new() -> Self {
         Self {
@@ -68,10 +85,46 @@ impl Config {
             idle_time: 600,
             mouse_sensitivity: 0x7f,
             key_repeat_delay: 127,
+            unknown: [
+                0x03, 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0C, 0x0D,
+                0x0F, 0x11, 0x14, 0x16, 0x18, 0x1A, 0x1D,
+            ],
+        }
+    }
+
+    // Make the chord count and command list offsets match the chord table,
+    // leaving only the command lists that are written, see intern
+    fn update_offsets(&mut self, share: bool) -> Result<Interned> {
+        self.number_of_chords = self.chords.len() as u16;
+
+        let command_lists_command_count = self
+            .chords
+            .iter()
+            .filter(|c| c.command.command_type == CommandType::ListOfCommands)
+            .count();
+        if command_lists_command_count != self.command_lists.len() {
+            return Err(Error::Invalid(
+                "Commands with CommandType::ListOfCommands count mismatch".to_owned(),
+            ));
+        }
+
+        let interned = intern::lay_out(&intern::encode(&self.command_lists)?, share);
+        interned.check()?;
+
+        let mut offsets = interned.offsets.iter();
+        for chord in &mut self.chords {
+            if chord.command.command_type == CommandType::ListOfCommands {
+                let offset = *offsets.next().unwrap() as u16;
+                chord.command.data = CommandData::ListOfCommands(0, offset);
+            }
         }
+        self.command_lists = interned.take_stored(std::mem::take(&mut self.command_lists));
+
+        Ok(interned)
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 #[binrw]
 #[brw(little)]
@@ -81,6 +134,7 @@ pub struct Chord {
     pub command: Command,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 #[binrw]
 #[brw(little)]
@@ -90,21 +144,28 @@ pub struct Command {
     pub data: CommandData,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 #[binrw]
 #[br(little)]
 #[br(import { command_type: &CommandType })]
-pub(crate) enum CommandData {
+pub enum CommandData {
     #[br(assert(*command_type == CommandType::ListOfCommands))]
     ListOfCommands(u8, u16),
     #[br(assert(*command_type == CommandType::Keyboard))]
     Keyboard(HidCommand, u8),
+    #[br(assert(*command_type == CommandType::Mouse))]
+    Mouse(MouseCommand, u8),
     #[br(assert(*command_type == CommandType::System))]
     System(u8, u8, u8),
+    /// Pause between the commands of a list, in milliseconds
+    #[br(assert(*command_type == CommandType::Delay))]
+    Delay(u16, u8),
     #[br(assert(*command_type == CommandType::None))]
     None(u8, u8, u8),
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 #[binrw]
 pub struct HidCommand {
@@ -112,7 +173,17 @@ pub struct HidCommand {
     pub key_code: u8,
 }
 
-#[derive(Default, Debug)]
+/// Mouse buttons held while the chord is pressed, see `hid::MOUSE_BUTTONS`
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+#[binrw]
+pub struct MouseCommand {
+    pub buttons: u8,
+    pub unknown: u8,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Default, Debug, Clone)]
 pub struct CommandList(pub Vec<Command>);
 
 impl BinRead for CommandList {
@@ -151,142 +222,206 @@ impl BinWrite for CommandList {
     }
 }
 
+// See intern::read_lists
+fn read_command_lists<R: Read + Seek>(
+    reader: &mut R,
+    endian: Endian,
+    (chords,): (&Vec<Chord>,),
+) -> BinResult<Vec<CommandList>> {
+    let offsets: Vec<(usize, u64)> = chords
+        .iter()
+        .enumerate()
+        .filter_map(|(i, chord)| match chord.command.data {
+            CommandData::ListOfCommands(_, offset) => Some((i, offset.into())),
+            _ => None,
+        })
+        .collect();
+    intern::read_lists(reader, endian, &offsets, 0x28)
+}
+
 #[bitfield]
 #[derive(BinRead, BinWrite, Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[br(map = Self::from_bytes)]
 pub struct ButtonData {
-    t1: bool,
-    f1r: bool,
-    f1m: bool,
-    f1l: bool,
-
-    t2: bool,
-    f2r: bool,
-    f2m: bool,
-    f2l: bool,
-
-    t3: bool,
-    f3r: bool,
-    f3m: bool,
-    f3l: bool,
-
-    t4: bool,
-    f4r: bool,
-    f4m: bool,
-    f4l: bool,
-
-    t0: bool,
-    f0r: bool,
-    f0m: bool,
-    f0l: bool,
-
+    pub t1: bool,
+    pub f1r: bool,
+    pub f1m: bool,
+    pub f1l: bool,
+
+    pub t2: bool,
+    pub f2r: bool,
+    pub f2m: bool,
+    pub f2l: bool,
+
+    pub t3: bool,
+    pub f3r: bool,
+    pub f3m: bool,
+    pub f3l: bool,
+
+    pub t4: bool,
+    pub f4r: bool,
+    pub f4m: bool,
+    pub f4l: bool,
+
+    pub t0: bool,
+    pub f0r: bool,
+    pub f0m: bool,
+    pub f0l: bool,
+
+    #[skip]
     unknown: B4,
 }
 
 impl From<ButtonState> for ButtonData {
     fn from(state: ButtonState) -> Self {
         ButtonData::new()
-            .with_f0l(state.f0l)
-            .with_f0m(state.f0m)
-            .with_f0r(state.f0r)
+            .with_f0l(state.f0l())
+            .with_f0m(state.f0m())
+            .with_f0r(state.f0r())
             .with_t0(false)
-            .with_t3(state.t3)
-            .with_f3r(state.f3r)
-            .with_f3m(state.f3m)
-            .with_f3l(state.f3l)
-            .with_t4(state.t4)
-            .with_f4r(state.f4r)
-            .with_f4m(state.f4m)
-            .with_f4l(state.f4l)
-            .with_t1(state.t1)
-            .with_f1r(state.f1r)
-            .with_f1m(state.f1m)
-            .with_f1l(state.f1l)
-            .with_t2(state.t2)
-            .with_f2r(state.f2r)
-            .with_f2m(state.f2m)
-            .with_f2l(state.f2l)
+            .with_t3(state.t3())
+            .with_f3r(state.f3r())
+            .with_f3m(state.f3m())
+            .with_f3l(state.f3l())
+            .with_t4(state.t4())
+            .with_f4r(state.f4r())
+            .with_f4m(state.f4m())
+            .with_f4l(state.f4l())
+            .with_t1(state.t1())
+            .with_f1r(state.f1r())
+            .with_f1m(state.f1m())
+            .with_f1l(state.f1l())
+            .with_t2(state.t2())
+            .with_f2r(state.f2r())
+            .with_f2m(state.f2m())
+            .with_f2l(state.f2l())
     }
 }
 
 impl From<&ButtonState> for ButtonData {
     fn from(state: &ButtonState) -> Self {
         ButtonData::new()
-            .with_f0l(state.f0l)
-            .with_f0m(state.f0m)
-            .with_f0r(state.f0r)
+            .with_f0l(state.f0l())
+            .with_f0m(state.f0m())
+            .with_f0r(state.f0r())
             .with_t0(false)
-            .with_t3(state.t3)
-            .with_f3r(state.f3r)
-            .with_f3m(state.f3m)
-            .with_f3l(state.f3l)
-            .with_t4(state.t4)
-            .with_f4r(state.f4r)
-            .with_f4m(state.f4m)
-            .with_f4l(state.f4l)
-            .with_t1(state.t1)
-            .with_f1r(state.f1r)
-            .with_f1m(state.f1m)
-            .with_f1l(state.f1l)
-            .with_t2(state.t2)
-            .with_f2r(state.f2r)
-            .with_f2m(state.f2m)
-            .with_f2l(state.f2l)
+            .with_t3(state.t3())
+            .with_f3r(state.f3r())
+            .with_f3m(state.f3m())
+            .with_f3l(state.f3l())
+            .with_t4(state.t4())
+            .with_f4r(state.f4r())
+            .with_f4m(state.f4m())
+            .with_f4l(state.f4l())
+            .with_t1(state.t1())
+            .with_f1r(state.f1r())
+            .with_f1m(state.f1m())
+            .with_f1l(state.f1l())
+            .with_t2(state.t2())
+            .with_f2r(state.f2r())
+            .with_f2m(state.f2m())
+            .with_f2l(state.f2l())
     }
 }
 
-impl Into<ButtonState> for ButtonData {
-    fn into(self) -> ButtonState {
-        ButtonState {
-            t1: self.t1(),
-            t2: self.t2(),
-            t3: self.t3(),
-            t4: self.t4(),
-            f0l: self.f0l(),
-            f0m: self.f0m(),
-            f0r: self.f0r(),
-            f1r: self.f1r(),
-            f1m: self.f1m(),
-            f1l: self.f1l(),
-            f2r: self.f2r(),
-            f2m: self.f2m(),
-            f2l: self.f2l(),
-            f3r: self.f3r(),
-            f3m: self.f3m(),
-            f3l: self.f3l(),
-            f4r: self.f4r(),
-            f4m: self.f4m(),
-            f4l: self.f4l(),
-        }
+impl From<ButtonData> for ButtonState {
+    fn from(data: ButtonData) -> Self {
+        ButtonState::empty()
+            .with(ButtonState::T1, data.t1())
+            .with(ButtonState::T2, data.t2())
+            .with(ButtonState::T3, data.t3())
+            .with(ButtonState::T4, data.t4())
+            .with(ButtonState::F0L, data.f0l())
+            .with(ButtonState::F0M, data.f0m())
+            .with(ButtonState::F0R, data.f0r())
+            .with(ButtonState::F1R, data.f1r())
+            .with(ButtonState::F1M, data.f1m())
+            .with(ButtonState::F1L, data.f1l())
+            .with(ButtonState::F2R, data.f2r())
+            .with(ButtonState::F2M, data.f2m())
+            .with(ButtonState::F2L, data.f2l())
+            .with(ButtonState::F3R, data.f3r())
+            .with(ButtonState::F3M, data.f3m())
+            .with(ButtonState::F3L, data.f3l())
+            .with(ButtonState::F4R, data.f4r())
+            .with(ButtonState::F4M, data.f4m())
+            .with(ButtonState::F4L, data.f4l())
     }
 }
 
-pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn std::error::Error>> {
-    let res = Config::read(reader);
-    match res {
-        Ok(config) => {
-            //println!("{:?}", config);
-            return Ok(config);
-        }
-        Err(e) => Err(Box::new(e)),
+#[rustfmt::skip]
+config_settings! {
+    key_repeat repeat_delay_enable with_repeat_delay_enable,
+    haptic haptic with_haptic,
+    direct direct with_direct,
+    sticky_num sticky_num with_sticky_num,
+    sticky_alt sticky_alt with_sticky_alt,
+    sticky_ctrl sticky_ctrl with_sticky_ctrl,
+    sticky_shift sticky_shift with_sticky_shift,
+    left_mouse_pos left_mouse_pos with_left_mouse_pos,
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config> {
+    check_size(reader)?;
+    let config = Config::read(reader)?;
+    event!(
+        debug,
+        chords = config.chords.len(),
+        end = ?reader.stream_position().ok(),
+        "Read v6 config"
+    );
+    Ok(config)
+}
+
+// Like twiddler7's, the header has to be there and the chord table has to fit
+fn check_size<R: Read + Seek>(reader: &mut R) -> Result<()> {
+    let start = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))? - start;
+    reader.seek(SeekFrom::Start(start))?;
+    if len < 0x28 {
+        return Err(Error::Invalid(format!(
+            "The config is {} bytes, too short for the 40 byte v6 header",
+            len
+        )));
+    }
+
+    let mut count = [0; 2];
+    reader.seek(SeekFrom::Start(start + 0x06))?;
+    reader.read_exact(&mut count)?;
+    reader.seek(SeekFrom::Start(start))?;
+    let chords = u64::from(u16::from_le_bytes(count));
+    if 0x28 + chords * 8 > len {
+        return Err(Error::Invalid(format!(
+            "The header at 0x06 says there are {} chords but the {} byte config only has room for {}",
+            chords,
+            len,
+            (len - 0x28) / 8
+        )));
     }
+    Ok(())
 }
 
-pub(crate) fn write<W: Write + Seek>(
+/// Write the config, returning where its command lists went to report how
+/// much of the room for them they take. With `share` repeated command lists
+/// are written once, see [`intern`].
+pub fn write<W: Write + Seek>(
     mut config: Config,
     writer: &mut W,
     gen_caps: Option<i32>,
-) -> std::io::Result<()> {
+    share: bool,
+) -> Result<Interned> {
     // Generate chords for caps
     if let Some(caps) = gen_caps {
         let mut new_chords = vec![];
         for chord in &config.chords {
             if chord.command.command_type == CommandType::Keyboard
-                && chord.buttons.t0() == false
-                && chord.buttons.t1() == false
-                && chord.buttons.t2() == false
-                && chord.buttons.t3() == false
-                && chord.buttons.t4() == false
+                && !chord.buttons.t0()
+                && !chord.buttons.t1()
+                && !chord.buttons.t2()
+                && !chord.buttons.t3()
+                && !chord.buttons.t4()
             {
                 if let CommandData::Keyboard(hid_command, _) = &chord.command.data {
                     if hid::ALPHA_HID_CODES.contains(&hid_command.key_code) {
@@ -314,62 +449,122 @@ pub(crate) fn write<W: Write + Seek>(
             }
         }
 
-        if new_chords.len() > 0 {
-            println!("Adding {} uppercase chords", new_chords.len());
+        if !new_chords.is_empty() {
+            event!(info, chords = new_chords.len(), "Adding uppercase chords");
             config.chords.append(&mut new_chords);
         }
     }
 
-    // update number of chords
-    config.number_of_chords = config.chords.len() as u16;
-
-    // update offsets in config
-    let command_lists_command_count = config
-        .chords
-        .iter()
-        .filter(|c| c.command.command_type == CommandType::ListOfCommands)
-        .count();
-    assert!(
-        command_lists_command_count == config.command_lists.len(),
-        "Commands with CommandType::ListOfCommands count mismatch"
+    let interned = export_interned(writer, &config, share)?;
+    event!(
+        info,
+        command_lists = interned.len,
+        limit = intern::OFFSET_LIMIT,
+        saved = interned.saved(),
+        "Wrote config"
     );
 
-    let mut offset = 0;
-
-    let mut j = 0;
-    for i in 0..config.chords.len() {
-        if config.chords[i].command.command_type == CommandType::ListOfCommands {
-            let size = config.command_lists[j].0.len() * 4;
-            config.chords[i].command.data = CommandData::ListOfCommands(0, offset);
-            offset += size as u16;
-            offset += 4; // 0u32
-            j += 1;
-        }
-    }
-
-    let res = Config::write(&config, writer);
-    match res {
-        Ok(_) => {
-            println!("Wrote config");
-        }
-        Err(e) => {
-            println!("{:?}", e);
-        }
-    }
-
-    // TODO: Figure out more config format details
-    writer.seek(SeekFrom::Start(0x13));
-    let data = hex::decode("03000102030405060708090A0C0D0F111416181A1D").unwrap();
-    writer.write(&data)?;
+    Ok(interned)
+}
 
+/// Serialize a config in the v6 layout, only the chord count and command
+/// list offsets are recomputed so a parsed file is written back unchanged
+pub fn export<W: Write + Seek>(writer: &mut W, config: &Config) -> Result<()> {
+    export_interned(writer, config, false)?;
     Ok(())
 }
 
+fn export_interned<W: Write + Seek>(
+    writer: &mut W,
+    config: &Config,
+    share: bool,
+) -> Result<Interned> {
+    let mut config = config.clone();
+    let interned = config.update_offsets(share)?;
+    config.write(writer)?;
+
+    Ok(interned)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    fn test_export_round_trip() {
+        let mut paths: Vec<_> = std::fs::read_dir("test/configs/v6")
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        paths.push("configs/backspicev2_v6.cfg".into());
+
+        for path in paths {
+            let original = std::fs::read(&path).unwrap();
+            let conf = parse(&mut std::io::Cursor::new(&original)).unwrap();
+
+            let mut written = std::io::Cursor::new(vec![]);
+            export(&mut written, &conf).unwrap();
+            assert!(written.into_inner() == original, "{:?} changed", path);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let original = std::fs::read("configs/backspicev2_v6.cfg").unwrap();
+        let conf = parse(&mut std::io::Cursor::new(&original)).unwrap();
+        let json = serde_json::to_string(&conf).unwrap();
+        let read: Config = serde_json::from_str(&json).unwrap();
+
+        let mut written = std::io::Cursor::new(vec![]);
+        export(&mut written, &read).unwrap();
+        assert!(written.into_inner() == original);
+    }
+
+    #[test]
+    fn test_export_shared_command_lists() {
+        // The Tuner wrote a list per chord, repeats and all
+        let original = std::fs::read("configs/backspicev2_v6.cfg").unwrap();
+        let conf = parse(&mut std::io::Cursor::new(&original)).unwrap();
+
+        let mut written = std::io::Cursor::new(vec![]);
+        let interned = export_interned(&mut written, &conf, true).unwrap();
+        let written = written.into_inner();
+        assert!(interned.saved() > 0);
+        assert!(written.len() == original.len() - interned.saved());
+
+        let shared = parse(&mut std::io::Cursor::new(&written)).unwrap();
+        assert!(format!("{:?}", shared.chords) != format!("{:?}", conf.chords));
+        assert!(format!("{:?}", shared.command_lists) == format!("{:?}", conf.command_lists));
+
+        let mut again = std::io::Cursor::new(vec![]);
+        export_interned(&mut again, &shared, true).unwrap();
+        assert!(again.into_inner() == written);
+
+        // Written without sharing it's the Tuner's file again
+        let mut unshared = std::io::Cursor::new(vec![]);
+        export(&mut unshared, &shared).unwrap();
+        assert!(unshared.into_inner() == original);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let data = std::fs::read("configs/backspicev2_v6.cfg").unwrap();
+        let error = |data: &[u8]| {
+            parse(&mut std::io::Cursor::new(data))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error(&data[..0x28 + 8 * 100]).contains("only has room for 100"));
+
+        // Chord 13 has the first command list, point it at the second
+        let mut offset = data.clone();
+        offset[0x28 + 13 * 8 + 6] = 12;
+        assert!(error(&offset).contains("Chord 13 at 0x90 points to a command list at +0xc"));
+    }
+
+    #[test]
+    #[allow(clippy::bool_comparison)]
     fn test_header() {
         let mut file = std::fs::File::open("test/configs/v6/haptic_off.cfg").unwrap();
         let conf = Config::read(&mut file).unwrap();
@@ -409,5 +604,16 @@ mod tests {
         assert!(conf.flags.sticky_shift() == false);
         assert!(conf.flags.sticky_ctrl() == false);
         assert!(conf.flags.repeat_delay_enable() == true);
+        assert!(conf.idle_time == 600);
+        assert!(conf.mouse_sensitivity == 0x7f);
+        assert!(conf.key_repeat_delay == 100);
+
+        let mut file = std::fs::File::open("test/configs/v6/key_repeat_delay_10ms.cfg").unwrap();
+        let conf = Config::read(&mut file).unwrap();
+        assert!(conf.key_repeat_delay == 1);
+
+        let mut file = std::fs::File::open("test/configs/v6/mouse_sensitivity_1.cfg").unwrap();
+        let conf = Config::read(&mut file).unwrap();
+        assert!(conf.mouse_sensitivity == 0xfe);
     }
 }
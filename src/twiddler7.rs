@@ -1,42 +1,46 @@
-use std::{
-    default,
-    io::{Read, Seek, SeekFrom, Write},
-};
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom, Write};
 
 use binrw::{binrw, BinRead, BinResult, BinWrite, Endian};
 use modular_bitfield::{
     bitfield,
-    prelude::{B1, B4, B7},
+    prelude::{B4, B7},
 };
 
 use crate::{
-    buttons::{self, ButtonState},
+    buttons::{ButtonState, THUMB_MODIFIERS},
     hid,
+    intern::{self, Interned},
+    settings::config_settings,
+    Error, Metadata, Result, Settings,
 };
 
 #[bitfield]
 #[derive(BinRead, BinWrite, Debug, Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[br(map = Self::from_bytes)]
 pub struct ConfigFlags {
-    repeat_delay_enable: bool,
-    unknown: B1, // maybe bluetooth?
-    haptic: bool,
-    direct: bool,
-    sticky_num: bool,
-    sticky_alt: bool,
-    sticky_ctrl: bool,
-    sticky_shift: bool,
-    left_mouse_pos: bool, // FOL or FOR
-    unknown2: B7,         // future expansion??
+    pub repeat_delay_enable: bool,
+    pub direct: bool,
+    pub bluetooth: bool, // no Tuner config in test/configs sets it
+    pub haptic: bool,
+    pub sticky_num: bool,
+    pub sticky_alt: bool,
+    pub sticky_ctrl: bool,
+    pub sticky_shift: bool,
+    pub left_mouse_pos: bool, // FOL or FOR
+    #[skip]
+    unknown2: B7,  // future expansion??
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[binrw]
 #[brw(little)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Config {
     #[brw(pad_before = 0x4)]
     version: u8,
-    flags: ConfigFlags,
+    pub flags: ConfigFlags,
     #[brw(pad_before = 0x1)]
     pub number_of_chords: u16,
     pub idle_time: u16,
@@ -47,10 +51,23 @@ pub struct Config {
     #[br(count = number_of_chords)]
     pub chords: Vec<Chord>,
 
-    #[br(count = chords.iter().filter(|c| c.command.command_type == CommandType::ListOfCommands).count())]
+    /// One per chord with a [`CommandType::ListOfCommands`] command, in
+    /// order, even when chords share one in the file
+    #[br(parse_with = read_command_lists, args(&chords))]
     pub command_lists: Vec<CommandList>,
+
+    /// Read and written after the command lists by [`parse`] and [`write`]
+    #[brw(ignore)]
+    pub metadata: Metadata,
+
+    /// Keymaps besides this one, `layers[0]` is layer 1. Chords switch to
+    /// them with [`Command::switch_layer`]. Only CSV and JSON configs keep
+    /// layers, [`write`] rejects a config with any.
+    #[brw(ignore)]
+    pub layers: Vec<Config>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 #[binrw]
 #[brw(little)]
@@ -60,6 +77,7 @@ pub struct Chord {
     pub command: Command,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Eq, PartialEq, Clone)]
 #[binrw]
 #[brw(big, repr = u8)]
@@ -72,6 +90,7 @@ pub enum CommandType {
     ListOfCommands = 7,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 #[binrw]
 #[brw(little)]
@@ -82,21 +101,28 @@ pub struct Command {
     pub data: CommandData,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 #[binrw]
 #[br(little)]
 #[br(import { command_type: &CommandType })]
-pub(crate) enum CommandData {
+pub enum CommandData {
     #[br(assert(*command_type == CommandType::ListOfCommands))]
     ListOfCommands(u16),
     #[br(assert(*command_type == CommandType::Keyboard))]
     Keyboard(HidCommand),
+    #[br(assert(*command_type == CommandType::Mouse))]
+    Mouse(MouseCommand),
     #[br(assert(*command_type == CommandType::System))]
     System(u8, u8),
+    /// Pause between the commands of a list, in milliseconds
+    #[br(assert(*command_type == CommandType::Delay))]
+    Delay(u16),
     #[br(assert(*command_type == CommandType::None))]
     None(u8, u8),
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, PartialEq)]
 #[binrw]
 pub struct HidCommand {
@@ -104,7 +130,66 @@ pub struct HidCommand {
     pub key_code: u8,
 }
 
-#[derive(Default, Debug)]
+/// Mouse buttons held while the chord is pressed, see `hid::MOUSE_BUTTONS`
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+#[binrw]
+pub struct MouseCommand {
+    pub buttons: u8,
+    pub unknown: u8,
+}
+
+/// System command the Tuner writes to go to the next config
+pub const CONFIG_CYCLE: u8 = 4;
+
+// Holds sticky modifiers for CSV and JSON configs, no Tuner system command
+// goes this high
+const STICKY: u8 = 0xFF;
+
+impl Command {
+    /// Switch to a layer of [`Config::layers`], layer 0 being the config
+    /// itself. The Twiddler has no such command, this only carries
+    /// `<Layer:n>` between CSV and JSON configs and [`write`] rejects it. It's
+    /// held as the config cycle system command with the layer plus one as
+    /// its argument, the Tuner only writes it with 0 to go to the next
+    /// config.
+    pub fn switch_layer(layer: u8) -> Command {
+        Command {
+            command_type: CommandType::System,
+            data: CommandData::System(CONFIG_CYCLE, layer.saturating_add(1)),
+        }
+    }
+
+    /// The layer [`Command::switch_layer`] switches to
+    pub fn switched_layer(&self) -> Option<u8> {
+        match self.data {
+            CommandData::System(CONFIG_CYCLE, argument) if argument > 0 => Some(argument - 1),
+            _ => None,
+        }
+    }
+
+    /// Hold `modifier` for the next chord. Like [`Command::switch_layer`]
+    /// the Twiddler has no such command, so it only carries `<Sticky:...>`
+    /// between CSV and JSON configs and [`write`] rejects it. A keyboard
+    /// command of only modifiers holds them while the chord is pressed.
+    pub fn sticky(modifier: u8) -> Command {
+        Command {
+            command_type: CommandType::System,
+            data: CommandData::System(STICKY, modifier),
+        }
+    }
+
+    /// The modifiers [`Command::sticky`] holds
+    pub fn sticky_modifiers(&self) -> Option<u8> {
+        match self.data {
+            CommandData::System(STICKY, modifier) if modifier != 0 => Some(modifier),
+            _ => None,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Default, Debug, Clone)]
 pub struct CommandList(pub Vec<Command>);
 
 impl BinRead for CommandList {
@@ -143,113 +228,137 @@ impl BinWrite for CommandList {
     }
 }
 
+// See intern::read_lists
+fn read_command_lists<R: Read + Seek>(
+    reader: &mut R,
+    endian: Endian,
+    (chords,): (&Vec<Chord>,),
+) -> BinResult<Vec<CommandList>> {
+    let offsets: Vec<(usize, u64)> = chords
+        .iter()
+        .enumerate()
+        .filter_map(|(i, chord)| match chord.command.data {
+            CommandData::ListOfCommands(offset) => Some((i, offset.into())),
+            _ => None,
+        })
+        .collect();
+    intern::read_lists(reader, endian, &offsets, 0x80)
+}
+
 #[bitfield]
 #[derive(BinRead, BinWrite, Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[br(map = Self::from_bytes)]
 pub struct ButtonData {
-    t1: bool,
-    f1r: bool,
-    f1m: bool,
-    f1l: bool,
-
-    t2: bool,
-    f2r: bool,
-    f2m: bool,
-    f2l: bool,
-
-    t3: bool,
-    f3r: bool,
-    f3m: bool,
-    f3l: bool,
-
-    t4: bool,
-    f4r: bool,
-    f4m: bool,
-    f4l: bool,
-
-    f0r: bool,
-    f0m: bool,
-    f0l: bool,
-    t0: bool,
-
+    pub t1: bool,
+    pub f1r: bool,
+    pub f1m: bool,
+    pub f1l: bool,
+
+    pub t2: bool,
+    pub f2r: bool,
+    pub f2m: bool,
+    pub f2l: bool,
+
+    pub t3: bool,
+    pub f3r: bool,
+    pub f3m: bool,
+    pub f3l: bool,
+
+    pub t4: bool,
+    pub f4r: bool,
+    pub f4m: bool,
+    pub f4l: bool,
+
+    pub f0r: bool,
+    pub f0m: bool,
+    pub f0l: bool,
+    pub t0: bool,
+
+    #[skip]
     unknown: B4,
 }
 
 impl From<ButtonState> for ButtonData {
     fn from(state: ButtonState) -> Self {
         ButtonData::new()
-            .with_f0l(state.f0l)
-            .with_f0m(state.f0m)
-            .with_f0r(state.f0r)
+            .with_f0l(state.f0l())
+            .with_f0m(state.f0m())
+            .with_f0r(state.f0r())
             .with_t0(false)
-            .with_t3(state.t3)
-            .with_f3r(state.f3r)
-            .with_f3m(state.f3m)
-            .with_f3l(state.f3l)
-            .with_t4(state.t4)
-            .with_f4r(state.f4r)
-            .with_f4m(state.f4m)
-            .with_f4l(state.f4l)
-            .with_t1(state.t1)
-            .with_f1r(state.f1r)
-            .with_f1m(state.f1m)
-            .with_f1l(state.f1l)
-            .with_t2(state.t2)
-            .with_f2r(state.f2r)
-            .with_f2m(state.f2m)
-            .with_f2l(state.f2l)
+            .with_t3(state.t3())
+            .with_f3r(state.f3r())
+            .with_f3m(state.f3m())
+            .with_f3l(state.f3l())
+            .with_t4(state.t4())
+            .with_f4r(state.f4r())
+            .with_f4m(state.f4m())
+            .with_f4l(state.f4l())
+            .with_t1(state.t1())
+            .with_f1r(state.f1r())
+            .with_f1m(state.f1m())
+            .with_f1l(state.f1l())
+            .with_t2(state.t2())
+            .with_f2r(state.f2r())
+            .with_f2m(state.f2m())
+            .with_f2l(state.f2l())
     }
 }
 
 impl From<&ButtonState> for ButtonData {
     fn from(state: &ButtonState) -> Self {
         ButtonData::new()
-            .with_f0l(state.f0l)
-            .with_f0m(state.f0m)
-            .with_f0r(state.f0r)
+            .with_f0l(state.f0l())
+            .with_f0m(state.f0m())
+            .with_f0r(state.f0r())
             .with_t0(false)
-            .with_t3(state.t3)
-            .with_f3r(state.f3r)
-            .with_f3m(state.f3m)
-            .with_f3l(state.f3l)
-            .with_t4(state.t4)
-            .with_f4r(state.f4r)
-            .with_f4m(state.f4m)
-            .with_f4l(state.f4l)
-            .with_t1(state.t1)
-            .with_f1r(state.f1r)
-            .with_f1m(state.f1m)
-            .with_f1l(state.f1l)
-            .with_t2(state.t2)
-            .with_f2r(state.f2r)
-            .with_f2m(state.f2m)
-            .with_f2l(state.f2l)
+            .with_t3(state.t3())
+            .with_f3r(state.f3r())
+            .with_f3m(state.f3m())
+            .with_f3l(state.f3l())
+            .with_t4(state.t4())
+            .with_f4r(state.f4r())
+            .with_f4m(state.f4m())
+            .with_f4l(state.f4l())
+            .with_t1(state.t1())
+            .with_f1r(state.f1r())
+            .with_f1m(state.f1m())
+            .with_f1l(state.f1l())
+            .with_t2(state.t2())
+            .with_f2r(state.f2r())
+            .with_f2m(state.f2m())
+            .with_f2l(state.f2l())
     }
 }
 
-impl Into<ButtonState> for ButtonData {
-    fn into(self) -> ButtonState {
-        ButtonState {
-            t1: self.t1(),
-            t2: self.t2(),
-            t3: self.t3(),
-            t4: self.t4(),
-            f0l: self.f0l(),
-            f0m: self.f0m(),
-            f0r: self.f0r(),
-            f1r: self.f1r(),
-            f1m: self.f1m(),
-            f1l: self.f1l(),
-            f2r: self.f2r(),
-            f2m: self.f2m(),
-            f2l: self.f2l(),
-            f3r: self.f3r(),
-            f3m: self.f3m(),
-            f3l: self.f3l(),
-            f4r: self.f4r(),
-            f4m: self.f4m(),
-            f4l: self.f4l(),
-        }
+impl From<ButtonData> for ButtonState {
+    fn from(data: ButtonData) -> Self {
+        ButtonState::empty()
+            .with(ButtonState::T1, data.t1())
+            .with(ButtonState::T2, data.t2())
+            .with(ButtonState::T3, data.t3())
+            .with(ButtonState::T4, data.t4())
+            .with(ButtonState::F0L, data.f0l())
+            .with(ButtonState::F0M, data.f0m())
+            .with(ButtonState::F0R, data.f0r())
+            .with(ButtonState::F1R, data.f1r())
+            .with(ButtonState::F1M, data.f1m())
+            .with(ButtonState::F1L, data.f1l())
+            .with(ButtonState::F2R, data.f2r())
+            .with(ButtonState::F2M, data.f2m())
+            .with(ButtonState::F2L, data.f2l())
+            .with(ButtonState::F3R, data.f3r())
+            .with(ButtonState::F3M, data.f3m())
+            .with(ButtonState::F3L, data.f3l())
+            .with(ButtonState::F4R, data.f4r())
+            .with(ButtonState::F4M, data.f4m())
+            .with(ButtonState::F4L, data.f4l())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -266,27 +375,170 @@ impl Config {
             key_repeat_delay: 100,
             chords: vec![],
             command_lists: vec![],
+            metadata: Metadata::default(),
+            layers: vec![],
         }
     }
 }
 
-pub(crate) fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config, Box<dyn std::error::Error>> {
-    let res = Config::read(reader);
-    match res {
-        Ok(config) => {
-            //println!("{:?}", config);
-            return Ok(config);
-        }
-        Err(e) => Err(Box::new(e)),
+/// A config of arbitrary [`csv::Chord`](crate::csv::Chord)s, settings and
+/// metadata, with layers when the chords have them
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Config {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let chords = u.arbitrary()?;
+        let options = crate::output::ParseOptions::STRICT;
+        let (mut config, _) =
+            crate::convert::csv_to_twiddler7(chords, crate::layout::Layout::Us.into(), options)
+                .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+        config.set_settings(&u.arbitrary()?);
+        config.metadata = Metadata {
+            name: u.arbitrary()?,
+            author: u.arbitrary()?,
+            version: u.arbitrary()?,
+            description: u.arbitrary()?,
+        };
+        Ok(config)
+    }
+}
+
+#[rustfmt::skip]
+config_settings! {
+    key_repeat repeat_delay_enable with_repeat_delay_enable,
+    haptic haptic with_haptic,
+    direct direct with_direct,
+    bluetooth bluetooth with_bluetooth,
+    sticky_num sticky_num with_sticky_num,
+    sticky_alt sticky_alt with_sticky_alt,
+    sticky_ctrl sticky_ctrl with_sticky_ctrl,
+    sticky_shift sticky_shift with_sticky_shift,
+    left_mouse_pos left_mouse_pos with_left_mouse_pos,
+}
+
+impl Config {
+    /// Errors if the config has what only CSV and JSON configs keep, which
+    /// the `version` binary format, e.g. "v6", has no place for: layers,
+    /// chords switching to them and sticky chords
+    pub(crate) fn check_binary(&self, version: &str) -> Result<()> {
+        let commands = || {
+            self.chords
+                .iter()
+                .map(|chord| &chord.command)
+                .chain(self.command_lists.iter().flat_map(|list| &list.0))
+        };
+        let unsupported = if !self.layers.is_empty()
+            || commands().any(|command| command.switched_layer().is_some())
+        {
+            "layers"
+        } else if commands().any(|command| command.sticky_modifiers().is_some()) {
+            "sticky chords"
+        } else {
+            return Ok(());
+        };
+        Err(Error::Unsupported(format!(
+            "{} configs have no {}, only CSV and JSON configs can keep them",
+            version, unsupported
+        )))
     }
 }
 
-pub(crate) fn write<W: Write + Seek>(
+// twiddler-cfg keeps the metadata, which the Tuner's format has no place
+// for, in a block after the command lists: a magic, a u32 length and then
+// the metadata as JSON
+const METADATA_MAGIC: &[u8; 4] = b"TCMD";
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+pub fn parse<R: Read + Seek>(reader: &mut R) -> Result<Config> {
+    check_size(reader)?;
+    let mut config = Config::read(reader)?;
+    event!(
+        debug,
+        chords = config.chords.len(),
+        command_lists = config.command_lists.len(),
+        end = ?reader.stream_position().ok(),
+        "Read v7 config"
+    );
+    while let Some(data) = read_block(reader)? {
+        event!(
+            debug,
+            len = data.len(),
+            end = ?reader.stream_position().ok(),
+            "Read block"
+        );
+        config.metadata = serde_json::from_slice(&data)?;
+    }
+    Ok(config)
+}
+
+// The header has to be there and the chord table has to fit, otherwise a
+// bad chord count reads garbage chords until the file runs out
+fn check_size<R: Read + Seek>(reader: &mut R) -> Result<()> {
+    let start = reader.stream_position()?;
+    let len = reader.seek(SeekFrom::End(0))? - start;
+    reader.seek(SeekFrom::Start(start))?;
+    if len < 0x80 {
+        return Err(Error::Invalid(format!(
+            "The config is {} bytes, too short for the 128 byte v7 header",
+            len
+        )));
+    }
+
+    let mut count = [0; 2];
+    reader.seek(SeekFrom::Start(start + 0x08))?;
+    reader.read_exact(&mut count)?;
+    reader.seek(SeekFrom::Start(start))?;
+    let chords = u64::from(u16::from_le_bytes(count));
+    if 0x80 + chords * 8 > len {
+        return Err(Error::Invalid(format!(
+            "The header at 0x08 says there are {} chords but the {} byte config only has room for {}",
+            chords,
+            len,
+            (len - 0x80) / 8
+        )));
+    }
+    Ok(())
+}
+
+// Configs from the Tuner end with the command lists
+fn read_block<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0; 8];
+    if reader.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    if header[..4] != *METADATA_MAGIC {
+        return Ok(None);
+    }
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap());
+    let mut data = vec![];
+    reader.take(len.into()).read_to_end(&mut data)?;
+    if data.len() != len as usize {
+        return Err(Error::Invalid(format!(
+            "A {} block says it has {} bytes but the config ends after {}",
+            String::from_utf8_lossy(METADATA_MAGIC),
+            len,
+            data.len()
+        )));
+    }
+    Ok(Some(data))
+}
+
+fn write_block<W: Write>(writer: &mut W, magic: &[u8; 4], data: &[u8]) -> Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+/// Write the config, returning where its command lists went to report how
+/// much of the room for them they take. With `share` repeated command lists
+/// are written once, see [`intern`].
+pub fn write<W: Write + Seek>(
     mut config: Config,
     writer: &mut W,
     gen_caps: Option<i32>,
     ensure_system_chords: bool,
-) -> std::io::Result<()> {
+    share: bool,
+) -> Result<Interned> {
     if let Some(t_key) = gen_caps {
         config.generate_caps(t_key);
     }
@@ -295,6 +547,25 @@ pub(crate) fn write<W: Write + Seek>(
         config.ensure_system_chords();
     }
 
+    let interned = write_config(config, writer, share)?;
+    event!(
+        info,
+        command_lists = interned.len,
+        limit = intern::OFFSET_LIMIT,
+        saved = interned.saved(),
+        "Wrote config"
+    );
+    Ok(interned)
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+fn write_config<W: Write + Seek>(
+    mut config: Config,
+    writer: &mut W,
+    share: bool,
+) -> Result<Interned> {
+    config.check_binary("v7")?;
+
     // update number of chords
     config.number_of_chords = config.chords.len() as u16;
 
@@ -304,54 +575,122 @@ pub(crate) fn write<W: Write + Seek>(
         .iter()
         .filter(|c| c.command.command_type == CommandType::ListOfCommands)
         .count();
-    assert!(
-        command_lists_command_count == config.command_lists.len(),
-        "Commands with CommandType::ListOfCommands count mismatch"
-    );
+    if command_lists_command_count != config.command_lists.len() {
+        return Err(Error::Invalid(
+            "Commands with CommandType::ListOfCommands count mismatch".to_owned(),
+        ));
+    }
 
-    let mut offset = 0;
+    let interned = intern::lay_out(&intern::encode(&config.command_lists)?, share);
+    interned.check()?;
 
-    let mut j = 0;
-    for i in 0..config.chords.len() {
-        if config.chords[i].command.command_type == CommandType::ListOfCommands {
-            let size = config.command_lists[j].0.len() * 4;
-            config.chords[i].command.data = CommandData::ListOfCommands(offset);
-            offset += size as u16;
-            offset += 4; // 0u32
-            j += 1;
+    let mut offsets = interned.offsets.iter();
+    for chord in &mut config.chords {
+        if chord.command.command_type == CommandType::ListOfCommands {
+            let offset = *offsets.next().unwrap() as u16;
+            chord.command.data = CommandData::ListOfCommands(offset);
         }
     }
-
-    let res = Config::write(&config, writer);
-    match res {
-        Ok(_) => {
-            println!("Wrote config");
-        }
-        Err(e) => {
-            println!("{:?}", e);
-        }
+    config.command_lists = interned.take_stored(std::mem::take(&mut config.command_lists));
+
+    Config::write(&config, writer)?;
+    event!(
+        debug,
+        chords = config.chords.len(),
+        command_lists = config.command_lists.len(),
+        saved = interned.saved(),
+        end = ?writer.stream_position().ok(),
+        "Wrote v7 config"
+    );
+    if !config.metadata.is_empty() {
+        write_block(
+            writer,
+            METADATA_MAGIC,
+            &serde_json::to_vec(&config.metadata)?,
+        )?;
     }
 
     // TODO: Figure out more config format details
-    writer.seek(SeekFrom::Start(0x44));
+    writer.seek(SeekFrom::Start(0x44))?;
     let data = hex::decode("0300000001000000020000000A0B0909000000000000000000000000000102030405060708090A0C0D0F111416181A1D808080808080808080808080").unwrap();
-    writer.write(&data)?;
+    writer.write_all(&data)?;
 
-    Ok(())
+    Ok(interned)
 }
 
 impl Config {
+    /// Add the chords Twiddler 2 firmware types by holding the Alt, Ctrl or
+    /// Shift thumbs with a chord typing one key, see [`THUMB_MODIFIERS`], so
+    /// they type the same on a Twiddler where thumbs are only chord buttons.
+    /// Buttons that already have a chord keep it. Returns how many were
+    /// added, layers included.
+    pub fn add_thumb_modifier_chords(&mut self) -> usize {
+        // Chords on T0 are system chords, on buttons of their own
+        let mut taken: HashSet<ButtonState> = self
+            .chords
+            .iter()
+            .filter(|chord| !chord.buttons.t0())
+            .map(|chord| chord.buttons.into())
+            .collect();
+
+        let mut added = vec![];
+        for chord in &self.chords {
+            let buttons = ButtonState::from(chord.buttons);
+            let CommandData::Keyboard(command) = &chord.command.data else {
+                continue;
+            };
+            if chord.command.command_type != CommandType::Keyboard
+                || chord.buttons.t0()
+                || command.key_code == 0
+                || buttons.thumb_modifiers() != 0
+            {
+                continue;
+            }
+            // Every combination of the modifier thumbs, they can be held
+            // together
+            for held in 1..1 << THUMB_MODIFIERS.len() {
+                let thumbs = THUMB_MODIFIERS
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| held & 1 << i != 0)
+                    .fold(ButtonState::empty(), |thumbs, (_, (thumb, _))| {
+                        thumbs | *thumb
+                    });
+                if !taken.insert(buttons | thumbs) {
+                    continue;
+                }
+                added.push(Chord {
+                    buttons: (buttons | thumbs).into(),
+                    command: Command {
+                        command_type: CommandType::Keyboard,
+                        data: CommandData::Keyboard(HidCommand {
+                            modifier: command.modifier | thumbs.thumb_modifiers(),
+                            key_code: command.key_code,
+                        }),
+                    },
+                });
+            }
+        }
+
+        let mut count = added.len();
+        self.chords.append(&mut added);
+        for layer in &mut self.layers {
+            count += layer.add_thumb_modifier_chords();
+        }
+        count
+    }
+
     fn generate_caps(&mut self, t_key: i32) {
         // Generate chords for caps
 
         let mut new_chords = vec![];
         for chord in &self.chords {
             if chord.command.command_type == CommandType::Keyboard
-                && chord.buttons.t0() == false
-                && chord.buttons.t1() == false
-                && chord.buttons.t2() == false
-                && chord.buttons.t3() == false
-                && chord.buttons.t4() == false
+                && !chord.buttons.t0()
+                && !chord.buttons.t1()
+                && !chord.buttons.t2()
+                && !chord.buttons.t3()
+                && !chord.buttons.t4()
             {
                 if let CommandData::Keyboard(hid_command) = &chord.command.data {
                     if hid::ALPHA_HID_CODES.contains(&hid_command.key_code) {
@@ -376,29 +715,26 @@ impl Config {
             }
         }
 
-        if new_chords.len() > 0 {
-            println!("Adding {} uppercase chords", new_chords.len());
+        if !new_chords.is_empty() {
+            event!(info, chords = new_chords.len(), "Adding uppercase chords");
             self.chords.append(&mut new_chords);
         }
     }
 
     fn ensure_system_chords(&mut self) {
-        let system_commands = vec![
-            CommandData::System(6, 0), // Bluetooth hosts: clear
-            CommandData::System(2, 0),  // LED: Keyboard Flags
-            CommandData::System(10, 0), // LED: Battery Level
-            CommandData::System(12, 0), // Print status to keyboard
-            CommandData::System(1, 0), // Sleep now
-            CommandData::System(5, 0), // Bluetooth hosts: cycle
-            CommandData::System(4, 0), // Config cycle
-            CommandData::System(11, 0), // Nav mode: cycle
+        let system_commands = [
+            CommandData::System(6, 0),            // Bluetooth hosts: clear
+            CommandData::System(2, 0),            // LED: Keyboard Flags
+            CommandData::System(10, 0),           // LED: Battery Level
+            CommandData::System(12, 0),           // Print status to keyboard
+            CommandData::System(1, 0),            // Sleep now
+            CommandData::System(5, 0),            // Bluetooth hosts: cycle
+            CommandData::System(CONFIG_CYCLE, 0), // Config cycle
+            CommandData::System(11, 0),           // Nav mode: cycle
         ];
 
-        let buttons = vec![
-            ButtonData::new()
-                .with_t1(true)
-                .with_t4(true)
-                .with_f4r(true),
+        let buttons = [
+            ButtonData::new().with_t1(true).with_t4(true).with_f4r(true),
             ButtonData::new().with_f1l(true).with_t0(true),
             ButtonData::new().with_f1m(true).with_t0(true),
             ButtonData::new().with_f1r(true).with_t0(true),
@@ -422,7 +758,7 @@ impl Config {
 
             if !found {
                 self.chords.push(Chord {
-                    buttons: button.clone(),
+                    buttons: *button,
                     command: Command {
                         command_type: CommandType::System,
                         data: command.clone(),
@@ -438,6 +774,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_comparison)]
     fn test_header() {
         let mut file = std::fs::File::open("test/configs/v7/haptic_off.cfg").unwrap();
         let conf: Config = Config::read(&mut file).unwrap();
@@ -475,7 +812,8 @@ mod tests {
         let conf = Config::read(&mut file).unwrap();
         assert!(conf.flags.left_mouse_pos() == false);
         assert!(conf.flags.repeat_delay_enable() == true);
-        assert!(conf.flags.haptic() == false);
+        assert!(conf.flags.haptic() == true);
+        assert!(conf.flags.direct() == false);
         assert!(conf.flags.sticky_alt() == false);
         assert!(conf.flags.sticky_num() == false);
         assert!(conf.flags.sticky_shift() == false);
@@ -490,7 +828,7 @@ mod tests {
         let conf = Config::read(&mut file).unwrap();
         for chord in &conf.chords {
             if chord.command.command_type == CommandType::System {
-                println!("{:?}", chord);
+                eprintln!("{:?}", chord);
             }
         }
 
@@ -498,6 +836,109 @@ mod tests {
         let conf = Config::read(&mut file).unwrap();
         assert!(conf.number_of_chords == 157);
         assert!(conf.chords.len() == 157);
-        assert!(conf.chords[0].buttons.f1r() == true);
+        assert!(conf.chords[0].buttons.f1r());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let original = std::fs::read("test/configs/v7/more_system.cfg").unwrap();
+        let conf = parse(&mut std::io::Cursor::new(&original)).unwrap();
+        let json = serde_json::to_string(&conf).unwrap();
+        let read: Config = serde_json::from_str(&json).unwrap();
+        assert!(read.settings() == conf.settings());
+
+        let write = |config| {
+            let mut data = std::io::Cursor::new(vec![]);
+            super::write(config, &mut data, None, false, false).unwrap();
+            data.into_inner()
+        };
+        assert!(write(read) == write(conf));
+    }
+
+    #[test]
+    fn test_shared_command_lists() {
+        let csv = "thumbs,fingers,output\n,1L,the\n,1M,he\n,1R,the\n,2L,x\n";
+        let config =
+            crate::convert::read(crate::Format::Csv, &mut std::io::Cursor::new(csv)).unwrap();
+        let mut data = std::io::Cursor::new(vec![]);
+        write(config, &mut data, None, false, true).unwrap();
+        let data = data.into_inner();
+
+        // Only "the" is written, "he" points into it
+        let offsets: Vec<_> = (0..3).map(|i| data[0x80 + i * 8 + 5]).collect();
+        assert!(offsets == [0, 4, 0]);
+        assert!(data.len() == 0x80 + 4 * 8 + 16);
+
+        let config = parse(&mut std::io::Cursor::new(&data)).unwrap();
+        let lists: Vec<_> = config.command_lists.iter().map(|l| l.0.len()).collect();
+        assert!(lists == [3, 2, 3]);
+
+        let regions = crate::inspect::annotate(&data).unwrap();
+        assert!(regions.iter().any(|r| r.label == "end of command list 0"));
+        assert!(!regions.iter().any(|r| r.label.contains("command list 1,")));
+    }
+
+    #[test]
+    fn test_thumb_modifier_chords() {
+        let csv = "thumbs,fingers,output\n,1L,a\n4,1L,<L-Shift>b</L-Shift>\n,1M,the\n1,1R,x\n";
+        let mut config =
+            crate::convert::read(crate::Format::Csv, &mut std::io::Cursor::new(csv)).unwrap();
+        // Every combination of T2 to T4 for "a" but T4 alone, which was
+        // taken, and the same for "x" held with T1
+        assert!(config.add_thumb_modifier_chords() == 6 + 7);
+        let chord = |buttons: &str| {
+            let buttons = ButtonData::from(buttons.parse::<ButtonState>().unwrap());
+            config
+                .chords
+                .iter()
+                .find(|chord| chord.buttons == buttons)
+                .map(|chord| chord.command.data.clone())
+        };
+        let key =
+            |modifier, key_code| Some(CommandData::Keyboard(HidCommand { modifier, key_code }));
+        assert!(chord("T2 1L") == key(0x04, 0x04));
+        assert!(chord("T3 T4 1L") == key(0x03, 0x04));
+        assert!(chord("T4 1L") == key(0x02, 0x05));
+        assert!(chord("T1 T2 T3 T4 1R") == key(0x07, 0x1B));
+        assert!(chord("T2 1M").is_none());
+        assert!(config.add_thumb_modifier_chords() == 0);
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let mut config = Config::new();
+        config.chords.push(Chord {
+            buttons: ButtonState::F1L.into(),
+            command: Command {
+                command_type: CommandType::ListOfCommands,
+                data: CommandData::ListOfCommands(0),
+            },
+        });
+        config.command_lists.push(CommandList(vec![Command {
+            command_type: CommandType::Delay,
+            data: CommandData::Delay(10),
+        }]));
+        config.metadata.name = Some("Corrupt".to_string());
+        let mut data = std::io::Cursor::new(vec![]);
+        write(config, &mut data, None, false, false).unwrap();
+        let data = data.into_inner();
+        let error = |data: &[u8]| {
+            parse(&mut std::io::Cursor::new(data))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(parse(&mut std::io::Cursor::new(&data)).is_ok());
+
+        let mut count = data.clone();
+        count[0x08] = 200;
+        assert!(error(&count).contains("says there are 200 chords"));
+
+        let mut offset = data.clone();
+        offset[0x85] = 8;
+        assert!(error(&offset).contains("Chord 0 at 0x80 points to a command list at +0x8"));
+
+        assert!(error(&data[..data.len() - 1]).contains("TCMD block says it has"));
+        assert!(error(&data[..0x40]).contains("too short"));
     }
 }
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use crate::{buttons::ButtonState, csv::Chord};
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Conflict {
+    /// The same buttons are mapped to different outputs, only one of them
+    /// will work on the device
+    SameButtons {
+        buttons: ButtonState,
+        chords: Vec<usize>,
+    },
+    /// The exact same row appears more than once
+    Duplicate { chords: Vec<usize> },
+}
+
+/// Find chords that can't all be used at once. Chords are referred to by
+/// their index in `chords`.
+pub fn find_conflicts(chords: &[Chord]) -> Vec<Conflict> {
+    let mut groups: Vec<(ButtonState, Vec<usize>)> = vec![];
+    // Chords on other layers never clash
+    let mut group_index: HashMap<(u8, ButtonState), usize> = HashMap::new();
+    for (i, chord) in chords.iter().enumerate() {
+        let buttons = ButtonState::lossy(chord);
+        let index = *group_index
+            .entry((chord.layer(), buttons))
+            .or_insert_with(|| {
+                groups.push((buttons, vec![]));
+                groups.len() - 1
+            });
+        groups[index].1.push(i);
+    }
+
+    let mut conflicts = vec![];
+    for (buttons, indices) in groups.into_iter().filter(|(_, i)| i.len() > 1) {
+        let mut outputs: Vec<(&str, Vec<usize>)> = vec![];
+        for &i in &indices {
+            match outputs.iter_mut().find(|(o, _)| *o == chords[i].output) {
+                Some((_, same)) => same.push(i),
+                None => outputs.push((&chords[i].output, vec![i])),
+            }
+        }
+
+        if outputs.len() > 1 {
+            conflicts.push(Conflict::SameButtons {
+                buttons,
+                chords: indices,
+            });
+        }
+        for (_, same) in outputs.into_iter().filter(|(_, s)| s.len() > 1) {
+            conflicts.push(Conflict::Duplicate { chords: same });
+        }
+    }
+
+    conflicts
+}
+
+/// Find chords no hand can press, with L and R of the same row, by their
+/// index in `chords`
+pub fn find_impossible(chords: &[Chord]) -> Vec<usize> {
+    chords
+        .iter()
+        .enumerate()
+        .filter(|(_, chord)| !ButtonState::lossy(chord).is_physically_possible())
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Chords on different buttons that type the same output
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuplicateOutput {
+    pub output: String,
+    pub chords: Vec<usize>,
+}
+
+/// Find outputs that more than one chord types, which is allowed but often
+/// left over from merging layouts. Rows that are exact duplicates are left to
+/// [`find_conflicts`].
+pub fn find_duplicate_outputs(chords: &[Chord]) -> Vec<DuplicateOutput> {
+    let mut groups: Vec<(&str, Vec<usize>, Vec<ButtonState>)> = vec![];
+    for (i, chord) in chords.iter().enumerate() {
+        let buttons = ButtonState::lossy(chord);
+        match groups.iter_mut().find(|(o, _, _)| *o == chord.output) {
+            Some((_, _, seen)) if seen.contains(&buttons) => (),
+            Some((_, indices, seen)) => {
+                indices.push(i);
+                seen.push(buttons);
+            }
+            None => groups.push((&chord.output, vec![i], vec![buttons])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, indices, _)| indices.len() > 1)
+        .map(|(output, chords, _)| DuplicateOutput {
+            output: output.to_string(),
+            chords,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(thumbs: &str, fingers: &str, output: &str) -> Chord {
+        Chord {
+            thumbs: Some(thumbs.to_string()),
+            fingers: Some(fingers.to_string()),
+            output: output.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let chords = vec![
+            chord("", "1L", "a"),
+            chord("", "1M", "b"),
+            chord("", "1L", "c"),
+            chord("1", "1M", "B"),
+            chord("", "1M", "b"),
+        ];
+
+        let conflicts = find_conflicts(&chords);
+        assert!(conflicts.len() == 2);
+        assert!(matches!(
+            &conflicts[0],
+            Conflict::SameButtons { chords, .. } if chords == &[0, 2]
+        ));
+        assert!(conflicts[1] == Conflict::Duplicate { chords: vec![1, 4] });
+    }
+
+    #[test]
+    fn test_find_impossible() {
+        let chords = vec![
+            chord("", "1L 1M", "a"),
+            chord("", "1L 1R", "b"),
+            chord("1", "0M 2L 2M 2R", "c"),
+        ];
+        assert!(find_impossible(&chords) == [1, 2]);
+    }
+
+    #[test]
+    fn test_find_duplicate_outputs() {
+        let chords = vec![
+            chord("", "1L", "the "),
+            chord("", "1M", "b"),
+            chord("1", "2L", "the "),
+            chord("", "1L", "the "),
+        ];
+
+        assert!(
+            find_duplicate_outputs(&chords)
+                == vec![DuplicateOutput {
+                    output: "the ".to_string(),
+                    chords: vec![0, 2],
+                }]
+        );
+    }
+
+    #[test]
+    fn test_no_conflicts() {
+        let chords = std::fs::File::open("configs/twiddler_cfg_CoolHand.csv").unwrap();
+        let chords = crate::csv::parse(&mut std::io::BufReader::new(chords)).unwrap();
+        assert!(find_conflicts(&chords).is_empty());
+        assert!(find_impossible(&chords).is_empty());
+    }
+}
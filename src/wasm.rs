@@ -0,0 +1,123 @@
+use std::io::Cursor;
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    config,
+    convert::{self, Format},
+    export::{self, Target},
+    json,
+    layout::Layout,
+    output::ParseOptions,
+    render,
+    validate::{self, Conflict, DuplicateOutput},
+    Error, Result,
+};
+
+/// What [`validate`] finds, like the `validate` command
+#[derive(Debug, serde::Serialize)]
+struct Validation {
+    conflicts: Vec<Conflict>,
+    duplicate_outputs: Vec<DuplicateOutput>,
+}
+
+fn target(name: &str) -> Result<Target> {
+    match name {
+        "qmk" => Ok(Target::Qmk),
+        "kanata" => Ok(Target::Kanata),
+        "karabiner" => Ok(Target::Karabiner),
+        _ => Err(Error::Unsupported(format!("Unknown target {}", name))),
+    }
+}
+
+fn read_config(data: &[u8], format: Option<Format>) -> Result<json::Config> {
+    let mut reader = Cursor::new(data);
+    let format = match format {
+        Some(format) => format,
+        None => config::detect_version(&mut reader)?.format()?,
+    };
+    convert::to_json(convert::read(format, &mut reader)?, Layout::Us)
+}
+
+fn write_config(config: json::Config, format: Format) -> Result<Vec<u8>> {
+    let (config, _) = convert::from_json(config, Layout::Us, ParseOptions::STRICT)?;
+    let mut output = Cursor::new(vec![]);
+    convert::write(format, config, &mut output)?;
+    Ok(output.into_inner())
+}
+
+// Maps become plain objects instead of JS Maps
+fn to_js<T: serde::Serialize>(value: &T) -> std::result::Result<JsValue, JsError> {
+    value
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn from_js(config: JsValue) -> std::result::Result<json::Config, JsError> {
+    serde_wasm_bindgen::from_value(config).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Reads a config file into an object shaped like a JSON config, see
+/// [`json::Config`]. The format is detected when left out.
+#[wasm_bindgen]
+pub fn parse(data: &[u8], format: Option<String>) -> std::result::Result<JsValue, JsError> {
+    let format = format.as_deref().map(str::parse).transpose()?;
+    to_js(&read_config(data, format)?)
+}
+
+/// Writes a config object as a file in `format`
+#[wasm_bindgen]
+pub fn write(config: JsValue, format: &str) -> std::result::Result<Vec<u8>, JsError> {
+    Ok(write_config(from_js(config)?, format.parse()?)?)
+}
+
+/// The chords of a config object for "qmk", "kanata" or "karabiner"
+#[wasm_bindgen]
+pub fn export(config: JsValue, target: &str) -> std::result::Result<String, JsError> {
+    Ok(export::export(
+        self::target(target)?,
+        &from_js(config)?.chords,
+    ))
+}
+
+/// `{ conflicts, duplicate_outputs }` for the chords of a config object,
+/// chords are referred to by their index
+#[wasm_bindgen]
+pub fn validate(config: JsValue) -> std::result::Result<JsValue, JsError> {
+    let chords = from_js(config)?.chords;
+    to_js(&Validation {
+        conflicts: validate::find_conflicts(&chords),
+        duplicate_outputs: validate::find_duplicate_outputs(&chords),
+    })
+}
+
+/// A cheat sheet of a config object's chords as "svg", "html" or "anki"
+#[wasm_bindgen]
+pub fn render(config: JsValue, kind: &str) -> std::result::Result<String, JsError> {
+    let chords = from_js(config)?.chords;
+    match kind {
+        "svg" => Ok(render::svg(&chords)),
+        "html" => Ok(render::html(&chords)),
+        "anki" => Ok(render::anki(&chords)),
+        _ => Err(Error::Unsupported(format!("Unknown cheat sheet {}", kind)).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"Thumbs,Fingers,Keyboard Output\n,1L,a\n,1M,<Return>\n";
+        let config = read_config(data, None).unwrap();
+        assert!(config.chords.len() == 2 && config.chords[1].output() == "<Return>");
+        assert!(read_config(data, Some(Format::Twiddler7)).is_err());
+
+        let binary = write_config(config, Format::Twiddler7).unwrap();
+        let config = read_config(&binary, None).unwrap();
+        let csv = write_config(config, Format::Csv).unwrap();
+        assert!(csv == b"thumbs,fingers,output\n,1L,a\n,1M,<Return>\n");
+        assert!(target("qmk").is_ok() && target("zmk").is_err());
+    }
+}